@@ -0,0 +1,151 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Bitácora de auditoría
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Registro de quién cambió qué en las tablas clínicas y
+-- financieras: usuario, fecha, tabla, registro y el contenido antes
+-- y después del cambio (JSON).
+--
+-- Se usa la tabla audit_logs que ya existía (user_id, action,
+-- target_table, target_id, meta, created_at), sin escritores hasta
+-- ahora, y se le agregan el paciente y el contenido antes/después.
+-- Los registros anteriores se conservan; su detalle sigue en meta.
+--
+-- La aplicación fija app.user_id en cada conexión con el usuario
+-- que inició sesión; los triggers lo leen. Los cambios hechos fuera
+-- de la aplicación quedan registrados sin usuario. La bitácora no se
+-- modifica ni se borra.
+--
+-- Esta migración incluye:
+-- 1. Columnas de auditoría en audit_logs
+-- 2. Función y triggers de auditoría
+-- 3. Protección contra cambios en la bitácora
+-- ============================================================
+
+
+-- ============================================================
+-- 1. BITÁCORA
+-- ============================================================
+-- action es 'INSERT', 'UPDATE' o 'DELETE' en los registros de los
+-- triggers; target_table y target_id identifican el registro.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS audit_logs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID,
+    action TEXT NOT NULL,
+    target_table TEXT,
+    target_id TEXT,
+    meta JSONB,
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+ALTER TABLE audit_logs ADD COLUMN IF NOT EXISTS patient_id UUID;
+ALTER TABLE audit_logs ADD COLUMN IF NOT EXISTS before_data JSONB;
+ALTER TABLE audit_logs ADD COLUMN IF NOT EXISTS after_data JSONB;
+
+CREATE INDEX IF NOT EXISTS idx_audit_logs_patient ON audit_logs(patient_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_logs_user ON audit_logs(user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_logs_created ON audit_logs(created_at);
+
+
+-- ============================================================
+-- 2. TRIGGERS
+-- ============================================================
+
+CREATE OR REPLACE FUNCTION audit_row_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    old_data JSONB;
+    new_data JSONB;
+    row_data JSONB;
+    audit_patient UUID;
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        old_data := to_jsonb(OLD);
+    END IF;
+    IF TG_OP <> 'DELETE' THEN
+        new_data := to_jsonb(NEW);
+    END IF;
+    -- UPDATE que no cambió nada
+    IF TG_OP = 'UPDATE' AND old_data = new_data THEN
+        RETURN NULL;
+    END IF;
+    row_data := COALESCE(new_data, old_data);
+
+    audit_patient := CASE TG_TABLE_NAME
+        WHEN 'patients' THEN (row_data->>'id')::uuid
+        WHEN 'encounters' THEN (row_data->>'patient_id')::uuid
+        WHEN 'surgeries' THEN (row_data->>'patient_id')::uuid
+        WHEN 'invoices' THEN (row_data->>'patient_id')::uuid
+        WHEN 'exam_eye' THEN (SELECT patient_id FROM encounters WHERE id = (row_data->>'encounter_id')::uuid)
+        WHEN 'diagnoses' THEN (SELECT patient_id FROM encounters WHERE id = (row_data->>'encounter_id')::uuid)
+        WHEN 'payments' THEN (SELECT patient_id FROM invoices WHERE id = (row_data->>'invoice_id')::uuid)
+    END;
+
+    INSERT INTO audit_logs (user_id, target_table, target_id, patient_id, action, before_data, after_data)
+    VALUES (
+        NULLIF(current_setting('app.user_id', true), '')::uuid,
+        TG_TABLE_NAME,
+        row_data->>'id',
+        audit_patient,
+        TG_OP,
+        old_data,
+        new_data
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_audit_patients ON patients;
+CREATE TRIGGER trg_audit_patients
+    AFTER INSERT OR UPDATE OR DELETE ON patients
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_encounters ON encounters;
+CREATE TRIGGER trg_audit_encounters
+    AFTER INSERT OR UPDATE OR DELETE ON encounters
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_exam_eye ON exam_eye;
+CREATE TRIGGER trg_audit_exam_eye
+    AFTER INSERT OR UPDATE OR DELETE ON exam_eye
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_diagnoses ON diagnoses;
+CREATE TRIGGER trg_audit_diagnoses
+    AFTER INSERT OR UPDATE OR DELETE ON diagnoses
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_surgeries ON surgeries;
+CREATE TRIGGER trg_audit_surgeries
+    AFTER INSERT OR UPDATE OR DELETE ON surgeries
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_invoices ON invoices;
+CREATE TRIGGER trg_audit_invoices
+    AFTER INSERT OR UPDATE OR DELETE ON invoices
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+DROP TRIGGER IF EXISTS trg_audit_payments ON payments;
+CREATE TRIGGER trg_audit_payments
+    AFTER INSERT OR UPDATE OR DELETE ON payments
+    FOR EACH ROW EXECUTE FUNCTION audit_row_change();
+
+
+-- ============================================================
+-- 3. PROTECCIÓN DE LA BITÁCORA
+-- ============================================================
+
+CREATE OR REPLACE FUNCTION prevent_audit_log_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'La bitácora de auditoría no puede modificarse ni eliminarse';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_prevent_audit_log_changes ON audit_logs;
+CREATE TRIGGER trg_prevent_audit_log_changes
+    BEFORE UPDATE OR DELETE ON audit_logs
+    FOR EACH ROW EXECUTE FUNCTION prevent_audit_log_changes();
@@ -0,0 +1,38 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Códigos de barras y GS1-128
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Los productos guardan su EAN/UPC/GTIN y los lotes pueden tener un
+-- código propio. Al escanear un código GS1-128 (por ejemplo en cajas
+-- de lentes intraoculares) se obtienen GTIN (01), vencimiento (17),
+-- lote (10) y serie (21) sin digitarlos a mano.
+--
+-- Esta migración incluye:
+-- 1. Código de barras en productos
+-- 2. Código de barras en lotes
+-- ============================================================
+
+
+-- ============================================================
+-- 1. PRODUCTOS
+-- ============================================================
+
+ALTER TABLE inventory_items ADD COLUMN IF NOT EXISTS barcode TEXT;
+
+-- Un código por producto activo dentro de cada sucursal
+CREATE UNIQUE INDEX IF NOT EXISTS idx_inventory_items_branch_barcode
+ON inventory_items(branch_id, barcode) WHERE barcode IS NOT NULL AND deleted_at IS NULL;
+
+
+-- ============================================================
+-- 2. LOTES
+-- ============================================================
+
+ALTER TABLE inventory_lots ADD COLUMN IF NOT EXISTS barcode TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_inventory_lots_barcode
+ON inventory_lots(barcode) WHERE barcode IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_inventory_lots_item_lot_number
+ON inventory_lots(item_id, lot_number);
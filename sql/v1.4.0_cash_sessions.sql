@@ -0,0 +1,86 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Sesiones de caja
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Cada cajero abre una sesión con un fondo inicial, registra
+-- entradas/salidas de efectivo (caja chica) y cierra con un conteo
+-- ciego por denominación. Al cerrar se calcula el sobrante/faltante:
+--   esperado = fondo + ventas en efectivo (GTQ) + entradas - salidas
+--   sobrante/faltante = contado - esperado
+--
+-- Esta migración incluye:
+-- 1. Tabla de sesiones de caja
+-- 2. Movimientos de efectivo por sesión
+-- 3. Conteo por denominación
+-- 4. Sesión de caja en pagos
+-- ============================================================
+
+
+-- ============================================================
+-- 1. SESIONES DE CAJA
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS cash_register_sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    branch_id UUID NOT NULL REFERENCES branches(id),
+    cashier_id UUID NOT NULL,
+    status TEXT NOT NULL DEFAULT 'abierta' CHECK (status IN ('abierta', 'cerrada')),
+    opening_float NUMERIC(12,2) NOT NULL DEFAULT 0 CHECK (opening_float >= 0),
+    opened_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    closed_at TIMESTAMPTZ,
+    efectivo_total NUMERIC(12,2),
+    cash_in_total NUMERIC(12,2),
+    cash_out_total NUMERIC(12,2),
+    expected_cash NUMERIC(12,2),
+    counted_cash NUMERIC(12,2),
+    over_short NUMERIC(12,2),
+    notes TEXT,
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cash_register_sessions_branch ON cash_register_sessions(branch_id, opened_at DESC);
+
+-- Un cajero solo puede tener una sesión abierta por sucursal
+CREATE UNIQUE INDEX IF NOT EXISTS idx_cash_register_sessions_one_open
+ON cash_register_sessions(branch_id, cashier_id) WHERE status = 'abierta';
+
+
+-- ============================================================
+-- 2. MOVIMIENTOS DE EFECTIVO
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS cash_register_movements (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES cash_register_sessions(id) ON DELETE CASCADE,
+    movement_type TEXT NOT NULL CHECK (movement_type IN ('entrada', 'salida')),
+    amount NUMERIC(12,2) NOT NULL CHECK (amount > 0),
+    reason TEXT NOT NULL,
+    created_by UUID,
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cash_register_movements_session ON cash_register_movements(session_id);
+
+
+-- ============================================================
+-- 3. CONTEO POR DENOMINACIÓN
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS cash_register_counts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES cash_register_sessions(id) ON DELETE CASCADE,
+    denomination NUMERIC(8,2) NOT NULL CHECK (denomination > 0),
+    quantity INTEGER NOT NULL CHECK (quantity >= 0),
+    UNIQUE (session_id, denomination)
+);
+
+
+-- ============================================================
+-- 4. SESIÓN DE CAJA EN PAGOS
+-- ============================================================
+
+ALTER TABLE payments
+ADD COLUMN IF NOT EXISTS cash_session_id UUID REFERENCES cash_register_sessions(id);
+
+CREATE INDEX IF NOT EXISTS idx_payments_cash_session ON payments(cash_session_id) WHERE cash_session_id IS NOT NULL;
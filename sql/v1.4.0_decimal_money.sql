@@ -0,0 +1,50 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Montos con decimales exactos
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- La aplicación maneja todos los montos como decimales exactos
+-- (rust_decimal <-> NUMERIC), redondeados a centavos con la regla
+-- "mitad lejos de cero" (Q0.005 -> Q0.01).
+--
+-- Esta migración incluye:
+-- 1. Precisión fija en columnas de montos agregadas en multi-moneda
+-- 2. Redondeo de montos base existentes a centavos
+-- ============================================================
+
+
+-- ============================================================
+-- 1. PRECISIÓN DE COLUMNAS
+-- ============================================================
+-- Los tipos de cambio guardan 6 decimales; los montos, 2.
+-- ============================================================
+
+ALTER TABLE payments
+ALTER COLUMN amount_base TYPE NUMERIC(12,2) USING ROUND(amount_base, 2),
+ALTER COLUMN exchange_rate TYPE NUMERIC(12,6);
+
+ALTER TABLE exchange_rates
+ALTER COLUMN rate TYPE NUMERIC(12,6);
+
+ALTER TABLE cash_closure_currency_totals
+ALTER COLUMN efectivo_total TYPE NUMERIC(12,2),
+ALTER COLUMN tarjeta_total TYPE NUMERIC(12,2),
+ALTER COLUMN transferencia_total TYPE NUMERIC(12,2),
+ALTER COLUMN cheque_total TYPE NUMERIC(12,2),
+ALTER COLUMN otro_total TYPE NUMERIC(12,2),
+ALTER COLUMN total_collected TYPE NUMERIC(12,2),
+ALTER COLUMN total_collected_base TYPE NUMERIC(12,2);
+
+
+-- ============================================================
+-- 2. REDONDEO DE SALDOS EXISTENTES
+-- ============================================================
+-- Facturas creadas con aritmética de punto flotante pueden tener
+-- residuos (ej. 99.99999999); se normalizan a centavos.
+-- ============================================================
+
+UPDATE invoices
+SET total_amount = ROUND(total_amount, 2),
+    balance_due = ROUND(balance_due, 2)
+WHERE total_amount <> ROUND(total_amount, 2)
+   OR balance_due <> ROUND(balance_due, 2);
@@ -0,0 +1,140 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Firma de consultas y enmiendas
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Requisito de acreditación: la consulta es parte del expediente
+-- clínico. El médico la firma (sign_encounter) y desde ese momento
+-- queda bloqueada: el texto de la consulta, el examen de ambos ojos
+-- y los diagnósticos solo cambian mediante enmiendas
+-- (amend_encounter), que guardan el contenido anterior y el nuevo,
+-- el autor, el motivo y la fecha.
+--
+-- Al firmar se guarda el hash SHA-256 del contenido; cada enmienda
+-- guarda el nuevo hash. Los triggers rechazan cambios directos a una
+-- consulta firmada, y la tabla de enmiendas no admite UPDATE ni
+-- DELETE.
+--
+-- Esta migración incluye:
+-- 1. Columnas de firma en encounters
+-- 2. Tabla encounter_amendments
+-- 3. Triggers de bloqueo
+-- ============================================================
+
+
+-- ============================================================
+-- 1. FIRMA
+-- ============================================================
+
+ALTER TABLE encounters ADD COLUMN IF NOT EXISTS signed_at TIMESTAMPTZ;
+ALTER TABLE encounters ADD COLUMN IF NOT EXISTS signed_by UUID;
+ALTER TABLE encounters ADD COLUMN IF NOT EXISTS content_hash TEXT;
+
+
+-- ============================================================
+-- 2. ENMIENDAS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS encounter_amendments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    encounter_id UUID NOT NULL REFERENCES encounters(id),
+    -- 1 para la primera enmienda después de la firma
+    version INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    author_id UUID NOT NULL,
+    previous_content JSONB NOT NULL,
+    new_content JSONB NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (encounter_id, version)
+);
+
+CREATE INDEX IF NOT EXISTS idx_encounter_amendments_encounter
+ON encounter_amendments(encounter_id, version);
+
+
+-- ============================================================
+-- 3. TRIGGERS
+-- ============================================================
+
+-- amend_encounter activa app.encounter_amendment en su transacción
+
+CREATE OR REPLACE FUNCTION enforce_signed_encounter()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.signed_at IS NULL OR current_setting('app.encounter_amendment', true) = 'on' THEN
+        RETURN NEW;
+    END IF;
+
+    IF NEW.summary IS DISTINCT FROM OLD.summary
+        OR NEW.motivo_consulta IS DISTINCT FROM OLD.motivo_consulta
+        OR NEW.plan_tratamiento IS DISTINCT FROM OLD.plan_tratamiento
+        OR NEW.cirugias IS DISTINCT FROM OLD.cirugias
+        OR NEW.estudios IS DISTINCT FROM OLD.estudios
+        OR NEW.proxima_cita IS DISTINCT FROM OLD.proxima_cita
+        OR NEW.excursiones_od IS DISTINCT FROM OLD.excursiones_od
+        OR NEW.excursiones_os IS DISTINCT FROM OLD.excursiones_os
+        OR NEW.signed_at IS DISTINCT FROM OLD.signed_at
+        OR NEW.signed_by IS DISTINCT FROM OLD.signed_by
+        OR NEW.content_hash IS DISTINCT FROM OLD.content_hash
+        OR NEW.deleted_at IS DISTINCT FROM OLD.deleted_at
+    THEN
+        RAISE EXCEPTION 'La consulta está firmada; los cambios deben registrarse como enmienda';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_enforce_signed_encounter ON encounters;
+CREATE TRIGGER trg_enforce_signed_encounter
+    BEFORE UPDATE ON encounters
+    FOR EACH ROW EXECUTE FUNCTION enforce_signed_encounter();
+
+-- Examen y diagnósticos de una consulta firmada
+CREATE OR REPLACE FUNCTION enforce_signed_encounter_content()
+RETURNS TRIGGER AS $$
+DECLARE
+    target_encounter UUID;
+BEGIN
+    IF current_setting('app.encounter_amendment', true) = 'on' THEN
+        IF TG_OP = 'DELETE' THEN RETURN OLD; END IF;
+        RETURN NEW;
+    END IF;
+
+    IF TG_OP = 'INSERT' THEN
+        target_encounter := NEW.encounter_id;
+    ELSE
+        target_encounter := OLD.encounter_id;
+    END IF;
+
+    IF EXISTS (SELECT 1 FROM encounters WHERE id = target_encounter AND signed_at IS NOT NULL) THEN
+        RAISE EXCEPTION 'La consulta está firmada; los cambios deben registrarse como enmienda';
+    END IF;
+
+    IF TG_OP = 'DELETE' THEN RETURN OLD; END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_enforce_signed_exam_eye ON exam_eye;
+CREATE TRIGGER trg_enforce_signed_exam_eye
+    BEFORE INSERT OR UPDATE OR DELETE ON exam_eye
+    FOR EACH ROW EXECUTE FUNCTION enforce_signed_encounter_content();
+
+DROP TRIGGER IF EXISTS trg_enforce_signed_diagnoses ON diagnoses;
+CREATE TRIGGER trg_enforce_signed_diagnoses
+    BEFORE INSERT OR UPDATE OR DELETE ON diagnoses
+    FOR EACH ROW EXECUTE FUNCTION enforce_signed_encounter_content();
+
+-- Las enmiendas no se modifican ni se borran
+CREATE OR REPLACE FUNCTION prevent_amendment_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'Las enmiendas de consultas no pueden modificarse ni eliminarse';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_prevent_amendment_changes ON encounter_amendments;
+CREATE TRIGGER trg_prevent_amendment_changes
+    BEFORE UPDATE OR DELETE ON encounter_amendments
+    FOR EACH ROW EXECUTE FUNCTION prevent_amendment_changes();
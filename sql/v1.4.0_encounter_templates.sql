@@ -0,0 +1,48 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Plantillas de consulta
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Plantillas reutilizables por tipo de consulta (control de
+-- glaucoma, postoperatorio día 1...) y opcionalmente por médico.
+-- Al crear una consulta con template_id se copian el motivo de
+-- consulta, el plan y el resumen, los textos por defecto del examen
+-- de ambos ojos, los diagnósticos habituales y las órdenes de
+-- estudios.
+--
+-- Esta migración incluye:
+-- 1. Tabla encounter_templates
+-- ============================================================
+
+
+-- ============================================================
+-- 1. PLANTILLAS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS encounter_templates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    -- NULL: cualquier tipo de consulta
+    encounter_type TEXT,
+    -- NULL: compartida por todos los médicos
+    doctor_id UUID,
+    -- Texto de la consulta
+    summary TEXT,
+    motivo_consulta TEXT,
+    plan_tratamiento TEXT,
+    proxima_cita TEXT,
+    -- Examen (se aplica a OD y OS)
+    exam_slit_lamp TEXT,
+    exam_fundus TEXT,
+    exam_plan TEXT,
+    -- [{"code": "H40.1", "description": "...", "eye": "OU", "is_primary": true}]
+    diagnoses JSONB NOT NULL DEFAULT '[]'::jsonb,
+    -- Tipos de estudio a ordenar
+    studies TEXT[] NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_encounter_templates_lookup
+ON encounter_templates(encounter_type, doctor_id) WHERE active;
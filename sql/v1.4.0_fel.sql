@@ -0,0 +1,43 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Factura Electrónica en Línea (FEL)
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Esta migración incluye:
+-- 1. Tabla de documentos FEL certificados por factura
+-- ============================================================
+
+
+-- ============================================================
+-- 1. DOCUMENTOS FEL
+-- ============================================================
+-- Un documento por certificación. Una factura puede tener varios
+-- documentos si fue anulada y certificada de nuevo, pero solo uno
+-- en estado 'certificado' a la vez.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS fel_documents (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    invoice_id UUID NOT NULL REFERENCES invoices(id) ON DELETE RESTRICT,
+    document_type TEXT NOT NULL DEFAULT 'FACT',
+    status TEXT NOT NULL DEFAULT 'certificado' CHECK (status IN ('certificado', 'anulado')),
+    authorization_uuid TEXT NOT NULL UNIQUE,
+    serie TEXT NOT NULL,
+    numero TEXT NOT NULL,
+    receiver_nit TEXT NOT NULL DEFAULT 'CF',
+    receiver_name TEXT NOT NULL,
+    certifier TEXT NOT NULL,
+    signed_xml TEXT NOT NULL,
+    certified_xml TEXT NOT NULL,
+    pdf BYTEA,
+    certified_at TIMESTAMPTZ NOT NULL,
+    cancelled_at TIMESTAMPTZ,
+    cancellation_reason TEXT,
+    cancellation_xml TEXT,
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_fel_documents_invoice ON fel_documents(invoice_id, created_at DESC);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_fel_documents_one_active
+ON fel_documents(invoice_id) WHERE status = 'certificado';
@@ -0,0 +1,42 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Catálogo CIE-10
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Los diagnósticos guardaban un código libre. La aplicación incluye
+-- ahora el catálogo CIE-10 en español (capítulo de ojo H00-H59 y los
+-- códigos de otros capítulos usados en oftalmología). Se carga en
+-- esta tabla la primera vez que se busca un código, o con el comando
+-- load_icd10_catalog al actualizar la aplicación.
+--
+-- Al crear un diagnóstico el código se normaliza ("h251" -> "H25.1"),
+-- debe existir en el catálogo y, si corresponde a un ojo, indicar
+-- OD, OI u OU.
+--
+-- Esta migración incluye:
+-- 1. Tabla icd10_codes
+-- 2. Índice de diagnósticos por código
+-- ============================================================
+
+
+-- ============================================================
+-- 1. CATÁLOGO
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS icd10_codes (
+    code TEXT PRIMARY KEY,
+    description TEXT NOT NULL,
+    synonyms TEXT,
+    -- Código, descripción y sinónimos en minúsculas y sin acentos
+    search_text TEXT NOT NULL,
+    -- El diagnóstico debe indicar el ojo
+    lateral BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+
+-- ============================================================
+-- 2. DIAGNÓSTICOS
+-- ============================================================
+
+CREATE INDEX IF NOT EXISTS idx_diagnoses_code ON diagnoses(code) WHERE deleted_at IS NULL;
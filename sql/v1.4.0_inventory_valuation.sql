@@ -0,0 +1,66 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Costo histórico de movimientos de inventario
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- La valorización FIFO y el costo de ventas usan el costo unitario
+-- vigente cuando se hizo cada movimiento. Sin esta columna los
+-- movimientos sin lote se valoraban con el cost_price actual del
+-- producto, que cambia con cada compra.
+--
+-- Esta migración incluye:
+-- 1. Costo unitario en inventory_movements
+-- 2. Trigger que lo registra al insertar el movimiento
+-- ============================================================
+
+
+-- ============================================================
+-- 1. COSTO UNITARIO POR MOVIMIENTO
+-- ============================================================
+-- Los movimientos anteriores a esta migración toman el costo del
+-- lote o, sin lote, el cost_price del producto a la fecha de la
+-- migración (no hay un costo histórico del cual recuperarlo).
+-- ============================================================
+
+ALTER TABLE inventory_movements
+ADD COLUMN IF NOT EXISTS unit_cost NUMERIC(12,2);
+
+UPDATE inventory_movements m
+SET unit_cost = COALESCE(
+      (SELECT l.cost_price FROM inventory_lots l WHERE l.id = m.lot_id),
+      i.cost_price,
+      0)
+FROM inventory_items i
+WHERE m.item_id = i.id AND m.unit_cost IS NULL;
+
+
+-- ============================================================
+-- 2. REGISTRO DEL COSTO AL INSERTAR
+-- ============================================================
+-- Aplica a todos los movimientos (facturación, traslados, conteos,
+-- compras y los que se crean desde la aplicación web).
+-- ============================================================
+
+CREATE OR REPLACE FUNCTION public.set_movement_unit_cost()
+RETURNS trigger
+LANGUAGE plpgsql
+SET search_path TO 'public'
+AS $$
+BEGIN
+  IF NEW.unit_cost IS NULL THEN
+    SELECT COALESCE(
+             (SELECT l.cost_price FROM public.inventory_lots l WHERE l.id = NEW.lot_id),
+             i.cost_price,
+             0)
+    INTO NEW.unit_cost
+    FROM public.inventory_items i
+    WHERE i.id = NEW.item_id;
+  END IF;
+  RETURN NEW;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS set_movement_unit_cost ON inventory_movements;
+CREATE TRIGGER set_movement_unit_cost
+    BEFORE INSERT ON inventory_movements
+    FOR EACH ROW EXECUTE FUNCTION public.set_movement_unit_cost();
@@ -0,0 +1,75 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Biometría y cálculo de lente intraocular
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Registro de biometrías por ojo (longitud axial, queratometría,
+-- cámara anterior) y catálogo de modelos de lente intraocular con
+-- sus constantes. El cálculo (SRK/T, Holladay 1, Hoffer Q) se hace
+-- en la aplicación; el lente elegido queda en la cirugía.
+--
+-- Las constantes de cada modelo se cargan desde la ficha del
+-- fabricante o de ULIB; el factor de cirujano y el pACD son
+-- opcionales y se derivan de la constante A cuando faltan.
+--
+-- Esta migración incluye:
+-- 1. Modelos de lente intraocular
+-- 2. Biometrías
+-- 3. Lente planificado en cirugías
+-- ============================================================
+
+
+-- ============================================================
+-- 1. MODELOS DE LENTE INTRAOCULAR
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS iol_models (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL UNIQUE,
+    manufacturer TEXT,
+    a_constant NUMERIC(5,2) NOT NULL CHECK (a_constant BETWEEN 110 AND 125),
+    surgeon_factor NUMERIC(4,2),
+    pacd NUMERIC(4,2),
+    active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+-- ============================================================
+-- 2. BIOMETRÍAS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS biometry_records (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id UUID NOT NULL REFERENCES patients(id),
+    eye TEXT NOT NULL CHECK (eye IN ('OD', 'OS')),
+    axial_length NUMERIC(5,2) NOT NULL CHECK (axial_length BETWEEN 15 AND 38),
+    k1 NUMERIC(5,2) NOT NULL CHECK (k1 BETWEEN 30 AND 60),
+    k2 NUMERIC(5,2) NOT NULL CHECK (k2 BETWEEN 30 AND 60),
+    k1_axis INTEGER CHECK (k1_axis BETWEEN 0 AND 180),
+    acd NUMERIC(4,2),
+    lens_thickness NUMERIC(4,2),
+    white_to_white NUMERIC(4,2),
+    device TEXT,
+    measured_at DATE NOT NULL DEFAULT CURRENT_DATE,
+    notes TEXT,
+    created_by UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    deleted_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_biometry_records_patient
+ON biometry_records(patient_id, measured_at DESC) WHERE deleted_at IS NULL;
+
+
+-- ============================================================
+-- 3. LENTE PLANIFICADO EN CIRUGÍAS
+-- ============================================================
+
+ALTER TABLE surgeries ADD COLUMN IF NOT EXISTS biometry_id UUID REFERENCES biometry_records(id);
+ALTER TABLE surgeries ADD COLUMN IF NOT EXISTS iol_model_id UUID REFERENCES iol_models(id);
+ALTER TABLE surgeries ADD COLUMN IF NOT EXISTS iol_power NUMERIC(5,2);
+ALTER TABLE surgeries ADD COLUMN IF NOT EXISTS iol_formula TEXT
+    CHECK (iol_formula IN ('srkt', 'holladay1', 'hofferq'));
+ALTER TABLE surgeries ADD COLUMN IF NOT EXISTS iol_target_refraction NUMERIC(4,2);
@@ -0,0 +1,65 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - IVA en facturas
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Los precios de servicios y productos incluyen IVA. El impuesto
+-- se desglosa por línea (después de repartir el descuento de la
+-- factura) y se totaliza por factura y en el cierre de caja.
+--
+-- Esta migración incluye:
+-- 1. Tasa de IVA y exención en servicios y productos
+-- 2. Desglose de IVA por línea de factura
+-- 3. Totales de IVA por factura
+-- 4. IVA en cierres de caja
+-- ============================================================
+
+
+-- ============================================================
+-- 1. TASA DE IVA EN CATÁLOGOS
+-- ============================================================
+-- Tasa general 12%. Los servicios médicos exentos se marcan con
+-- tax_exempt = true desde la configuración de precios.
+-- ============================================================
+
+ALTER TABLE service_prices
+ADD COLUMN IF NOT EXISTS tax_rate NUMERIC(5,2) NOT NULL DEFAULT 12 CHECK (tax_rate >= 0),
+ADD COLUMN IF NOT EXISTS tax_exempt BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE inventory_items
+ADD COLUMN IF NOT EXISTS tax_rate NUMERIC(5,2) NOT NULL DEFAULT 12 CHECK (tax_rate >= 0),
+ADD COLUMN IF NOT EXISTS tax_exempt BOOLEAN NOT NULL DEFAULT false;
+
+
+-- ============================================================
+-- 2. IVA POR LÍNEA DE FACTURA
+-- ============================================================
+-- discount_amount: parte del descuento de la factura aplicada a la línea
+-- tax_amount: IVA incluido en (subtotal - discount_amount)
+-- ============================================================
+
+ALTER TABLE invoice_items
+ADD COLUMN IF NOT EXISTS discount_amount NUMERIC(12,2) NOT NULL DEFAULT 0,
+ADD COLUMN IF NOT EXISTS tax_rate NUMERIC(5,2) NOT NULL DEFAULT 12,
+ADD COLUMN IF NOT EXISTS tax_exempt BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN IF NOT EXISTS tax_amount NUMERIC(12,2) NOT NULL DEFAULT 0;
+
+
+-- ============================================================
+-- 3. TOTALES DE IVA POR FACTURA
+-- ============================================================
+
+ALTER TABLE invoices
+ADD COLUMN IF NOT EXISTS taxable_amount NUMERIC(12,2) NOT NULL DEFAULT 0,
+ADD COLUMN IF NOT EXISTS exempt_amount NUMERIC(12,2) NOT NULL DEFAULT 0,
+ADD COLUMN IF NOT EXISTS tax_amount NUMERIC(12,2) NOT NULL DEFAULT 0;
+
+-- Las facturas anteriores quedan sin desglose (tax_amount = 0).
+
+
+-- ============================================================
+-- 4. IVA EN CIERRES DE CAJA
+-- ============================================================
+
+ALTER TABLE cash_closures
+ADD COLUMN IF NOT EXISTS total_tax NUMERIC(12,2) NOT NULL DEFAULT 0;
@@ -0,0 +1,150 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Vencimiento de lotes (FEFO)
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Las salidas de las categorías configuradas en app_settings
+-- ('fefo_categories', por defecto gotas y lentes) consumen primero el
+-- lote que vence antes (FEFO); el resto de productos por orden de
+-- ingreso (FIFO).
+-- Los lotes vencidos no pueden venderse: solo se dan de baja con un
+-- movimiento de tipo 'merma'.
+--
+-- Esta migración incluye:
+-- 1. Tipo de movimiento 'merma'
+-- 2. Trigger de stock con mermas como salidas
+-- 3. Columna de vencimiento de lotes (expiry_date)
+-- 4. Índice para el reporte de lotes por vencer
+-- 5. Configuración de categorías FEFO
+-- ============================================================
+
+
+-- ============================================================
+-- 1. TIPO DE MOVIMIENTO 'MERMA'
+-- ============================================================
+-- En servidores donde movement_type es un enum se agrega el valor;
+-- donde es texto se amplía la restricción de la migración
+-- 20251116191152 con los mismos valores más 'merma'.
+-- ============================================================
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_type WHERE typname = 'inventory_movement_type') THEN
+        ALTER TYPE inventory_movement_type ADD VALUE IF NOT EXISTS 'merma';
+    END IF;
+END $$;
+
+ALTER TABLE inventory_movements
+DROP CONSTRAINT IF EXISTS inventory_movements_movement_type_check;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'inventory_movement_type') THEN
+        ALTER TABLE inventory_movements
+        ADD CONSTRAINT inventory_movements_movement_type_check
+        CHECK (movement_type IN ('entrada', 'salida', 'ajuste', 'cortesia', 'merma'));
+    END IF;
+END $$;
+
+
+-- ============================================================
+-- 2. TRIGGER DE STOCK
+-- ============================================================
+
+CREATE OR REPLACE FUNCTION public.update_item_stock()
+RETURNS trigger
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path TO 'public'
+AS $$
+BEGIN
+  IF NEW.movement_type = 'entrada' THEN
+    UPDATE public.inventory_items
+    SET current_stock = current_stock + ABS(NEW.quantity),
+        updated_at = now()
+    WHERE id = NEW.item_id;
+
+    IF NEW.lot_id IS NOT NULL THEN
+      UPDATE public.inventory_lots
+      SET quantity = quantity + ABS(NEW.quantity)
+      WHERE id = NEW.lot_id;
+    END IF;
+
+  ELSIF NEW.movement_type IN ('salida', 'cortesia', 'merma') THEN
+    UPDATE public.inventory_items
+    SET current_stock = current_stock - ABS(NEW.quantity),
+        updated_at = now()
+    WHERE id = NEW.item_id;
+
+    IF NEW.lot_id IS NOT NULL THEN
+      UPDATE public.inventory_lots
+      SET quantity = quantity - ABS(NEW.quantity)
+      WHERE id = NEW.lot_id;
+    END IF;
+
+  ELSIF NEW.movement_type = 'ajuste' THEN
+    UPDATE public.inventory_items
+    SET current_stock = current_stock + NEW.quantity,
+        updated_at = now()
+    WHERE id = NEW.item_id;
+
+    IF NEW.lot_id IS NOT NULL THEN
+      UPDATE public.inventory_lots
+      SET quantity = quantity + NEW.quantity
+      WHERE id = NEW.lot_id;
+    END IF;
+  END IF;
+
+  RETURN NEW;
+END;
+$$;
+
+
+-- ============================================================
+-- 3. COLUMNA DE VENCIMIENTO
+-- ============================================================
+-- La columna de inventory_lots es expiry_date desde su creación
+-- (migración 20251008025834). La consulta de lotes por producto leía
+-- expiration_date, que no existe, y se corrigió en esta versión; el
+-- campo expiration_date de la respuesta a la interfaz no cambia.
+-- Si algún servidor local creó la columna con el nombre anterior, se
+-- renombra aquí.
+-- ============================================================
+
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'inventory_lots' AND column_name = 'expiration_date'
+    ) AND NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'inventory_lots' AND column_name = 'expiry_date'
+    ) THEN
+        ALTER TABLE inventory_lots RENAME COLUMN expiration_date TO expiry_date;
+    END IF;
+END $$;
+
+
+-- ============================================================
+-- 4. ÍNDICE DE VENCIMIENTOS
+-- ============================================================
+
+CREATE INDEX IF NOT EXISTS idx_inventory_lots_expiry
+ON inventory_lots(expiry_date) WHERE quantity > 0 AND expiry_date IS NOT NULL;
+
+
+-- ============================================================
+-- 5. CATEGORÍAS FEFO
+-- ============================================================
+-- Arreglo JSON de categorías de inventario que se despachan por
+-- fecha de vencimiento. Si falta o no es un arreglo de textos se usan
+-- gotas y lentes.
+-- ============================================================
+
+INSERT INTO app_settings (key, value, description)
+VALUES (
+    'fefo_categories',
+    '["gota", "lente"]'::jsonb,
+    'Categorías de inventario que se despachan por fecha de vencimiento (FEFO)'
+)
+ON CONFLICT (key) DO NOTHING;
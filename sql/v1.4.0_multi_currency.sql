@@ -0,0 +1,80 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Multi-moneda (GTQ / USD)
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Esta migración incluye:
+-- 1. Moneda en precios de servicios e items de inventario
+-- 2. Moneda y tipo de cambio en pagos
+-- 3. Tabla de tipos de cambio por fecha
+-- 4. Totales por moneda en cierres de caja
+-- ============================================================
+
+
+-- ============================================================
+-- 1. MONEDA EN PRECIOS
+-- ============================================================
+-- Las facturas y saldos siempre se manejan en GTQ (moneda base).
+-- ============================================================
+
+ALTER TABLE service_prices
+ADD COLUMN IF NOT EXISTS currency TEXT NOT NULL DEFAULT 'GTQ';
+
+ALTER TABLE inventory_items
+ADD COLUMN IF NOT EXISTS currency TEXT NOT NULL DEFAULT 'GTQ';
+
+
+-- ============================================================
+-- 2. MONEDA EN PAGOS
+-- ============================================================
+-- amount queda en la moneda recibida; amount_base es el monto
+-- aplicado al saldo de la factura en GTQ.
+-- ============================================================
+
+ALTER TABLE payments
+ADD COLUMN IF NOT EXISTS currency TEXT NOT NULL DEFAULT 'GTQ',
+ADD COLUMN IF NOT EXISTS exchange_rate NUMERIC NOT NULL DEFAULT 1,
+ADD COLUMN IF NOT EXISTS amount_base NUMERIC;
+
+UPDATE payments
+SET amount_base = amount
+WHERE amount_base IS NULL;
+
+
+-- ============================================================
+-- 3. TIPOS DE CAMBIO
+-- ============================================================
+-- rate = unidades de GTQ por una unidad de la moneda.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS exchange_rates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    currency TEXT NOT NULL,
+    rate_date DATE NOT NULL,
+    rate NUMERIC NOT NULL CHECK (rate > 0),
+    source TEXT,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    UNIQUE (currency, rate_date)
+);
+
+CREATE INDEX IF NOT EXISTS idx_exchange_rates_lookup ON exchange_rates(currency, rate_date DESC);
+
+
+-- ============================================================
+-- 4. TOTALES POR MONEDA EN CIERRES DE CAJA
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS cash_closure_currency_totals (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    closure_id UUID NOT NULL REFERENCES cash_closures(id) ON DELETE CASCADE,
+    currency TEXT NOT NULL,
+    efectivo_total NUMERIC NOT NULL DEFAULT 0,
+    tarjeta_total NUMERIC NOT NULL DEFAULT 0,
+    transferencia_total NUMERIC NOT NULL DEFAULT 0,
+    cheque_total NUMERIC NOT NULL DEFAULT 0,
+    otro_total NUMERIC NOT NULL DEFAULT 0,
+    total_collected NUMERIC NOT NULL DEFAULT 0,
+    total_collected_base NUMERIC NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    UNIQUE (closure_id, currency)
+);
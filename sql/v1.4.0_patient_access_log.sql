@@ -0,0 +1,38 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Registro de accesos a expedientes
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Además de las escrituras (audit_log), las normas de privacidad
+-- exigen registrar las lecturas de datos clínicos. Los comandos que
+-- abren el expediente de un paciente (ficha, consultas, estudios,
+-- cirugías, procedimientos, biometría, historia por ojo) guardan el
+-- usuario, el paciente, lo consultado, el motivo y la hora.
+--
+-- El reporte de accesos inusuales para la dirección señala:
+-- - usuarios que abren pacientes atendidos solo en sucursales donde
+--   no trabajan
+-- - usuarios que abren más pacientes en un día que el límite indicado
+--
+-- Esta migración incluye:
+-- 1. Tabla patient_access_log
+-- ============================================================
+
+
+-- ============================================================
+-- 1. REGISTRO DE ACCESOS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS patient_access_log (
+    id BIGSERIAL PRIMARY KEY,
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID,
+    patient_id UUID NOT NULL,
+    -- patient, encounter, encounters, studies, surgeries, procedures, biometry, timeline
+    resource TEXT NOT NULL,
+    purpose TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_patient_access_log_patient ON patient_access_log(patient_id, accessed_at);
+CREATE INDEX IF NOT EXISTS idx_patient_access_log_user ON patient_access_log(user_id, accessed_at);
+CREATE INDEX IF NOT EXISTS idx_patient_access_log_accessed ON patient_access_log(accessed_at);
@@ -0,0 +1,56 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Recetas de lentes
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Recetas de lentes y lentes de contacto generadas localmente a
+-- partir del Rx final del examen OD/OI de la consulta. Se guarda
+-- una copia de los valores y el PDF impreso, de modo que la receta
+-- no cambia si luego se edita el examen.
+--
+-- Esta migración incluye:
+-- 1. Tabla de recetas
+-- 2. Adaptación de lentes de contacto
+-- ============================================================
+
+
+-- ============================================================
+-- 1. RECETAS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS prescriptions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    encounter_id UUID NOT NULL REFERENCES encounters(id) ON DELETE CASCADE,
+    patient_id UUID NOT NULL REFERENCES patients(id),
+    kind TEXT NOT NULL CHECK (kind IN ('lentes', 'contacto')),
+    od_sphere NUMERIC(5,2),
+    od_cylinder NUMERIC(5,2),
+    od_axis INTEGER CHECK (od_axis BETWEEN 0 AND 180),
+    od_add NUMERIC(4,2),
+    os_sphere NUMERIC(5,2),
+    os_cylinder NUMERIC(5,2),
+    os_axis INTEGER CHECK (os_axis BETWEEN 0 AND 180),
+    os_add NUMERIC(4,2),
+    pupillary_distance NUMERIC(4,1),
+    notes TEXT,
+    pdf BYTEA,
+    created_by UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_prescriptions_encounter ON prescriptions(encounter_id);
+CREATE INDEX IF NOT EXISTS idx_prescriptions_patient ON prescriptions(patient_id, created_at DESC);
+
+
+-- ============================================================
+-- 2. LENTES DE CONTACTO
+-- ============================================================
+
+-- Las recetas 'contacto' guardan la potencia convertida al plano
+-- corneal y la adaptación de cada ojo
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS od_base_curve NUMERIC(4,2);
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS od_diameter NUMERIC(4,2);
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS od_brand TEXT;
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS os_base_curve NUMERIC(4,2);
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS os_diameter NUMERIC(4,2);
+ALTER TABLE prescriptions ADD COLUMN IF NOT EXISTS os_brand TEXT;
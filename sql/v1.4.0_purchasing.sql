@@ -0,0 +1,116 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Compras a proveedores
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Flujo: orden de compra (borrador -> enviada) -> recepciones
+-- parciales o totales -> cuenta por pagar al proveedor.
+-- Cada recepción crea/actualiza lotes y registra movimientos de
+-- 'entrada' con referencia 'compra' al costo recibido.
+--
+-- Esta migración incluye:
+-- 1. Órdenes de compra y sus líneas
+-- 2. Recepciones de mercadería
+-- 3. Cuentas por pagar y pagos a proveedores
+-- ============================================================
+
+
+-- ============================================================
+-- 1. ÓRDENES DE COMPRA
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS purchase_orders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_number TEXT NOT NULL,
+    branch_id UUID NOT NULL REFERENCES branches(id),
+    supplier_id UUID NOT NULL REFERENCES suppliers(id),
+    status TEXT NOT NULL DEFAULT 'borrador'
+        CHECK (status IN ('borrador', 'enviada', 'parcial', 'recibida', 'cancelada')),
+    currency TEXT NOT NULL DEFAULT 'GTQ',
+    expected_date DATE,
+    notes TEXT,
+    total_amount NUMERIC(12,2) NOT NULL DEFAULT 0,
+    created_by UUID,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    updated_at TIMESTAMPTZ DEFAULT now(),
+    UNIQUE (branch_id, order_number)
+);
+
+CREATE INDEX IF NOT EXISTS idx_purchase_orders_branch_status ON purchase_orders(branch_id, status);
+
+CREATE TABLE IF NOT EXISTS purchase_order_lines (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL REFERENCES purchase_orders(id) ON DELETE CASCADE,
+    item_id UUID NOT NULL REFERENCES inventory_items(id),
+    quantity_ordered NUMERIC(12,3) NOT NULL CHECK (quantity_ordered > 0),
+    quantity_received NUMERIC(12,3) NOT NULL DEFAULT 0 CHECK (quantity_received >= 0),
+    unit_cost NUMERIC(12,2) NOT NULL CHECK (unit_cost >= 0),
+    line_total NUMERIC(12,2) NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_purchase_order_lines_order ON purchase_order_lines(order_id);
+CREATE INDEX IF NOT EXISTS idx_purchase_order_lines_item ON purchase_order_lines(item_id);
+
+
+-- ============================================================
+-- 2. RECEPCIONES DE MERCADERÍA
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS goods_receipts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL REFERENCES purchase_orders(id),
+    branch_id UUID NOT NULL REFERENCES branches(id),
+    received_by UUID,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    supplier_invoice TEXT,
+    total_amount NUMERIC(12,2) NOT NULL DEFAULT 0,
+    notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_goods_receipts_order ON goods_receipts(order_id);
+
+CREATE TABLE IF NOT EXISTS goods_receipt_lines (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    receipt_id UUID NOT NULL REFERENCES goods_receipts(id) ON DELETE CASCADE,
+    order_line_id UUID NOT NULL REFERENCES purchase_order_lines(id),
+    item_id UUID NOT NULL REFERENCES inventory_items(id),
+    lot_id UUID REFERENCES inventory_lots(id) ON DELETE SET NULL,
+    quantity NUMERIC(12,3) NOT NULL CHECK (quantity > 0),
+    unit_cost NUMERIC(12,2) NOT NULL CHECK (unit_cost >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_goods_receipt_lines_receipt ON goods_receipt_lines(receipt_id);
+
+
+-- ============================================================
+-- 3. CUENTAS POR PAGAR A PROVEEDORES
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS supplier_payables (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    supplier_id UUID NOT NULL REFERENCES suppliers(id),
+    branch_id UUID NOT NULL REFERENCES branches(id),
+    receipt_id UUID NOT NULL UNIQUE REFERENCES goods_receipts(id),
+    currency TEXT NOT NULL DEFAULT 'GTQ',
+    amount NUMERIC(12,2) NOT NULL CHECK (amount > 0),
+    balance NUMERIC(12,2) NOT NULL CHECK (balance >= 0),
+    due_date DATE,
+    status TEXT NOT NULL DEFAULT 'pendiente' CHECK (status IN ('pendiente', 'parcial', 'pagada')),
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_supplier_payables_open
+ON supplier_payables(branch_id, due_date) WHERE status <> 'pagada';
+
+CREATE TABLE IF NOT EXISTS supplier_payments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    payable_id UUID NOT NULL REFERENCES supplier_payables(id) ON DELETE CASCADE,
+    amount NUMERIC(12,2) NOT NULL CHECK (amount > 0),
+    payment_method TEXT NOT NULL,
+    reference TEXT,
+    notes TEXT,
+    paid_by UUID,
+    paid_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_supplier_payments_payable ON supplier_payments(payable_id);
@@ -0,0 +1,42 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Roles y permisos
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- La aplicación valida en cada comando el rol del usuario que inició
+-- sesión (permissions.rs). Los roles se leen de user_roles; los que
+-- envía la interfaz no se usan. Se usan los valores existentes de
+-- app_role:
+-- - admin: todo
+-- - doctor: pacientes, agenda, expediente, firma de consultas
+-- - nurse: pacientes, agenda, expediente, inventario de sala
+-- - reception: pacientes, agenda y CRM
+-- - diagnostico, estudios: pacientes, agenda, expediente y estudios
+-- - caja: facturación, cobros, caja y cortesías
+-- - contabilidad: lo de caja más anulación de pagos, precios e
+--   inventario
+--
+-- Recepción ya no puede anular pagos (delete_payment); solo admin y
+-- contabilidad, como en la pantalla de pagos.
+--
+-- Esta migración no modifica el esquema; solo verifica que los roles
+-- usados por la aplicación existan.
+-- ============================================================
+
+
+-- ============================================================
+-- 1. ROLES
+-- ============================================================
+
+DO $$
+DECLARE
+    missing TEXT[];
+BEGIN
+    SELECT array_agg(r) INTO missing
+    FROM unnest(ARRAY['admin', 'doctor', 'nurse', 'reception', 'diagnostico', 'estudios', 'caja', 'contabilidad']) AS r
+    WHERE r NOT IN (SELECT unnest(enum_range(NULL::app_role))::text);
+
+    IF missing IS NOT NULL THEN
+        RAISE EXCEPTION 'Faltan valores en app_role: %', missing;
+    END IF;
+END $$;
@@ -0,0 +1,63 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Conteos físicos de inventario
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Reemplaza las hojas de cálculo de los conteos trimestrales.
+-- Un conteo se abre por sucursal e inventario (farmacia o sala),
+-- opcionalmente por categoría, y guarda la cantidad esperada de
+-- cada producto/lote al iniciar. Al publicarlo se generan
+-- movimientos de 'ajuste' por las diferencias en una sola
+-- transacción.
+--
+-- Esta migración incluye:
+-- 1. Tabla de sesiones de conteo
+-- 2. Líneas de conteo por producto y lote
+-- ============================================================
+
+
+-- ============================================================
+-- 1. SESIONES DE CONTEO
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS stock_count_sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    branch_id UUID NOT NULL REFERENCES branches(id),
+    inventory_type TEXT NOT NULL CHECK (inventory_type IN ('farmacia', 'sala')),
+    -- Categoría de farmacia o id de categoría de sala; NULL = todo
+    category TEXT,
+    status TEXT NOT NULL DEFAULT 'abierto' CHECK (status IN ('abierto', 'publicado', 'cancelado')),
+    started_by UUID,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    posted_at TIMESTAMPTZ,
+    notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_stock_count_sessions_branch ON stock_count_sessions(branch_id, started_at DESC);
+
+-- Un solo conteo abierto por inventario y sucursal
+CREATE UNIQUE INDEX IF NOT EXISTS idx_stock_count_sessions_one_open
+ON stock_count_sessions(branch_id, inventory_type) WHERE status = 'abierto';
+
+
+-- ============================================================
+-- 2. LÍNEAS DE CONTEO
+-- ============================================================
+-- item_id apunta a inventory_items o room_inventory_items según el
+-- tipo de inventario de la sesión, por eso no lleva llave foránea.
+-- variance se fija al publicar (contado - stock al publicar).
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS stock_count_lines (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES stock_count_sessions(id) ON DELETE CASCADE,
+    item_id UUID NOT NULL,
+    lot_id UUID REFERENCES inventory_lots(id) ON DELETE SET NULL,
+    expected_quantity NUMERIC(12,3) NOT NULL DEFAULT 0,
+    counted_quantity NUMERIC(12,3) CHECK (counted_quantity >= 0),
+    counted_at TIMESTAMPTZ,
+    variance NUMERIC(12,3)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_stock_count_lines_item_lot
+ON stock_count_lines(session_id, item_id, COALESCE(lot_id, '00000000-0000-0000-0000-000000000000'::uuid));
@@ -0,0 +1,81 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Traslados de inventario entre sucursales
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Al despachar un traslado se registra una 'salida' por lote en la
+-- sucursal de origen y el documento queda 'en_transito'. La sucursal
+-- de destino confirma la recepción, que registra las 'entradas' en
+-- el producto equivalente (mismo código, o mismo nombre si no tiene
+-- código) y en el mismo número de lote con su vencimiento y costo.
+-- Ambos lados usan reference_type 'traslado'.
+--
+-- Esta migración incluye:
+-- 1. Tabla de traslados
+-- 2. Líneas de traslado por producto y lote
+-- 3. reference_type 'traslado' en movimientos de inventario
+-- ============================================================
+
+
+-- ============================================================
+-- 1. TRASLADOS
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS stock_transfers (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    transfer_number TEXT NOT NULL,
+    from_branch_id UUID NOT NULL REFERENCES branches(id),
+    to_branch_id UUID NOT NULL REFERENCES branches(id),
+    status TEXT NOT NULL DEFAULT 'en_transito'
+        CHECK (status IN ('en_transito', 'recibida', 'cancelada')),
+    notes TEXT,
+    created_by UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    received_by UUID,
+    received_at TIMESTAMPTZ,
+    cancelled_at TIMESTAMPTZ,
+    cancellation_reason TEXT,
+    UNIQUE (from_branch_id, transfer_number),
+    CHECK (from_branch_id <> to_branch_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_stock_transfers_from ON stock_transfers(from_branch_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_stock_transfers_to ON stock_transfers(to_branch_id, status);
+
+
+-- ============================================================
+-- 2. LÍNEAS DE TRASLADO
+-- ============================================================
+-- lot_number, expiry_date y cost_price se copian del lote de origen
+-- para crear el lote equivalente en destino al recibir.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS stock_transfer_lines (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    transfer_id UUID NOT NULL REFERENCES stock_transfers(id) ON DELETE CASCADE,
+    item_id UUID NOT NULL REFERENCES inventory_items(id),
+    lot_id UUID REFERENCES inventory_lots(id) ON DELETE SET NULL,
+    lot_number TEXT,
+    expiry_date DATE,
+    cost_price NUMERIC(12,2),
+    quantity NUMERIC(12,3) NOT NULL CHECK (quantity > 0),
+    dest_item_id UUID REFERENCES inventory_items(id),
+    dest_lot_id UUID REFERENCES inventory_lots(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_stock_transfer_lines_transfer ON stock_transfer_lines(transfer_id);
+
+
+-- ============================================================
+-- 3. REFERENCIA 'traslado' EN MOVIMIENTOS
+-- ============================================================
+-- La restricción viene de la migración 20251116191152; se vuelve a
+-- crear con los mismos valores más 'traslado'.
+-- ============================================================
+
+ALTER TABLE inventory_movements
+DROP CONSTRAINT IF EXISTS inventory_movements_reference_type_check;
+
+ALTER TABLE inventory_movements
+ADD CONSTRAINT inventory_movements_reference_type_check
+CHECK (reference_type IN ('compra', 'venta', 'ajuste', 'devolucion', 'cortesia', 'traslado'));
@@ -0,0 +1,64 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Kits de materiales por tipo de cirugía
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- Cada tipo de cirugía define, por sucursal, los materiales del
+-- inventario de sala que consume. Al marcar una cirugía como
+-- 'completed' se registran movimientos de 'uso' en la sala de la
+-- sucursal de la cita y se guarda el costo de cada material en ese
+-- momento, para reportar el costo de materiales por cirugía.
+--
+-- Esta migración incluye:
+-- 1. Costo unitario en el inventario de sala
+-- 2. Materiales por tipo de cirugía
+-- 3. Consumo de materiales por cirugía
+-- ============================================================
+
+
+-- ============================================================
+-- 1. COSTO UNITARIO EN INVENTARIO DE SALA
+-- ============================================================
+
+ALTER TABLE room_inventory_items
+ADD COLUMN IF NOT EXISTS unit_cost NUMERIC(12,2) NOT NULL DEFAULT 0;
+
+
+-- ============================================================
+-- 2. MATERIALES POR TIPO DE CIRUGÍA
+-- ============================================================
+-- El item de sala pertenece a una sucursal, por lo que el kit queda
+-- definido por sucursal. per_eye duplica la cantidad en cirugías OU.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS surgery_type_materials (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    surgery_type_id UUID NOT NULL REFERENCES surgery_types(id) ON DELETE CASCADE,
+    room_item_id UUID NOT NULL REFERENCES room_inventory_items(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    per_eye BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    UNIQUE (surgery_type_id, room_item_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_surgery_type_materials_type ON surgery_type_materials(surgery_type_id);
+
+
+-- ============================================================
+-- 3. CONSUMO DE MATERIALES POR CIRUGÍA
+-- ============================================================
+-- Una línea por material y cirugía: volver a completar una cirugía
+-- no descuenta el kit dos veces.
+-- ============================================================
+
+CREATE TABLE IF NOT EXISTS surgery_material_usage (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    surgery_id UUID NOT NULL REFERENCES surgeries(id) ON DELETE CASCADE,
+    room_item_id UUID NOT NULL REFERENCES room_inventory_items(id),
+    movement_id UUID REFERENCES room_inventory_movements(id) ON DELETE SET NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_cost NUMERIC(12,2) NOT NULL DEFAULT 0,
+    total_cost NUMERIC(12,2) NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (surgery_id, room_item_id)
+);
@@ -0,0 +1,28 @@
+-- ============================================================
+-- MIGRACION v1.4.0 - Agudeza visual en logMAR
+-- ============================================================
+-- Fecha: 2026-10-18
+--
+-- La agudeza visual (av_sc / av_cc) se sigue capturando como texto
+-- libre ("20/40", "0.5", "CD 2m", "MM", "PL"). Junto al texto se
+-- guarda su equivalente logMAR, calculado por la aplicación al
+-- guardar el examen, para filtrar y comparar la agudeza pre y
+-- postoperatoria en investigación.
+--
+-- Los exámenes existentes se convierten con el comando
+-- recalculate_exam_logmar después de aplicar esta migración.
+--
+-- Esta migración incluye:
+-- 1. Columnas logMAR en exam_eye
+-- ============================================================
+
+
+-- ============================================================
+-- 1. LOGMAR
+-- ============================================================
+
+ALTER TABLE exam_eye ADD COLUMN IF NOT EXISTS av_sc_logmar NUMERIC(4,2);
+ALTER TABLE exam_eye ADD COLUMN IF NOT EXISTS av_cc_logmar NUMERIC(4,2);
+
+CREATE INDEX IF NOT EXISTS idx_exam_eye_av_logmar
+ON exam_eye((COALESCE(av_cc_logmar, av_sc_logmar)));
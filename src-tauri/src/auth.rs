@@ -1,10 +1,17 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
 use tauri::{AppHandle, Manager};
 use tauri_plugin_store::StoreExt;
 
+use crate::permissions::SessionUser;
+use crate::AppState;
+
 const AUTH_STORE_FILE: &str = "auth_store.json";
 const SESSION_KEY: &str = "cached_session";
 const SESSION_MAX_AGE_DAYS: i64 = 7;
+/// sync_metadata key of the per-install secret that tags verified sessions
+const SESSION_SECRET_KEY: &str = "auth_session_secret";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedSession {
@@ -15,6 +22,10 @@ pub struct CachedSession {
     pub roles: Vec<String>,
     pub full_name: Option<String>,
     pub cached_at: String,
+    /// Set by cache_auth_session once the token was verified; a session
+    /// written to the store by anything else is not trusted
+    #[serde(default)]
+    pub verification: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +36,85 @@ pub struct CachedUser {
     pub full_name: Option<String>,
 }
 
+/// Check an access token with Supabase and return the user it belongs to
+async fn verify_access_token(state: &AppState, access_token: &str) -> Result<String, String> {
+    let supabase = &state.config.supabase;
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let response = client
+        .get(format!("{}/auth/v1/user", supabase.url))
+        .header("apikey", &supabase.anon_key)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()
+        .await
+        .map_err(|e| format!("No se pudo verificar la sesión: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err("La sesión no es válida o expiró; inicie sesión de nuevo".to_string());
+    }
+
+    let user: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+    user.get("id")
+        .and_then(|id| id.as_str())
+        .map(String::from)
+        .ok_or_else(|| "La sesión no es válida o expiró; inicie sesión de nuevo".to_string())
+}
+
+/// Tag binding a verified session to this install (secret kept in SQLite,
+/// out of reach of the webview)
+fn session_tag(state: &AppState, session: &CachedSession) -> Result<String, String> {
+    let secret = match state.db.get_sync_metadata(SESSION_SECRET_KEY).map_err(|e| e.to_string())? {
+        Some(secret) => secret,
+        None => {
+            let secret = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+            state
+                .db
+                .set_sync_metadata(SESSION_SECRET_KEY, &secret)
+                .map_err(|e| e.to_string())?;
+            secret
+        }
+    };
+    let digest = Sha256::digest(format!("{}|{}|{}|{}", secret, session.user_id, session.cached_at, secret));
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Roles that authorize commands, from user_roles (PostgreSQL, or the
+/// synced SQLite cache offline). The roles sent by the UI are never used.
+async fn session_roles(state: &AppState, user_id: &str) -> Vec<String> {
+    if let Some(pool) = state.connection_manager.get_postgres_pool().await {
+        match pool.get_user_roles(user_id).await {
+            Ok(roles) => return roles,
+            Err(e) => log::warn!("Failed to load roles from PostgreSQL: {}", e),
+        }
+    }
+    state.db.get_user_roles(user_id).unwrap_or_else(|e| {
+        log::warn!("Failed to load roles from SQLite: {}", e);
+        Vec::new()
+    })
+}
+
+/// Set (or clear on logout) the user that authorizes commands and is
+/// recorded in the audit log
+async fn set_session_user(app: &AppHandle, session: Option<&CachedSession>) {
+    let Some(state) = app.try_state::<Arc<AppState>>() else {
+        return;
+    };
+    let user_id = session.map(|s| s.user_id.as_str());
+    state.db.set_audit_user(user_id);
+    if let Some(pool) = &state.connection_manager.postgres_pool {
+        pool.set_audit_user(user_id);
+    }
+
+    let user = match session {
+        Some(session) => Some(SessionUser::new(&session.user_id, &session_roles(&state, &session.user_id).await)),
+        None => None,
+    };
+    state.set_session_user(user);
+}
+
 /// Save session to secure store
 #[tauri::command]
 pub async fn cache_auth_session(
@@ -36,11 +126,20 @@ pub async fn cache_auth_session(
     roles: Vec<String>,
     full_name: Option<String>,
 ) -> Result<(), String> {
+    let state = app
+        .try_state::<Arc<AppState>>()
+        .ok_or("La aplicación no ha terminado de iniciar")?;
+    let token_user = verify_access_token(&state, &access_token).await?;
+    if token_user != user_id {
+        log::warn!("Session for {} rejected: token belongs to {}", user_id, token_user);
+        return Err("La sesión no corresponde al usuario indicado".to_string());
+    }
+
     let store = app
         .store(AUTH_STORE_FILE)
         .map_err(|e| format!("Failed to open store: {}", e))?;
 
-    let session = CachedSession {
+    let mut session = CachedSession {
         user_id,
         email,
         access_token,
@@ -48,7 +147,9 @@ pub async fn cache_auth_session(
         roles,
         full_name,
         cached_at: chrono::Utc::now().to_rfc3339(),
+        verification: None,
     };
+    session.verification = Some(session_tag(&state, &session)?);
 
     store.set(
         SESSION_KEY,
@@ -57,6 +158,7 @@ pub async fn cache_auth_session(
 
     store.save().map_err(|e| format!("Failed to save store: {}", e))?;
 
+    set_session_user(&app, Some(&session)).await;
     log::info!("Auth session cached for user: {}", session.email);
     Ok(())
 }
@@ -85,10 +187,24 @@ pub async fn get_cached_session(app: AppHandle) -> Result<Option<CachedSession>,
                 log::info!("Cached session expired, clearing");
                 store.delete(SESSION_KEY);
                 store.save().ok();
+                set_session_user(&app, None).await;
+                return Ok(None);
+            }
+
+            let verified = match app.try_state::<Arc<AppState>>() {
+                Some(state) => session_tag(&state, &session).ok() == session.verification,
+                None => false,
+            };
+            if !verified {
+                log::warn!("Cached session for {} was not verified by this app, clearing", session.email);
+                store.delete(SESSION_KEY);
+                store.save().ok();
+                set_session_user(&app, None).await;
                 return Ok(None);
             }
 
             log::info!("Found valid cached session for: {}", session.email);
+            set_session_user(&app, Some(&session)).await;
             Ok(Some(session))
         }
         None => {
@@ -107,6 +223,7 @@ pub async fn clear_cached_session(app: AppHandle) -> Result<(), String> {
 
     store.delete(SESSION_KEY);
     store.save().map_err(|e| format!("Failed to save store: {}", e))?;
+    set_session_user(&app, None).await;
 
     log::info!("Cached session cleared");
     Ok(())
@@ -0,0 +1,87 @@
+// Cash register session arithmetic: physical counts and over/short
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Quetzal bills and coins accepted in a count, in centavos
+const DENOMINATION_CENTAVOS: &[i64] = &[20000, 10000, 5000, 2000, 1000, 500, 100, 50, 25, 10, 5, 1];
+
+/// Valid denominations for the base currency, largest first
+pub fn denominations() -> Vec<Decimal> {
+    DENOMINATION_CENTAVOS
+        .iter()
+        .map(|c| Decimal::new(*c, 2))
+        .collect()
+}
+
+/// Number of bills or coins of one denomination in the drawer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DenominationCount {
+    pub denomination: Decimal,
+    pub quantity: i32,
+}
+
+/// Total of a physical count. Rejects unknown denominations and negative quantities.
+pub fn count_total(counts: &[DenominationCount]) -> Result<Decimal, String> {
+    let valid = denominations();
+    let mut total = Decimal::ZERO;
+
+    for count in counts {
+        if !valid.contains(&count.denomination) {
+            return Err(format!("Denominación no válida: Q{}", count.denomination));
+        }
+        if count.quantity < 0 {
+            return Err(format!(
+                "Cantidad negativa para la denominación Q{}",
+                count.denomination
+            ));
+        }
+        total += count.denomination * Decimal::from(count.quantity);
+    }
+
+    Ok(total)
+}
+
+/// Cash that should be in the drawer at closing
+pub fn expected_cash(
+    opening_float: Decimal,
+    cash_sales: Decimal,
+    cash_in: Decimal,
+    cash_out: Decimal,
+) -> Decimal {
+    opening_float + cash_sales + cash_in - cash_out
+}
+
+/// Positive when the drawer has more than expected (sobrante),
+/// negative when it has less (faltante)
+pub fn over_short(counted: Decimal, expected: Decimal) -> Decimal {
+    counted - expected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::billing::d;
+
+    fn count(denomination: &str, quantity: i32) -> DenominationCount {
+        DenominationCount { denomination: d(denomination), quantity }
+    }
+
+    #[test]
+    fn test_count_total() {
+        let counts = vec![count("100", 3), count("20", 2), count("0.25", 4), count("0.01", 7)];
+        assert_eq!(count_total(&counts).unwrap(), d("341.07"));
+
+        assert!(count_total(&[count("3", 1)]).is_err());
+        assert!(count_total(&[count("10", -1)]).is_err());
+    }
+
+    #[test]
+    fn test_over_short() {
+        // Float 500, cash sales 1250.50, petty cash out 75
+        let expected = expected_cash(d("500"), d("1250.50"), Decimal::ZERO, d("75"));
+        assert_eq!(expected, d("1675.50"));
+        assert_eq!(over_short(d("1670.50"), expected), d("-5.00"));
+        assert_eq!(over_short(d("1675.50"), expected), Decimal::ZERO);
+    }
+}
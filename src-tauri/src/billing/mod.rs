@@ -0,0 +1,242 @@
+// Billing helpers for CentroVision EHR
+// Currency handling and money arithmetic shared by prices, payments and cash closures
+
+pub mod cash;
+pub mod tax;
+
+use rust_decimal::prelude::*;
+use rust_decimal::RoundingStrategy;
+use serde::{Deserialize, Serialize};
+
+/// Local currency (Quetzal). Invoices and balances are always kept in this currency.
+pub const BASE_CURRENCY: &str = "GTQ";
+
+/// Currencies accepted at the clinics
+pub const SUPPORTED_CURRENCIES: &[&str] = &["GTQ", "USD"];
+
+/// Normalize a currency code coming from the UI, defaulting to the base currency
+pub fn normalize_currency(code: Option<&str>) -> Result<String, String> {
+    let code = match code.map(str::trim) {
+        Some(c) if !c.is_empty() => c.to_uppercase(),
+        _ => return Ok(BASE_CURRENCY.to_string()),
+    };
+
+    if SUPPORTED_CURRENCIES.contains(&code.as_str()) {
+        Ok(code)
+    } else {
+        Err(format!("Moneda no soportada: {}", code))
+    }
+}
+
+/// Whether the given (normalized) currency is the local currency
+pub fn is_base_currency(code: &str) -> bool {
+    code == BASE_CURRENCY
+}
+
+// ============================================================
+// MONEY ARITHMETIC
+// ============================================================
+//
+// All amounts are `Decimal` (NUMERIC in Postgres) and are stored with two
+// decimal places. Rounding is half away from zero (Q0.005 -> Q0.01), the rule
+// used on printed invoices and by SAT. Intermediate values are never rounded;
+// only the final amount of each line, discount or tax is.
+
+/// Decimal places kept for stored amounts
+pub const MONEY_SCALE: u32 = 2;
+
+/// Round an amount to centavos (half away from zero)
+pub fn round_money(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(MONEY_SCALE, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// `pct` percent of `amount`, rounded to centavos
+pub fn percentage_of(amount: Decimal, pct: Decimal) -> Decimal {
+    round_money(amount * pct / Decimal::ONE_HUNDRED)
+}
+
+/// Subtotal of an invoice line (unit price x quantity), rounded to centavos
+pub fn line_subtotal(unit_price: Decimal, quantity: i32) -> Decimal {
+    round_money(unit_price * Decimal::from(quantity))
+}
+
+/// Discount amount for an invoice subtotal.
+/// Percentage discounts are rounded once on the whole subtotal; fixed discounts
+/// are capped so the total never goes negative.
+pub fn discount_amount(
+    subtotal: Decimal,
+    discount_type: Option<&str>,
+    discount_value: Option<Decimal>,
+) -> Decimal {
+    let value = match discount_value {
+        Some(v) if v > Decimal::ZERO => v,
+        _ => return Decimal::ZERO,
+    };
+
+    let discount = match discount_type {
+        Some("percentage") => percentage_of(subtotal, value.min(Decimal::ONE_HUNDRED)),
+        Some("fixed") => round_money(value),
+        _ => Decimal::ZERO,
+    };
+    discount.min(subtotal)
+}
+
+/// Convert an amount to the base currency at the given rate, rounded to centavos
+pub fn to_base_currency(amount: Decimal, exchange_rate: Decimal) -> Decimal {
+    round_money(amount * exchange_rate)
+}
+
+/// Unit price of an invoice line in the base currency. Catalog prices in
+/// another currency are converted at `exchange_rate`, the rate in effect on
+/// the invoice date; without one the line cannot be billed.
+pub fn unit_price_in_base(unit_price: Decimal, currency: &str, exchange_rate: Option<Decimal>) -> Result<Decimal, String> {
+    if is_base_currency(currency) {
+        return Ok(unit_price);
+    }
+    exchange_rate
+        .map(|rate| to_base_currency(unit_price, rate))
+        .ok_or_else(|| format!("No hay tipo de cambio registrado para {}", currency))
+}
+
+/// Per-currency totals for a cash closure period, broken down by payment method
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CurrencyTotals {
+    pub currency: String,
+    pub efectivo_total: Decimal,
+    pub tarjeta_total: Decimal,
+    pub transferencia_total: Decimal,
+    pub cheque_total: Decimal,
+    pub otro_total: Decimal,
+    /// Sum of all methods, in the original currency
+    pub total_collected: Decimal,
+    /// Sum of all methods, converted to the base currency at each payment's rate
+    pub total_collected_base: Decimal,
+}
+
+impl CurrencyTotals {
+    fn new(currency: &str) -> Self {
+        Self {
+            currency: currency.to_string(),
+            efectivo_total: Decimal::ZERO,
+            tarjeta_total: Decimal::ZERO,
+            transferencia_total: Decimal::ZERO,
+            cheque_total: Decimal::ZERO,
+            otro_total: Decimal::ZERO,
+            total_collected: Decimal::ZERO,
+            total_collected_base: Decimal::ZERO,
+        }
+    }
+
+    fn add(&mut self, payment_method: &str, amount: Decimal, amount_base: Decimal) {
+        match payment_method {
+            "efectivo" => self.efectivo_total += amount,
+            "tarjeta" => self.tarjeta_total += amount,
+            "transferencia" => self.transferencia_total += amount,
+            "cheque" => self.cheque_total += amount,
+            _ => self.otro_total += amount,
+        }
+        self.total_collected += amount;
+        self.total_collected_base += amount_base;
+    }
+}
+
+/// Fold (currency, payment_method, amount, amount_base) rows into per-currency totals.
+/// The base currency is always listed first so closures print consistently.
+pub fn group_currency_totals(rows: &[(String, String, Decimal, Decimal)]) -> Vec<CurrencyTotals> {
+    let mut totals: Vec<CurrencyTotals> = Vec::new();
+
+    for (currency, method, amount, amount_base) in rows {
+        let idx = match totals.iter().position(|t| &t.currency == currency) {
+            Some(idx) => idx,
+            None => {
+                totals.push(CurrencyTotals::new(currency));
+                totals.len() - 1
+            }
+        };
+        totals[idx].add(method, *amount, *amount_base);
+    }
+
+    totals.sort_by(|a, b| {
+        is_base_currency(&b.currency)
+            .cmp(&is_base_currency(&a.currency))
+            .then_with(|| a.currency.cmp(&b.currency))
+    });
+    totals
+}
+
+/// Decimal from a literal, for tests
+#[cfg(test)]
+pub(crate) fn d(s: &str) -> Decimal {
+    Decimal::from_str(s).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_currency() {
+        assert_eq!(normalize_currency(None).unwrap(), "GTQ");
+        assert_eq!(normalize_currency(Some("")).unwrap(), "GTQ");
+        assert_eq!(normalize_currency(Some(" usd ")).unwrap(), "USD");
+        assert!(normalize_currency(Some("EUR")).is_err());
+    }
+
+    #[test]
+    fn test_group_currency_totals() {
+        let rows = vec![
+            ("USD".to_string(), "efectivo".to_string(), d("100"), d("770")),
+            ("GTQ".to_string(), "efectivo".to_string(), d("250"), d("250")),
+            ("GTQ".to_string(), "tarjeta".to_string(), d("50.10"), d("50.10")),
+            ("USD".to_string(), "vale".to_string(), d("10"), d("77")),
+        ];
+        let totals = group_currency_totals(&rows);
+
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0].currency, "GTQ");
+        assert_eq!(totals[0].efectivo_total, d("250"));
+        assert_eq!(totals[0].tarjeta_total, d("50.10"));
+        assert_eq!(totals[0].total_collected, d("300.10"));
+        assert_eq!(totals[1].currency, "USD");
+        assert_eq!(totals[1].otro_total, d("10"));
+        assert_eq!(totals[1].total_collected_base, d("847"));
+    }
+
+    #[test]
+    fn test_round_money_half_away_from_zero() {
+        assert_eq!(round_money(d("10.005")), d("10.01"));
+        assert_eq!(round_money(d("10.004")), d("10.00"));
+        assert_eq!(round_money(d("-10.005")), d("-10.01"));
+        // 0.1 + 0.2 is exact in decimal
+        assert_eq!(d("0.1") + d("0.2"), d("0.3"));
+    }
+
+    #[test]
+    fn test_discount_amount() {
+        // 15% of 333.33 = 49.9995 -> 50.00
+        assert_eq!(
+            discount_amount(d("333.33"), Some("percentage"), Some(d("15"))),
+            d("50.00")
+        );
+        assert_eq!(discount_amount(d("100"), Some("fixed"), Some(d("25.5"))), d("25.50"));
+        // Fixed discounts never exceed the subtotal
+        assert_eq!(discount_amount(d("20"), Some("fixed"), Some(d("50"))), d("20"));
+        assert_eq!(discount_amount(d("100"), None, Some(d("10"))), Decimal::ZERO);
+        assert_eq!(discount_amount(d("100"), Some("percentage"), None), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_line_subtotal_and_conversion() {
+        assert_eq!(line_subtotal(d("33.333"), 3), d("100.00"));
+        assert_eq!(to_base_currency(d("12.34"), d("7.7125")), d("95.17"));
+    }
+
+    #[test]
+    fn test_usd_line_is_billed_in_quetzales() {
+        // A service priced at USD 100 is Q775.00 at 7.75, not Q100
+        assert_eq!(unit_price_in_base(d("100"), "USD", Some(d("7.75"))), Ok(d("775.00")));
+        assert_eq!(line_subtotal(unit_price_in_base(d("12.34"), "USD", Some(d("7.7125"))).unwrap(), 2), d("190.34"));
+        assert!(unit_price_in_base(d("100"), "USD", None).is_err());
+        assert_eq!(unit_price_in_base(d("100"), "GTQ", None), Ok(d("100")));
+    }
+}
@@ -0,0 +1,169 @@
+// IVA (impuesto al valor agregado) calculation for invoices
+//
+// Prices in the catalog are final consumer prices, i.e. they already include
+// IVA. Tax is therefore extracted from each line after the invoice discount
+// has been spread across the lines, so the invoice total never changes
+// because of tax and the tax shown always matches what the patient paid.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::round_money;
+
+/// General IVA rate in Guatemala (percent)
+pub const DEFAULT_TAX_RATE: Decimal = Decimal::from_parts(12, 0, 0, false, 0);
+
+/// An invoice line as seen by the tax calculation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaxableLine {
+    /// Line subtotal (unit price x quantity), IVA included
+    pub amount: Decimal,
+    /// Rate in percent, e.g. 12
+    pub tax_rate: Decimal,
+    pub tax_exempt: bool,
+}
+
+/// Tax breakdown of a single line after the invoice discount
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LineTax {
+    /// Share of the invoice discount applied to this line
+    pub discount_amount: Decimal,
+    /// Amount charged for the line (subtotal - discount), IVA included
+    pub net_amount: Decimal,
+    /// Net amount without IVA; zero for exempt lines
+    pub taxable_amount: Decimal,
+    pub tax_amount: Decimal,
+}
+
+/// Tax breakdown of a whole invoice
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceTax {
+    pub lines: Vec<LineTax>,
+    /// Sum of taxable line amounts without IVA
+    pub taxable_amount: Decimal,
+    /// Sum of exempt line amounts
+    pub exempt_amount: Decimal,
+    pub tax_amount: Decimal,
+}
+
+/// IVA contained in a tax-inclusive amount: `amount - amount / (1 + rate/100)`
+pub fn tax_included(amount: Decimal, tax_rate: Decimal) -> Decimal {
+    if tax_rate <= Decimal::ZERO {
+        return Decimal::ZERO;
+    }
+    let divisor = Decimal::ONE + tax_rate / Decimal::ONE_HUNDRED;
+    round_money(amount - amount / divisor)
+}
+
+/// Spread an invoice-level discount across lines in proportion to their amounts.
+/// Each share is rounded to centavos; the last line with a non-zero amount absorbs
+/// the rounding remainder so the shares always add up to the discount.
+pub fn allocate_discount(amounts: &[Decimal], discount: Decimal) -> Vec<Decimal> {
+    let mut shares = vec![Decimal::ZERO; amounts.len()];
+    let total: Decimal = amounts.iter().copied().sum();
+    if discount <= Decimal::ZERO || total <= Decimal::ZERO {
+        return shares;
+    }
+
+    let last = match amounts.iter().rposition(|a| *a > Decimal::ZERO) {
+        Some(idx) => idx,
+        None => return shares,
+    };
+
+    let mut allocated = Decimal::ZERO;
+    for (idx, amount) in amounts.iter().enumerate() {
+        if idx == last {
+            shares[idx] = discount - allocated;
+            break;
+        }
+        let share = round_money(discount * *amount / total);
+        shares[idx] = share;
+        allocated += share;
+    }
+    shares
+}
+
+/// Compute IVA per line and per invoice, applying `discount` (already rounded)
+/// proportionally before extracting the tax
+pub fn compute_invoice_tax(lines: &[TaxableLine], discount: Decimal) -> InvoiceTax {
+    let amounts: Vec<Decimal> = lines.iter().map(|l| l.amount).collect();
+    let discounts = allocate_discount(&amounts, discount);
+
+    let mut result = InvoiceTax {
+        lines: Vec::with_capacity(lines.len()),
+        taxable_amount: Decimal::ZERO,
+        exempt_amount: Decimal::ZERO,
+        tax_amount: Decimal::ZERO,
+    };
+
+    for (line, discount_amount) in lines.iter().zip(discounts) {
+        let net_amount = line.amount - discount_amount;
+        let (taxable_amount, tax_amount) = if line.tax_exempt {
+            result.exempt_amount += net_amount;
+            (Decimal::ZERO, Decimal::ZERO)
+        } else {
+            let tax = tax_included(net_amount, line.tax_rate);
+            result.taxable_amount += net_amount - tax;
+            result.tax_amount += tax;
+            (net_amount - tax, tax)
+        };
+
+        result.lines.push(LineTax {
+            discount_amount,
+            net_amount,
+            taxable_amount,
+            tax_amount,
+        });
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::billing::d;
+
+    fn line(amount: &str, exempt: bool) -> TaxableLine {
+        TaxableLine {
+            amount: d(amount),
+            tax_rate: DEFAULT_TAX_RATE,
+            tax_exempt: exempt,
+        }
+    }
+
+    #[test]
+    fn test_tax_included() {
+        // Q112.00 includes Q12.00 of IVA
+        assert_eq!(tax_included(d("112"), DEFAULT_TAX_RATE), d("12.00"));
+        // 100 - 100/1.12 = 10.714... -> 10.71
+        assert_eq!(tax_included(d("100"), DEFAULT_TAX_RATE), d("10.71"));
+        assert_eq!(tax_included(d("100"), Decimal::ZERO), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_allocate_discount_adds_up() {
+        let shares = allocate_discount(&[d("100"), d("100"), d("100")], d("10"));
+        assert_eq!(shares, vec![d("3.33"), d("3.33"), d("3.34")]);
+        assert_eq!(allocate_discount(&[d("50")], Decimal::ZERO), vec![Decimal::ZERO]);
+    }
+
+    #[test]
+    fn test_compute_invoice_tax_with_exempt_line() {
+        // Consultation exempt, product taxed, Q20 discount spread 50/50
+        let tax = compute_invoice_tax(&[line("200", true), line("200", false)], d("20"));
+
+        assert_eq!(tax.lines[0].net_amount, d("190"));
+        assert_eq!(tax.lines[0].tax_amount, Decimal::ZERO);
+        assert_eq!(tax.lines[1].net_amount, d("190"));
+        // 190 - 190/1.12 = 20.357... -> 20.36
+        assert_eq!(tax.lines[1].tax_amount, d("20.36"));
+        assert_eq!(tax.exempt_amount, d("190"));
+        assert_eq!(tax.taxable_amount, d("169.64"));
+        assert_eq!(tax.tax_amount, d("20.36"));
+        assert_eq!(
+            tax.exempt_amount + tax.taxable_amount + tax.tax_amount,
+            d("380")
+        );
+    }
+}
@@ -0,0 +1,227 @@
+// Visual acuity normalization to logMAR
+//
+// av_sc / av_cc are typed freely in the exam: Snellen in feet ("20/40") or
+// metres ("6/12"), with letters missed or gained ("20/30-2"), decimal
+// acuity ("0.5"), or the low-vision categories counting fingers, hand
+// motion and light perception (in English or Spanish). The logMAR value is
+// stored next to the text so research queries can compare numerically.
+//
+// Low-vision categories use the usual research equivalents: CF 1.9 (or the
+// Snellen equivalent d/60 when the distance is given), HM 2.3, LP 2.7 and
+// NLP 3.0.
+
+/// Each letter read or missed on a Snellen line
+const LOGMAR_PER_LETTER: f64 = 0.02;
+
+pub const LOGMAR_COUNTING_FINGERS: f64 = 1.9;
+pub const LOGMAR_HAND_MOTION: f64 = 2.3;
+pub const LOGMAR_LIGHT_PERCEPTION: f64 = 2.7;
+pub const LOGMAR_NO_LIGHT_PERCEPTION: f64 = 3.0;
+
+fn round2(value: f64) -> f64 {
+    let rounded = (value * 100.0).round() / 100.0;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+/// Text uppercased, without accents, abbreviation dots or repeated spaces
+/// (a dot between digits is a decimal point and is kept)
+fn normalize(text: &str) -> String {
+    let chars: Vec<char> = text.trim().to_uppercase().chars().collect();
+    chars
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| match c {
+            'Á' => Some('A'),
+            'É' => Some('E'),
+            'Í' => Some('I'),
+            'Ó' => Some('O'),
+            'Ú' => Some('U'),
+            '.' if !(i > 0 && chars[i - 1].is_ascii_digit() && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) => None,
+            c => Some(*c),
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Distance in metres from e.g. "2M", "50 CM", "3 FT", "1 PIE"
+fn parse_distance_m(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == ',' || c == '.'))?;
+    let value: f64 = text[..split].replace(',', ".").parse().ok()?;
+    let unit = text[split..].trim();
+    let metres = match unit {
+        "M" | "MT" | "MTS" | "METROS" | "METRO" => value,
+        "CM" => value / 100.0,
+        "FT" | "PIES" | "PIE" | "'" => value * 0.3048,
+        _ => return None,
+    };
+    (metres > 0.0).then_some(metres)
+}
+
+fn low_vision(text: &str) -> Option<f64> {
+    const CF: &[&str] = &["CF", "CD", "CUENTA DEDOS", "CUENTADEDOS", "COUNTING FINGERS", "CUENTA LOS DEDOS"];
+    const HM: &[&str] = &["HM", "MM", "MOVIMIENTO DE MANOS", "MOVIMIENTO MANOS", "HAND MOTION", "MMO"];
+    const LP: &[&str] = &["LP", "PL", "PPL", "PERCEPCION DE LUZ", "PERCIBE LUZ", "LIGHT PERCEPTION"];
+    const NLP: &[&str] = &["NLP", "NPL", "NO PL", "NO PERCEPCION DE LUZ", "NO PERCIBE LUZ", "NO LIGHT PERCEPTION"];
+
+    let matches = |names: &[&'static str]| -> Option<&'static str> {
+        names.iter().find(|n| text == **n || text.starts_with(&format!("{} ", n))).copied()
+    };
+
+    // NLP first: "NO PL" would otherwise read as PL
+    if matches(NLP).is_some() {
+        return Some(LOGMAR_NO_LIGHT_PERCEPTION);
+    }
+    if let Some(name) = matches(CF) {
+        let rest = text[name.len()..].trim().trim_start_matches("A ").trim();
+        return Some(match parse_distance_m(rest) {
+            Some(d) => round2((60.0 / d).log10().min(LOGMAR_HAND_MOTION - 0.1)),
+            None => LOGMAR_COUNTING_FINGERS,
+        });
+    }
+    if matches(HM).is_some() {
+        return Some(LOGMAR_HAND_MOTION);
+    }
+    if matches(LP).is_some() {
+        return Some(LOGMAR_LIGHT_PERCEPTION);
+    }
+    None
+}
+
+/// "20/40", "6/12", "20/30-2", "20/25+1"
+fn snellen(text: &str) -> Option<f64> {
+    let (numerator, rest) = text.split_once('/')?;
+    let numerator: f64 = numerator.trim().parse().ok()?;
+    let rest = rest.trim();
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let denominator: f64 = rest[..end].parse().ok()?;
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return None;
+    }
+
+    let modifier = rest[end..].replace(' ', "");
+    let letters: f64 = match modifier.as_str() {
+        "" => 0.0,
+        m if m.starts_with('-') || m.starts_with('+') => m.parse().ok()?,
+        _ => return None,
+    };
+    // Missed letters (-) make acuity worse, i.e. logMAR higher
+    Some(round2((denominator / numerator).log10() - letters * LOGMAR_PER_LETTER))
+}
+
+/// Decimal acuity between 0.01 and 2.0, e.g. "0.5" or "0,8"
+fn decimal(text: &str) -> Option<f64> {
+    let value: f64 = text.replace(',', ".").parse().ok()?;
+    if !(0.01..=2.0).contains(&value) {
+        return None;
+    }
+    Some(round2(-value.log10()))
+}
+
+/// logMAR of a recorded acuity; None when the text is not recognized
+pub fn to_logmar(text: &str) -> Option<f64> {
+    let text = normalize(text);
+    if text.is_empty() {
+        return None;
+    }
+    if let Some(value) = low_vision(&text) {
+        return Some(value);
+    }
+    if text.contains('/') {
+        return snellen(&text);
+    }
+    decimal(&text)
+}
+
+/// logMAR of an optional exam field
+pub fn field_logmar(text: Option<&str>) -> Option<f64> {
+    text.and_then(to_logmar)
+}
+
+/// Acuity of the operated eye before and after surgery
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SurgicalAcuity {
+    pub preop: Option<f64>,
+    pub postop: Option<f64>,
+    /// postop - preop; negative means the eye sees better
+    pub change: Option<f64>,
+}
+
+/// Pre/post-op comparison from the dated logMAR measurements of the operated
+/// eye: the last one before the surgery date against the last one after it.
+/// Measurements taken on the day of surgery count as neither.
+pub fn surgical_acuity(measurements: &[(chrono::NaiveDate, f64)], surgery_date: chrono::NaiveDate) -> SurgicalAcuity {
+    let latest = |before: bool| {
+        measurements
+            .iter()
+            .filter(|(date, _)| if before { *date < surgery_date } else { *date > surgery_date })
+            .max_by_key(|(date, _)| *date)
+            .map(|(_, logmar)| *logmar)
+    };
+    let preop = latest(true);
+    let postop = latest(false);
+    SurgicalAcuity {
+        preop,
+        postop,
+        change: preop.zip(postop).map(|(pre, post)| round2(post - pre)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snellen_and_decimal() {
+        assert_eq!(to_logmar("20/20"), Some(0.0));
+        assert_eq!(to_logmar("20/40"), Some(0.3));
+        assert_eq!(to_logmar("6/12"), Some(0.3));
+        assert_eq!(to_logmar("20/200"), Some(1.0));
+        assert_eq!(to_logmar("20/15"), Some(-0.12));
+        assert_eq!(to_logmar("20/30-2"), Some(0.22));
+        assert_eq!(to_logmar("20/25 +1"), Some(0.08));
+        assert_eq!(to_logmar("0.5"), Some(0.3));
+        assert_eq!(to_logmar("1,0"), Some(0.0));
+        assert_eq!(to_logmar("20/"), None);
+        assert_eq!(to_logmar("5.0"), None);
+    }
+
+    #[test]
+    fn test_low_vision() {
+        assert_eq!(to_logmar("CF"), Some(LOGMAR_COUNTING_FINGERS));
+        assert_eq!(to_logmar("CF 2m"), Some(1.48));
+        assert_eq!(to_logmar("cuenta dedos a 1 m"), Some(1.78));
+        assert_eq!(to_logmar("C.D. 30 cm"), Some(2.2));
+        assert_eq!(to_logmar("HM"), Some(LOGMAR_HAND_MOTION));
+        assert_eq!(to_logmar("Movimiento de manos"), Some(LOGMAR_HAND_MOTION));
+        assert_eq!(to_logmar("PL"), Some(LOGMAR_LIGHT_PERCEPTION));
+        assert_eq!(to_logmar("No percepción de luz"), Some(LOGMAR_NO_LIGHT_PERCEPTION));
+        assert_eq!(to_logmar("NPL"), Some(LOGMAR_NO_LIGHT_PERCEPTION));
+        assert_eq!(field_logmar(Some("no colabora")), None);
+        assert_eq!(field_logmar(None), None);
+    }
+
+    #[test]
+    fn test_surgical_acuity() {
+        let date = |s: &str| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let measurements = [
+            (date("2026-01-10"), 1.0),
+            (date("2026-02-01"), 0.7),
+            (date("2026-02-15"), 2.3),
+            (date("2026-03-01"), 0.1),
+            (date("2026-02-20"), 0.3),
+        ];
+
+        let outcome = surgical_acuity(&measurements, date("2026-02-15"));
+        assert_eq!(outcome.preop, Some(0.7));
+        assert_eq!(outcome.postop, Some(0.1));
+        assert_eq!(outcome.change, Some(-0.6));
+
+        let no_follow_up = surgical_acuity(&measurements, date("2026-03-01"));
+        assert_eq!(no_follow_up.preop, Some(0.3));
+        assert_eq!(no_follow_up.postop, None);
+        assert_eq!(no_follow_up.change, None);
+    }
+}
@@ -0,0 +1,186 @@
+// ICD-10 (CIE-10, Spanish) diagnosis catalog
+//
+// The catalog bundled in icd10_es.tsv covers the eye chapter (H00-H59) and
+// the codes from other chapters used in ophthalmology (diabetes with eye
+// complications, eye injuries and tumours, pseudophakia...). It is loaded
+// into PostgreSQL and the SQLite cache; searches match code prefixes and
+// every word of the query against description and synonyms, ignoring case
+// and accents.
+//
+// WHO ICD-10 has no laterality digit, so the eye is stored next to the
+// code. Codes for conditions of one eye (see `is_lateral`) require it.
+
+const CATALOG: &str = include_str!("icd10_es.tsv");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Icd10Entry {
+    pub code: String,
+    pub description: String,
+    pub synonyms: Vec<String>,
+}
+
+impl Icd10Entry {
+    /// Folded code, description and synonyms, matched by searches
+    pub fn search_text(&self) -> String {
+        let mut parts = vec![self.code.clone(), self.description.clone()];
+        parts.extend(self.synonyms.iter().cloned());
+        fold(&parts.join(" "))
+    }
+
+    pub fn is_lateral(&self) -> bool {
+        is_lateral(&self.code)
+    }
+}
+
+/// Bundled catalog entries
+pub fn entries() -> Vec<Icd10Entry> {
+    CATALOG
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let code = fields.next()?.trim().to_string();
+            let description = fields.next()?.trim().to_string();
+            let synonyms = fields
+                .next()
+                .map(|s| s.split(';').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect())
+                .unwrap_or_default();
+            Some(Icd10Entry { code, description, synonyms })
+        })
+        .collect()
+}
+
+/// Lowercase without accents, for matching
+pub fn fold(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'ä' => 'a',
+            'é' | 'è' | 'ë' => 'e',
+            'í' | 'ì' | 'ï' => 'i',
+            'ó' | 'ò' | 'ö' => 'o',
+            'ú' | 'ù' | 'ü' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+/// Folded words of a search query
+pub fn search_terms(query: &str) -> Vec<String> {
+    fold(query)
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Canonical form of a typed code: uppercase with the dot after the
+/// category ("h251" -> "H25.1"); None when it is not ICD-10 shaped
+pub fn normalize_code(code: &str) -> Option<String> {
+    let compact: String = code.trim().to_uppercase().chars().filter(|c| !c.is_whitespace() && *c != '.').collect();
+    let mut chars = compact.chars();
+    let letter = chars.next().filter(|c| c.is_ascii_uppercase())?;
+    let rest: String = chars.collect();
+    if !rest.is_ascii() || rest.len() < 2 || rest.len() > 4 || !rest[..2].chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if !rest[2..].chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    if rest.len() == 2 {
+        Some(format!("{}{}", letter, rest))
+    } else {
+        Some(format!("{}{}.{}", letter, &rest[..2], &rest[2..]))
+    }
+}
+
+/// Code prefix typed in a search ("h25", "H25.", "h251"), without the dot
+pub fn code_prefix(query: &str) -> Option<String> {
+    let compact: String = query.trim().to_uppercase().chars().filter(|c| *c != '.').collect();
+    let mut chars = compact.chars();
+    chars.next().filter(|c| c.is_ascii_uppercase())?;
+    let rest = chars.as_str();
+    (rest.len() <= 4 && rest.chars().all(|c| c.is_ascii_digit())).then_some(compact)
+}
+
+/// Whether the code describes a condition of one eye, so the diagnosis
+/// must say which (OD, OI or OU). Eye chapter codes are, except ocular
+/// motility, visual impairment categories and nystagmus; so are eye
+/// tumours, congenital eye malformations and eye injuries.
+pub fn is_lateral(code: &str) -> bool {
+    let category = code.get(..3).unwrap_or(code);
+    match category.chars().next() {
+        Some('H') => {
+            let n: u32 = category[1..].parse().unwrap_or(99);
+            n < 60 && !matches!(n, 49..=51 | 54 | 55)
+        }
+        _ => matches!(category, "C69" | "D31" | "Q12" | "Q13" | "Q15" | "S05" | "T15" | "T26"),
+    }
+}
+
+/// Canonical eye of a diagnosis: OD, OI or OU
+pub fn normalize_eye(eye: &str) -> Result<String, String> {
+    let canonical = match fold(eye.trim()).as_str() {
+        "od" | "derecho" | "ojo derecho" => "OD",
+        "oi" | "os" | "izquierdo" | "ojo izquierdo" => "OI",
+        "ou" | "ao" | "ambos" | "ambos ojos" | "bilateral" => "OU",
+        _ => return Err(format!("Ojo no válido: {} (use OD, OI u OU)", eye.trim())),
+    };
+    Ok(canonical.to_string())
+}
+
+/// Check a diagnosis against the catalog entry of its code and return the
+/// canonical eye. `entry` is None when the code is not in the catalog.
+pub fn validate_diagnosis(code: &str, entry: Option<&Icd10Entry>, eye: Option<&str>) -> Result<Option<String>, String> {
+    let entry = entry.ok_or_else(|| format!("Código CIE-10 no encontrado en el catálogo: {}", code))?;
+    let eye = match eye.filter(|e| !e.trim().is_empty()) {
+        Some(e) => Some(normalize_eye(e)?),
+        None => None,
+    };
+    if entry.is_lateral() && eye.is_none() {
+        return Err(format!("Indique el ojo (OD, OI u OU) para {} {}", entry.code, entry.description));
+    }
+    Ok(eye)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_and_codes() {
+        let catalog = entries();
+        assert!(catalog.len() > 150);
+        let cataract = catalog.iter().find(|e| e.code == "H25.1").unwrap();
+        assert_eq!(cataract.description, "Catarata senil nuclear");
+        assert!(cataract.search_text().contains("esclerosis nuclear"));
+        assert!(catalog.iter().all(|e| normalize_code(&e.code).as_deref() == Some(e.code.as_str())));
+
+        assert_eq!(normalize_code(" h251 ").as_deref(), Some("H25.1"));
+        assert_eq!(normalize_code("I10").as_deref(), Some("I10"));
+        assert_eq!(normalize_code("25.1"), None);
+        assert_eq!(normalize_code("H€"), None);
+        assert_eq!(normalize_code("H2€"), None);
+        assert_eq!(normalize_code("H25.€"), None);
+        assert_eq!(search_terms("Catarata  NUCLEAR"), vec!["catarata", "nuclear"]);
+        assert_eq!(code_prefix("h25.").as_deref(), Some("H25"));
+        assert_eq!(code_prefix("catarata"), None);
+        assert_eq!(fold("Miopía"), "miopia");
+    }
+
+    #[test]
+    fn test_laterality() {
+        assert!(is_lateral("H40.1"));
+        assert!(is_lateral("S05.0"));
+        assert!(is_lateral("H52.4"));
+        assert!(!is_lateral("H54.0"));
+        assert!(!is_lateral("E11.3"));
+
+        let glaucoma = Icd10Entry { code: "H40.1".into(), description: "Glaucoma".into(), synonyms: vec![] };
+        assert!(validate_diagnosis("H40.1", Some(&glaucoma), None).is_err());
+        assert_eq!(validate_diagnosis("H40.1", Some(&glaucoma), Some("os")).unwrap().as_deref(), Some("OI"));
+        assert!(validate_diagnosis("X99", None, Some("OD")).is_err());
+        let htn = Icd10Entry { code: "I10".into(), description: "HTA".into(), synonyms: vec![] };
+        assert_eq!(validate_diagnosis("I10", Some(&htn), None).unwrap(), None);
+    }
+}
@@ -0,0 +1,375 @@
+// Intraocular lens power calculation for cataract surgery
+//
+// Third-generation theoretical formulas, as published:
+// - SRK/T (Retzlaff, Sanders & Kraff 1990, with the 1990 erratum)
+// - Holladay 1 (Holladay et al. 1988)
+// - Hoffer Q (Hoffer 1993, with the 1994/2007 corrections)
+// Each predicts the effective lens position from axial length and
+// keratometry and solves the vergence equation for the IOL power that
+// leaves the eye at the target spectacle refraction (vertex 12 mm).
+//
+// Lens constants: the A-constant is the reference; when a model has no
+// optimized Holladay surgeon factor or Hoffer pACD they are derived from it
+// with the usual conversions (SF = 0.5663·A − 65.60, pACD = (SF + 3.595) / 0.9704).
+
+/// Spectacle vertex distance assumed by the formulas, in mm
+const VERTEX_MM: f64 = 12.0;
+/// Aqueous and vitreous refractive index
+const N_AQUEOUS: f64 = 1.336;
+
+/// Step between available lens powers
+pub const POWER_STEP: f64 = 0.5;
+/// Powers listed on each side of the one closest to target
+const TABLE_STEPS: i32 = 5;
+
+pub const FORMULAS: &[Formula] = &[Formula::SrkT, Formula::Holladay1, Formula::HofferQ];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formula {
+    SrkT,
+    Holladay1,
+    HofferQ,
+}
+
+impl Formula {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Formula::SrkT => "srkt",
+            Formula::Holladay1 => "holladay1",
+            Formula::HofferQ => "hofferq",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Formula::SrkT => "SRK/T",
+            Formula::Holladay1 => "Holladay 1",
+            Formula::HofferQ => "Hoffer Q",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Result<Formula, String> {
+        FORMULAS
+            .iter()
+            .find(|f| f.code() == code)
+            .copied()
+            .ok_or_else(|| format!("Fórmula no válida: {}", code))
+    }
+}
+
+/// Ocular biometry of one eye
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biometry {
+    /// Axial length, mm
+    pub axial_length: f64,
+    /// Flat and steep keratometry, D
+    pub k1: f64,
+    pub k2: f64,
+}
+
+impl Biometry {
+    pub fn mean_k(&self) -> f64 {
+        (self.k1 + self.k2) / 2.0
+    }
+}
+
+/// Per-model lens constants
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensConstants {
+    pub a_constant: f64,
+    pub surgeon_factor: Option<f64>,
+    pub pacd: Option<f64>,
+}
+
+impl LensConstants {
+    pub fn surgeon_factor(&self) -> f64 {
+        self.surgeon_factor.unwrap_or(0.5663 * self.a_constant - 65.60)
+    }
+
+    pub fn pacd(&self) -> f64 {
+        self.pacd.unwrap_or((self.surgeon_factor() + 3.595) / 0.9704)
+    }
+}
+
+/// Validate biometry values; measurements outside these ranges are typos
+pub fn validate_biometry(axial_length: f64, k1: f64, k2: f64, acd: Option<f64>) -> Result<(), String> {
+    if !(15.0..=38.0).contains(&axial_length) {
+        return Err("La longitud axial debe estar entre 15 y 38 mm".to_string());
+    }
+    if !(30.0..=60.0).contains(&k1) || !(30.0..=60.0).contains(&k2) {
+        return Err("La queratometría debe estar entre 30 y 60 D".to_string());
+    }
+    if let Some(acd) = acd {
+        if !(1.5..=6.0).contains(&acd) {
+            return Err("La profundidad de cámara anterior debe estar entre 1.5 y 6 mm".to_string());
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_constants(constants: &LensConstants) -> Result<(), String> {
+    if !(110.0..=125.0).contains(&constants.a_constant) {
+        return Err("La constante A debe estar entre 110 y 125".to_string());
+    }
+    Ok(())
+}
+
+/// Formula usually preferred for the axial length: Hoffer Q for short
+/// eyes, SRK/T for long eyes and Holladay 1 in between
+pub fn suggested_formula(axial_length: f64) -> Formula {
+    if axial_length < 22.0 {
+        Formula::HofferQ
+    } else if axial_length > 26.0 {
+        Formula::SrkT
+    } else {
+        Formula::Holladay1
+    }
+}
+
+/// Thin-lens vergence model shared by SRK/T and Holladay 1: corneal radius
+/// `r`, optical axial length `lo` and lens position `d` (mm), cornea index
+/// minus one `ncm1`. Target and result refraction are at the spectacle plane.
+struct VergenceEye {
+    r: f64,
+    lo: f64,
+    d: f64,
+    ncm1: f64,
+}
+
+impl VergenceEye {
+    fn terms(&self) -> (f64, f64, f64, f64, f64) {
+        let a1 = N_AQUEOUS * self.r - self.ncm1 * self.lo;
+        let b1 = VERTEX_MM * a1 + self.lo * self.r;
+        let a2 = N_AQUEOUS * self.r - self.ncm1 * self.d;
+        let b2 = VERTEX_MM * a2 + self.d * self.r;
+        (a1, b1, a2, b2, self.lo - self.d)
+    }
+
+    fn power(&self, target: f64) -> f64 {
+        let (a1, b1, a2, b2, c) = self.terms();
+        1000.0 * N_AQUEOUS * (a1 - 0.001 * target * b1) / (c * (a2 - 0.001 * target * b2))
+    }
+
+    fn refraction(&self, power: f64) -> f64 {
+        let (a1, b1, a2, b2, c) = self.terms();
+        (1000.0 * N_AQUEOUS * a1 - power * c * a2) / (N_AQUEOUS * b1 - 0.001 * power * c * b2)
+    }
+}
+
+fn srkt_eye(bio: &Biometry, a_constant: f64) -> VergenceEye {
+    let l = bio.axial_length;
+    let k = bio.mean_k();
+    let r = 337.5 / k;
+    // Axial length correction for long eyes
+    let lcor = if l > 24.2 { -3.446 + 1.716 * l - 0.0237 * l * l } else { l };
+    // Corneal width and height
+    let cw = -5.41 + 0.58412 * lcor + 0.098 * k;
+    let h = r - (r * r - cw * cw / 4.0).max(0.0).sqrt();
+    let acd_constant = 0.62467 * a_constant - 68.747;
+    let retinal_thickness = 0.65696 - 0.02029 * l;
+    VergenceEye {
+        r,
+        lo: l + retinal_thickness,
+        d: h + acd_constant - 3.3357,
+        ncm1: 0.333,
+    }
+}
+
+fn holladay_eye(bio: &Biometry, surgeon_factor: f64) -> VergenceEye {
+    let r = 337.5 / bio.mean_k();
+    // The 7 mm floor on the radius is only for the lens position estimate
+    let r_acd = r.max(7.0);
+    let ag = (12.5 * bio.axial_length / 23.45).min(13.5);
+    let acd = 0.56 + r_acd - (r_acd * r_acd - ag * ag / 4.0).sqrt();
+    VergenceEye {
+        r,
+        lo: bio.axial_length + 0.2,
+        d: acd + surgeon_factor,
+        ncm1: 4.0 / 3.0 - 1.0,
+    }
+}
+
+fn tan_deg(value: f64) -> f64 {
+    value.to_radians().tan()
+}
+
+/// Hoffer Q predicted lens position
+fn hoffer_acd(bio: &Biometry, pacd: f64) -> f64 {
+    let l = bio.axial_length.clamp(18.5, 31.0);
+    let (m, g) = if l <= 23.0 { (1.0, 28.0) } else { (-1.0, 23.5) };
+    let acd = pacd + 0.3 * (l - 23.5) + tan_deg(bio.mean_k()).powi(2)
+        + 0.1 * m * (23.5 - l).powi(2) * tan_deg(0.1 * (g - l).powi(2))
+        - 0.99166;
+    acd.clamp(2.5, 6.5)
+}
+
+/// IOL power (unrounded) that leaves the eye at `target` refraction
+pub fn power_for_target(formula: Formula, bio: &Biometry, constants: &LensConstants, target: f64) -> f64 {
+    match formula {
+        Formula::SrkT => srkt_eye(bio, constants.a_constant).power(target),
+        Formula::Holladay1 => holladay_eye(bio, constants.surgeon_factor()).power(target),
+        Formula::HofferQ => {
+            let acd = hoffer_acd(bio, constants.pacd());
+            let corneal = target / (1.0 - VERTEX_MM / 1000.0 * target);
+            1336.0 / (bio.axial_length - acd - 0.05)
+                - N_AQUEOUS / (N_AQUEOUS / (bio.mean_k() + corneal) - (acd + 0.05) / 1000.0)
+        }
+    }
+}
+
+/// Spectacle refraction expected with an IOL of `power`
+pub fn predicted_refraction(formula: Formula, bio: &Biometry, constants: &LensConstants, power: f64) -> f64 {
+    match formula {
+        Formula::SrkT => srkt_eye(bio, constants.a_constant).refraction(power),
+        Formula::Holladay1 => holladay_eye(bio, constants.surgeon_factor()).refraction(power),
+        Formula::HofferQ => {
+            let acd = hoffer_acd(bio, constants.pacd());
+            let image = 1336.0 / (bio.axial_length - acd - 0.05) - power;
+            let corneal = N_AQUEOUS / (N_AQUEOUS / image + (acd + 0.05) / 1000.0) - bio.mean_k();
+            corneal / (1.0 + VERTEX_MM / 1000.0 * corneal)
+        }
+    }
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// One row of the printed power table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerOption {
+    pub power: f64,
+    pub refraction: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormulaResult {
+    pub formula: Formula,
+    /// Exact power for the target, before rounding to available steps
+    pub exact_power: f64,
+    /// Available power whose predicted refraction is closest to target
+    /// (the more myopic one on a tie)
+    pub recommended: PowerOption,
+    /// Powers around the recommended one, highest first
+    pub options: Vec<PowerOption>,
+}
+
+pub fn calculate(formula: Formula, bio: &Biometry, constants: &LensConstants, target: f64) -> FormulaResult {
+    let exact = power_for_target(formula, bio, constants, target);
+    let center = (exact / POWER_STEP).round() * POWER_STEP;
+    let options: Vec<PowerOption> = (-TABLE_STEPS..=TABLE_STEPS)
+        .rev()
+        .map(|i| {
+            let power = center + i as f64 * POWER_STEP;
+            PowerOption {
+                power,
+                refraction: round2(predicted_refraction(formula, bio, constants, power)),
+            }
+        })
+        .collect();
+
+    let recommended = options
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            let da = (a.refraction - target).abs();
+            let db = (b.refraction - target).abs();
+            da.partial_cmp(&db)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.refraction.partial_cmp(&b.refraction).unwrap_or(std::cmp::Ordering::Equal))
+        })
+        .unwrap_or(PowerOption { power: center, refraction: target });
+
+    FormulaResult {
+        formula,
+        exact_power: round2(exact),
+        recommended,
+        options,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A_118_4: LensConstants = LensConstants { a_constant: 118.4, surgeon_factor: None, pacd: None };
+
+    fn bio(axial_length: f64, k: f64) -> Biometry {
+        Biometry { axial_length, k1: k, k2: k }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.05, "{} != {}", actual, expected);
+    }
+
+    // Reference values for A-constant 118.4 (SF 1.45, pACD 5.20): IOL power
+    // for emmetropia and spectacle refraction with a 20.00 D lens, in D.
+    // Computed to 0.01 D with 30-digit arithmetic from the equations as
+    // printed in Retzlaff, Sanders & Kraff, J Cataract Refract Surg
+    // 1990;16:333-340 (erratum p. 528); Holladay et al., J Cataract Refract
+    // Surg 1988;14:17-24; and Hoffer, J Cataract Refract Surg 1993;19:700-712
+    // (errata 1994;20:677, 2007;33:2-3).
+    // AL, K, [(power, refraction) for SRK/T, Holladay 1, Hoffer Q]
+    type ReferenceCase = (f64, f64, [(f64, f64); 3]);
+
+    const REFERENCE: &[ReferenceCase] = &[
+        (23.5, 44.0, [(20.04, 0.03), (20.01, 0.01), (19.89, -0.07)]),
+        (22.0, 45.0, [(23.86, 2.62), (23.97, 2.67), (24.05, 2.71)]),
+        (20.5, 47.0, [(27.43, 4.86), (27.53, 4.93), (27.94, 5.12)]),
+        (25.0, 43.0, [(16.55, -2.45), (16.66, -2.33), (16.38, -2.55)]),
+        (27.0, 42.0, [(12.05, -5.95), (11.72, -6.21), (11.95, -5.80)]),
+        // Steep cornea (radius 6.75 mm): Holladay 1 limits only the ACD radius
+        (22.5, 50.0, [(17.07, -1.79), (15.53, -3.01), (15.44, -3.06)]),
+    ];
+
+    #[test]
+    fn test_reference_powers_and_refractions() {
+        for (al, k, expected) in REFERENCE {
+            let b = bio(*al, *k);
+            for (formula, (power, refraction)) in FORMULAS.iter().zip(expected) {
+                let actual_power = power_for_target(*formula, &b, &A_118_4, 0.0);
+                let actual_refraction = predicted_refraction(*formula, &b, &A_118_4, 20.0);
+                assert!((actual_power - power).abs() < 0.006, "{} AL {} K {}: {} != {}", formula.name(), al, k, actual_power, power);
+                assert!(
+                    (actual_refraction - refraction).abs() < 0.006,
+                    "{} AL {} K {}: {} != {}",
+                    formula.name(), al, k, actual_refraction, refraction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_emmetropic_powers() {
+        // A steeper cornea needs less power even past the 7 mm radius floor,
+        // which Holladay 1 applies only to the ACD estimate
+        let steep = power_for_target(Formula::Holladay1, &bio(22.5, 50.0), &A_118_4, 0.0);
+        let at_floor = power_for_target(Formula::Holladay1, &bio(22.5, 337.5 / 7.0), &A_118_4, 0.0);
+        assert!(steep < at_floor - 1.0, "{} vs {}", steep, at_floor);
+
+        // Aiming myopic needs more power
+        let b = bio(23.5, 44.0);
+        assert!(power_for_target(Formula::SrkT, &b, &A_118_4, -0.5) > power_for_target(Formula::SrkT, &b, &A_118_4, 0.0));
+        assert_close(A_118_4.surgeon_factor(), 1.45);
+        assert_close(A_118_4.pacd(), 5.20);
+    }
+
+    #[test]
+    fn test_refraction_round_trip_and_table() {
+        let b = Biometry { axial_length: 24.1, k1: 43.25, k2: 44.5 };
+        for formula in FORMULAS {
+            let power = power_for_target(*formula, &b, &A_118_4, -0.25);
+            assert_close(predicted_refraction(*formula, &b, &A_118_4, power), -0.25);
+
+            let result = calculate(*formula, &b, &A_118_4, -0.25);
+            assert_eq!(result.options.len(), 11);
+            assert!(result.options[0].power > result.options[10].power);
+            assert!(result.options[0].refraction < result.options[10].refraction);
+            assert!((result.recommended.refraction + 0.25).abs() <= 0.2);
+        }
+        assert_eq!(suggested_formula(21.5), Formula::HofferQ);
+        assert_eq!(suggested_formula(27.0), Formula::SrkT);
+        assert_eq!(Formula::from_code("holladay1").unwrap(), Formula::Holladay1);
+        assert!(validate_biometry(12.0, 44.0, 44.0, None).is_err());
+    }
+}
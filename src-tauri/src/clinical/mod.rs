@@ -0,0 +1,9 @@
+// Clinical domain logic shared by the PostgreSQL commands
+
+pub mod acuity;
+pub mod icd10;
+pub mod iol;
+pub mod prescription;
+pub mod record;
+pub mod refraction;
+pub mod timeline;
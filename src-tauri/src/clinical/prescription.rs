@@ -0,0 +1,277 @@
+// Optical prescriptions (glasses and contact lenses)
+//
+// A prescription is built from the final Rx (rx_sphere, rx_cyl, rx_axis,
+// rx_add) of the encounter's OD/OI exam. Powers follow the usual optical
+// conventions: 0.25 D steps, axis 0-180 and mandatory whenever there is
+// cylinder, and a reading addition of at most +4.00.
+//
+// Contact lenses sit on the cornea, not 12 mm in front of it: each
+// principal meridian of ±4.00 D or more is converted to the corneal plane
+// (F / (1 - d·F)), and the lens fit (base curve, diameter, brand) is
+// required for each corrected eye.
+
+use serde::{Deserialize, Serialize};
+
+use crate::clinical::refraction::{round_quarter, vertex_adjust};
+
+/// 'lentes' = spectacles, 'contacto' = contact lenses
+pub const PRESCRIPTION_KINDS: &[&str] = &["lentes", "contacto"];
+
+const DIOPTER_STEP: f64 = 0.25;
+const MAX_SPHERE: f64 = 30.0;
+const MAX_CYLINDER: f64 = 10.0;
+const MAX_ADD: f64 = 4.0;
+/// Spectacle vertex distance, mm
+const VERTEX_MM: f64 = 12.0;
+/// Below this power the vertex change is less than a 0.25 D step
+const VERTEX_MIN_POWER: f64 = 4.0;
+
+/// Final refraction of one eye
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EyeRx {
+    pub sphere: Option<f64>,
+    pub cylinder: Option<f64>,
+    pub axis: Option<i32>,
+    pub add: Option<f64>,
+}
+
+impl EyeRx {
+    pub fn is_empty(&self) -> bool {
+        self.sphere.is_none() && self.cylinder.is_none() && self.add.is_none()
+    }
+}
+
+/// Contact lens fit of one eye
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ContactLensFit {
+    /// Base curve, mm
+    pub base_curve: Option<f64>,
+    /// Diameter, mm
+    pub diameter: Option<f64>,
+    pub brand: Option<String>,
+}
+
+/// Spectacle power at the corneal plane, rounded to 0.25 D
+pub fn vertex_power(power: f64) -> f64 {
+    if power.abs() < VERTEX_MIN_POWER {
+        return round_quarter(power);
+    }
+    round_quarter(vertex_adjust(power, VERTEX_MM))
+}
+
+/// Contact lens power of one eye: both principal meridians are converted
+pub fn contact_lens_rx(rx: &EyeRx) -> EyeRx {
+    if rx.is_empty() {
+        return *rx;
+    }
+    let sphere = rx.sphere.unwrap_or(0.0);
+    let first = vertex_power(sphere);
+    let second = vertex_power(sphere + rx.cylinder.unwrap_or(0.0));
+    EyeRx {
+        sphere: Some(first),
+        cylinder: rx.cylinder.map(|_| second - first),
+        axis: rx.axis,
+        add: rx.add,
+    }
+}
+
+/// A contact lens needs base curve and diameter for every corrected eye
+pub fn validate_fit(side: &str, rx: &EyeRx, fit: Option<&ContactLensFit>) -> Result<(), String> {
+    if rx.is_empty() {
+        return Ok(());
+    }
+    let fit = fit.ok_or_else(|| format!("{}: indique curva base y diámetro del lente de contacto", side))?;
+    match fit.base_curve {
+        Some(bc) if (7.0..=10.0).contains(&bc) => {}
+        _ => return Err(format!("{}: la curva base debe estar entre 7.00 y 10.00 mm", side)),
+    }
+    match fit.diameter {
+        Some(dia) if (8.0..=16.0).contains(&dia) => {}
+        _ => return Err(format!("{}: el diámetro debe estar entre 8.00 y 16.00 mm", side)),
+    }
+    Ok(())
+}
+
+fn format_fit(fit: &ContactLensFit) -> String {
+    let mut parts = Vec::new();
+    if let Some(bc) = fit.base_curve {
+        parts.push(format!("CB {:.2}", bc));
+    }
+    if let Some(dia) = fit.diameter {
+        parts.push(format!("Diám {:.2}", dia));
+    }
+    if let Some(brand) = fit.brand.as_deref().filter(|b| !b.trim().is_empty()) {
+        parts.push(brand.trim().to_string());
+    }
+    parts.join("   ")
+}
+
+fn is_quarter_step(value: f64) -> bool {
+    let steps = value / DIOPTER_STEP;
+    (steps - steps.round()).abs() < 1e-6
+}
+
+pub fn validate_kind(kind: &str) -> Result<(), String> {
+    if PRESCRIPTION_KINDS.contains(&kind) {
+        Ok(())
+    } else {
+        Err(format!("Tipo de receta no válido: {}", kind))
+    }
+}
+
+/// Validate one eye; `side` ("OD"/"OI") prefixes the error message
+pub fn validate_eye(side: &str, rx: &EyeRx) -> Result<(), String> {
+    if let Some(sphere) = rx.sphere {
+        if sphere.abs() > MAX_SPHERE || !is_quarter_step(sphere) {
+            return Err(format!("{}: la esfera debe estar entre ±{:.2} en pasos de 0.25", side, MAX_SPHERE));
+        }
+    }
+    if let Some(cylinder) = rx.cylinder {
+        if cylinder.abs() > MAX_CYLINDER || !is_quarter_step(cylinder) {
+            return Err(format!("{}: el cilindro debe estar entre ±{:.2} en pasos de 0.25", side, MAX_CYLINDER));
+        }
+        if cylinder != 0.0 && rx.axis.is_none() {
+            return Err(format!("{}: falta el eje del cilindro", side));
+        }
+    }
+    if let Some(axis) = rx.axis {
+        if !(0..=180).contains(&axis) {
+            return Err(format!("{}: el eje debe estar entre 0 y 180", side));
+        }
+    }
+    if let Some(add) = rx.add {
+        if add <= 0.0 || add > MAX_ADD || !is_quarter_step(add) {
+            return Err(format!("{}: la adición debe estar entre +0.25 y +{:.2} en pasos de 0.25", side, MAX_ADD));
+        }
+    }
+    Ok(())
+}
+
+/// Signed power with two decimals, e.g. "+1.25" or "-0.50"
+pub fn format_diopters(value: f64) -> String {
+    if value.abs() < 1e-9 {
+        "0.00".to_string()
+    } else {
+        format!("{:+.2}", value)
+    }
+}
+
+/// One eye as printed on the prescription
+pub fn format_eye(rx: &EyeRx) -> String {
+    if rx.is_empty() {
+        return "Sin corrección".to_string();
+    }
+    let mut parts = vec![match rx.sphere {
+        Some(s) if s.abs() >= 1e-9 => format!("Esf {}", format_diopters(s)),
+        _ => "Esf Plano".to_string(),
+    }];
+    if let Some(cylinder) = rx.cylinder.filter(|c| c.abs() >= 1e-9) {
+        parts.push(format!("Cil {}", format_diopters(cylinder)));
+        if let Some(axis) = rx.axis {
+            parts.push(format!("Eje {}°", axis));
+        }
+    }
+    if let Some(add) = rx.add {
+        parts.push(format!("Add {}", format_diopters(add)));
+    }
+    parts.join("   ")
+}
+
+pub fn title(kind: &str) -> &'static str {
+    match kind {
+        "contacto" => "RECETA DE LENTES DE CONTACTO",
+        _ => "RECETA DE LENTES",
+    }
+}
+
+/// Data printed on a prescription
+pub struct PrescriptionDocument<'a> {
+    pub kind: &'a str,
+    pub patient_name: &'a str,
+    pub patient_code: Option<&'a str>,
+    pub date: &'a str,
+    pub doctor_name: Option<&'a str>,
+    pub od: EyeRx,
+    pub os: EyeRx,
+    /// Contact lens fit ('contacto' only)
+    pub od_fit: Option<&'a ContactLensFit>,
+    pub os_fit: Option<&'a ContactLensFit>,
+    pub pupillary_distance: Option<f64>,
+    pub notes: Option<&'a str>,
+}
+
+/// Body lines for `pdf::text_document`
+pub fn document_lines(doc: &PrescriptionDocument) -> Vec<String> {
+    let mut lines = vec![
+        format!("Paciente: {}", doc.patient_name),
+        format!("Expediente: {}", doc.patient_code.unwrap_or("-")),
+        format!("Fecha: {}", doc.date),
+        String::new(),
+    ];
+    for (side, rx, fit) in [("OD", &doc.od, doc.od_fit), ("OI", &doc.os, doc.os_fit)] {
+        lines.push(format!("{}: {}", side, format_eye(rx)));
+        if let Some(fit) = fit.filter(|_| !rx.is_empty()) {
+            lines.push(format!("    {}", format_fit(fit)));
+        }
+    }
+    if let Some(pd) = doc.pupillary_distance {
+        lines.push(format!("Distancia pupilar: {:.1} mm", pd));
+    }
+    if let Some(notes) = doc.notes.filter(|n| !n.trim().is_empty()) {
+        lines.push(String::new());
+        lines.extend(notes.lines().map(|l| l.to_string()));
+    }
+    lines.push(String::new());
+    lines.push(String::new());
+    lines.push("_______________________________".to_string());
+    lines.push(doc.doctor_name.unwrap_or("Médico tratante").to_string());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_eye() {
+        let rx = EyeRx { sphere: Some(-2.25), cylinder: Some(-0.75), axis: Some(180), add: Some(2.0) };
+        assert!(validate_eye("OD", &rx).is_ok());
+        assert!(validate_eye("OD", &EyeRx { axis: Some(181), ..rx }).is_err());
+        assert!(validate_eye("OD", &EyeRx { cylinder: Some(-0.30), ..rx }).is_err());
+        assert!(validate_eye("OD", &EyeRx { axis: None, ..rx }).is_err());
+        assert!(validate_eye("OI", &EyeRx { add: Some(4.5), ..rx }).is_err());
+        assert!(validate_eye("OI", &EyeRx { sphere: Some(1.0), ..Default::default() }).is_ok());
+        assert!(validate_kind("monoculo").is_err());
+    }
+
+    #[test]
+    fn test_format_eye() {
+        let rx = EyeRx { sphere: Some(0.0), cylinder: Some(-1.5), axis: Some(90), add: None };
+        assert_eq!(format_eye(&rx), "Esf Plano   Cil -1.50   Eje 90°");
+        let rx = EyeRx { sphere: Some(1.25), cylinder: None, axis: None, add: Some(2.5) };
+        assert_eq!(format_eye(&rx), "Esf +1.25   Add +2.50");
+        assert_eq!(format_eye(&EyeRx::default()), "Sin corrección");
+    }
+
+    #[test]
+    fn test_contact_lens_rx() {
+        // -6.00 / 0.012 m -> -5.60 -> -5.50; -6.00 -1.00 meridian -7.00 -> -6.46 -> -6.50
+        let rx = EyeRx { sphere: Some(-6.0), cylinder: Some(-1.0), axis: Some(180), add: None };
+        assert_eq!(contact_lens_rx(&rx), EyeRx { sphere: Some(-5.5), cylinder: Some(-1.0), axis: Some(180), add: None });
+        // +8.00 -> +8.85 -> +8.75; low powers are unchanged
+        assert_eq!(vertex_power(8.0), 8.75);
+        assert_eq!(vertex_power(-3.75), -3.75);
+        // At ±4.00 the conversion applies (+4.20 -> +4.25, -3.82 -> -3.75);
+        // just below it the power is only rounded
+        assert_eq!(vertex_power(4.0), 4.25);
+        assert_eq!(vertex_power(-4.0), -3.75);
+        assert_eq!(vertex_power(3.9), 4.0);
+        assert_eq!(vertex_power(-3.99), -4.0);
+
+        let fit = ContactLensFit { base_curve: Some(8.6), diameter: Some(14.2), brand: None };
+        assert!(validate_fit("OD", &rx, Some(&fit)).is_ok());
+        assert!(validate_fit("OD", &rx, None).is_err());
+        assert!(validate_fit("OD", &rx, Some(&ContactLensFit { base_curve: Some(12.0), ..fit })).is_err());
+        assert!(validate_fit("OI", &EyeRx::default(), None).is_ok());
+    }
+}
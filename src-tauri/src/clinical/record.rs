@@ -0,0 +1,57 @@
+// Signed medical record rules
+//
+// An encounter is editable until the doctor signs it. Signing stores a
+// SHA-256 hash of its content (encounter text, exam of both eyes and
+// diagnoses); from then on the content changes only through amendments,
+// which keep the previous and new content, the author, the reason and the
+// new hash. Recomputing the hash of the current content detects changes
+// made outside the application.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Shortest reason accepted for an amendment
+pub const MIN_AMENDMENT_REASON_LEN: usize = 10;
+
+/// Hex SHA-256 of the JSON serialization of a record's content
+pub fn content_hash<T: Serialize>(content: &T) -> Result<String, String> {
+    let json = serde_json::to_vec(content).map_err(|e| e.to_string())?;
+    Ok(Sha256::digest(&json).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// An amendment must explain why the signed content changes
+pub fn validate_amendment_reason(reason: &str) -> Result<(), String> {
+    if reason.trim().chars().count() < MIN_AMENDMENT_REASON_LEN {
+        return Err(format!(
+            "Indique el motivo de la enmienda (mínimo {} caracteres)",
+            MIN_AMENDMENT_REASON_LEN
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Content {
+        plan: Option<String>,
+        iop: Option<f64>,
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let original = Content { plan: Some("Control en 3 meses".into()), iop: Some(18.0) };
+        let hash = content_hash(&original).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, content_hash(&Content { plan: Some("Control en 3 meses".into()), iop: Some(18.0) }).unwrap());
+        assert_ne!(hash, content_hash(&Content { plan: Some("Control en 3 meses".into()), iop: Some(21.0) }).unwrap());
+    }
+
+    #[test]
+    fn test_amendment_reason() {
+        assert!(validate_amendment_reason("  error  ").is_err());
+        assert!(validate_amendment_reason("PIO transcrita en el ojo equivocado").is_ok());
+    }
+}
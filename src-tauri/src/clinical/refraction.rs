@@ -0,0 +1,163 @@
+// Refraction arithmetic: cylinder transposition, spherical equivalent,
+// vertex distance correction and anisometropia
+//
+// Powers are in diopters and axes in degrees (1-180). A sphero-cylinder is
+// the same lens whether written in minus or plus cylinder form; the clinic
+// records minus cylinder, while some lensometers and surgeons use plus.
+// Contact lens powers are derived from the spectacle Rx by moving each
+// principal meridian from the vertex distance to the cornea.
+
+use serde::{Deserialize, Serialize};
+
+/// Usual vertex distance of spectacles, in millimetres
+pub const DEFAULT_VERTEX_DISTANCE_MM: f64 = 12.0;
+
+/// Spherical equivalent difference between eyes considered anisometropia
+pub const ANISOMETROPIA_THRESHOLD: f64 = 1.0;
+
+/// Sphero-cylindrical power of one eye
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Refraction {
+    pub sphere: f64,
+    pub cylinder: f64,
+    pub axis: i32,
+}
+
+/// Values derived from a refraction
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RefractionSummary {
+    pub minus_cylinder: Refraction,
+    pub plus_cylinder: Refraction,
+    pub spherical_equivalent: f64,
+    pub vertex_distance_mm: f64,
+    /// Power at the corneal plane, in minus cylinder form
+    pub contact_lens: Refraction,
+}
+
+/// Round to the nearest 0.25 D
+pub fn round_quarter(value: f64) -> f64 {
+    let rounded = (value * 4.0).round() / 4.0;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+/// Axis in 1-180 (0 and 180 are the same meridian)
+fn normalize_axis(axis: i32) -> i32 {
+    match axis.rem_euclid(180) {
+        0 => 180,
+        a => a,
+    }
+}
+
+impl Refraction {
+    /// Build from exam fields; None when neither sphere nor cylinder was recorded
+    pub fn from_parts(sphere: Option<f64>, cylinder: Option<f64>, axis: Option<i32>) -> Option<Self> {
+        if sphere.is_none() && cylinder.is_none() {
+            return None;
+        }
+        let cylinder = cylinder.unwrap_or(0.0);
+        Some(Refraction {
+            sphere: sphere.unwrap_or(0.0),
+            cylinder,
+            axis: if cylinder == 0.0 { axis.unwrap_or(180) } else { axis.unwrap_or(0) },
+        })
+    }
+
+    /// Same lens written with the opposite cylinder sign
+    pub fn transpose(&self) -> Self {
+        if self.cylinder == 0.0 {
+            return *self;
+        }
+        Refraction {
+            sphere: self.sphere + self.cylinder,
+            cylinder: -self.cylinder,
+            axis: normalize_axis(self.axis + 90),
+        }
+    }
+
+    pub fn to_minus_cylinder(&self) -> Self {
+        if self.cylinder > 0.0 { self.transpose() } else { *self }
+    }
+
+    pub fn to_plus_cylinder(&self) -> Self {
+        if self.cylinder < 0.0 { self.transpose() } else { *self }
+    }
+
+    pub fn spherical_equivalent(&self) -> f64 {
+        self.sphere + self.cylinder / 2.0
+    }
+
+    /// Power needed at `distance_mm` closer to the eye (positive) or
+    /// farther away (negative), rounded to 0.25 D per meridian
+    pub fn vertex_adjusted(&self, distance_mm: f64) -> Self {
+        let rx = self.to_minus_cylinder();
+        let first = round_quarter(vertex_adjust(rx.sphere, distance_mm));
+        let second = round_quarter(vertex_adjust(rx.sphere + rx.cylinder, distance_mm));
+        Refraction {
+            sphere: first,
+            cylinder: round_quarter(second - first),
+            axis: rx.axis,
+        }
+    }
+
+    pub fn summary(&self, vertex_distance_mm: f64) -> RefractionSummary {
+        RefractionSummary {
+            minus_cylinder: self.to_minus_cylinder(),
+            plus_cylinder: self.to_plus_cylinder(),
+            spherical_equivalent: self.spherical_equivalent(),
+            vertex_distance_mm,
+            contact_lens: self.vertex_adjusted(vertex_distance_mm),
+        }
+    }
+}
+
+/// Effective power of a lens `power` moved `distance_mm` toward the eye:
+/// F' = F / (1 - d·F), with d in metres
+pub fn vertex_adjust(power: f64, distance_mm: f64) -> f64 {
+    let denominator = 1.0 - distance_mm / 1000.0 * power;
+    if denominator.abs() < 1e-9 { power } else { power / denominator }
+}
+
+/// Absolute spherical equivalent difference between both eyes
+pub fn anisometropia(od: &Refraction, os: &Refraction) -> f64 {
+    (od.spherical_equivalent() - os.spherical_equivalent()).abs()
+}
+
+pub fn is_anisometropic(od: &Refraction, os: &Refraction) -> bool {
+    anisometropia(od, os) >= ANISOMETROPIA_THRESHOLD - 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpose_and_spherical_equivalent() {
+        let rx = Refraction { sphere: -2.00, cylinder: -1.50, axis: 180 };
+        let plus = rx.transpose();
+        assert_eq!(plus, Refraction { sphere: -3.50, cylinder: 1.50, axis: 90 });
+        assert_eq!(plus.transpose(), rx);
+        assert_eq!(plus.to_minus_cylinder(), rx);
+        assert_eq!(Refraction { sphere: 1.0, cylinder: -0.5, axis: 135 }.transpose().axis, 45);
+        assert_eq!(rx.spherical_equivalent(), -2.75);
+        assert_eq!(plus.spherical_equivalent(), -2.75);
+        assert_eq!(Refraction::from_parts(None, None, Some(90)), None);
+    }
+
+    #[test]
+    fn test_vertex_and_anisometropia() {
+        // -8.00 at 12 mm is -7.30 at the cornea; +8.00 becomes +8.85
+        assert_eq!(round_quarter(vertex_adjust(-8.0, 12.0)), -7.25);
+        assert_eq!(round_quarter(vertex_adjust(8.0, 12.0)), 8.75);
+        let rx = Refraction { sphere: -6.00, cylinder: -2.00, axis: 10 };
+        let cl = rx.vertex_adjusted(DEFAULT_VERTEX_DISTANCE_MM);
+        assert_eq!(cl, Refraction { sphere: -5.50, cylinder: -1.75, axis: 10 });
+        // Low powers are unchanged
+        let low = Refraction { sphere: -1.25, cylinder: -0.50, axis: 90 };
+        assert_eq!(low.vertex_adjusted(DEFAULT_VERTEX_DISTANCE_MM), low);
+
+        let os = Refraction { sphere: -3.00, cylinder: 0.0, axis: 180 };
+        assert_eq!(anisometropia(&low, &os), 1.50);
+        assert!(is_anisometropic(&low, &os));
+        assert!(!is_anisometropic(&low, &Refraction { sphere: -2.0, ..os }));
+    }
+}
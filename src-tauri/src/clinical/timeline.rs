@@ -0,0 +1,74 @@
+// Per-eye clinical timeline helpers
+//
+// Records name the eye in several ways: exams, surgeries and procedures use
+// the eye_side values OD/OI/OU, diagnoses free text (sometimes OS for the
+// left eye). A record with no eye or for both eyes belongs to either eye's
+// timeline. The IOP trend is the least squares slope over the measurements,
+// in mmHg per year.
+
+use chrono::NaiveDate;
+
+/// Canonical eye ('OD' or 'OI', as in exam_eye.side) of a requested side
+pub fn normalize_eye(eye: &str) -> Result<&'static str, String> {
+    match eye.trim().to_uppercase().as_str() {
+        "OD" => Ok("OD"),
+        "OI" | "OS" => Ok("OI"),
+        _ => Err(format!("Ojo no válido: {} (use OD u OI)", eye)),
+    }
+}
+
+/// Whether a record for `record_eye` belongs to the timeline of `eye`
+pub fn applies_to_eye(record_eye: Option<&str>, eye: &str) -> bool {
+    match record_eye.map(|e| e.trim().to_uppercase()) {
+        None => true,
+        Some(e) if e.is_empty() || e == "OU" || e == "AO" || e == "AMBOS" => true,
+        Some(e) => normalize_eye(&e).map(|e| e == eye).unwrap_or(false),
+    }
+}
+
+/// Least squares slope of the values in units per year; None with fewer
+/// than two measurements or all on the same day
+pub fn trend_per_year(points: &[(NaiveDate, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let origin = points.iter().map(|(d, _)| *d).min()?;
+    let xs: Vec<f64> = points.iter().map(|(d, _)| (*d - origin).num_days() as f64 / 365.25).collect();
+    let n = points.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, v)| v).sum::<f64>() / n;
+
+    let sxx: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    if sxx < 1e-12 {
+        return None;
+    }
+    let sxy: f64 = xs.iter().zip(points).map(|(x, (_, y))| (x - mean_x) * (y - mean_y)).sum();
+    Some((sxy / sxx * 100.0).round() / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eye_matching() {
+        assert_eq!(normalize_eye("oi").unwrap(), "OI");
+        assert_eq!(normalize_eye("OS").unwrap(), "OI");
+        assert!(normalize_eye("OU").is_err());
+        assert!(applies_to_eye(Some("OI"), "OI"));
+        assert!(applies_to_eye(Some("os"), "OI"));
+        assert!(applies_to_eye(Some("OU"), "OD"));
+        assert!(applies_to_eye(None, "OD"));
+        assert!(!applies_to_eye(Some("OD"), "OI"));
+    }
+
+    #[test]
+    fn test_trend_per_year() {
+        let d = |y, m, day| NaiveDate::from_ymd_opt(y, m, day).unwrap();
+        let points = [(d(2022, 1, 1), 18.0), (d(2023, 1, 1), 20.0), (d(2024, 1, 1), 22.0)];
+        let trend = trend_per_year(&points).unwrap();
+        assert!((trend - 2.0).abs() < 0.01);
+        assert_eq!(trend_per_year(&points[..1]), None);
+        assert_eq!(trend_per_year(&[(d(2024, 1, 1), 15.0), (d(2024, 1, 1), 17.0)]), None);
+    }
+}
@@ -1,7 +1,11 @@
 use crate::db::Database;
 use crate::AppState;
+use crate::billing::CurrencyTotals;
+use crate::billing::cash::DenominationCount;
+use crate::clinical::prescription::ContactLensFit;
 use crate::connection_manager::ConnectionStatus;
 use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
 use tauri::{State, AppHandle, Manager};
 use std::sync::Arc;
 
@@ -480,15 +484,31 @@ pub async fn get_patient_by_id(
     db: State<'_, Arc<Database>>,
     app_state: State<'_, Arc<AppState>>,
     id: String,
+    purpose: Option<String>,
 ) -> Result<Option<Patient>, String> {
     // Check if we should use local PostgreSQL
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_patient_by_id: Using local PostgreSQL");
-        return pool.get_patient_by_id(&id).await;
+        let patient = pool.get_patient_by_id(&id).await?;
+        if patient.is_some() {
+            pool.record_patient_access(&id, "patient", purpose.as_deref()).await;
+        }
+        return Ok(patient);
     }
 
     // Fallback to SQLite cache
     log::info!("get_patient_by_id: Using SQLite cache");
+    let patient = cached_patient_by_id(&db, &id)?;
+    if patient.is_some() {
+        if let Err(e) = db.add_patient_access(&id, "patient", purpose.as_deref()) {
+            log::warn!("Failed to record access to patient {}: {}", id, e);
+        }
+    }
+    Ok(patient)
+}
+
+/// Patient from the SQLite cache, without recording an access
+fn cached_patient_by_id(db: &Database, id: &str) -> Result<Option<Patient>, String> {
     let conn = db.conn.lock().map_err(|e| e.to_string())?;
 
     let mut stmt = conn
@@ -499,7 +519,7 @@ pub async fn get_patient_by_id(
         )
         .map_err(|e| e.to_string())?;
 
-    let result = stmt.query_row([&id], |row| {
+    let result = stmt.query_row([id], |row| {
         Ok(Patient {
             id: row.get(0)?,
             code: row.get(1)?,
@@ -923,6 +943,8 @@ pub async fn create_patient(
     let patient_json = serde_json::to_string(&new_patient).map_err(|e| e.to_string())?;
     db.add_to_sync_queue("patients", &id, "INSERT", &patient_json)
         .map_err(|e| e.to_string())?;
+    db.add_audit_log("patients", &id, Some(&id), "INSERT", None, Some(&patient_json))
+        .map_err(|e| e.to_string())?;
 
     log::info!("Created patient {} locally, added to sync queue", id);
 
@@ -949,6 +971,10 @@ pub async fn update_patient(
     // Fallback to SQLite (with sync queue)
     log::info!("update_patient: Using SQLite with sync queue");
     let now = chrono::Utc::now().to_rfc3339();
+    let before = cached_patient_by_id(&db, &id)?
+        .map(|p| serde_json::to_string(&p))
+        .transpose()
+        .map_err(|e| e.to_string())?;
 
     {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
@@ -1019,14 +1045,15 @@ pub async fn update_patient(
     }
 
     // Get updated patient
-    let patient = get_patient_by_id(db.clone(), app_state.clone(), id.clone())
-        .await?
+    let patient = cached_patient_by_id(&db, &id)?
         .ok_or("Patient not found after update")?;
 
     // Add to sync queue
     let patient_json = serde_json::to_string(&patient).map_err(|e| e.to_string())?;
     db.add_to_sync_queue("patients", &id, "UPDATE", &patient_json)
         .map_err(|e| e.to_string())?;
+    db.add_audit_log("patients", &id, Some(&id), "UPDATE", before.as_deref(), Some(&patient_json))
+        .map_err(|e| e.to_string())?;
 
     log::info!("Updated patient {} locally, added to sync queue", id);
 
@@ -1051,6 +1078,10 @@ pub async fn delete_patient(
 
     // Fallback to SQLite (with sync queue)
     log::info!("delete_patient: Using SQLite with sync queue");
+    let before = cached_patient_by_id(&db, &id)?
+        .map(|p| serde_json::to_string(&p))
+        .transpose()
+        .map_err(|e| e.to_string())?;
 
     {
         let conn = db.conn.lock().map_err(|e| e.to_string())?;
@@ -1061,6 +1092,8 @@ pub async fn delete_patient(
     // Add to sync queue
     db.add_to_sync_queue("patients", &id, "DELETE", "{}")
         .map_err(|e| e.to_string())?;
+    db.add_audit_log("patients", &id, Some(&id), "DELETE", before.as_deref(), None)
+        .map_err(|e| e.to_string())?;
 
     log::info!("Deleted patient {} locally, added to sync queue", id);
 
@@ -1443,6 +1476,9 @@ pub struct Encounter {
     pub proxima_cita: Option<String>,
     pub excursiones_od: Option<String>,
     pub excursiones_os: Option<String>,
+    /// Set when the doctor signs; the encounter is then locked
+    pub signed_at: Option<String>,
+    pub signed_by: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patient: Option<PatientEmbed>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1456,6 +1492,9 @@ pub struct EncounterInput {
     pub doctor_id: Option<String>,
     #[serde(rename = "type")]
     pub encounter_type: String,
+    /// Encounter template to pre-fill text, exam, diagnoses and study orders
+    #[serde(default)]
+    pub template_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1478,10 +1517,15 @@ pub struct EncounterUpdate {
 pub async fn get_encounter_by_id(
     app_state: State<'_, Arc<AppState>>,
     id: String,
+    purpose: Option<String>,
 ) -> Result<Option<Encounter>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_encounter_by_id: Using local PostgreSQL");
-        return pool.get_encounter_by_id(&id).await;
+        let encounter = pool.get_encounter_by_id(&id).await?;
+        if let Some(enc) = &encounter {
+            pool.record_patient_access(&enc.patient_id, "encounter", purpose.as_deref()).await;
+        }
+        return Ok(encounter);
     }
     Err("No database connection available".to_string())
 }
@@ -1490,10 +1534,13 @@ pub async fn get_encounter_by_id(
 pub async fn get_encounters_by_patient(
     app_state: State<'_, Arc<AppState>>,
     patient_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<Encounter>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_encounters_by_patient: Using local PostgreSQL");
-        return pool.get_encounters_by_patient(&patient_id).await;
+        let result = pool.get_encounters_by_patient(&patient_id).await?;
+        pool.record_patient_access(&patient_id, "encounters", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -1502,10 +1549,15 @@ pub async fn get_encounters_by_patient(
 pub async fn get_encounter_by_appointment(
     app_state: State<'_, Arc<AppState>>,
     appointment_id: String,
+    purpose: Option<String>,
 ) -> Result<Option<Encounter>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_encounter_by_appointment: Using local PostgreSQL");
-        return pool.get_encounter_by_appointment(&appointment_id).await;
+        let encounter = pool.get_encounter_by_appointment(&appointment_id).await?;
+        if let Some(enc) = &encounter {
+            pool.record_patient_access(&enc.patient_id, "encounter", purpose.as_deref()).await;
+        }
+        return Ok(encounter);
     }
     Err("No database connection available".to_string())
 }
@@ -1547,6 +1599,9 @@ pub struct ExamEye {
     // Agudeza Visual
     pub av_sc: Option<String>,
     pub av_cc: Option<String>,
+    /// logMAR of av_sc / av_cc (None when the text is not recognized)
+    pub av_sc_logmar: Option<f64>,
+    pub av_cc_logmar: Option<f64>,
     // Refracción Objetiva
     pub ref_sphere: Option<f64>,
     pub ref_cyl: Option<f64>,
@@ -1598,10 +1653,13 @@ pub async fn get_exam_eye(
     app_state: State<'_, Arc<AppState>>,
     encounter_id: String,
     side: String,
+    purpose: Option<String>,
 ) -> Result<Option<ExamEye>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_exam_eye: Using local PostgreSQL");
-        return pool.get_exam_eye(&encounter_id, &side).await;
+        let result = pool.get_exam_eye(&encounter_id, &side).await?;
+        pool.record_encounter_access(&encounter_id, "exam", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -1610,10 +1668,13 @@ pub async fn get_exam_eye(
 pub async fn get_exam_eyes_by_encounter(
     app_state: State<'_, Arc<AppState>>,
     encounter_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<ExamEye>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_exam_eyes_by_encounter: Using local PostgreSQL");
-        return pool.get_exam_eyes_by_encounter(&encounter_id).await;
+        let result = pool.get_exam_eyes_by_encounter(&encounter_id).await?;
+        pool.record_encounter_access(&encounter_id, "exam", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -1630,6 +1691,18 @@ pub async fn upsert_exam_eye(
     Err("No database connection available".to_string())
 }
 
+/// Recompute stored logMAR values from the acuity text of all exams
+#[tauri::command]
+pub async fn recalculate_exam_logmar(
+    app_state: State<'_, Arc<AppState>>,
+) -> Result<u64, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("recalculate_exam_logmar: Using local PostgreSQL");
+        return pool.recalculate_exam_logmar().await;
+    }
+    Err("No database connection available".to_string())
+}
+
 // ============================================================
 // STUDIES (ESTUDIOS) - TYPES
 // ============================================================
@@ -1695,10 +1768,13 @@ pub async fn get_studies_by_appointment(
 pub async fn get_studies_by_patient(
     app_state: State<'_, Arc<AppState>>,
     patient_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<Study>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_studies_by_patient: Using local PostgreSQL");
-        return pool.get_studies_by_patient(&patient_id).await;
+        let result = pool.get_studies_by_patient(&patient_id).await?;
+        pool.record_patient_access(&patient_id, "studies", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -1752,6 +1828,12 @@ pub struct Surgery {
     pub status: String,
     pub surgeon_id: Option<String>,
     pub notes: Option<String>,
+    // Planned intraocular lens (cataract)
+    pub biometry_id: Option<String>,
+    pub iol_model_id: Option<String>,
+    pub iol_power: Option<f64>,
+    pub iol_formula: Option<String>,
+    pub iol_target_refraction: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub surgery_files: Option<Vec<SurgeryFile>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1842,10 +1924,13 @@ pub async fn get_surgeries_by_appointment(
 pub async fn get_surgeries_by_patient(
     app_state: State<'_, Arc<AppState>>,
     patient_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<Surgery>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_surgeries_by_patient: Using local PostgreSQL");
-        return pool.get_surgeries_by_patient(&patient_id).await;
+        let result = pool.get_surgeries_by_patient(&patient_id).await?;
+        pool.record_patient_access(&patient_id, "surgeries", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -1943,10 +2028,13 @@ pub async fn get_procedures_by_appointment(
 pub async fn get_procedures_by_patient(
     app_state: State<'_, Arc<AppState>>,
     patient_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<Procedure>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_procedures_by_patient: Using local PostgreSQL");
-        return pool.get_procedures_by_patient(&patient_id).await;
+        let result = pool.get_procedures_by_patient(&patient_id).await?;
+        pool.record_patient_access(&patient_id, "procedures", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -2015,10 +2103,13 @@ pub struct DiagnosisUpdate {
 pub async fn get_diagnoses_by_encounter(
     app_state: State<'_, Arc<AppState>>,
     encounter_id: String,
+    purpose: Option<String>,
 ) -> Result<Vec<Diagnosis>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_diagnoses_by_encounter: Using local PostgreSQL");
-        return pool.get_diagnoses_by_encounter(&encounter_id).await;
+        let result = pool.get_diagnoses_by_encounter(&encounter_id).await?;
+        pool.record_encounter_access(&encounter_id, "diagnoses", purpose.as_deref()).await;
+        return Ok(result);
     }
     Err("No database connection available".to_string())
 }
@@ -2071,11 +2162,17 @@ pub struct Invoice {
     pub patient_id: String,
     pub appointment_id: Option<String>,
     pub branch_id: String,
-    pub total_amount: f64,
-    pub balance_due: f64,
+    pub total_amount: Decimal,
+    pub balance_due: Decimal,
     pub discount_type: Option<String>,
-    pub discount_value: Option<f64>,
+    pub discount_value: Option<Decimal>,
     pub discount_reason: Option<String>,
+    /// Amount subject to IVA, without the tax
+    pub taxable_amount: Decimal,
+    /// Amount of tax-exempt lines
+    pub exempt_amount: Decimal,
+    /// IVA included in total_amount
+    pub tax_amount: Decimal,
     pub status: String,
     pub notes: Option<String>,
     pub created_at: String,
@@ -2091,8 +2188,13 @@ pub struct InvoiceItem {
     pub product_id: Option<String>,
     pub description: String,
     pub quantity: i32,
-    pub unit_price: f64,
-    pub subtotal: f64,
+    pub unit_price: Decimal,
+    pub subtotal: Decimal,
+    /// Share of the invoice discount applied to this line
+    pub discount_amount: Decimal,
+    pub tax_rate: Decimal,
+    pub tax_exempt: bool,
+    pub tax_amount: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -2101,7 +2203,7 @@ pub struct InvoiceInput {
     pub appointment_id: Option<String>,
     pub branch_id: String,
     pub discount_type: Option<String>,
-    pub discount_value: Option<f64>,
+    pub discount_value: Option<Decimal>,
     pub discount_reason: Option<String>,
     pub notes: Option<String>,
 }
@@ -2112,7 +2214,7 @@ pub struct InvoiceItemInput {
     pub product_id: Option<String>,
     pub description: String,
     pub quantity: i32,
-    pub unit_price: f64,
+    pub unit_price: Decimal,
 }
 
 // ============================================================
@@ -2227,7 +2329,11 @@ pub async fn get_pending_invoices_by_branch(
 pub struct Payment {
     pub id: String,
     pub invoice_id: String,
-    pub amount: f64,
+    pub amount: Decimal,
+    pub currency: String,
+    pub exchange_rate: Decimal,
+    /// Amount applied to the invoice balance, in the base currency
+    pub amount_base: Decimal,
     pub payment_method: String,
     pub date: String,
     pub created_at: String,
@@ -2240,8 +2346,8 @@ pub struct InvoiceWithPatient {
     pub id: String,
     pub invoice_number: String,
     pub patient_id: String,
-    pub total_amount: f64,
-    pub balance_due: f64,
+    pub total_amount: Decimal,
+    pub balance_due: Decimal,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patient: Option<PatientEmbed>,
 }
@@ -2249,8 +2355,14 @@ pub struct InvoiceWithPatient {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PaymentInput {
     pub invoice_id: String,
-    pub amount: f64,
+    pub amount: Decimal,
     pub payment_method: String,
+    /// Defaults to the base currency (GTQ) when omitted
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// Open cash register session that received the payment
+    #[serde(default)]
+    pub cash_session_id: Option<String>,
 }
 
 // ============================================================
@@ -2307,6 +2419,112 @@ pub async fn delete_payment(
     Err("No database connection available".to_string())
 }
 
+// ============================================================
+// ELECTRONIC INVOICING (FEL) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FelDocument {
+    pub id: String,
+    pub invoice_id: String,
+    /// certificado | anulado
+    pub status: String,
+    pub authorization_uuid: String,
+    pub serie: String,
+    pub numero: String,
+    pub receiver_nit: String,
+    pub receiver_name: String,
+    pub certifier: String,
+    pub certified_at: String,
+    pub cancelled_at: Option<String>,
+    pub cancellation_reason: Option<String>,
+    pub has_pdf: bool,
+    pub created_at: String,
+}
+
+// ============================================================
+// COMMANDS - ELECTRONIC INVOICING (FEL)
+// ============================================================
+
+#[tauri::command]
+pub async fn certify_invoice_fel(
+    app_state: State<'_, Arc<AppState>>,
+    invoice_id: String,
+    receiver_nit: Option<String>,
+    receiver_name: Option<String>,
+) -> Result<FelDocument, String> {
+    let fel_config = app_state
+        .config
+        .get_fel_config()
+        .ok_or_else(|| "Facturación electrónica no configurada".to_string())?;
+    let receiver = crate::fel::FelReceiver::new(receiver_nit.as_deref(), receiver_name.as_deref())?;
+
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("certify_invoice_fel: Using local PostgreSQL");
+        return crate::fel::certify_invoice(&pool, fel_config, &invoice_id, &receiver).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn cancel_invoice_fel(
+    app_state: State<'_, Arc<AppState>>,
+    invoice_id: String,
+    reason: String,
+) -> Result<FelDocument, String> {
+    let fel_config = app_state
+        .config
+        .get_fel_config()
+        .ok_or_else(|| "Facturación electrónica no configurada".to_string())?;
+
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("cancel_invoice_fel: Using local PostgreSQL");
+        return crate::fel::cancel_invoice(&pool, fel_config, &invoice_id, &reason).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_fel_document(
+    app_state: State<'_, Arc<AppState>>,
+    invoice_id: String,
+) -> Result<Option<FelDocument>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_fel_document: Using local PostgreSQL");
+        return pool.get_fel_document(&invoice_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Certified XML of the invoice's latest FEL document
+#[tauri::command]
+pub async fn get_fel_xml(
+    app_state: State<'_, Arc<AppState>>,
+    invoice_id: String,
+) -> Result<Option<String>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_fel_xml: Using local PostgreSQL");
+        return pool.get_fel_xml(&invoice_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// PDF of the invoice's latest FEL document, base64 encoded
+#[tauri::command]
+pub async fn get_fel_pdf(
+    app_state: State<'_, Arc<AppState>>,
+    invoice_id: String,
+) -> Result<Option<String>, String> {
+    use base64::Engine;
+
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_fel_pdf: Using local PostgreSQL");
+        let pdf = pool.get_fel_pdf(&invoice_id).await?;
+        return Ok(pdf.map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes)));
+    }
+    Err("No database connection available".to_string())
+}
+
 // ============================================================
 // SERVICE PRICES (PRECIOS DE SERVICIOS) - TYPES
 // ============================================================
@@ -2316,7 +2534,11 @@ pub struct ServicePrice {
     pub id: String,
     pub service_name: String,
     pub service_type: String,
-    pub price: f64,
+    pub price: Decimal,
+    pub currency: String,
+    /// IVA rate in percent, included in the price
+    pub tax_rate: Decimal,
+    pub tax_exempt: bool,
     pub active: bool,
 }
 
@@ -2324,14 +2546,26 @@ pub struct ServicePrice {
 pub struct ServicePriceInput {
     pub service_name: String,
     pub service_type: String,
-    pub price: f64,
+    pub price: Decimal,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub tax_rate: Option<Decimal>,
+    #[serde(default)]
+    pub tax_exempt: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServicePriceUpdate {
     pub service_name: Option<String>,
     pub service_type: Option<String>,
-    pub price: Option<f64>,
+    pub price: Option<Decimal>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub tax_rate: Option<Decimal>,
+    #[serde(default)]
+    pub tax_exempt: Option<bool>,
     pub active: Option<bool>,
 }
 
@@ -2375,6 +2609,75 @@ pub async fn update_service_price(
     Err("No database connection available".to_string())
 }
 
+// ============================================================
+// EXCHANGE RATES (TIPOS DE CAMBIO) - TYPES
+// ============================================================
+
+/// Exchange rate of a foreign currency against the base currency for a given date
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExchangeRate {
+    pub id: String,
+    pub currency: String,
+    pub rate_date: String,
+    /// Units of base currency (GTQ) per unit of `currency`
+    pub rate: Decimal,
+    pub source: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExchangeRateInput {
+    pub currency: String,
+    pub rate_date: String,
+    pub rate: Decimal,
+    pub source: Option<String>,
+}
+
+// ============================================================
+// COMMANDS - EXCHANGE RATES
+// ============================================================
+
+/// Get stored exchange rates, most recent first
+#[tauri::command]
+pub async fn get_exchange_rates(
+    app_state: State<'_, Arc<AppState>>,
+    currency: Option<String>,
+    limit: Option<i64>,
+) -> Result<Vec<ExchangeRate>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_exchange_rates: Using local PostgreSQL");
+        return pool.get_exchange_rates(currency.as_deref(), limit.unwrap_or(30)).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Get the rate in effect for a currency on a date (latest rate on or before that date)
+#[tauri::command]
+pub async fn get_exchange_rate_for_date(
+    app_state: State<'_, Arc<AppState>>,
+    currency: String,
+    date: String,
+) -> Result<Option<ExchangeRate>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_exchange_rate_for_date: Using local PostgreSQL");
+        return pool.get_exchange_rate_for_date(&currency, &date).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Create or replace the exchange rate for a currency and date
+#[tauri::command]
+pub async fn upsert_exchange_rate(
+    app_state: State<'_, Arc<AppState>>,
+    rate: ExchangeRateInput,
+) -> Result<ExchangeRate, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("upsert_exchange_rate: Using local PostgreSQL");
+        return pool.upsert_exchange_rate(&rate).await;
+    }
+    Err("No database connection available".to_string())
+}
+
 // ============================================================
 // INVENTORY (INVENTARIO) - TYPES
 // ============================================================
@@ -2384,37 +2687,59 @@ pub struct InventoryItem {
     pub id: String,
     pub name: String,
     pub category: Option<String>,
-    pub cost_price: Option<f64>,
-    pub sell_price: f64,
+    pub cost_price: Option<Decimal>,
+    pub sell_price: Decimal,
+    pub currency: String,
+    /// IVA rate in percent, included in sell_price
+    pub tax_rate: Decimal,
+    pub tax_exempt: bool,
     pub supplier_id: Option<String>,
     pub branch_id: String,
     pub active: bool,
     pub current_stock: i32,
     pub reorder_level: Option<i32>,
+    /// EAN/UPC/GTIN printed on the product
+    pub barcode: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InventoryItemInput {
     pub name: String,
     pub category: Option<String>,
-    pub cost_price: Option<f64>,
-    pub sell_price: f64,
+    pub cost_price: Option<Decimal>,
+    pub sell_price: Decimal,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub tax_rate: Option<Decimal>,
+    #[serde(default)]
+    pub tax_exempt: Option<bool>,
     pub supplier_id: Option<String>,
     pub branch_id: String,
     pub current_stock: Option<i32>,
     pub reorder_level: Option<i32>,
+    #[serde(default)]
+    pub barcode: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InventoryItemUpdate {
     pub name: Option<String>,
     pub category: Option<String>,
-    pub cost_price: Option<f64>,
-    pub sell_price: Option<f64>,
+    pub cost_price: Option<Decimal>,
+    pub sell_price: Option<Decimal>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub tax_rate: Option<Decimal>,
+    #[serde(default)]
+    pub tax_exempt: Option<bool>,
     pub supplier_id: Option<String>,
     pub current_stock: Option<i32>,
     pub reorder_level: Option<i32>,
     pub active: Option<bool>,
+    #[serde(default)]
+    pub barcode: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -2506,6 +2831,7 @@ pub struct InventoryLot {
     pub expiration_date: Option<String>,
     pub quantity: f64,
     pub created_at: String,
+    pub barcode: Option<String>,
 }
 
 #[tauri::command]
@@ -2527,7 +2853,7 @@ pub struct InventoryLotWithProduct {
     pub lot_number: String,
     pub expiry_date: Option<String>,
     pub quantity: f64,
-    pub cost_price: Option<f64>,
+    pub cost_price: Option<Decimal>,
     pub created_at: String,
     pub inventory_items: Option<InventoryItemEmbed>,
 }
@@ -2538,7 +2864,9 @@ pub struct InventoryLotInput {
     pub lot_number: String,
     pub quantity: f64,
     pub expiry_date: Option<String>,
-    pub cost_price: Option<f64>,
+    pub cost_price: Option<Decimal>,
+    #[serde(default)]
+    pub barcode: Option<String>,
 }
 
 #[tauri::command]
@@ -2604,6 +2932,8 @@ pub struct InventoryMovementInput {
     pub movement_type: String,
     pub quantity: f64,
     pub reference_type: Option<String>,
+    #[serde(default)]
+    pub reference_id: Option<String>,
     pub notes: Option<String>,
 }
 
@@ -2632,6 +2962,113 @@ pub async fn create_inventory_movement(
     Err("No database connection available".to_string())
 }
 
+/// Outgoing movement that picks lots automatically (FEFO for the categories
+/// in the `fefo_categories` setting, FIFO otherwise). Returns one movement
+/// per lot consumed.
+#[tauri::command]
+pub async fn consume_inventory_stock(
+    app_state: State<'_, Arc<AppState>>,
+    movement: InventoryMovementInput,
+) -> Result<Vec<InventoryMovement>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("consume_inventory_stock: Using local PostgreSQL");
+        return pool.consume_inventory_stock(&movement).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// STOCK RECONCILIATION - TYPES
+// ============================================================
+
+/// Item whose recorded stock differs from the stock implied by its movements
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockReconciliationLine {
+    pub item_id: String,
+    pub item_name: String,
+    pub item_code: Option<String>,
+    pub category: Option<String>,
+    pub recorded_stock: f64,
+    pub ledger_stock: f64,
+    /// recorded_stock - ledger_stock
+    pub difference: f64,
+    pub movement_count: i64,
+}
+
+/// Pharmacy items whose current_stock does not match the movement ledger
+#[tauri::command]
+pub async fn get_inventory_reconciliation(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+) -> Result<Vec<StockReconciliationLine>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_inventory_reconciliation: Using local PostgreSQL");
+        return pool.get_inventory_reconciliation(&branch_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Room inventory items whose current_stock does not match the movement ledger
+#[tauri::command]
+pub async fn get_room_inventory_reconciliation(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+) -> Result<Vec<StockReconciliationLine>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_room_inventory_reconciliation: Using local PostgreSQL");
+        return pool.get_room_inventory_reconciliation(&branch_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// LOT EXPIRY - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExpiringLot {
+    pub lot_id: String,
+    pub item_id: String,
+    pub item_name: String,
+    pub item_code: Option<String>,
+    pub category: String,
+    pub lot_number: String,
+    pub expiry_date: String,
+    pub days_to_expiry: i64,
+    pub expired: bool,
+    pub quantity: f64,
+    pub cost_price: Option<Decimal>,
+}
+
+/// Lots with stock that expire within `days` (default 60), including
+/// those already expired
+#[tauri::command]
+pub async fn get_expiring_lots(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    days: Option<i32>,
+) -> Result<Vec<ExpiringLot>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_expiring_lots: Using local PostgreSQL");
+        return pool.get_expiring_lots(&branch_id, days.unwrap_or(60)).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Write off the remaining stock of every expired lot in the branch
+#[tauri::command]
+pub async fn write_off_expired_lots(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    notes: Option<String>,
+) -> Result<Vec<InventoryMovement>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("write_off_expired_lots: Using local PostgreSQL");
+        return pool.write_off_expired_lots(&branch_id, notes.as_deref()).await;
+    }
+    Err("No database connection available".to_string())
+}
+
 // ============================================================
 // CASH CLOSURE REPORTS - TYPES
 // ============================================================
@@ -2640,7 +3077,7 @@ pub async fn create_inventory_movement(
 pub struct ServiceSales {
     pub service_type: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -2648,14 +3085,14 @@ pub struct ServiceDetail {
     pub service_name: String,
     pub service_type: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InventorySales {
     pub category: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -2663,14 +3100,14 @@ pub struct InventoryDetail {
     pub category: String,
     pub product_name: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PaymentMethodSummary {
     pub payment_method: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[tauri::command]
@@ -2743,6 +3180,21 @@ pub async fn get_payment_method_summary(
     Err("No database connection available".to_string())
 }
 
+/// Get collected totals per currency and payment method (preview for cash closure)
+#[tauri::command]
+pub async fn get_payment_currency_summary(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    start_date: String,
+    end_date: String,
+) -> Result<Vec<CurrencyTotals>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_payment_currency_summary: Using local PostgreSQL");
+        return pool.get_payment_currency_summary(&branch_id, &start_date, &end_date).await;
+    }
+    Err("No database connection available".to_string())
+}
+
 #[tauri::command]
 pub async fn generate_invoice_number(
     app_state: State<'_, Arc<AppState>>,
@@ -2768,10 +3220,10 @@ pub struct ProductReportItem {
     pub category: String,
     pub supplier_name: String,
     pub quantity: f64,
-    pub unit_price: f64,
-    pub cost_price: f64,
-    pub subtotal: f64,
-    pub profit: f64,
+    pub unit_price: Decimal,
+    pub cost_price: Decimal,
+    pub subtotal: Decimal,
+    pub profit: Decimal,
 }
 
 #[tauri::command]
@@ -2800,11 +3252,14 @@ pub struct ServiceReportItem {
     pub service_name: String,
     pub service_type: String,
     pub quantity: f64,
-    pub unit_price: f64,
-    pub subtotal: f64,
+    pub unit_price: Decimal,
+    pub subtotal: Decimal,
     pub discount_type: Option<String>,
-    pub discount_value: f64,
+    pub discount_value: Decimal,
     pub discount_reason: Option<String>,
+    pub tax_rate: Decimal,
+    pub tax_exempt: bool,
+    pub tax_amount: Decimal,
 }
 
 #[tauri::command]
@@ -2827,17 +3282,18 @@ pub async fn get_services_report(
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DailySummary {
-    pub total_invoiced: f64,
-    pub total_collected: f64,
-    pub total_pending: f64,
-    pub total_discounts: f64,
+    pub total_invoiced: Decimal,
+    pub total_collected: Decimal,
+    pub total_pending: Decimal,
+    pub total_discounts: Decimal,
+    pub total_tax: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DailyInvoice {
     pub invoice_number: String,
     pub patient_name: String,
-    pub total_amount: f64,
+    pub total_amount: Decimal,
     pub status: String,
     pub payment_method: Option<String>,
 }
@@ -2847,25 +3303,25 @@ pub struct CashClosureInput {
     pub branch_id: String,
     pub period_start: String,
     pub period_end: String,
-    pub total_invoiced: f64,
-    pub total_collected: f64,
-    pub total_pending: f64,
-    pub total_discounts: f64,
-    pub consultas_total: f64,
+    pub total_invoiced: Decimal,
+    pub total_collected: Decimal,
+    pub total_pending: Decimal,
+    pub total_discounts: Decimal,
+    pub consultas_total: Decimal,
     pub consultas_count: i64,
-    pub cirugias_total: f64,
+    pub cirugias_total: Decimal,
     pub cirugias_count: i64,
-    pub procedimientos_total: f64,
+    pub procedimientos_total: Decimal,
     pub procedimientos_count: i64,
-    pub estudios_total: f64,
+    pub estudios_total: Decimal,
     pub estudios_count: i64,
-    pub inventory_total: f64,
+    pub inventory_total: Decimal,
     pub inventory_count: i64,
-    pub efectivo_total: f64,
-    pub tarjeta_total: f64,
-    pub transferencia_total: f64,
-    pub cheque_total: f64,
-    pub otro_total: f64,
+    pub efectivo_total: Decimal,
+    pub tarjeta_total: Decimal,
+    pub transferencia_total: Decimal,
+    pub cheque_total: Decimal,
+    pub otro_total: Decimal,
     pub detailed_data: Option<serde_json::Value>,
     pub closed_by: String,
 }
@@ -2911,72 +3367,220 @@ pub async fn create_cash_closure(
 }
 
 // ============================================================
-// CRM PIPELINES - TYPES
+// CASH REGISTER SESSIONS (SESIONES DE CAJA) - TYPES
 // ============================================================
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct BranchEmbed {
+pub struct CashSession {
     pub id: String,
-    pub name: String,
-    pub code: Option<String>,
+    pub branch_id: String,
+    pub cashier_id: String,
+    /// abierta | cerrada
+    pub status: String,
+    pub opening_float: Decimal,
+    pub opened_at: String,
+    pub closed_at: Option<String>,
+    /// Cash sales (GTQ, efectivo) received during the session; set at closing
+    pub efectivo_total: Option<Decimal>,
+    pub cash_in_total: Option<Decimal>,
+    pub cash_out_total: Option<Decimal>,
+    pub expected_cash: Option<Decimal>,
+    pub counted_cash: Option<Decimal>,
+    /// counted - expected: positive = sobrante, negative = faltante
+    pub over_short: Option<Decimal>,
+    pub notes: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMProcedureType {
-    pub id: String,
-    pub name: String,
-    pub color: Option<String>,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CashSessionOpenInput {
+    pub branch_id: String,
+    pub cashier_id: String,
+    pub opening_float: Decimal,
+    pub notes: Option<String>,
 }
 
-// CRM Activity Log types
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMUnreadActivity {
+pub struct CashMovement {
     pub id: String,
-    pub procedure_name: Option<String>,
+    pub session_id: String,
+    /// entrada | salida
+    pub movement_type: String,
+    pub amount: Decimal,
+    pub reason: String,
+    pub created_by: Option<String>,
+    pub created_at: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMActivityPatient {
-    pub first_name: String,
-    pub last_name: String,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CashMovementInput {
+    pub session_id: String,
+    pub movement_type: String,
+    pub amount: Decimal,
+    pub reason: String,
+    pub created_by: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMActivityProcedureType {
-    pub name: String,
-    pub color: String,
+/// Blind count submitted at closing: the cashier never sees the expected amount
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CashSessionCloseInput {
+    pub session_id: String,
+    pub counts: Vec<DenominationCount>,
+    pub notes: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMActivityCreator {
-    pub full_name: String,
+pub struct CashSessionDetail {
+    pub session: CashSession,
+    pub movements: Vec<CashMovement>,
+    pub counts: Vec<DenominationCount>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMActivityLog {
-    pub id: String,
-    pub pipeline_id: String,
-    pub activity_type: String,
-    pub from_stage: Option<String>,
-    pub to_stage: Option<String>,
-    pub reason: Option<String>,
-    pub created_by: Option<String>,
-    pub branch_id: String,
-    pub created_at: String,
-    pub eye_side: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub patient: Option<CRMActivityPatient>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub procedure_type: Option<CRMActivityProcedureType>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub creator: Option<CRMActivityCreator>,
+// ============================================================
+// COMMANDS - CASH REGISTER SESSIONS
+// ============================================================
+
+#[tauri::command]
+pub async fn open_cash_session(
+    app_state: State<'_, Arc<AppState>>,
+    session: CashSessionOpenInput,
+) -> Result<CashSession, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("open_cash_session: Using local PostgreSQL");
+        return pool.open_cash_session(&session).await;
+    }
+    Err("No database connection available".to_string())
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CRMPipeline {
-    pub id: String,
-    pub patient_id: String,
-    pub procedure_type_id: String,
+#[tauri::command]
+pub async fn get_open_cash_session(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    cashier_id: String,
+) -> Result<Option<CashSession>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_open_cash_session: Using local PostgreSQL");
+        return pool.get_open_cash_session(&branch_id, &cashier_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_cash_sessions(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    start_date: String,
+    end_date: String,
+) -> Result<Vec<CashSession>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_cash_sessions: Using local PostgreSQL");
+        return pool.get_cash_sessions(&branch_id, &start_date, &end_date).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_cash_session_detail(
+    app_state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<CashSessionDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_cash_session_detail: Using local PostgreSQL");
+        return pool.get_cash_session_detail(&session_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn create_cash_movement(
+    app_state: State<'_, Arc<AppState>>,
+    movement: CashMovementInput,
+) -> Result<CashMovement, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_cash_movement: Using local PostgreSQL");
+        return pool.create_cash_movement(&movement).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn close_cash_session(
+    app_state: State<'_, Arc<AppState>>,
+    closing: CashSessionCloseInput,
+) -> Result<CashSessionDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("close_cash_session: Using local PostgreSQL");
+        return pool.close_cash_session(&closing).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// CRM PIPELINES - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BranchEmbed {
+    pub id: String,
+    pub name: String,
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMProcedureType {
+    pub id: String,
+    pub name: String,
+    pub color: Option<String>,
+}
+
+// CRM Activity Log types
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMUnreadActivity {
+    pub id: String,
+    pub procedure_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMActivityPatient {
+    pub first_name: String,
+    pub last_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMActivityProcedureType {
+    pub name: String,
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMActivityCreator {
+    pub full_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMActivityLog {
+    pub id: String,
+    pub pipeline_id: String,
+    pub activity_type: String,
+    pub from_stage: Option<String>,
+    pub to_stage: Option<String>,
+    pub reason: Option<String>,
+    pub created_by: Option<String>,
+    pub branch_id: String,
+    pub created_at: String,
+    pub eye_side: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patient: Option<CRMActivityPatient>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub procedure_type: Option<CRMActivityProcedureType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<CRMActivityCreator>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CRMPipeline {
+    pub id: String,
+    pub patient_id: String,
+    pub procedure_type_id: String,
     pub doctor_id: Option<String>,
     pub branch_id: String,
     pub current_stage: String,
@@ -3903,6 +4507,8 @@ pub struct RoomInventoryItem {
     pub branch_id: String,
     pub created_at: String,
     pub updated_at: String,
+    /// Unit cost used to value surgery kit consumption
+    pub unit_cost: Decimal,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub category: Option<RoomInventoryCategory>,
 }
@@ -3919,6 +4525,8 @@ pub struct RoomInventoryItemInput {
     pub unit: Option<String>,
     pub notes: Option<String>,
     pub branch_id: String,
+    #[serde(default)]
+    pub unit_cost: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -3931,6 +4539,8 @@ pub struct RoomInventoryItemUpdate {
     pub min_stock: Option<i32>,
     pub unit: Option<String>,
     pub notes: Option<String>,
+    #[serde(default)]
+    pub unit_cost: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -4068,7 +4678,7 @@ pub async fn delete_room_inventory_item(
     Err("No database connection available".to_string())
 }
 
-/// Update room inventory item stock
+/// Set room inventory item stock to a counted value (recorded as an 'ajuste' movement)
 #[tauri::command]
 pub async fn update_room_inventory_stock(
     app_state: State<'_, Arc<AppState>>,
@@ -4110,6 +4720,1717 @@ pub async fn get_room_inventory_movements(
     Err("No database connection available".to_string())
 }
 
+// ============================================================
+// STOCK COUNTS (CONTEOS FÍSICOS) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockCountSession {
+    pub id: String,
+    pub branch_id: String,
+    /// 'farmacia' or 'sala'
+    pub inventory_type: String,
+    /// Pharmacy category, or room category id; None counts everything
+    pub category: Option<String>,
+    pub status: String,
+    pub started_by: Option<String>,
+    pub started_at: String,
+    pub posted_at: Option<String>,
+    pub notes: Option<String>,
+    pub line_count: i64,
+    pub counted_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StockCountSessionInput {
+    pub branch_id: String,
+    pub inventory_type: String,
+    pub category: Option<String>,
+    pub started_by: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockCountLine {
+    pub id: String,
+    pub item_id: String,
+    pub item_name: String,
+    pub item_code: Option<String>,
+    pub lot_id: Option<String>,
+    pub lot_number: Option<String>,
+    /// System quantity when the session started
+    pub expected_quantity: f64,
+    /// System quantity now (after posting, the adjusted stock)
+    pub current_quantity: f64,
+    pub counted_quantity: Option<f64>,
+    /// Counted minus system quantity; fixed when the session is posted
+    pub variance: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StockCountEntry {
+    pub item_id: String,
+    pub lot_id: Option<String>,
+    pub counted_quantity: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockCountDetail {
+    pub session: StockCountSession,
+    pub lines: Vec<StockCountLine>,
+}
+
+// ============================================================
+// STOCK COUNTS (CONTEOS FÍSICOS) - COMMANDS
+// ============================================================
+
+/// Start a count session, snapshotting the expected quantities
+#[tauri::command]
+pub async fn start_stock_count(
+    app_state: State<'_, Arc<AppState>>,
+    input: StockCountSessionInput,
+) -> Result<StockCountSession, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("start_stock_count: Using local PostgreSQL");
+        return pool.start_stock_count(&input).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_stock_counts(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+) -> Result<Vec<StockCountSession>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_stock_counts: Using local PostgreSQL");
+        return pool.get_stock_counts(&branch_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Session lines with variances against the current stock
+#[tauri::command]
+pub async fn get_stock_count_detail(
+    app_state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<StockCountDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_stock_count_detail: Using local PostgreSQL");
+        return pool.get_stock_count_detail(&session_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn record_stock_counts(
+    app_state: State<'_, Arc<AppState>>,
+    session_id: String,
+    entries: Vec<StockCountEntry>,
+) -> Result<StockCountDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("record_stock_counts: Using local PostgreSQL");
+        return pool.record_stock_counts(&session_id, &entries).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Post adjustment movements for every counted line and close the session
+#[tauri::command]
+pub async fn post_stock_count(
+    app_state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<StockCountDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("post_stock_count: Using local PostgreSQL");
+        return pool.post_stock_count(&session_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn cancel_stock_count(
+    app_state: State<'_, Arc<AppState>>,
+    session_id: String,
+) -> Result<(), String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("cancel_stock_count: Using local PostgreSQL");
+        return pool.cancel_stock_count(&session_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// PURCHASING (COMPRAS) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrder {
+    pub id: String,
+    pub order_number: String,
+    pub branch_id: String,
+    pub supplier_id: String,
+    pub supplier_name: Option<String>,
+    pub status: String,
+    pub currency: String,
+    pub expected_date: Option<String>,
+    pub notes: Option<String>,
+    pub total_amount: Decimal,
+    pub created_by: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrderLine {
+    pub id: String,
+    pub item_id: String,
+    pub item_name: String,
+    pub quantity_ordered: f64,
+    pub quantity_received: f64,
+    pub unit_cost: Decimal,
+    pub line_total: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurchaseOrderLineInput {
+    pub item_id: String,
+    pub quantity: f64,
+    pub unit_cost: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurchaseOrderInput {
+    pub branch_id: String,
+    pub supplier_id: String,
+    #[serde(default)]
+    pub currency: Option<String>,
+    pub expected_date: Option<String>,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub lines: Vec<PurchaseOrderLineInput>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GoodsReceipt {
+    pub id: String,
+    pub order_id: String,
+    pub received_by: Option<String>,
+    pub received_at: String,
+    pub supplier_invoice: Option<String>,
+    pub total_amount: Decimal,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoodsReceiptLineInput {
+    pub order_line_id: String,
+    pub quantity: f64,
+    /// Creates (or adds to) this lot; without it stock enters unlotted
+    pub lot_number: Option<String>,
+    pub expiry_date: Option<String>,
+    /// Defaults to the order line's unit cost
+    pub unit_cost: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoodsReceiptInput {
+    pub order_id: String,
+    pub received_by: Option<String>,
+    /// Supplier's invoice number
+    pub supplier_invoice: Option<String>,
+    /// Payment due date for the payable, YYYY-MM-DD
+    pub due_date: Option<String>,
+    pub notes: Option<String>,
+    pub lines: Vec<GoodsReceiptLineInput>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrderDetail {
+    pub order: PurchaseOrder,
+    pub lines: Vec<PurchaseOrderLine>,
+    pub receipts: Vec<GoodsReceipt>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SupplierPayable {
+    pub id: String,
+    pub supplier_id: String,
+    pub supplier_name: Option<String>,
+    pub branch_id: String,
+    pub receipt_id: String,
+    pub order_number: Option<String>,
+    pub supplier_invoice: Option<String>,
+    pub currency: String,
+    pub amount: Decimal,
+    pub balance: Decimal,
+    pub due_date: Option<String>,
+    pub status: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SupplierPaymentInput {
+    pub payable_id: String,
+    pub amount: Decimal,
+    pub payment_method: String,
+    pub reference: Option<String>,
+    pub notes: Option<String>,
+    pub paid_by: Option<String>,
+}
+
+// ============================================================
+// PURCHASING (COMPRAS) - COMMANDS
+// ============================================================
+
+#[tauri::command]
+pub async fn create_purchase_order(
+    app_state: State<'_, Arc<AppState>>,
+    order: PurchaseOrderInput,
+) -> Result<PurchaseOrderDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_purchase_order: Using local PostgreSQL");
+        return pool.create_purchase_order(&order).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_purchase_orders(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    status: Option<String>,
+) -> Result<Vec<PurchaseOrder>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_purchase_orders: Using local PostgreSQL");
+        return pool.get_purchase_orders(&branch_id, status.as_deref()).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_purchase_order_detail(
+    app_state: State<'_, Arc<AppState>>,
+    order_id: String,
+) -> Result<PurchaseOrderDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_purchase_order_detail: Using local PostgreSQL");
+        return pool.get_purchase_order_detail(&order_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Send a draft order to the supplier or cancel it
+#[tauri::command]
+pub async fn update_purchase_order_status(
+    app_state: State<'_, Arc<AppState>>,
+    order_id: String,
+    status: String,
+) -> Result<PurchaseOrder, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("update_purchase_order_status: Using local PostgreSQL");
+        return pool.update_purchase_order_status(&order_id, &status).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Receive goods (fully or partially): creates lots, 'entrada' movements and a supplier payable
+#[tauri::command]
+pub async fn receive_purchase_order(
+    app_state: State<'_, Arc<AppState>>,
+    receipt: GoodsReceiptInput,
+) -> Result<PurchaseOrderDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("receive_purchase_order: Using local PostgreSQL");
+        return pool.receive_purchase_order(&receipt).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_supplier_payables(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    supplier_id: Option<String>,
+    include_paid: Option<bool>,
+) -> Result<Vec<SupplierPayable>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_supplier_payables: Using local PostgreSQL");
+        return pool.get_supplier_payables(&branch_id, supplier_id.as_deref(), include_paid.unwrap_or(false)).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn pay_supplier_payable(
+    app_state: State<'_, Arc<AppState>>,
+    payment: SupplierPaymentInput,
+) -> Result<SupplierPayable, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("pay_supplier_payable: Using local PostgreSQL");
+        return pool.pay_supplier_payable(&payment).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// REORDER SUGGESTIONS - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReorderSuggestion {
+    pub item_id: String,
+    pub item_name: String,
+    pub category: Option<String>,
+    pub current_stock: f64,
+    pub reorder_level: i32,
+    /// Pending quantity on draft, sent or partially received orders
+    pub on_order: f64,
+    /// Outgoing quantity (sales and courtesies) over the window
+    pub consumed: f64,
+    pub avg_daily_consumption: f64,
+    pub days_of_cover: Option<f64>,
+    pub suggested_quantity: f64,
+    pub unit_cost: Option<Decimal>,
+    pub estimated_cost: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReorderSupplierGroup {
+    /// None for items without a supplier assigned
+    pub supplier_id: Option<String>,
+    pub supplier_name: Option<String>,
+    pub items: Vec<ReorderSuggestion>,
+    pub estimated_total: Decimal,
+    /// Draft order created for this supplier, when requested
+    pub purchase_order_id: Option<String>,
+}
+
+/// Items at or below reorder level grouped by supplier. With `create_drafts`,
+/// a draft purchase order is created per supplier for the suggested quantities.
+#[tauri::command]
+pub async fn get_reorder_suggestions(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    window_days: Option<i32>,
+    coverage_days: Option<i32>,
+    create_drafts: Option<bool>,
+    created_by: Option<String>,
+) -> Result<Vec<ReorderSupplierGroup>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_reorder_suggestions: Using local PostgreSQL");
+        return pool
+            .get_reorder_suggestions(
+                &branch_id,
+                window_days.unwrap_or(90),
+                coverage_days.unwrap_or(30),
+                create_drafts.unwrap_or(false),
+                created_by.as_deref(),
+            )
+            .await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// STOCK TRANSFERS (TRASLADOS) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockTransfer {
+    pub id: String,
+    pub transfer_number: String,
+    pub from_branch_id: String,
+    pub from_branch_name: Option<String>,
+    pub to_branch_id: String,
+    pub to_branch_name: Option<String>,
+    /// 'en_transito', 'recibida' or 'cancelada'
+    pub status: String,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub received_by: Option<String>,
+    pub received_at: Option<String>,
+    pub cancelled_at: Option<String>,
+    pub cancellation_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockTransferLine {
+    pub id: String,
+    pub item_id: String,
+    pub item_name: String,
+    pub lot_id: Option<String>,
+    pub lot_number: Option<String>,
+    pub expiry_date: Option<String>,
+    pub quantity: f64,
+    /// Item and lot at the receiving branch, set on receipt
+    pub dest_item_id: Option<String>,
+    pub dest_lot_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StockTransferLineInput {
+    pub item_id: String,
+    /// Without a lot, lots are chosen automatically (FEFO/FIFO)
+    pub lot_id: Option<String>,
+    pub quantity: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StockTransferInput {
+    pub from_branch_id: String,
+    pub to_branch_id: String,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub lines: Vec<StockTransferLineInput>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockTransferDetail {
+    pub transfer: StockTransfer,
+    pub lines: Vec<StockTransferLine>,
+}
+
+// ============================================================
+// STOCK TRANSFERS (TRASLADOS) - COMMANDS
+// ============================================================
+
+/// Dispatch stock to another branch; it stays in transit until received
+#[tauri::command]
+pub async fn create_stock_transfer(
+    app_state: State<'_, Arc<AppState>>,
+    transfer: StockTransferInput,
+) -> Result<StockTransferDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_stock_transfer: Using local PostgreSQL");
+        return pool.create_stock_transfer(&transfer).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Transfers sent from or to a branch
+#[tauri::command]
+pub async fn get_stock_transfers(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    status: Option<String>,
+) -> Result<Vec<StockTransfer>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_stock_transfers: Using local PostgreSQL");
+        return pool.get_stock_transfers(&branch_id, status.as_deref()).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_stock_transfer_detail(
+    app_state: State<'_, Arc<AppState>>,
+    transfer_id: String,
+) -> Result<StockTransferDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_stock_transfer_detail: Using local PostgreSQL");
+        return pool.get_stock_transfer_detail(&transfer_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Confirm arrival at the receiving branch
+#[tauri::command]
+pub async fn receive_stock_transfer(
+    app_state: State<'_, Arc<AppState>>,
+    transfer_id: String,
+    received_by: Option<String>,
+) -> Result<StockTransferDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("receive_stock_transfer: Using local PostgreSQL");
+        return pool.receive_stock_transfer(&transfer_id, received_by.as_deref()).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Cancel a transfer in transit, returning the stock to the origin branch
+#[tauri::command]
+pub async fn cancel_stock_transfer(
+    app_state: State<'_, Arc<AppState>>,
+    transfer_id: String,
+    reason: String,
+) -> Result<StockTransferDetail, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("cancel_stock_transfer: Using local PostgreSQL");
+        return pool.cancel_stock_transfer(&transfer_id, &reason).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// SURGERY KITS (MATERIALES POR TIPO DE CIRUGÍA) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SurgeryTypeMaterial {
+    pub id: String,
+    pub surgery_type_id: String,
+    pub room_item_id: String,
+    pub item_name: String,
+    pub unit: Option<String>,
+    pub branch_id: String,
+    pub quantity: i32,
+    /// Doubled for surgeries on both eyes (OU)
+    pub per_eye: bool,
+    pub unit_cost: Decimal,
+    pub current_stock: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SurgeryTypeMaterialInput {
+    pub room_item_id: String,
+    pub quantity: i32,
+    #[serde(default)]
+    pub per_eye: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SurgeryMaterialUsage {
+    pub id: String,
+    pub surgery_id: String,
+    pub room_item_id: String,
+    pub item_name: String,
+    pub quantity: i32,
+    pub unit_cost: Decimal,
+    pub total_cost: Decimal,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SurgeryCostLine {
+    pub surgery_id: String,
+    pub date: Option<String>,
+    pub surgery_type: String,
+    pub eye: Option<String>,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub surgeon_name: Option<String>,
+    pub material_count: i64,
+    pub material_cost: Decimal,
+}
+
+// ============================================================
+// SURGERY KITS (MATERIALES POR TIPO DE CIRUGÍA) - COMMANDS
+// ============================================================
+
+/// Get the kit of a surgery type, optionally for one branch
+#[tauri::command]
+pub async fn get_surgery_type_materials(
+    app_state: State<'_, Arc<AppState>>,
+    surgery_type_id: String,
+    branch_id: Option<String>,
+) -> Result<Vec<SurgeryTypeMaterial>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_surgery_type_materials: Using local PostgreSQL");
+        return pool.get_surgery_type_materials(&surgery_type_id, branch_id.as_deref()).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Replace the kit of a surgery type for a branch
+#[tauri::command]
+pub async fn set_surgery_type_materials(
+    app_state: State<'_, Arc<AppState>>,
+    surgery_type_id: String,
+    branch_id: String,
+    materials: Vec<SurgeryTypeMaterialInput>,
+) -> Result<Vec<SurgeryTypeMaterial>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("set_surgery_type_materials: Using local PostgreSQL");
+        return pool.set_surgery_type_materials(&surgery_type_id, &branch_id, &materials).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Get the materials consumed by a surgery
+#[tauri::command]
+pub async fn get_surgery_material_usage(
+    app_state: State<'_, Arc<AppState>>,
+    surgery_id: String,
+) -> Result<Vec<SurgeryMaterialUsage>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_surgery_material_usage: Using local PostgreSQL");
+        return pool.get_surgery_material_usage(&surgery_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Material cost per completed surgery in a date range
+#[tauri::command]
+pub async fn get_surgery_cost_report(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    start_date: String,
+    end_date: String,
+) -> Result<Vec<SurgeryCostLine>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_surgery_cost_report: Using local PostgreSQL");
+        return pool.get_surgery_cost_report(&branch_id, &start_date, &end_date).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// BARCODES (CÓDIGOS DE BARRAS / GS1) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BarcodeResolution {
+    /// Code as scanned, without symbology prefix
+    pub scanned: String,
+    pub gtin: Option<String>,
+    /// Lot and expiry read from GS1 AIs (10) and (17)
+    pub lot_number: Option<String>,
+    pub expiry_date: Option<String>,
+    pub serial_number: Option<String>,
+    pub item: Option<InventoryItem>,
+    /// Existing lot of the item; None when the scanned lot is new
+    pub lot: Option<InventoryLot>,
+    pub lot_expired: bool,
+}
+
+// ============================================================
+// BARCODES (CÓDIGOS DE BARRAS / GS1) - COMMANDS
+// ============================================================
+
+/// Resolve a scanned barcode (EAN/UPC, internal code or GS1-128) to an item and lot
+#[tauri::command]
+pub async fn resolve_inventory_barcode(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    code: String,
+) -> Result<BarcodeResolution, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("resolve_inventory_barcode: Using local PostgreSQL");
+        return pool.resolve_inventory_barcode(&branch_id, &code).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// INVENTORY VALUATION (VALORIZACIÓN Y COSTO DE VENTAS) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InventoryValuationLine {
+    pub item_id: String,
+    pub item_name: String,
+    pub item_code: Option<String>,
+    pub category: Option<String>,
+    pub quantity: f64,
+    /// Weighted cost of the remaining FIFO layers
+    pub average_cost: Decimal,
+    pub total_value: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProductMarginLine {
+    pub item_id: String,
+    pub item_name: String,
+    pub category: Option<String>,
+    pub quantity_sold: f64,
+    /// Invoiced amount after the invoice discount, without IVA
+    pub revenue: Decimal,
+    /// FIFO cost of 'salida' movements in the period (transfers excluded)
+    pub cost_of_goods_sold: Decimal,
+    pub gross_margin: Decimal,
+    pub margin_percent: Option<Decimal>,
+    pub courtesy_cost: Decimal,
+    pub write_off_cost: Decimal,
+}
+
+// ============================================================
+// INVENTORY VALUATION (VALORIZACIÓN Y COSTO DE VENTAS) - COMMANDS
+// ============================================================
+
+/// Stock value per item at the end of a date (FIFO)
+#[tauri::command]
+pub async fn get_inventory_valuation(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    as_of: String,
+) -> Result<Vec<InventoryValuationLine>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_inventory_valuation: Using local PostgreSQL");
+        return pool.get_inventory_valuation(&branch_id, &as_of).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Cost of goods sold and gross margin per product for a period
+#[tauri::command]
+pub async fn get_product_margin_report(
+    app_state: State<'_, Arc<AppState>>,
+    branch_id: String,
+    start_date: String,
+    end_date: String,
+) -> Result<Vec<ProductMarginLine>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_product_margin_report: Using local PostgreSQL");
+        return pool.get_product_margin_report(&branch_id, &start_date, &end_date).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// PRESCRIPTIONS (RECETAS DE LENTES) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Prescription {
+    pub id: String,
+    pub encounter_id: String,
+    pub patient_id: String,
+    /// 'lentes' or 'contacto'
+    pub kind: String,
+    pub od_sphere: Option<f64>,
+    pub od_cylinder: Option<f64>,
+    pub od_axis: Option<i32>,
+    pub od_add: Option<f64>,
+    pub os_sphere: Option<f64>,
+    pub os_cylinder: Option<f64>,
+    pub os_axis: Option<i32>,
+    pub os_add: Option<f64>,
+    /// Contact lens fit ('contacto' only)
+    pub od_fit: Option<ContactLensFit>,
+    pub os_fit: Option<ContactLensFit>,
+    pub pupillary_distance: Option<f64>,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrescriptionInput {
+    pub encounter_id: String,
+    pub kind: String,
+    /// Required for 'contacto'; powers are converted to the corneal plane
+    #[serde(default)]
+    pub od_fit: Option<ContactLensFit>,
+    #[serde(default)]
+    pub os_fit: Option<ContactLensFit>,
+    pub pupillary_distance: Option<f64>,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+}
+
+// ============================================================
+// PRESCRIPTIONS (RECETAS DE LENTES) - COMMANDS
+// ============================================================
+
+/// Build a prescription from the encounter's final Rx and store it with its PDF
+#[tauri::command]
+pub async fn create_prescription(
+    app_state: State<'_, Arc<AppState>>,
+    prescription: PrescriptionInput,
+) -> Result<Prescription, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_prescription: Using local PostgreSQL");
+        return pool.create_prescription(&prescription).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_prescriptions_by_encounter(
+    app_state: State<'_, Arc<AppState>>,
+    encounter_id: String,
+    purpose: Option<String>,
+) -> Result<Vec<Prescription>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_prescriptions_by_encounter: Using local PostgreSQL");
+        let result = pool.get_prescriptions_by_encounter(&encounter_id).await?;
+        pool.record_encounter_access(&encounter_id, "prescriptions", purpose.as_deref()).await;
+        return Ok(result);
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Prescription PDF as base64
+#[tauri::command]
+pub async fn get_prescription_pdf(
+    app_state: State<'_, Arc<AppState>>,
+    prescription_id: String,
+    purpose: Option<String>,
+) -> Result<Option<String>, String> {
+    use base64::Engine;
+
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_prescription_pdf: Using local PostgreSQL");
+        let pdf = pool.get_prescription_pdf(&prescription_id).await?;
+        pool.record_prescription_access(&prescription_id, "prescription_pdf", purpose.as_deref()).await;
+        return Ok(pdf.map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes)));
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// REFRACTION (CÁLCULOS DE REFRACCIÓN) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefractionInput {
+    pub sphere: Option<f64>,
+    pub cylinder: Option<f64>,
+    pub axis: Option<i32>,
+    /// Spectacle vertex distance in mm (12 when not given)
+    pub vertex_distance_mm: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EyeRefraction {
+    pub side: String,
+    /// Exam fields used: 'rx' (final), 'subjetiva' or 'objetiva'
+    pub source: String,
+    pub summary: crate::clinical::refraction::RefractionSummary,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncounterRefraction {
+    pub encounter_id: String,
+    pub od: Option<EyeRefraction>,
+    pub os: Option<EyeRefraction>,
+    /// Spherical equivalent difference between eyes
+    pub anisometropia: Option<f64>,
+    pub is_anisometropic: bool,
+}
+
+// ============================================================
+// REFRACTION (CÁLCULOS DE REFRACCIÓN) - COMMANDS
+// ============================================================
+
+/// Transposition, spherical equivalent and contact lens power of a refraction
+#[tauri::command]
+pub async fn calculate_refraction(
+    refraction: RefractionInput,
+) -> Result<crate::clinical::refraction::RefractionSummary, String> {
+    use crate::clinical::{prescription, refraction as rx};
+
+    prescription::validate_eye("Rx", &prescription::EyeRx {
+        sphere: refraction.sphere,
+        cylinder: refraction.cylinder,
+        axis: refraction.axis,
+        add: None,
+    })?;
+    let vertex = refraction.vertex_distance_mm.unwrap_or(rx::DEFAULT_VERTEX_DISTANCE_MM);
+    if !(0.0..=25.0).contains(&vertex) {
+        return Err("La distancia al vértice debe estar entre 0 y 25 mm".to_string());
+    }
+    let value = rx::Refraction::from_parts(refraction.sphere, refraction.cylinder, refraction.axis)
+        .ok_or("Ingrese esfera o cilindro")?;
+    Ok(value.summary(vertex))
+}
+
+/// Refraction summary of both eyes of an encounter, with anisometropia
+#[tauri::command]
+pub async fn get_encounter_refraction(
+    app_state: State<'_, Arc<AppState>>,
+    encounter_id: String,
+    vertex_distance_mm: Option<f64>,
+    purpose: Option<String>,
+) -> Result<EncounterRefraction, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_encounter_refraction: Using local PostgreSQL");
+        let result = pool.get_encounter_refraction(&encounter_id, vertex_distance_mm).await?;
+        pool.record_encounter_access(&encounter_id, "refraction", purpose.as_deref()).await;
+        return Ok(result);
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// IOL CALCULATION (BIOMETRÍA Y CÁLCULO DE LENTE INTRAOCULAR) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IolModel {
+    pub id: String,
+    pub name: String,
+    pub manufacturer: Option<String>,
+    pub a_constant: f64,
+    /// Optimized Holladay 1 surgeon factor (derived from A when None)
+    pub surgeon_factor: Option<f64>,
+    /// Optimized Hoffer Q pACD (derived from A when None)
+    pub pacd: Option<f64>,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IolModelInput {
+    pub name: String,
+    pub manufacturer: Option<String>,
+    pub a_constant: f64,
+    pub surgeon_factor: Option<f64>,
+    pub pacd: Option<f64>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BiometryRecord {
+    pub id: String,
+    pub patient_id: String,
+    /// 'OD' or 'OS'
+    pub eye: String,
+    pub axial_length: f64,
+    pub k1: f64,
+    pub k2: f64,
+    pub k1_axis: Option<i32>,
+    pub acd: Option<f64>,
+    pub lens_thickness: Option<f64>,
+    pub white_to_white: Option<f64>,
+    pub device: Option<String>,
+    pub measured_at: String,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BiometryInput {
+    pub patient_id: String,
+    pub eye: String,
+    pub axial_length: f64,
+    pub k1: f64,
+    pub k2: f64,
+    pub k1_axis: Option<i32>,
+    pub acd: Option<f64>,
+    pub lens_thickness: Option<f64>,
+    pub white_to_white: Option<f64>,
+    pub device: Option<String>,
+    /// YYYY-MM-DD, today when not given
+    pub measured_at: Option<String>,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IolCalculationInput {
+    pub biometry_id: String,
+    pub iol_model_id: String,
+    /// Target spectacle refraction, emmetropia when not given
+    pub target_refraction: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IolPowerOption {
+    pub power: f64,
+    pub refraction: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IolFormulaResult {
+    /// 'srkt', 'holladay1' or 'hofferq'
+    pub formula: String,
+    pub formula_name: String,
+    pub exact_power: f64,
+    pub recommended_power: f64,
+    pub recommended_refraction: f64,
+    pub options: Vec<IolPowerOption>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IolCalculation {
+    pub biometry: BiometryRecord,
+    pub model: IolModel,
+    pub target_refraction: f64,
+    /// Formula usually preferred for this axial length
+    pub suggested_formula: String,
+    pub results: Vec<IolFormulaResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SurgeryIolInput {
+    pub biometry_id: String,
+    pub iol_model_id: String,
+    pub iol_power: f64,
+    pub iol_formula: String,
+    pub target_refraction: f64,
+}
+
+// ============================================================
+// IOL CALCULATION (BIOMETRÍA Y CÁLCULO DE LENTE INTRAOCULAR) - COMMANDS
+// ============================================================
+
+#[tauri::command]
+pub async fn get_iol_models(
+    app_state: State<'_, Arc<AppState>>,
+    include_inactive: Option<bool>,
+) -> Result<Vec<IolModel>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_iol_models: Using local PostgreSQL");
+        return pool.get_iol_models(include_inactive.unwrap_or(false)).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn create_iol_model(
+    app_state: State<'_, Arc<AppState>>,
+    model: IolModelInput,
+) -> Result<IolModel, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_iol_model: Using local PostgreSQL");
+        return pool.create_iol_model(&model).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn update_iol_model(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+    model: IolModelInput,
+) -> Result<IolModel, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("update_iol_model: Using local PostgreSQL");
+        return pool.update_iol_model(&id, &model).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Biometry records of a patient, newest first
+#[tauri::command]
+pub async fn get_biometry_by_patient(
+    app_state: State<'_, Arc<AppState>>,
+    patient_id: String,
+    purpose: Option<String>,
+) -> Result<Vec<BiometryRecord>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_biometry_by_patient: Using local PostgreSQL");
+        let result = pool.get_biometry_by_patient(&patient_id).await?;
+        pool.record_patient_access(&patient_id, "biometry", purpose.as_deref()).await;
+        return Ok(result);
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn create_biometry(
+    app_state: State<'_, Arc<AppState>>,
+    biometry: BiometryInput,
+) -> Result<BiometryRecord, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_biometry: Using local PostgreSQL");
+        return pool.create_biometry(&biometry).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn delete_biometry(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+) -> Result<(), String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("delete_biometry: Using local PostgreSQL");
+        return pool.delete_biometry(&id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// IOL power by SRK/T, Holladay 1 and Hoffer Q for a biometry and lens model
+#[tauri::command]
+pub async fn calculate_iol(
+    app_state: State<'_, Arc<AppState>>,
+    calculation: IolCalculationInput,
+) -> Result<IolCalculation, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("calculate_iol: Using local PostgreSQL");
+        return pool.calculate_iol(&calculation).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Store the chosen lens on a surgery
+#[tauri::command]
+pub async fn set_surgery_iol(
+    app_state: State<'_, Arc<AppState>>,
+    surgery_id: String,
+    iol: SurgeryIolInput,
+) -> Result<Surgery, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("set_surgery_iol: Using local PostgreSQL");
+        return pool.set_surgery_iol(&surgery_id, &iol).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// CLINICAL TIMELINE (HISTORIA POR OJO) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClinicalTimelineEvent {
+    pub date: String,
+    /// 'examen', 'diagnostico', 'estudio', 'cirugia' or 'procedimiento'
+    pub kind: String,
+    pub source_id: String,
+    pub encounter_id: Option<String>,
+    pub title: String,
+    pub detail: Option<String>,
+    pub status: Option<String>,
+    /// Eye as recorded (None or OU for both eyes)
+    pub eye: Option<String>,
+    // Exam values
+    pub iop: Option<f64>,
+    pub av_sc: Option<String>,
+    pub av_cc: Option<String>,
+    pub av_sc_logmar: Option<f64>,
+    pub av_cc_logmar: Option<f64>,
+    pub sphere: Option<f64>,
+    pub cylinder: Option<f64>,
+    pub axis: Option<i32>,
+    pub spherical_equivalent: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClinicalTimeline {
+    pub patient_id: String,
+    /// 'OD' or 'OI'
+    pub eye: String,
+    /// Oldest first
+    pub events: Vec<ClinicalTimelineEvent>,
+    pub iop_last: Option<f64>,
+    pub iop_max: Option<f64>,
+    /// Least squares IOP slope, mmHg per year
+    pub iop_trend_per_year: Option<f64>,
+}
+
+// ============================================================
+// CLINICAL TIMELINE (HISTORIA POR OJO) - COMMANDS
+// ============================================================
+
+/// Chronological history of one eye: exams, diagnoses, studies, surgeries and procedures
+#[tauri::command]
+pub async fn get_eye_timeline(
+    app_state: State<'_, Arc<AppState>>,
+    patient_id: String,
+    eye: String,
+    purpose: Option<String>,
+) -> Result<ClinicalTimeline, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_eye_timeline: Using local PostgreSQL");
+        let timeline = pool.get_eye_timeline(&patient_id, &eye).await?;
+        pool.record_patient_access(&patient_id, "timeline", purpose.as_deref()).await;
+        return Ok(timeline);
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// ICD-10 (CATÁLOGO CIE-10) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Icd10Code {
+    pub code: String,
+    pub description: String,
+    pub synonyms: Vec<String>,
+    /// Diagnoses with this code must say the eye (OD, OI or OU)
+    pub lateral: bool,
+}
+
+// ============================================================
+// ICD-10 (CATÁLOGO CIE-10) - COMMANDS
+// ============================================================
+
+/// Search the ICD-10 catalog by code prefix ("H25", "h25.1") or by words
+/// of the description and synonyms ("catarata nuclear")
+#[tauri::command]
+pub async fn search_icd10(
+    db: State<'_, Arc<Database>>,
+    app_state: State<'_, Arc<AppState>>,
+    query: String,
+    limit: Option<i64>,
+) -> Result<Vec<Icd10Code>, String> {
+    use crate::clinical::icd10;
+
+    let limit = limit.unwrap_or(30).clamp(1, 200);
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("search_icd10: Using local PostgreSQL");
+        return pool.search_icd10(&query, limit).await;
+    }
+
+    // Fallback to SQLite cache
+    log::info!("search_icd10: Using SQLite cache");
+    let terms = icd10::search_terms(&query);
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    // ?1 never matches when the query is not a code prefix
+    let prefix = icd10::code_prefix(&query).map(|p| format!("{}%", p)).unwrap_or_default();
+    let term_filter = (0..terms.len())
+        .map(|i| format!("search_text LIKE ?{}", i + 2))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let sql = format!(
+        "SELECT code, description, synonyms, lateral FROM icd10_codes
+         WHERE replace(code, '.', '') LIKE ?1 OR ({})
+         ORDER BY replace(code, '.', '') LIKE ?1 DESC, code
+         LIMIT {}",
+        term_filter, limit
+    );
+    let mut params = vec![prefix];
+    params.extend(terms.iter().map(|t| format!("%{}%", t)));
+
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let codes = stmt
+        .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+            let synonyms: Option<String> = row.get(2)?;
+            Ok(Icd10Code {
+                code: row.get(0)?,
+                description: row.get(1)?,
+                synonyms: synonyms
+                    .map(|s| s.split(';').filter(|x| !x.is_empty()).map(String::from).collect())
+                    .unwrap_or_default(),
+                lateral: row.get::<_, i32>(3)? == 1,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(codes)
+}
+
+#[tauri::command]
+pub async fn get_icd10_code(
+    app_state: State<'_, Arc<AppState>>,
+    code: String,
+) -> Result<Option<Icd10Code>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_icd10_code: Using local PostgreSQL");
+        return pool.get_icd10_code(&code).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Reload the bundled catalog into PostgreSQL (after an application update)
+#[tauri::command]
+pub async fn load_icd10_catalog(
+    app_state: State<'_, Arc<AppState>>,
+) -> Result<usize, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("load_icd10_catalog: Using local PostgreSQL");
+        return pool.load_icd10_catalog().await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// ENCOUNTER TEMPLATES (PLANTILLAS DE CONSULTA) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateDiagnosis {
+    pub code: Option<String>,
+    pub description: String,
+    /// OD, OI or OU (required for eye-specific codes)
+    pub eye: Option<String>,
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncounterTemplate {
+    pub id: String,
+    pub name: String,
+    /// None: any encounter type
+    pub encounter_type: Option<String>,
+    /// None: shared by all doctors
+    pub doctor_id: Option<String>,
+    pub summary: Option<String>,
+    pub motivo_consulta: Option<String>,
+    pub plan_tratamiento: Option<String>,
+    pub proxima_cita: Option<String>,
+    // Exam defaults, applied to both eyes
+    pub exam_slit_lamp: Option<String>,
+    pub exam_fundus: Option<String>,
+    pub exam_plan: Option<String>,
+    pub diagnoses: Vec<TemplateDiagnosis>,
+    /// Study types ordered with the encounter
+    pub studies: Vec<String>,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncounterTemplateInput {
+    pub name: String,
+    pub encounter_type: Option<String>,
+    pub doctor_id: Option<String>,
+    pub summary: Option<String>,
+    pub motivo_consulta: Option<String>,
+    pub plan_tratamiento: Option<String>,
+    pub proxima_cita: Option<String>,
+    pub exam_slit_lamp: Option<String>,
+    pub exam_fundus: Option<String>,
+    pub exam_plan: Option<String>,
+    #[serde(default)]
+    pub diagnoses: Vec<TemplateDiagnosis>,
+    #[serde(default)]
+    pub studies: Vec<String>,
+    pub active: Option<bool>,
+}
+
+// ============================================================
+// ENCOUNTER TEMPLATES (PLANTILLAS DE CONSULTA) - COMMANDS
+// ============================================================
+
+/// Templates a doctor can use for an encounter type (their own first, then shared ones)
+#[tauri::command]
+pub async fn get_encounter_templates(
+    app_state: State<'_, Arc<AppState>>,
+    doctor_id: Option<String>,
+    encounter_type: Option<String>,
+    include_inactive: Option<bool>,
+) -> Result<Vec<EncounterTemplate>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_encounter_templates: Using local PostgreSQL");
+        return pool
+            .get_encounter_templates(doctor_id.as_deref(), encounter_type.as_deref(), include_inactive.unwrap_or(false))
+            .await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn create_encounter_template(
+    app_state: State<'_, Arc<AppState>>,
+    template: EncounterTemplateInput,
+) -> Result<EncounterTemplate, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("create_encounter_template: Using local PostgreSQL");
+        return pool.create_encounter_template(&template).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn update_encounter_template(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+    template: EncounterTemplateInput,
+) -> Result<EncounterTemplate, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("update_encounter_template: Using local PostgreSQL");
+        return pool.update_encounter_template(&id, &template).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// ENCOUNTER SIGNING (FIRMA Y ENMIENDAS) - TYPES
+// ============================================================
+
+/// Content covered by the signature: encounter text, exam of both eyes and diagnoses
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncounterContent {
+    pub summary: Option<String>,
+    pub motivo_consulta: Option<String>,
+    pub plan_tratamiento: Option<String>,
+    pub cirugias: Option<serde_json::Value>,
+    pub estudios: Option<serde_json::Value>,
+    pub proxima_cita: Option<String>,
+    pub excursiones_od: Option<String>,
+    pub excursiones_os: Option<String>,
+    pub exams: Vec<ExamEye>,
+    pub diagnoses: Vec<Diagnosis>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncounterAmendmentInput {
+    pub reason: String,
+    pub changes: EncounterUpdate,
+    /// Corrected exam of one or both eyes
+    #[serde(default)]
+    pub exams: Vec<ExamEyeInput>,
+    /// Replacement diagnosis list (None keeps the current diagnoses)
+    pub diagnoses: Option<Vec<TemplateDiagnosis>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncounterAmendment {
+    pub id: String,
+    pub encounter_id: String,
+    /// 1 for the first amendment after signing
+    pub version: i32,
+    pub reason: String,
+    pub author_id: String,
+    pub author_name: Option<String>,
+    pub created_at: String,
+    pub previous_content: EncounterContent,
+    pub new_content: EncounterContent,
+    pub content_hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncounterHistory {
+    pub encounter_id: String,
+    pub signed_at: Option<String>,
+    pub signed_by: Option<String>,
+    pub signed_by_name: Option<String>,
+    /// SHA-256 of the content at signing or at the last amendment
+    pub content_hash: Option<String>,
+    /// Whether the current content still matches content_hash
+    pub integrity_ok: bool,
+    /// Oldest first; the signed original is the previous content of version 1
+    pub amendments: Vec<EncounterAmendment>,
+}
+
+// ============================================================
+// ENCOUNTER SIGNING (FIRMA Y ENMIENDAS) - COMMANDS
+// ============================================================
+
+/// Sign an encounter as the logged-in doctor; afterwards it changes only through amendments
+#[tauri::command]
+pub async fn sign_encounter(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+) -> Result<Encounter, String> {
+    let user = app_state.session_user().ok_or("Inicie sesión para firmar la consulta")?;
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("sign_encounter: Using local PostgreSQL");
+        return pool.sign_encounter(&id, &user.user_id).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+/// Correct a signed encounter keeping the previous content, reason and
+/// author (the logged-in user)
+#[tauri::command]
+pub async fn amend_encounter(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+    amendment: EncounterAmendmentInput,
+) -> Result<EncounterHistory, String> {
+    let user = app_state.session_user().ok_or("Inicie sesión para enmendar la consulta")?;
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("amend_encounter: Using local PostgreSQL");
+        return pool.amend_encounter(&id, &user.user_id, &amendment).await;
+    }
+    Err("No database connection available".to_string())
+}
+
+#[tauri::command]
+pub async fn get_encounter_history(
+    app_state: State<'_, Arc<AppState>>,
+    id: String,
+    purpose: Option<String>,
+) -> Result<EncounterHistory, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_encounter_history: Using local PostgreSQL");
+        let result = pool.get_encounter_history(&id).await?;
+        pool.record_encounter_access(&id, "encounter_history", purpose.as_deref()).await;
+        return Ok(result);
+    }
+    Err("No database connection available".to_string())
+}
+
+// ============================================================
+// AUDIT LOG (BITÁCORA DE AUDITORÍA) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogEntry {
+    pub id: String,
+    pub occurred_at: String,
+    /// None for changes made outside the application
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    pub table_name: String,
+    pub record_id: String,
+    pub patient_id: Option<String>,
+    /// 'INSERT', 'UPDATE' or 'DELETE'
+    pub action: String,
+    pub before_data: Option<serde_json::Value>,
+    pub after_data: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AuditLogFilters {
+    pub patient_id: Option<String>,
+    pub user_id: Option<String>,
+    pub table_name: Option<String>,
+    /// YYYY-MM-DD, inclusive
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// ============================================================
+// AUDIT LOG (BITÁCORA DE AUDITORÍA) - COMMANDS
+// ============================================================
+
+/// Audit log entries, newest first, filtered by patient, user, table or dates
+#[tauri::command]
+pub async fn get_audit_log(
+    db: State<'_, Arc<Database>>,
+    app_state: State<'_, Arc<AppState>>,
+    filters: AuditLogFilters,
+) -> Result<Vec<AuditLogEntry>, String> {
+    let limit = filters.limit.unwrap_or(200).clamp(1, 1000);
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_audit_log: Using local PostgreSQL");
+        return pool.get_audit_log(&filters, limit).await;
+    }
+
+    // Fallback to SQLite cache (local writes only)
+    log::info!("get_audit_log: Using SQLite cache");
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.occurred_at, a.user_id, p.full_name, a.table_name, a.record_id,
+                    a.patient_id, a.action, a.before_data, a.after_data
+             FROM audit_log a
+             LEFT JOIN profiles p ON p.user_id = a.user_id
+             WHERE (?1 IS NULL OR a.patient_id = ?1)
+               AND (?2 IS NULL OR a.user_id = ?2)
+               AND (?3 IS NULL OR a.table_name = ?3)
+               AND (?4 IS NULL OR date(a.occurred_at) >= ?4)
+               AND (?5 IS NULL OR date(a.occurred_at) <= ?5)
+             ORDER BY a.id DESC
+             LIMIT ?6",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let json = |text: Option<String>| text.and_then(|t| serde_json::from_str(&t).ok());
+    let entries = stmt
+        .query_map(
+            rusqlite::params![
+                filters.patient_id,
+                filters.user_id,
+                filters.table_name,
+                filters.start_date,
+                filters.end_date,
+                limit,
+            ],
+            |row| {
+                Ok(AuditLogEntry {
+                    id: row.get::<_, i64>(0)?.to_string(),
+                    occurred_at: row.get(1)?,
+                    user_id: row.get(2)?,
+                    user_name: row.get(3)?,
+                    table_name: row.get(4)?,
+                    record_id: row.get(5)?,
+                    patient_id: row.get(6)?,
+                    action: row.get(7)?,
+                    before_data: json(row.get(8)?),
+                    after_data: json(row.get(9)?),
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(entries)
+}
+
+// ============================================================
+// PATIENT ACCESS LOG (REGISTRO DE ACCESOS) - TYPES
+// ============================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PatientAccessEntry {
+    pub id: i64,
+    pub accessed_at: String,
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    pub patient_id: String,
+    /// What was read: 'patient', 'encounters', 'studies', 'timeline'...
+    pub resource: String,
+    pub purpose: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnusualAccess {
+    /// 'fuera_de_sucursal': patient seen only at branches the user does not work in
+    /// 'volumen_alto': more patients opened in one day than the threshold
+    pub kind: String,
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub patient_id: Option<String>,
+    pub patient_name: Option<String>,
+    /// Day of the accesses ('volumen_alto' only)
+    pub day: Option<String>,
+    /// Accesses to the patient, or distinct patients opened that day
+    pub access_count: i64,
+    pub first_access: String,
+    pub last_access: String,
+}
+
+// ============================================================
+// PATIENT ACCESS LOG (REGISTRO DE ACCESOS) - COMMANDS
+// ============================================================
+
+/// Who opened a patient's clinical record, newest first
+#[tauri::command]
+pub async fn get_patient_access_log(
+    db: State<'_, Arc<Database>>,
+    app_state: State<'_, Arc<AppState>>,
+    patient_id: String,
+    limit: Option<i64>,
+) -> Result<Vec<PatientAccessEntry>, String> {
+    let limit = limit.unwrap_or(200).clamp(1, 1000);
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_patient_access_log: Using local PostgreSQL");
+        return pool.get_patient_access_log(&patient_id, limit).await;
+    }
+
+    // Fallback to SQLite cache (local reads only)
+    log::info!("get_patient_access_log: Using SQLite cache");
+    let conn = db.conn.lock().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.accessed_at, a.user_id, p.full_name, a.patient_id, a.resource, a.purpose
+             FROM patient_access_log a
+             LEFT JOIN profiles p ON p.user_id = a.user_id
+             WHERE a.patient_id = ?
+             ORDER BY a.id DESC
+             LIMIT ?",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let entries = stmt
+        .query_map(rusqlite::params![patient_id, limit], |row| {
+            Ok(PatientAccessEntry {
+                id: row.get(0)?,
+                accessed_at: row.get(1)?,
+                user_id: row.get(2)?,
+                user_name: row.get(3)?,
+                patient_id: row.get(4)?,
+                resource: row.get(5)?,
+                purpose: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(entries)
+}
+
+/// Unusual access report for the clinic director
+#[tauri::command]
+pub async fn get_unusual_patient_access(
+    app_state: State<'_, Arc<AppState>>,
+    start_date: String,
+    end_date: String,
+    daily_patient_limit: Option<i64>,
+) -> Result<Vec<UnusualAccess>, String> {
+    if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
+        log::info!("get_unusual_patient_access: Using local PostgreSQL");
+        return pool.get_unusual_patient_access(&start_date, &end_date, daily_patient_limit.unwrap_or(40)).await;
+    }
+    Err("No database connection available".to_string())
+}
+
 // ============================================================
 // ANALYTICS V2 - For Analytics.tsx offline support
 // ============================================================
@@ -4118,14 +6439,14 @@ pub async fn get_room_inventory_movements(
 pub struct AnalyticsServiceSales {
     pub service_type: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnalyticsPaymentMethod {
     pub metodo: String,
     pub cantidad: i64,
-    pub total: f64,
+    pub total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -4133,7 +6454,7 @@ pub struct AnalyticsItemDetail {
     pub item_id: String,
     pub item_name: String,
     pub total_quantity: i64,
-    pub total_revenue: f64,
+    pub total_revenue: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -4143,18 +6464,18 @@ pub struct ClinicalStatsWithRevenue {
     pub doctor_name: String,
     pub cantidad: i64,
     pub pacientes_unicos: i64,
-    pub revenue_real: f64,
-    pub revenue_estimado: f64,
-    pub revenue_total: f64,
+    pub revenue_real: Decimal,
+    pub revenue_estimado: Decimal,
+    pub revenue_total: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnalyticsInvoice {
     pub id: String,
-    pub total_amount: f64,
+    pub total_amount: Decimal,
     pub created_at: String,
     pub status: String,
-    pub discount_value: f64,
+    pub discount_value: Decimal,
     pub discount_type: Option<String>,
 }
 
@@ -4162,9 +6483,9 @@ pub struct AnalyticsInvoice {
 pub struct AnalyticsClosure {
     pub id: String,
     pub closure_date: String,
-    pub total_invoiced: f64,
-    pub total_collected: f64,
-    pub total_pending: f64,
+    pub total_invoiced: Decimal,
+    pub total_collected: Decimal,
+    pub total_pending: Decimal,
     pub closed_by: Option<String>,
     pub user_name: String,
 }
@@ -4192,7 +6513,7 @@ pub struct DoctorActivityDetail {
     pub appointment_date: String,
     pub is_invoiced: bool,
     pub is_courtesy: bool,
-    pub invoice_amount: f64,
+    pub invoice_amount: Decimal,
     pub surgery_type: Option<String>,
     pub procedure_type: Option<String>,
 }
@@ -4239,6 +6560,15 @@ pub struct ResearchFilters {
     pub has_visual_acuity: Option<bool>,
     pub has_subjective_refraction: Option<bool>,
     pub has_prescription: Option<bool>,
+    /// Best available acuity (cc, else sc) in logMAR, of the eye given in
+    /// `logmar_eye` or of the better-seeing eye when it is not set
+    #[serde(default)]
+    pub min_logmar: Option<f64>,
+    #[serde(default)]
+    pub max_logmar: Option<f64>,
+    /// "OD" or "OI"
+    #[serde(default)]
+    pub logmar_eye: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -4259,6 +6589,7 @@ pub struct ClinicalResearchRow {
     pub chief_complaint: Option<String>,
     // Diagnosis
     pub diagnosis_summary: Option<String>,
+    pub diagnosis_codes: Option<String>,
     pub treatment_plan: Option<String>,
     pub recommended_surgeries: Option<String>,
     pub recommended_studies: Option<String>,
@@ -4284,10 +6615,24 @@ pub struct ClinicalResearchRow {
     pub oi_final_prescription: Option<String>,
     pub oi_slit_lamp: Option<String>,
     pub oi_fundus: Option<String>,
+    // Visual acuity in logMAR
+    pub od_avsc_logmar: Option<f64>,
+    pub od_avcc_logmar: Option<f64>,
+    pub oi_avsc_logmar: Option<f64>,
+    pub oi_avcc_logmar: Option<f64>,
+    // Refraction (final Rx, else subjective, else objective)
+    pub od_spherical_equivalent: Option<f64>,
+    pub oi_spherical_equivalent: Option<f64>,
+    pub anisometropia: Option<f64>,
+    pub is_anisometropic: Option<bool>,
     // Surgery info
     pub surgery_type: Option<String>,
     pub surgery_eye: Option<String>,
     pub surgery_date: Option<String>,
+    // Operated eye acuity (logMAR) before and after surgery
+    pub preop_logmar: Option<f64>,
+    pub postop_logmar: Option<f64>,
+    pub logmar_change: Option<f64>,
     // Procedure info
     pub procedure_type: Option<String>,
     pub procedure_eye: Option<String>,
@@ -4472,10 +6817,14 @@ pub async fn get_analytics_doctors(
 pub async fn get_clinical_research_data(
     app_state: State<'_, Arc<AppState>>,
     filters: ResearchFilters,
+    purpose: Option<String>,
 ) -> Result<Vec<ClinicalResearchRow>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_clinical_research_data: Using local PostgreSQL");
-        return pool.get_clinical_research_data(&filters).await;
+        let rows = pool.get_clinical_research_data(&filters).await?;
+        let patient_ids: Vec<String> = rows.iter().filter_map(|r| r.patient_id.clone()).collect();
+        pool.record_patients_access(&patient_ids, "research", purpose.as_deref()).await;
+        return Ok(rows);
     }
     Err("No database connection available".to_string())
 }
@@ -4485,10 +6834,14 @@ pub async fn get_clinical_research_data(
 pub async fn get_clinical_research_data_by_patient(
     app_state: State<'_, Arc<AppState>>,
     filters: ResearchFilters,
+    purpose: Option<String>,
 ) -> Result<Vec<ClinicalResearchPatient>, String> {
     if let Some(pool) = app_state.connection_manager.get_postgres_pool().await {
         log::info!("get_clinical_research_data_by_patient: Using local PostgreSQL");
-        return pool.get_clinical_research_data_by_patient(&filters).await;
+        let patients = pool.get_clinical_research_data_by_patient(&filters).await?;
+        let patient_ids: Vec<String> = patients.iter().map(|p| p.patient_id.clone()).collect();
+        pool.record_patients_access(&patient_ids, "research", purpose.as_deref()).await;
+        return Ok(patients);
     }
     Err("No database connection available".to_string())
 }
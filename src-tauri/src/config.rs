@@ -10,6 +10,7 @@ pub struct AppConfig {
     pub supabase: SupabaseConfig,
     pub local_server: Option<LocalServerConfig>,
     pub local_storage: Option<LocalStorageConfig>,
+    pub fel: Option<FelConfig>,
 }
 
 /// Local file storage configuration (SMB share on clinic server)
@@ -26,6 +27,43 @@ pub struct LocalStorageConfig {
     pub enabled: bool,
 }
 
+/// Electronic invoicing (FEL) issuer and certifier configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FelConfig {
+    /// Issuer NIT, without dashes (e.g., 12345678K)
+    pub nit: String,
+    /// Legal name registered with SAT
+    pub nombre_emisor: String,
+    pub nombre_comercial: String,
+    /// Establishment code assigned by SAT
+    pub codigo_establecimiento: String,
+    pub direccion: String,
+    pub codigo_postal: String,
+    pub municipio: String,
+    pub departamento: String,
+    /// IVA affiliation: GEN (general) or PEQ (pequeño contribuyente)
+    #[serde(default = "default_afiliacion_iva")]
+    pub afiliacion_iva: String,
+    /// PEM certificate used to sign documents
+    pub certificate_path: String,
+    /// PKCS#8 PEM private key matching the certificate
+    pub private_key_path: String,
+    /// Certifier provider name ("mock" for local testing)
+    #[serde(default = "default_fel_provider")]
+    pub provider: String,
+    /// Whether electronic invoicing is enabled
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_afiliacion_iva() -> String {
+    "GEN".to_string()
+}
+
+fn default_fel_provider() -> String {
+    "mock".to_string()
+}
+
 /// Supabase cloud configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SupabaseConfig {
@@ -59,6 +97,7 @@ impl Default for AppConfig {
             },
             local_server: None,
             local_storage: None,
+            fel: None,
         }
     }
 }
@@ -134,6 +173,20 @@ anon_key = "sb_publishable_NtN71TmE2M_nuh60B6YvjQ_8rje5HuB"
 # user = "centrovision_app"
 # password = "your-password"
 # enabled = true
+
+# Optional: Electronic invoicing (FEL)
+# [fel]
+# nit = "12345678K"
+# nombre_emisor = "CENTRO VISION, S.A."
+# nombre_comercial = "Centro Vision"
+# codigo_establecimiento = "1"
+# direccion = "Ciudad"
+# codigo_postal = "01001"
+# municipio = "Guatemala"
+# departamento = "Guatemala"
+# certificate_path = "C:/CentroVision/fel/certificado.pem"
+# private_key_path = "C:/CentroVision/fel/llave.pem"
+# provider = "mock"
 "#;
 
         std::fs::write(&config_path, default_config)
@@ -159,6 +212,11 @@ anon_key = "sb_publishable_NtN71TmE2M_nuh60B6YvjQ_8rje5HuB"
             .unwrap_or(false)
     }
 
+    /// Get the FEL configuration if configured and enabled
+    pub fn get_fel_config(&self) -> Option<&FelConfig> {
+        self.fel.as_ref().filter(|f| f.enabled)
+    }
+
     /// Get the local storage SMB path if configured
     pub fn get_storage_path(&self) -> Option<&str> {
         self.local_storage
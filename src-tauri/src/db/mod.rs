@@ -3,6 +3,8 @@ use std::sync::Mutex;
 
 pub struct Database {
     pub conn: Mutex<Connection>,
+    /// Signed-in user, recorded in audit_log
+    audit_user: Mutex<Option<String>>,
 }
 
 impl Database {
@@ -14,6 +16,7 @@ impl Database {
 
         Ok(Database {
             conn: Mutex::new(conn),
+            audit_user: Mutex::new(None),
         })
     }
 
@@ -25,9 +28,34 @@ impl Database {
         conn.execute_batch(schema)?;
 
         log::info!("Database schema initialized successfully");
+        drop(conn);
+
+        let loaded = self.load_icd10_catalog()?;
+        log::info!("ICD-10 catalog loaded into SQLite cache ({} codes)", loaded);
         Ok(())
     }
 
+    /// Load (or refresh) the bundled ICD-10 catalog for offline searches
+    pub fn load_icd10_catalog(&self) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let entries = crate::clinical::icd10::entries();
+        for entry in &entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO icd10_codes (code, description, synonyms, search_text, lateral) VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    entry.code,
+                    entry.description,
+                    entry.synonyms.join(";"),
+                    entry.search_text(),
+                    entry.is_lateral() as i32,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(entries.len())
+    }
+
     pub fn get_sync_metadata(&self, key: &str) -> Result<Option<String>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare("SELECT value FROM sync_metadata WHERE key = ?")?;
@@ -67,6 +95,54 @@ impl Database {
         )?;
         Ok(())
     }
+
+    /// Set (or clear on logout) the user recorded in the audit log
+    pub fn set_audit_user(&self, user_id: Option<&str>) {
+        *self.audit_user.lock().unwrap() = user_id.map(String::from);
+    }
+
+    /// Roles of a user from the synced user_roles cache
+    pub fn get_user_roles(&self, user_id: &str) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT role FROM user_roles WHERE user_id = ?")?;
+        let roles = stmt
+            .query_map([user_id], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(roles)
+    }
+
+    /// Record a local write with the row before and after it (JSON)
+    pub fn add_audit_log(
+        &self,
+        table_name: &str,
+        record_id: &str,
+        patient_id: Option<&str>,
+        action: &str,
+        before: Option<&str>,
+        after: Option<&str>,
+    ) -> Result<()> {
+        let user_id = self.audit_user.lock().unwrap().clone();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO audit_log (user_id, table_name, record_id, patient_id, action, before_data, after_data)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![user_id, table_name, record_id, patient_id, action, before, after],
+        )?;
+        Ok(())
+    }
+
+    /// Record a read of a patient's record by the signed-in user
+    pub fn add_patient_access(&self, patient_id: &str, resource: &str, purpose: Option<&str>) -> Result<()> {
+        let user_id = self.audit_user.lock().unwrap().clone();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO patient_access_log (user_id, patient_id, resource, purpose)
+             VALUES (?, ?, ?, ?)",
+            rusqlite::params![user_id, patient_id, resource, purpose],
+        )?;
+        Ok(())
+    }
 }
 
 // Helper function to convert SQLite row to JSON
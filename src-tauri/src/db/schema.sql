@@ -255,6 +255,15 @@ CREATE TABLE IF NOT EXISTS diagnoses (
 
 CREATE INDEX IF NOT EXISTS idx_diagnoses_encounter ON diagnoses(encounter_id);
 
+-- Catálogo CIE-10 (se carga desde el catálogo incluido en la aplicación)
+CREATE TABLE IF NOT EXISTS icd10_codes (
+    code TEXT PRIMARY KEY,
+    description TEXT NOT NULL,
+    synonyms TEXT,
+    search_text TEXT NOT NULL,
+    lateral INTEGER NOT NULL DEFAULT 0
+);
+
 -- ============================================================
 -- CONFIGURACIÓN DE APP
 -- ============================================================
@@ -270,6 +279,52 @@ CREATE TABLE IF NOT EXISTS app_settings (
     local_only INTEGER DEFAULT 0
 );
 
+-- ============================================================
+-- AUDITORÍA
+-- ============================================================
+
+-- Escrituras locales (modo sin servidor); en PostgreSQL las registran
+-- triggers en audit_logs
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    occurred_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    user_id TEXT,
+    table_name TEXT NOT NULL,
+    record_id TEXT NOT NULL,
+    patient_id TEXT,
+    action TEXT NOT NULL CHECK (action IN ('INSERT', 'UPDATE', 'DELETE')),
+    before_data TEXT,
+    after_data TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_patient ON audit_log(patient_id, occurred_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_user ON audit_log(user_id, occurred_at);
+
+-- La bitácora no se modifica ni se borra
+CREATE TRIGGER IF NOT EXISTS prevent_audit_log_update
+    BEFORE UPDATE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'La bitácora de auditoría no puede modificarse ni eliminarse');
+    END;
+
+CREATE TRIGGER IF NOT EXISTS prevent_audit_log_delete
+    BEFORE DELETE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'La bitácora de auditoría no puede modificarse ni eliminarse');
+    END;
+
+-- Lecturas de expedientes (quién abrió qué paciente)
+CREATE TABLE IF NOT EXISTS patient_access_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    accessed_at TEXT DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    user_id TEXT,
+    patient_id TEXT NOT NULL,
+    resource TEXT NOT NULL,
+    purpose TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_patient_access_log_patient ON patient_access_log(patient_id, accessed_at);
+
 -- ============================================================
 -- TRIGGERS PARA updated_at
 -- ============================================================
@@ -0,0 +1,312 @@
+// Exclusive XML canonicalization without comments (exc-c14n,
+// http://www.w3.org/2001/10/xml-exc-c14n#) of one element and its content
+//
+// The element is re-serialized from the parsed document: namespace
+// declarations appear on the first output element that uses them (including
+// those declared on ancestors), attributes are sorted, empty elements get an
+// end tag, text and attribute values use the canonical escapes, comments are
+// dropped and an enveloped ds:Signature is left out. The digest and the
+// signature of FEL documents are computed over this form, so they verify
+// regardless of how the XML was serialized.
+
+use std::borrow::Cow;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const DSIG_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+/// Canonical form of the element with attribute `ID="{id}"`
+pub fn canonicalize_by_id(xml: &str, id: &str) -> Result<String, String> {
+    canonicalize(xml, |element| element.id.as_deref() == Some(id))
+        .map_err(|e| format!("No se pudo canonicalizar el elemento {}: {}", id, e))
+}
+
+/// Canonical form of the first element with qualified name `name`, e.g. "ds:SignedInfo"
+pub fn canonicalize_element(xml: &str, name: &str) -> Result<String, String> {
+    canonicalize(xml, |element| element.name == name)
+        .map_err(|e| format!("No se pudo canonicalizar {}: {}", name, e))
+}
+
+/// A parsed start tag
+struct Element {
+    name: String,
+    id: Option<String>,
+    /// Namespace declarations, prefix ("" for the default namespace) and URI
+    declarations: Vec<(String, String)>,
+    /// Qualified name and value of the other attributes
+    attributes: Vec<(String, String)>,
+}
+
+fn prefix_of(qname: &str) -> &str {
+    qname.split_once(':').map(|(prefix, _)| prefix).unwrap_or("")
+}
+
+fn local_of(qname: &str) -> &str {
+    qname.split_once(':').map(|(_, local)| local).unwrap_or(qname)
+}
+
+fn parse_start(start: &BytesStart) -> Result<Element, String> {
+    let name = std::str::from_utf8(start.name().as_ref()).map_err(|e| e.to_string())?.to_string();
+    let mut element = Element { name, id: None, declarations: Vec::new(), attributes: Vec::new() };
+
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let key = std::str::from_utf8(attribute.key.as_ref()).map_err(|e| e.to_string())?.to_string();
+        let raw = std::str::from_utf8(&attribute.value).map_err(|e| e.to_string())?;
+        // Attribute value normalization: literal white space becomes a space
+        let normalized = raw.replace("\r\n", " ").replace(['\r', '\n', '\t'], " ");
+        let value = quick_xml::escape::unescape(&normalized).map_err(|e| e.to_string())?.into_owned();
+
+        if key == "xmlns" {
+            element.declarations.push((String::new(), value));
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            element.declarations.push((prefix.to_string(), value));
+        } else {
+            if key == "ID" {
+                element.id = Some(value.clone());
+            }
+            element.attributes.push((key, value));
+        }
+    }
+    Ok(element)
+}
+
+/// Innermost value for `prefix` in a stack of declaration frames
+fn lookup<'a>(frames: &'a [Vec<(String, String)>], prefix: &str) -> Option<&'a str> {
+    frames
+        .iter()
+        .rev()
+        .find_map(|frame| frame.iter().find(|(p, _)| p == prefix).map(|(_, uri)| uri.as_str()))
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Write the start tag of an output element; returns the declarations it renders
+fn write_start(
+    element: &Element,
+    in_scope: &[Vec<(String, String)>],
+    rendered: &[Vec<(String, String)>],
+    out: &mut String,
+) -> Result<Vec<(String, String)>, String> {
+    let namespace = |prefix: &str| -> Result<String, String> {
+        match prefix {
+            "xml" => Ok(XML_NAMESPACE.to_string()),
+            "" => Ok(lookup(in_scope, "").unwrap_or("").to_string()),
+            p => lookup(in_scope, p)
+                .map(str::to_string)
+                .ok_or_else(|| format!("prefijo sin declarar: {}", p)),
+        }
+    };
+
+    // Prefixes visibly utilized: the element's own and those of its attributes
+    let mut utilized: Vec<&str> = vec![prefix_of(&element.name)];
+    for (qname, _) in &element.attributes {
+        let prefix = prefix_of(qname);
+        if !prefix.is_empty() && !utilized.contains(&prefix) {
+            utilized.push(prefix);
+        }
+    }
+
+    let mut declarations: Vec<(String, String)> = Vec::new();
+    for prefix in utilized.into_iter().filter(|p| *p != "xml") {
+        let uri = namespace(prefix)?;
+        let current = lookup(rendered, prefix);
+        let needed = if prefix.is_empty() && uri.is_empty() {
+            current.is_some_and(|c| !c.is_empty())
+        } else {
+            current != Some(uri.as_str())
+        };
+        if needed {
+            declarations.push((prefix.to_string(), uri));
+        }
+    }
+    declarations.sort();
+
+    let mut attributes: Vec<(String, &str, &str, &str)> = Vec::with_capacity(element.attributes.len());
+    for (qname, value) in &element.attributes {
+        let prefix = prefix_of(qname);
+        let uri = if prefix.is_empty() { String::new() } else { namespace(prefix)? };
+        attributes.push((uri, local_of(qname), qname, value));
+    }
+    attributes.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+    out.push('<');
+    out.push_str(&element.name);
+    for (prefix, uri) in &declarations {
+        out.push_str(if prefix.is_empty() { " xmlns" } else { " xmlns:" });
+        out.push_str(prefix);
+        out.push_str("=\"");
+        escape_attribute(uri, out);
+        out.push('"');
+    }
+    for (_, _, qname, value) in &attributes {
+        out.push(' ');
+        out.push_str(qname);
+        out.push_str("=\"");
+        escape_attribute(value, out);
+        out.push('"');
+    }
+    out.push('>');
+    Ok(declarations)
+}
+
+fn canonicalize(xml: &str, is_apex: impl Fn(&Element) -> bool) -> Result<String, String> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().expand_empty_elements = true;
+
+    let mut out = String::new();
+    // Declarations of every open element, and of every open output element
+    let mut in_scope: Vec<Vec<(String, String)>> = Vec::new();
+    let mut rendered: Vec<Vec<(String, String)>> = Vec::new();
+    // Depth of the open elements inside the apex (0 before and after it)
+    let mut depth = 0usize;
+    // Depth at which an enveloped signature being skipped was opened
+    let mut skipping: Option<usize> = None;
+
+    loop {
+        let event = reader.read_event().map_err(|e| e.to_string())?;
+        match event {
+            Event::Start(start) => {
+                let element = parse_start(&start)?;
+                in_scope.push(element.declarations.clone());
+                if depth == 0 && !is_apex(&element) {
+                    continue;
+                }
+                depth += 1;
+                if skipping.is_some() {
+                    continue;
+                }
+                let is_signature = local_of(&element.name) == "Signature"
+                    && lookup(&in_scope, prefix_of(&element.name)) == Some(DSIG_NAMESPACE);
+                if depth > 1 && is_signature {
+                    skipping = Some(depth);
+                    continue;
+                }
+                let declarations = write_start(&element, &in_scope, &rendered, &mut out)?;
+                rendered.push(declarations);
+            }
+            Event::End(end) => {
+                in_scope.pop();
+                if depth == 0 {
+                    continue;
+                }
+                if skipping.is_none() {
+                    out.push_str("</");
+                    out.push_str(std::str::from_utf8(end.name().as_ref()).map_err(|e| e.to_string())?);
+                    out.push('>');
+                    rendered.pop();
+                }
+                if skipping == Some(depth) {
+                    skipping = None;
+                }
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(out);
+                }
+            }
+            Event::Text(text) if depth > 0 && skipping.is_none() => {
+                escape_text(&text.xml10_content().map_err(|e| e.to_string())?, &mut out);
+            }
+            Event::CData(data) if depth > 0 && skipping.is_none() => {
+                escape_text(&data.xml10_content().map_err(|e| e.to_string())?, &mut out);
+            }
+            Event::GeneralRef(reference) if depth > 0 && skipping.is_none() => {
+                let resolved: Cow<str> = match reference.resolve_char_ref().map_err(|e| e.to_string())? {
+                    Some(c) => Cow::Owned(c.to_string()),
+                    None => {
+                        let name = reference.decode().map_err(|e| e.to_string())?;
+                        match quick_xml::escape::resolve_predefined_entity(&name) {
+                            Some(value) => Cow::Borrowed(value),
+                            None => return Err(format!("entidad no soportada: &{};", name)),
+                        }
+                    }
+                };
+                escape_text(&resolved, &mut out);
+            }
+            Event::PI(pi) if depth > 0 && skipping.is_none() => {
+                out.push_str("<?");
+                out.push_str(std::str::from_utf8(pi.target()).map_err(|e| e.to_string())?);
+                let content = std::str::from_utf8(pi.content()).map_err(|e| e.to_string())?.trim_start();
+                if !content.is_empty() {
+                    out.push(' ');
+                    out.push_str(content);
+                }
+                out.push_str("?>");
+            }
+            Event::Eof => return Err("elemento no encontrado".to_string()),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_namespaces() {
+        // W3C Exclusive XML Canonicalization 1.0, section 2.2: n0 and n3 of the
+        // ancestor are not rendered, n1 goes on the apex and n3 where it is used
+        let xml = "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">\n  \
+                   <n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+                   <n3:stuff xmlns:n3=\"ftp://example.org\"/>\n  </n1:elem2>\n</n0:local>";
+        assert_eq!(
+            canonicalize_element(xml, "n1:elem2").unwrap(),
+            "<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+             <n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>\n  </n1:elem2>"
+        );
+    }
+
+    #[test]
+    fn test_canonical_serialization() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                   <dte:GTDocumento xmlns:dte=\"urn:dte\" xmlns:x=\"urn:x\">\
+                   <dte:DatosEmision b='2' ID=\"DatosEmision\" a=\"&apos;&gt;\"><!-- nota -->\
+                   <dte:Item x:n=\"1\" Total='5'/>\
+                   <dte:Desc>A &amp; B &#62; C<![CDATA[ <D> ]]></dte:Desc>\
+                   </dte:DatosEmision></dte:GTDocumento>";
+        assert_eq!(
+            canonicalize_by_id(xml, "DatosEmision").unwrap(),
+            "<dte:DatosEmision xmlns:dte=\"urn:dte\" ID=\"DatosEmision\" a=\"'>\" b=\"2\">\
+             <dte:Item xmlns:x=\"urn:x\" Total=\"5\" x:n=\"1\"></dte:Item>\
+             <dte:Desc>A &amp; B &gt; C &lt;D&gt; </dte:Desc></dte:DatosEmision>"
+        );
+        assert!(canonicalize_by_id(xml, "Otro").is_err());
+    }
+
+    #[test]
+    fn test_enveloped_signature_is_left_out() {
+        let xml = "<a:Doc xmlns:a=\"urn:a\" ID=\"Doc\"><a:Total>1</a:Total>\
+                   <ds:Signature xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\"><ds:SignedInfo/></ds:Signature></a:Doc>";
+        assert_eq!(
+            canonicalize_by_id(xml, "Doc").unwrap(),
+            "<a:Doc xmlns:a=\"urn:a\" ID=\"Doc\"><a:Total>1</a:Total></a:Doc>"
+        );
+    }
+}
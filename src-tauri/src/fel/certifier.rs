@@ -0,0 +1,150 @@
+// FEL certifier providers
+//
+// A certifier receives the signed DTE, validates it against SAT and returns
+// the authorization number. Each provider (Infile, Digifact, ...) implements
+// `FelCertifier`; `MockCertifier` certifies locally for tests and training.
+
+use async_trait::async_trait;
+
+use crate::config::FelConfig;
+use crate::pdf;
+
+use super::signing;
+
+/// Result of certifying a document
+#[derive(Debug, Clone)]
+pub struct Certification {
+    /// Authorization number (UUID) assigned by SAT
+    pub authorization_uuid: String,
+    pub serie: String,
+    pub numero: String,
+    /// Certification timestamp, ISO 8601 with offset
+    pub certified_at: String,
+    /// XML returned by the certifier, including its certification block
+    pub certified_xml: String,
+    /// Printable representation, when the certifier provides one
+    pub pdf: Option<Vec<u8>>,
+}
+
+/// Result of cancelling a certified document
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    pub cancelled_at: String,
+    pub certified_xml: String,
+}
+
+#[async_trait]
+pub trait FelCertifier: Send + Sync {
+    /// Provider name stored with each document
+    fn name(&self) -> &str;
+
+    /// Certify a signed invoice document
+    async fn certify(&self, signed_xml: &str) -> Result<Certification, String>;
+
+    /// Certify a signed cancellation document
+    async fn cancel(&self, signed_xml: &str) -> Result<Cancellation, String>;
+}
+
+/// Build the certifier configured for this installation
+pub fn from_config(config: &FelConfig) -> Result<Box<dyn FelCertifier>, String> {
+    match config.provider.as_str() {
+        "mock" => Ok(Box::new(MockCertifier)),
+        other => Err(format!("Certificador FEL no soportado: {}", other)),
+    }
+}
+
+/// Local certifier: validates the signature and issues a random authorization
+pub struct MockCertifier;
+
+#[async_trait]
+impl FelCertifier for MockCertifier {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn certify(&self, signed_xml: &str) -> Result<Certification, String> {
+        signing::verify_digest(signed_xml)?;
+
+        let authorization = uuid::Uuid::new_v4().to_string().to_uppercase();
+        let serie = authorization[..8].to_string();
+        let numero = u32::from_str_radix(&authorization[9..13], 16)
+            .map_err(|e| e.to_string())?
+            .to_string();
+        let certified_at = super::now_gt();
+
+        let certified_xml = append_certification(
+            signed_xml,
+            &format!(
+                "<dte:Certificacion><dte:NITCertificador>00000000</dte:NITCertificador>\
+                 <dte:NombreCertificador>CERTIFICADOR DE PRUEBA</dte:NombreCertificador>\
+                 <dte:NumeroAutorizacion Serie=\"{}\" Numero=\"{}\">{}</dte:NumeroAutorizacion>\
+                 <dte:FechaHoraCertificacion>{}</dte:FechaHoraCertificacion></dte:Certificacion>",
+                serie, numero, authorization, certified_at
+            ),
+        );
+
+        let pdf = pdf::text_document(
+            "DOCUMENTO TRIBUTARIO ELECTRÓNICO (PRUEBA)",
+            &[
+                format!("Autorización: {}", authorization),
+                format!("Serie: {}  Número: {}", serie, numero),
+                format!("Certificado: {}", certified_at),
+                "Documento certificado localmente, sin validez fiscal.".to_string(),
+            ],
+        );
+
+        Ok(Certification {
+            authorization_uuid: authorization,
+            serie,
+            numero,
+            certified_at,
+            certified_xml,
+            pdf: Some(pdf),
+        })
+    }
+
+    async fn cancel(&self, signed_xml: &str) -> Result<Cancellation, String> {
+        signing::verify_digest(signed_xml)?;
+        let cancelled_at = super::now_gt();
+        let certified_xml = append_certification(
+            signed_xml,
+            &format!(
+                "<dte:Certificacion><dte:FechaHoraCertificacion>{}</dte:FechaHoraCertificacion></dte:Certificacion>",
+                cancelled_at
+            ),
+        );
+        Ok(Cancellation { cancelled_at, certified_xml })
+    }
+}
+
+/// Insert the certification block right after the signed SAT element
+fn append_certification(signed_xml: &str, block: &str) -> String {
+    match signed_xml.find("</dte:SAT>") {
+        Some(pos) => {
+            let pos = pos + "</dte:SAT>".len();
+            format!("{}{}{}", &signed_xml[..pos], block, &signed_xml[pos..])
+        }
+        None => signed_xml.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_certifier_requires_intact_signature() {
+        let signer = signing::test_signer();
+        let xml = r#"<dte:GTDocumento xmlns:dte="http://www.sat.gob.gt/dte/fel/0.2.0"><dte:SAT><dte:DatosEmision ID="DatosEmision"><dte:GranTotal>112.00</dte:GranTotal></dte:DatosEmision></dte:SAT></dte:GTDocumento>"#;
+        let signed = signer.sign(xml, "DatosEmision").unwrap();
+
+        let certification = MockCertifier.certify(&signed).await.unwrap();
+        assert_eq!(certification.serie.len(), 8);
+        assert!(certification.authorization_uuid.starts_with(&certification.serie));
+        assert!(certification.certified_xml.contains("</dte:SAT><dte:Certificacion>"));
+        assert!(certification.pdf.is_some());
+
+        let tampered = signed.replace("112.00", "12.00");
+        assert!(MockCertifier.certify(&tampered).await.is_err());
+    }
+}
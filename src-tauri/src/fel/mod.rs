@@ -0,0 +1,215 @@
+// Electronic invoicing (FEL - Factura Electrónica en Línea, SAT Guatemala)
+//
+// Flow: invoice + items -> DTE XML -> signed with the local certificate ->
+// certified by the configured provider -> stored in fel_documents with the
+// authorization UUID, certified XML and PDF.
+
+pub mod c14n;
+pub mod certifier;
+pub mod signing;
+pub mod xml;
+
+use crate::commands::FelDocument;
+use crate::config::FelConfig;
+use crate::postgres::PostgresPool;
+
+use self::signing::FelSigner;
+
+/// Receiver id used for sales without NIT
+pub const CONSUMIDOR_FINAL: &str = "CF";
+
+/// Invoice receiver as printed on the DTE
+#[derive(Debug, Clone, PartialEq)]
+pub struct FelReceiver {
+    pub nit: String,
+    pub name: String,
+}
+
+impl FelReceiver {
+    /// Build a receiver from UI input. Empty NIT means consumidor final.
+    pub fn new(nit: Option<&str>, name: Option<&str>) -> Result<Self, String> {
+        let nit = normalize_nit(nit.unwrap_or(""));
+        if nit.is_empty() || nit == CONSUMIDOR_FINAL {
+            return Ok(Self {
+                nit: CONSUMIDOR_FINAL.to_string(),
+                name: name
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .unwrap_or("Consumidor Final")
+                    .to_string(),
+            });
+        }
+
+        if !is_valid_nit(&nit) {
+            return Err(format!("NIT inválido: {}", nit));
+        }
+        let name = name
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| "El nombre del receptor es requerido cuando se indica NIT".to_string())?;
+
+        Ok(Self { nit, name: name.to_string() })
+    }
+}
+
+/// Uppercase and strip dashes/spaces (e.g. "1234567-k" -> "1234567K")
+pub fn normalize_nit(nit: &str) -> String {
+    nit.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Validate a normalized NIT with the SAT modulo 11 check digit
+pub fn is_valid_nit(nit: &str) -> bool {
+    if nit.len() < 2 {
+        return false;
+    }
+    let (body, check) = nit.split_at(nit.len() - 1);
+    if !body.chars().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+
+    let sum: u32 = body
+        .chars()
+        .rev()
+        .enumerate()
+        .map(|(idx, c)| c.to_digit(10).unwrap_or(0) * (idx as u32 + 2))
+        .sum();
+    let expected = match (11 - sum % 11) % 11 {
+        10 => 'K',
+        digit => char::from_digit(digit, 10).unwrap_or('0'),
+    };
+    check.starts_with(expected)
+}
+
+/// Timestamp as written on SAT documents, e.g. "2026-03-31T21:00:00-06:00"
+fn sat_timestamp(instant: chrono::DateTime<chrono::FixedOffset>) -> String {
+    instant.format("%Y-%m-%dT%H:%M:%S%:z").to_string()
+}
+
+/// Current time in Guatemala (UTC-6, no DST), formatted for SAT documents
+pub fn now_gt() -> String {
+    sat_timestamp(crate::timezone::now())
+}
+
+/// RFC 3339 timestamp (as stored, in UTC) converted to Guatemala time for SAT documents
+pub fn to_gt(timestamp: &str) -> Result<String, String> {
+    let instant = chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|e| format!("Fecha inválida ({}): {}", timestamp, e))?;
+    Ok(sat_timestamp(crate::timezone::local(instant.with_timezone(&chrono::Utc))))
+}
+
+/// Generate, sign and certify the DTE for an invoice, storing the result
+pub async fn certify_invoice(
+    pool: &PostgresPool,
+    config: &FelConfig,
+    invoice_id: &str,
+    receiver: &FelReceiver,
+) -> Result<FelDocument, String> {
+    if let Some(existing) = pool.get_fel_document(invoice_id).await? {
+        if existing.status == "certificado" {
+            return Err(format!(
+                "La factura ya fue certificada (autorización {})",
+                existing.authorization_uuid
+            ));
+        }
+    }
+
+    let invoice = pool
+        .get_invoice_by_id(invoice_id)
+        .await?
+        .ok_or_else(|| "Factura no encontrada".to_string())?;
+    if invoice.status == "cancelada" {
+        return Err("No se puede certificar una factura cancelada".to_string());
+    }
+    let items = pool.get_invoice_items(invoice_id).await?;
+    if items.is_empty() {
+        return Err("La factura no tiene líneas".to_string());
+    }
+
+    let document = xml::build_invoice_xml(config, receiver, &invoice, &items, &now_gt());
+    let signer = FelSigner::from_pem_files(&config.private_key_path, &config.certificate_path)?;
+    let signed_xml = signer.sign(&document, xml::DTE_SIGNED_ID)?;
+
+    let certifier = certifier::from_config(config)?;
+    let certification = certifier.certify(&signed_xml).await?;
+    log::info!(
+        "FEL: invoice {} certified by {} ({})",
+        invoice.invoice_number,
+        certifier.name(),
+        certification.authorization_uuid
+    );
+
+    pool.create_fel_document(invoice_id, receiver, certifier.name(), &signed_xml, &certification)
+        .await
+}
+
+/// Cancel (anular) the certified DTE of an invoice and mark the invoice cancelled
+pub async fn cancel_invoice(
+    pool: &PostgresPool,
+    config: &FelConfig,
+    invoice_id: &str,
+    reason: &str,
+) -> Result<FelDocument, String> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err("El motivo de anulación es requerido".to_string());
+    }
+
+    let document = pool
+        .get_fel_document(invoice_id)
+        .await?
+        .filter(|d| d.status == "certificado")
+        .ok_or_else(|| "La factura no tiene un documento FEL certificado".to_string())?;
+
+    let cancellation_xml = xml::build_cancellation_xml(
+        &config.nit,
+        &document.receiver_nit,
+        &document.authorization_uuid,
+        &to_gt(&document.certified_at)?,
+        &now_gt(),
+        reason,
+    );
+    let signer = FelSigner::from_pem_files(&config.private_key_path, &config.certificate_path)?;
+    let signed_xml = signer.sign(&cancellation_xml, xml::ANULACION_SIGNED_ID)?;
+
+    let certifier = certifier::from_config(config)?;
+    let cancellation = certifier.cancel(&signed_xml).await?;
+
+    pool.cancel_fel_document(&document.id, reason, &cancellation).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nit_validation() {
+        assert!(is_valid_nit(&normalize_nit("576937-k")));
+        assert!(is_valid_nit(&normalize_nit("1234567-9")));
+        assert!(!is_valid_nit("12345675"));
+        assert!(!is_valid_nit("A1"));
+    }
+
+    #[test]
+    fn test_receiver_defaults_to_consumidor_final() {
+        let receiver = FelReceiver::new(None, None).unwrap();
+        assert_eq!(receiver.nit, "CF");
+        assert_eq!(receiver.name, "Consumidor Final");
+
+        assert!(FelReceiver::new(Some("1234567-9"), None).is_err());
+        assert!(FelReceiver::new(Some("1234567-5"), Some("Juan")).is_err());
+        assert_eq!(
+            FelReceiver::new(Some("1234567-9"), Some("Juan")).unwrap().nit,
+            "12345679"
+        );
+    }
+
+    #[test]
+    fn test_certification_time_in_guatemala() {
+        // Certified on the evening of the 17th in Guatemala, stored in UTC
+        assert_eq!(to_gt("2026-10-18T03:30:00+00:00").unwrap(), "2026-10-17T21:30:00-06:00");
+        assert!(to_gt("18/10/2026").is_err());
+    }
+}
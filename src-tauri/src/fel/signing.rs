@@ -0,0 +1,208 @@
+// XML digital signature (RSA-SHA256, enveloped) for FEL documents
+//
+// The digest covers the exclusive canonical form (`c14n`) of the element
+// referenced by ID, and the signature the canonical form of SignedInfo, as
+// declared in CanonicalizationMethod. The certifier validates the signature
+// again before certifying.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+use super::c14n;
+
+const DSIG_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+/// Signing key and certificate loaded from the clinic workstation
+pub struct FelSigner {
+    signing_key: SigningKey<Sha256>,
+    public_key: RsaPublicKey,
+    /// DER certificate, base64 encoded, as embedded in KeyInfo
+    certificate: String,
+}
+
+impl FelSigner {
+    /// Load a PKCS#8 PEM private key and its PEM certificate from disk
+    pub fn from_pem_files(private_key_path: &str, certificate_path: &str) -> Result<Self, String> {
+        let key_pem = std::fs::read_to_string(private_key_path)
+            .map_err(|e| format!("No se pudo leer la llave privada FEL ({}): {}", private_key_path, e))?;
+        let cert_pem = std::fs::read_to_string(certificate_path)
+            .map_err(|e| format!("No se pudo leer el certificado FEL ({}): {}", certificate_path, e))?;
+        Self::from_pem(&key_pem, &cert_pem)
+    }
+
+    pub fn from_pem(key_pem: &str, cert_pem: &str) -> Result<Self, String> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(key_pem)
+            .map_err(|e| format!("Llave privada FEL inválida: {}", e))?;
+        let certificate = pem_body(cert_pem, "CERTIFICATE")
+            .ok_or_else(|| "Certificado FEL inválido: falta el bloque CERTIFICATE".to_string())?;
+        Ok(Self::from_key(private_key, certificate))
+    }
+
+    pub fn from_key(private_key: RsaPrivateKey, certificate: String) -> Self {
+        let public_key = private_key.to_public_key();
+        Self {
+            signing_key: SigningKey::<Sha256>::new(private_key),
+            public_key,
+            certificate,
+        }
+    }
+
+    /// Sign the element with `ID="{reference_id}"` and append the signature as
+    /// the last child of the document root
+    pub fn sign(&self, xml: &str, reference_id: &str) -> Result<String, String> {
+        let element = c14n::canonicalize_by_id(xml, reference_id)?;
+        let digest = BASE64.encode(Sha256::digest(element.as_bytes()));
+        let signed_info = signed_info(reference_id, &digest);
+        let canonical_signed_info = c14n::canonicalize_element(&signed_info, "ds:SignedInfo")?;
+        let signature = self.signing_key.sign(canonical_signed_info.as_bytes());
+
+        let signature_xml = format!(
+            "<ds:Signature xmlns:ds=\"{}\" Id=\"Signature-{}\">{}<ds:SignatureValue>{}</ds:SignatureValue>\
+             <ds:KeyInfo><ds:X509Data><ds:X509Certificate>{}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>\
+             </ds:Signature>",
+            DSIG_NAMESPACE,
+            reference_id,
+            signed_info,
+            BASE64.encode(signature.to_bytes()),
+            self.certificate
+        );
+
+        let root_end = xml
+            .rfind("</")
+            .ok_or_else(|| "Documento XML sin elemento raíz".to_string())?;
+        let mut signed = String::with_capacity(xml.len() + signature_xml.len());
+        signed.push_str(&xml[..root_end]);
+        signed.push_str(&signature_xml);
+        signed.push_str(&xml[root_end..]);
+        Ok(signed)
+    }
+
+    /// Check digest and RSA signature of a document signed with this key
+    pub fn verify(&self, signed_xml: &str) -> Result<(), String> {
+        verify_digest(signed_xml)?;
+
+        let signed_info = c14n::canonicalize_element(signed_xml, "ds:SignedInfo")
+            .map_err(|_| "Firma sin SignedInfo".to_string())?;
+        let value = inner_text(signed_xml, "ds:SignatureValue")
+            .ok_or_else(|| "Firma sin SignatureValue".to_string())?;
+        let bytes = BASE64.decode(value).map_err(|e| e.to_string())?;
+        let signature = Signature::try_from(bytes.as_slice()).map_err(|e| e.to_string())?;
+
+        VerifyingKey::<Sha256>::new(self.public_key.clone())
+            .verify(signed_info.as_bytes(), &signature)
+            .map_err(|_| "Firma FEL inválida".to_string())
+    }
+}
+
+/// Check that the signed element has not changed since it was signed
+pub fn verify_digest(signed_xml: &str) -> Result<(), String> {
+    let reference_id = attribute(signed_xml, "<ds:Reference", "URI")
+        .map(|uri| uri.trim_start_matches('#').to_string())
+        .ok_or_else(|| "Documento sin firma".to_string())?;
+    let expected = inner_text(signed_xml, "ds:DigestValue")
+        .ok_or_else(|| "Firma sin DigestValue".to_string())?;
+    let element = c14n::canonicalize_by_id(signed_xml, &reference_id)?;
+
+    if BASE64.encode(Sha256::digest(element.as_bytes())) == expected {
+        Ok(())
+    } else {
+        Err("El contenido del documento no coincide con la firma".to_string())
+    }
+}
+
+fn signed_info(reference_id: &str, digest: &str) -> String {
+    format!(
+        "<ds:SignedInfo xmlns:ds=\"{}\">\
+         <ds:CanonicalizationMethod Algorithm=\"http://www.w3.org/2001/10/xml-exc-c14n#\"/>\
+         <ds:SignatureMethod Algorithm=\"http://www.w3.org/2001/04/xmldsig-more#rsa-sha256\"/>\
+         <ds:Reference URI=\"#{}\"><ds:Transforms>\
+         <ds:Transform Algorithm=\"http://www.w3.org/2000/09/xmldsig#enveloped-signature\"/>\
+         </ds:Transforms><ds:DigestMethod Algorithm=\"http://www.w3.org/2001/04/xmlenc#sha256\"/>\
+         <ds:DigestValue>{}</ds:DigestValue></ds:Reference></ds:SignedInfo>",
+        DSIG_NAMESPACE, reference_id, digest
+    )
+}
+
+fn inner_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(&xml[start..end])
+}
+
+fn attribute<'a>(xml: &'a str, tag_start: &str, name: &str) -> Option<&'a str> {
+    let start = xml.find(tag_start)?;
+    let tag_end = start + xml[start..].find('>')?;
+    let marker = format!("{}=\"", name);
+    let value_start = start + xml[start..tag_end].find(&marker)? + marker.len();
+    let value_end = value_start + xml[value_start..].find('"')?;
+    Some(&xml[value_start..value_end])
+}
+
+/// Base64 body of a PEM block, without header, footer or line breaks
+fn pem_body(pem: &str, label: &str) -> Option<String> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let start = pem.find(&begin)? + begin.len();
+    let stop = start + pem[start..].find(&end)?;
+    Some(pem[start..stop].split_whitespace().collect())
+}
+
+/// Signer with a 2048-bit key, generated once per test run
+#[cfg(test)]
+pub(crate) fn test_signer() -> &'static FelSigner {
+    static SIGNER: std::sync::OnceLock<FelSigner> = std::sync::OnceLock::new();
+    SIGNER.get_or_init(|| {
+        let key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 2048).unwrap();
+        FelSigner::from_key(key, "TUlJQ2VydA==".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = test_signer();
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><a:Doc xmlns:a="urn:a"><a:Datos ID="DatosEmision"><a:Total>10.00</a:Total></a:Datos></a:Doc>"#;
+        let signed = signer.sign(xml, "DatosEmision").unwrap();
+
+        assert!(signed.ends_with("</ds:Signature></a:Doc>"));
+        assert!(signer.verify(&signed).is_ok());
+
+        let tampered = signed.replace("10.00", "1.00");
+        assert!(verify_digest(&tampered).is_err());
+        assert!(signer.verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_signature_survives_reserialization() {
+        let signer = test_signer();
+        let xml = r#"<a:Doc xmlns:a="urn:a"><a:Gen ID="DatosAnulacion" Motivo="x" Fecha="2026-10-17"/></a:Doc>"#;
+        let signed = signer.sign(xml, "DatosAnulacion").unwrap();
+
+        // Attribute order, quotes and empty-element syntax are not significant
+        let reserialized = signed.replace(
+            r#"<a:Gen ID="DatosAnulacion" Motivo="x" Fecha="2026-10-17"/>"#,
+            "<a:Gen Fecha='2026-10-17' ID='DatosAnulacion' Motivo='x'></a:Gen>",
+        );
+        assert_ne!(reserialized, signed);
+        assert!(signer.verify(&reserialized).is_ok());
+
+        let tampered = signed.replace("Motivo=\"x\"", "Motivo=\"y\"");
+        assert!(signer.verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_pem_body() {
+        let pem = "-----BEGIN CERTIFICATE-----\nAAAA\nBBBB\n-----END CERTIFICATE-----\n";
+        assert_eq!(pem_body(pem, "CERTIFICATE").as_deref(), Some("AAAABBBB"));
+    }
+}
@@ -0,0 +1,171 @@
+// DTE XML generation (SAT Guatemala FEL schema)
+//
+// Documents are written directly as strings in a fixed attribute order and
+// without insignificant whitespace. Signing canonicalizes the signed element
+// (see `c14n`), so the digest does not depend on this serialization.
+
+use rust_decimal::Decimal;
+
+use crate::commands::{Invoice, InvoiceItem};
+use crate::config::FelConfig;
+
+use super::FelReceiver;
+
+pub const DTE_NAMESPACE: &str = "http://www.sat.gob.gt/dte/fel/0.2.0";
+pub const ANULACION_NAMESPACE: &str = "http://www.sat.gob.gt/dte/fel/0.1.0";
+
+/// Id of the element covered by the signature in an invoice
+pub const DTE_SIGNED_ID: &str = "DatosEmision";
+/// Id of the element covered by the signature in a cancellation
+pub const ANULACION_SIGNED_ID: &str = "DatosAnulacion";
+
+/// Escape text and attribute values
+pub fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn money(value: Decimal) -> String {
+    format!("{:.2}", value)
+}
+
+/// Build the FACT document for an invoice. `issued_at` is the emission
+/// timestamp in ISO 8601 with offset (e.g. 2026-10-18T10:30:00-06:00).
+pub fn build_invoice_xml(
+    issuer: &FelConfig,
+    receiver: &FelReceiver,
+    invoice: &Invoice,
+    items: &[InvoiceItem],
+    issued_at: &str,
+) -> String {
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(&format!(
+        r#"<dte:GTDocumento xmlns:dte="{}" Version="0.1">"#,
+        DTE_NAMESPACE
+    ));
+    xml.push_str(r#"<dte:SAT ClaseDocumento="dte"><dte:DTE ID="DatosCertificados">"#);
+    xml.push_str(&format!(r#"<dte:DatosEmision ID="{}">"#, DTE_SIGNED_ID));
+
+    xml.push_str(&format!(
+        r#"<dte:DatosGenerales Tipo="FACT" FechaHoraEmision="{}" CodigoMoneda="GTQ"/>"#,
+        escape(issued_at)
+    ));
+
+    xml.push_str(&format!(
+        r#"<dte:Emisor NITEmisor="{}" NombreEmisor="{}" CodigoEstablecimiento="{}" NombreComercial="{}" AfiliacionIVA="{}">"#,
+        escape(&issuer.nit),
+        escape(&issuer.nombre_emisor),
+        escape(&issuer.codigo_establecimiento),
+        escape(&issuer.nombre_comercial),
+        escape(&issuer.afiliacion_iva)
+    ));
+    xml.push_str(&format!(
+        "<dte:DireccionEmisor><dte:Direccion>{}</dte:Direccion><dte:CodigoPostal>{}</dte:CodigoPostal>\
+         <dte:Municipio>{}</dte:Municipio><dte:Departamento>{}</dte:Departamento><dte:Pais>GT</dte:Pais>\
+         </dte:DireccionEmisor></dte:Emisor>",
+        escape(&issuer.direccion),
+        escape(&issuer.codigo_postal),
+        escape(&issuer.municipio),
+        escape(&issuer.departamento)
+    ));
+
+    xml.push_str(&format!(
+        r#"<dte:Receptor IDReceptor="{}" NombreReceptor="{}"/>"#,
+        escape(&receiver.nit),
+        escape(&receiver.name)
+    ));
+
+    // Phrase 1: retention regime; phrase 4: exempt operations
+    xml.push_str(r#"<dte:Frases><dte:Frase TipoFrase="1" CodigoEscenario="1"/>"#);
+    if items.iter().any(|i| i.tax_exempt) {
+        xml.push_str(r#"<dte:Frase TipoFrase="4" CodigoEscenario="1"/>"#);
+    }
+    xml.push_str("</dte:Frases>");
+
+    xml.push_str("<dte:Items>");
+    for (idx, item) in items.iter().enumerate() {
+        let net = item.subtotal - item.discount_amount;
+        let (unidad_gravable, monto_gravable) = if item.tax_exempt {
+            (2, net)
+        } else {
+            (1, net - item.tax_amount)
+        };
+        xml.push_str(&format!(
+            r#"<dte:Item NumeroLinea="{}" BienOServicio="{}">"#,
+            idx + 1,
+            if item.product_id.is_some() { "B" } else { "S" }
+        ));
+        xml.push_str(&format!(
+            "<dte:Cantidad>{}</dte:Cantidad><dte:UnidadMedida>UNI</dte:UnidadMedida>\
+             <dte:Descripcion>{}</dte:Descripcion><dte:PrecioUnitario>{}</dte:PrecioUnitario>\
+             <dte:Precio>{}</dte:Precio><dte:Descuento>{}</dte:Descuento>",
+            item.quantity,
+            escape(&item.description),
+            money(item.unit_price),
+            money(item.subtotal),
+            money(item.discount_amount)
+        ));
+        xml.push_str(&format!(
+            "<dte:Impuestos><dte:Impuesto><dte:NombreCorto>IVA</dte:NombreCorto>\
+             <dte:CodigoUnidadGravable>{}</dte:CodigoUnidadGravable>\
+             <dte:MontoGravable>{}</dte:MontoGravable><dte:MontoImpuesto>{}</dte:MontoImpuesto>\
+             </dte:Impuesto></dte:Impuestos>",
+            unidad_gravable,
+            money(monto_gravable),
+            money(item.tax_amount)
+        ));
+        xml.push_str(&format!("<dte:Total>{}</dte:Total></dte:Item>", money(net)));
+    }
+    xml.push_str("</dte:Items>");
+
+    xml.push_str(&format!(
+        "<dte:Totales><dte:TotalImpuestos><dte:TotalImpuesto NombreCorto=\"IVA\" TotalMontoImpuesto=\"{}\"/>\
+         </dte:TotalImpuestos><dte:GranTotal>{}</dte:GranTotal></dte:Totales>",
+        money(invoice.tax_amount),
+        money(invoice.total_amount)
+    ));
+
+    xml.push_str("</dte:DatosEmision></dte:DTE></dte:SAT></dte:GTDocumento>");
+    xml
+}
+
+/// Build the cancellation document for a certified invoice
+pub fn build_cancellation_xml(
+    issuer_nit: &str,
+    receiver_nit: &str,
+    authorization_uuid: &str,
+    certified_at: &str,
+    cancelled_at: &str,
+    reason: &str,
+) -> String {
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(&format!(
+        r#"<dte:GTAnulacionDocumento xmlns:dte="{}" Version="0.1">"#,
+        ANULACION_NAMESPACE
+    ));
+    xml.push_str(r#"<dte:SAT><dte:AnulacionDTE ID="DatosCertificados">"#);
+    xml.push_str(&format!(
+        r#"<dte:DatosGenerales ID="{}" NumeroDocumentoAAnular="{}" NITEmisor="{}" IDReceptor="{}" FechaEmisionDocumentoAnular="{}" FechaHoraAnulacion="{}" MotivoAnulacion="{}"/>"#,
+        ANULACION_SIGNED_ID,
+        escape(authorization_uuid),
+        escape(issuer_nit),
+        escape(receiver_nit),
+        escape(certified_at),
+        escape(cancelled_at),
+        escape(reason)
+    ));
+    xml.push_str("</dte:AnulacionDTE></dte:SAT></dte:GTAnulacionDocumento>");
+    xml
+}
@@ -0,0 +1,240 @@
+// Barcode and GS1-128 parsing for scanned inventory codes
+//
+// Scanners send either a plain code (EAN/UPC or an internal code) or a GS1
+// element string: application identifiers (AIs) followed by their data, with
+// variable-length fields ended by the FNC1 separator (ASCII 29). Lens boxes
+// carry GTIN (01), expiry (17), lot (10) and often serial (21). The
+// human-readable form with AIs in parentheses is accepted as well.
+
+use chrono::NaiveDate;
+
+/// FNC1 as transmitted by scanners (ASCII group separator)
+const GS: char = '\u{1d}';
+
+/// Symbology identifiers scanners may prepend (GS1-128, GS1 DataMatrix, GS1 QR)
+const GS1_SYMBOLOGY_PREFIXES: &[&str] = &["]C1", "]d2", "]Q3"];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScannedCode {
+    /// Code as read, without symbology prefix
+    pub raw: String,
+    /// GTIN padded to 14 digits
+    pub gtin: Option<String>,
+    pub lot_number: Option<String>,
+    pub expiry_date: Option<NaiveDate>,
+    pub serial_number: Option<String>,
+}
+
+/// Data length of an AI: Some(n) when fixed, None when variable
+fn ai_length(ai: &str) -> Option<Option<usize>> {
+    if !ai.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match ai {
+        "00" => Some(Some(18)),
+        "01" | "02" => Some(Some(14)),
+        "11" | "13" | "15" | "17" => Some(Some(6)),
+        "20" => Some(Some(2)),
+        "10" | "21" | "30" | "37" | "240" | "241" => Some(None),
+        _ if ai.len() == 4 && ("310".."370").contains(&&ai[..3]) => Some(Some(6)),
+        _ => None,
+    }
+}
+
+/// Read the AI at the start of `s` (2, 3 or 4 digits)
+fn read_ai(s: &str) -> Result<&str, String> {
+    for len in [2, 3, 4] {
+        if let Some(ai) = s.get(..len) {
+            if ai.chars().all(|c| c.is_ascii_digit()) && ai_length(ai).is_some() {
+                return Ok(ai);
+            }
+        }
+    }
+    Err(format!(
+        "Identificador de aplicación GS1 no soportado: {}",
+        s.chars().take(4).collect::<String>()
+    ))
+}
+
+/// GS1 check digit (mod 10, weights 3/1 from the right) of a digit string
+pub fn valid_check_digit(digits: &str) -> bool {
+    if digits.len() < 2 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+    let values: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    let (body, check) = values.split_at(values.len() - 1);
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    (10 - sum % 10) % 10 == check[0]
+}
+
+/// EAN-8, UPC-A, EAN-13 or GTIN-14 padded to 14 digits, if valid
+pub fn normalize_gtin(code: &str) -> Option<String> {
+    let code = code.trim();
+    if matches!(code.len(), 8 | 12 | 13 | 14) && valid_check_digit(code) {
+        Some(format!("{:0>14}", code))
+    } else {
+        None
+    }
+}
+
+/// Barcode as stored on items and lots: trimmed, None when blank
+pub fn clean_barcode(code: &str) -> Option<String> {
+    let code = code.trim();
+    if code.is_empty() {
+        None
+    } else {
+        Some(code.to_string())
+    }
+}
+
+/// GS1 date YYMMDD; day 00 means the last day of the month
+pub fn parse_gs1_date(value: &str) -> Result<NaiveDate, String> {
+    let invalid = || format!("Fecha GS1 no válida: {}", value);
+    if value.len() != 6 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let year = 2000 + value[0..2].parse::<i32>().map_err(|_| invalid())?;
+    let month = value[2..4].parse::<u32>().map_err(|_| invalid())?;
+    let day = value[4..6].parse::<u32>().map_err(|_| invalid())?;
+
+    if day == 0 {
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        return NaiveDate::from_ymd_opt(next_year, next_month, 1)
+            .and_then(|d| d.pred_opt())
+            .filter(|_| (1..=12).contains(&month))
+            .ok_or_else(invalid);
+    }
+    NaiveDate::from_ymd_opt(year, month, day).ok_or_else(invalid)
+}
+
+fn apply_element(result: &mut ScannedCode, ai: &str, value: &str) -> Result<(), String> {
+    match ai {
+        "01" | "02" => {
+            if !valid_check_digit(value) {
+                return Err(format!("GTIN con dígito verificador inválido: {}", value));
+            }
+            result.gtin = Some(value.to_string());
+        }
+        "10" => result.lot_number = Some(value.to_string()),
+        "17" => result.expiry_date = Some(parse_gs1_date(value)?),
+        "21" => result.serial_number = Some(value.to_string()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Element string: AI + data, with FNC1 after each variable-length field
+fn parse_element_string(data: &str, result: &mut ScannedCode) -> Result<(), String> {
+    let mut rest = data.trim_start_matches(GS);
+    while !rest.is_empty() {
+        let ai = read_ai(rest)?;
+        rest = &rest[ai.len()..];
+        let value = match ai_length(ai).flatten() {
+            Some(len) => {
+                let value = rest
+                    .get(..len)
+                    .ok_or_else(|| format!("Dato incompleto para el identificador GS1 ({})", ai))?;
+                rest = &rest[len..];
+                value
+            }
+            None => {
+                let end = rest.find(GS).unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            }
+        };
+        apply_element(result, ai, value)?;
+        rest = rest.trim_start_matches(GS);
+    }
+    Ok(())
+}
+
+/// Human-readable form, e.g. "(01)09506000134352(17)201225(10)ABC123"
+fn parse_bracketed(data: &str, result: &mut ScannedCode) -> Result<(), String> {
+    for segment in data.split('(').filter(|s| !s.is_empty()) {
+        let (ai, value) = segment
+            .split_once(')')
+            .ok_or_else(|| format!("Código GS1 mal formado: ({}", segment))?;
+        if ai_length(ai).is_none() {
+            return Err(format!("Identificador de aplicación GS1 no soportado: {}", ai));
+        }
+        apply_element(result, ai, value.trim())?;
+    }
+    Ok(())
+}
+
+/// Parse a scanned code into GTIN, lot, expiry and serial where present
+pub fn parse(code: &str) -> Result<ScannedCode, String> {
+    let trimmed = code.trim_matches(|c: char| c.is_whitespace() && c != GS);
+    if trimmed.is_empty() {
+        return Err("Código vacío".to_string());
+    }
+
+    let (is_gs1, data) = match GS1_SYMBOLOGY_PREFIXES.iter().find(|p| trimmed.starts_with(*p)) {
+        Some(prefix) => (true, &trimmed[prefix.len()..]),
+        None => (false, trimmed),
+    };
+
+    let mut result = ScannedCode {
+        raw: data.trim_start_matches(GS).to_string(),
+        ..Default::default()
+    };
+
+    if data.starts_with('(') {
+        parse_bracketed(data, &mut result)?;
+    } else if is_gs1 || data.contains(GS) || (data.len() > 16 && data.starts_with("01") && data.get(2..16).is_some_and(valid_check_digit)) {
+        parse_element_string(data, &mut result)?;
+    } else {
+        result.gtin = normalize_gtin(data);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gs1_element_string() {
+        let scanned = parse("]C1010950600013435217261100\u{1d}10IOL-4471\u{1d}21SN889").unwrap();
+        assert_eq!(scanned.gtin.as_deref(), Some("09506000134352"));
+        assert_eq!(scanned.expiry_date, NaiveDate::from_ymd_opt(2026, 11, 30));
+        assert_eq!(scanned.lot_number.as_deref(), Some("IOL-4471"));
+        assert_eq!(scanned.serial_number.as_deref(), Some("SN889"));
+
+        let bracketed = parse("(01)09506000134352(17)201225(10)ABC123").unwrap();
+        assert_eq!(bracketed.expiry_date, NaiveDate::from_ymd_opt(2020, 12, 25));
+        assert_eq!(bracketed.lot_number.as_deref(), Some("ABC123"));
+
+        assert!(parse("(01)09506000134353(10)X").is_err());
+        assert!(parse("]C19912345").is_err());
+    }
+
+    #[test]
+    fn test_parse_plain_codes() {
+        let ean = parse("4006381333931").unwrap();
+        assert_eq!(ean.gtin.as_deref(), Some("04006381333931"));
+        assert_eq!(ean.lot_number, None);
+
+        let internal = parse(" LC-001 ").unwrap();
+        assert_eq!(internal.raw, "LC-001");
+        assert_eq!(internal.gtin, None);
+
+        assert!(parse_gs1_date("261300").is_err());
+        assert_eq!(parse_gs1_date("240200").unwrap(), NaiveDate::from_ymd_opt(2024, 2, 29).unwrap());
+    }
+
+    #[test]
+    fn test_non_ascii_ai_is_rejected() {
+        assert!(parse("(a€)x").is_err());
+        assert!(parse("(31€0)123456").is_err());
+        assert!(parse("]C1€€€€").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+// Physical inventory counts (stocktake)
+//
+// A count session snapshots the expected quantity of every item (and lot,
+// for pharmacy stock) when it starts. Counted quantities are compared with
+// the stock at posting time, so sales made during the count are not
+// reported as shrinkage.
+
+/// Pharmacy stock (inventory_items) or procedure room stock (room_inventory_items)
+pub const INVENTORY_TYPES: &[&str] = &["farmacia", "sala"];
+
+pub fn validate_inventory_type(inventory_type: &str) -> Result<(), String> {
+    if INVENTORY_TYPES.contains(&inventory_type) {
+        Ok(())
+    } else {
+        Err(format!("Tipo de inventario no válido: {}", inventory_type))
+    }
+}
+
+/// Counted quantities must be non-negative; room stock is counted in whole units
+pub fn validate_counted(inventory_type: &str, counted: f64) -> Result<(), String> {
+    if !counted.is_finite() || counted < 0.0 {
+        return Err("La cantidad contada no puede ser negativa".to_string());
+    }
+    if inventory_type == "sala" && counted.fract() != 0.0 {
+        return Err("El inventario de sala se cuenta en unidades enteras".to_string());
+    }
+    Ok(())
+}
+
+/// Counted minus system stock; None while the line has not been counted
+pub fn variance(counted: Option<f64>, current: f64) -> Option<f64> {
+    counted.map(|c| c - current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_validation_and_variance() {
+        assert!(validate_inventory_type("farmacia").is_ok());
+        assert!(validate_inventory_type("bodega").is_err());
+
+        assert!(validate_counted("farmacia", 2.5).is_ok());
+        assert!(validate_counted("sala", 2.5).is_err());
+        assert!(validate_counted("sala", -1.0).is_err());
+
+        assert_eq!(variance(Some(8.0), 10.0), Some(-2.0));
+        assert_eq!(variance(None, 10.0), None);
+    }
+}
@@ -0,0 +1,58 @@
+// Surgery kits: per-branch bill of materials of room inventory items
+//
+// Each surgery type lists the room items it uses. When a surgery is marked
+// completed, the kit for the surgery's branch is consumed as 'uso' movements
+// and each line is recorded with its cost at that moment, once per surgery.
+
+use rust_decimal::Decimal;
+
+use crate::billing::round_money;
+
+/// Surgery status that triggers kit consumption
+pub const SURGERY_COMPLETED_STATUS: &str = "completed";
+
+/// Only the transition into completed consumes the kit
+pub fn completes_surgery(previous_status: &str, new_status: Option<&str>) -> bool {
+    previous_status != SURGERY_COMPLETED_STATUS && new_status == Some(SURGERY_COMPLETED_STATUS)
+}
+
+/// Units to consume for one kit line; per-eye lines double for OU surgeries
+pub fn kit_quantity(quantity: i32, per_eye: bool, eye: Option<&str>) -> i32 {
+    if per_eye && eye == Some("OU") {
+        quantity * 2
+    } else {
+        quantity
+    }
+}
+
+pub fn validate_kit_line(quantity: i32) -> Result<(), String> {
+    if quantity <= 0 {
+        return Err("La cantidad de cada material debe ser mayor a cero".to_string());
+    }
+    Ok(())
+}
+
+/// Cost of the consumed units, rounded to centavos
+pub fn usage_cost(quantity: i32, unit_cost: Decimal) -> Decimal {
+    round_money(Decimal::from(quantity) * unit_cost)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_kit_consumption_rules() {
+        assert!(completes_surgery("scheduled", Some("completed")));
+        assert!(!completes_surgery("completed", Some("completed")));
+        assert!(!completes_surgery("scheduled", None));
+
+        assert_eq!(kit_quantity(1, true, Some("OU")), 2);
+        assert_eq!(kit_quantity(1, true, Some("OD")), 1);
+        assert_eq!(kit_quantity(3, false, Some("OU")), 3);
+        assert!(validate_kit_line(0).is_err());
+
+        assert_eq!(usage_cost(3, Decimal::from_str("12.505").unwrap()), Decimal::from_str("37.52").unwrap());
+    }
+}
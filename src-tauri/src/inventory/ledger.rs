@@ -0,0 +1,77 @@
+// Stock as a function of the movement ledger
+//
+// Pharmacy movements mirror the `update_item_stock` trigger: entries add the
+// absolute quantity, outgoing movements subtract it and adjustments carry a
+// signed delta. Room inventory adjustments record the counted stock instead
+// of a delta, so the room ledger has to be replayed in order.
+
+use super::lots;
+
+/// Change in stock produced by one pharmacy movement
+pub fn stock_delta(movement_type: &str, quantity: f64) -> f64 {
+    match movement_type {
+        "entrada" => quantity.abs(),
+        "ajuste" => quantity,
+        t if lots::is_outgoing(t) => -quantity.abs(),
+        _ => 0.0,
+    }
+}
+
+/// Pharmacy stock implied by a sequence of (movement_type, quantity)
+pub fn ledger_stock<'a>(movements: impl IntoIterator<Item = (&'a str, f64)>) -> f64 {
+    movements
+        .into_iter()
+        .map(|(movement_type, quantity)| stock_delta(movement_type, quantity))
+        .sum()
+}
+
+/// Room movement types: 'entrada' adds, 'uso' consumes, 'ajuste' sets the counted stock
+pub const ROOM_MOVEMENT_TYPES: &[&str] = &["entrada", "uso", "ajuste"];
+
+/// Room stock after applying one movement. Stock never goes below zero.
+pub fn room_stock_after(current: i32, movement_type: &str, quantity: i32) -> Result<i32, String> {
+    if quantity < 0 {
+        return Err("La cantidad no puede ser negativa".to_string());
+    }
+    let stock = match movement_type {
+        "entrada" => current + quantity,
+        "uso" => current - quantity,
+        "ajuste" => quantity,
+        other => return Err(format!("Tipo de movimiento no válido: {}", other)),
+    };
+    Ok(stock.max(0))
+}
+
+/// Room stock implied by the ledger, replayed from zero in chronological order
+pub fn room_ledger_stock<'a>(movements: impl IntoIterator<Item = (&'a str, i32)>) -> i32 {
+    movements
+        .into_iter()
+        .fold(0, |stock, (movement_type, quantity)| {
+            room_stock_after(stock, movement_type, quantity).unwrap_or(stock)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pharmacy_ledger_matches_trigger_rules() {
+        let movements = vec![
+            ("entrada", 20.0),
+            ("salida", 3.0),
+            ("cortesia", -1.0), // sign is ignored for outgoing movements
+            ("ajuste", -2.0),
+            ("merma", 4.0),
+        ];
+        assert_eq!(ledger_stock(movements), 10.0);
+    }
+
+    #[test]
+    fn test_room_ledger_replays_counts() {
+        let movements = vec![("entrada", 10), ("uso", 4), ("ajuste", 5), ("uso", 7), ("entrada", 2)];
+        assert_eq!(room_ledger_stock(movements), 2);
+        assert!(room_stock_after(3, "prestamo", 1).is_err());
+        assert!(room_stock_after(3, "uso", -1).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+// Lot selection for outgoing stock
+//
+// Perishable categories are consumed first-expired-first-out; everything
+// else first-in-first-out. The categories come from the `fefo_categories`
+// app setting (drops and contact lenses by default). Expired lots are never
+// allocated: they can only leave inventory through a write-off.
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// App setting with the categories consumed by expiry date instead of
+/// arrival date, as a JSON array of category names
+pub const FEFO_SETTING: &str = "fefo_categories";
+
+/// Categories used when the setting is missing or not an array of names
+pub const DEFAULT_FEFO_CATEGORIES: &[&str] = &["gota", "lente"];
+
+/// Movement types that take stock out of a lot
+pub const OUTGOING_MOVEMENTS: &[&str] = &["salida", "cortesia", "merma"];
+
+/// Movement type used to write off expired stock
+pub const WRITE_OFF_MOVEMENT: &str = "merma";
+
+/// FEFO categories from the value of the `fefo_categories` setting
+pub fn fefo_categories(setting: Option<&serde_json::Value>) -> Vec<String> {
+    setting
+        .and_then(|value| value.as_array())
+        .and_then(|names| names.iter().map(|n| n.as_str().map(str::to_string)).collect())
+        .unwrap_or_else(|| DEFAULT_FEFO_CATEGORIES.iter().map(|c| c.to_string()).collect())
+}
+
+pub fn uses_fefo(category: &str, fefo_categories: &[String]) -> bool {
+    fefo_categories.iter().any(|c| c == category)
+}
+
+pub fn is_outgoing(movement_type: &str) -> bool {
+    OUTGOING_MOVEMENTS.contains(&movement_type)
+}
+
+/// A lot is usable through its expiry date and expired from the next day
+pub fn is_expired(expiry_date: Option<NaiveDate>, today: NaiveDate) -> bool {
+    matches!(expiry_date, Some(date) if date < today)
+}
+
+/// Days left before expiry; negative once expired
+pub fn days_to_expiry(expiry_date: NaiveDate, today: NaiveDate) -> i64 {
+    (expiry_date - today).num_days()
+}
+
+/// Reject taking stock out of an expired lot, except to write it off
+pub fn ensure_usable(
+    lot_number: &str,
+    expiry_date: Option<NaiveDate>,
+    movement_type: &str,
+    today: NaiveDate,
+) -> Result<(), String> {
+    if movement_type != WRITE_OFF_MOVEMENT && is_outgoing(movement_type) && is_expired(expiry_date, today) {
+        return Err(format!(
+            "El lote {} venció el {}; solo puede darse de baja",
+            lot_number,
+            expiry_date.map(|d| d.to_string()).unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// Available stock in one lot
+#[derive(Debug, Clone)]
+pub struct LotStock {
+    pub id: String,
+    pub lot_number: String,
+    pub expiry_date: Option<NaiveDate>,
+    pub quantity: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Quantity to take from one lot
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotAllocation {
+    pub lot_id: String,
+    pub quantity: f64,
+}
+
+/// Choose the lots that cover `quantity`, skipping expired and empty lots.
+/// With `fefo` lots are ordered by expiry date (lots without one last),
+/// otherwise by arrival.
+pub fn allocate(
+    lots: &[LotStock],
+    quantity: f64,
+    fefo: bool,
+    today: NaiveDate,
+) -> Result<Vec<LotAllocation>, String> {
+    if quantity <= 0.0 {
+        return Err("La cantidad debe ser mayor a cero".to_string());
+    }
+
+    let mut available: Vec<&LotStock> = lots
+        .iter()
+        .filter(|lot| lot.quantity > 0.0 && !is_expired(lot.expiry_date, today))
+        .collect();
+
+    if fefo {
+        available.sort_by_key(|lot| (lot.expiry_date.is_none(), lot.expiry_date, lot.created_at));
+    } else {
+        available.sort_by_key(|lot| lot.created_at);
+    }
+
+    let total: f64 = available.iter().map(|lot| lot.quantity).sum();
+    if total < quantity {
+        return Err(format!(
+            "Stock insuficiente en lotes vigentes: disponible {}, requerido {}",
+            total, quantity
+        ));
+    }
+
+    let mut remaining = quantity;
+    let mut allocations = Vec::new();
+    for lot in available {
+        if remaining <= 0.0 {
+            break;
+        }
+        let take = lot.quantity.min(remaining);
+        allocations.push(LotAllocation {
+            lot_id: lot.id.clone(),
+            quantity: take,
+        });
+        remaining -= take;
+    }
+
+    Ok(allocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn lot(id: &str, expiry: Option<&str>, quantity: f64, received_day: u32) -> LotStock {
+        LotStock {
+            id: id.to_string(),
+            lot_number: id.to_uppercase(),
+            expiry_date: expiry.map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap()),
+            quantity,
+            created_at: Utc.with_ymd_and_hms(2026, 1, received_day, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 6, 15).unwrap()
+    }
+
+    #[test]
+    fn test_fefo_skips_expired_and_orders_by_expiry() {
+        let lots = vec![
+            lot("a", Some("2026-12-31"), 10.0, 1),
+            lot("b", Some("2026-06-14"), 5.0, 2), // expired yesterday
+            lot("c", Some("2026-06-15"), 3.0, 3), // expires today, still usable
+            lot("d", None, 20.0, 4),
+        ];
+
+        let allocations = allocate(&lots, 8.0, true, today()).unwrap();
+        assert_eq!(
+            allocations,
+            vec![
+                LotAllocation { lot_id: "c".into(), quantity: 3.0 },
+                LotAllocation { lot_id: "a".into(), quantity: 5.0 },
+            ]
+        );
+
+        let fifo = allocate(&lots, 12.0, false, today()).unwrap();
+        assert_eq!(fifo[0].lot_id, "a");
+        assert_eq!(fifo[1], LotAllocation { lot_id: "c".into(), quantity: 2.0 });
+    }
+
+    #[test]
+    fn test_allocate_rejects_insufficient_valid_stock() {
+        let lots = vec![lot("a", Some("2026-01-31"), 50.0, 1), lot("b", None, 2.0, 2)];
+        assert!(allocate(&lots, 3.0, true, today()).is_err());
+        assert!(allocate(&lots, 0.0, true, today()).is_err());
+        assert_eq!(days_to_expiry(NaiveDate::from_ymd_opt(2026, 6, 10).unwrap(), today()), -5);
+    }
+
+    #[test]
+    fn test_fefo_categories_setting() {
+        let configured = fefo_categories(Some(&serde_json::json!(["gota", "medicamento"])));
+        assert!(uses_fefo("medicamento", &configured));
+        assert!(!uses_fefo("lente", &configured));
+
+        // Missing or malformed setting falls back to drops and lenses
+        for setting in [None, Some(serde_json::json!({"enabled": true})), Some(serde_json::json!(["gota", 1]))] {
+            let categories = fefo_categories(setting.as_ref());
+            assert!(uses_fefo("lente", &categories));
+            assert!(!uses_fefo("armazon", &categories));
+        }
+    }
+}
@@ -0,0 +1,11 @@
+// Inventory domain logic shared by the PostgreSQL commands
+
+pub mod barcode;
+pub mod count;
+pub mod kits;
+pub mod ledger;
+pub mod lots;
+pub mod purchasing;
+pub mod reorder;
+pub mod transfer;
+pub mod valuation;
@@ -0,0 +1,92 @@
+// Purchase orders, goods receipt and supplier payables
+//
+// Orders go borrador -> enviada -> parcial -> recibida (or cancelada before
+// anything is received). Each receipt creates lots and 'entrada' movements
+// at the received cost and opens a payable for the supplier.
+
+use rust_decimal::prelude::*;
+
+use crate::billing::round_money;
+
+/// Order statuses that still expect goods from the supplier
+pub const OPEN_ORDER_STATUSES: &[&str] = &["enviada", "parcial"];
+
+/// Allowed manual status changes; receipts move orders to parcial/recibida
+pub fn validate_status_change(from: &str, to: &str) -> Result<(), String> {
+    match (from, to) {
+        ("borrador", "enviada") | ("borrador", "cancelada") | ("enviada", "cancelada") => Ok(()),
+        _ => Err(format!("No se puede cambiar la orden de '{}' a '{}'", from, to)),
+    }
+}
+
+/// Quantity as a Decimal for money arithmetic
+fn quantity_decimal(quantity: f64) -> Result<Decimal, String> {
+    Decimal::from_f64(quantity).ok_or_else(|| format!("Cantidad no válida: {}", quantity))
+}
+
+/// Line amount at unit cost, rounded to centavos
+pub fn line_total(quantity: f64, unit_cost: Decimal) -> Result<Decimal, String> {
+    Ok(round_money(quantity_decimal(quantity)? * unit_cost))
+}
+
+/// Reject receipts above what is still pending on the order line
+pub fn validate_receipt(ordered: f64, already_received: f64, receiving: f64) -> Result<(), String> {
+    if receiving <= 0.0 {
+        return Err("La cantidad recibida debe ser mayor a cero".to_string());
+    }
+    let pending = ordered - already_received;
+    if receiving > pending + 1e-9 {
+        return Err(format!(
+            "Se reciben {} pero solo quedan {} pendientes en la orden",
+            receiving, pending
+        ));
+    }
+    Ok(())
+}
+
+/// Order status after a receipt, from (ordered, received) per line
+pub fn status_after_receipt(lines: &[(f64, f64)]) -> &'static str {
+    if lines.iter().all(|(ordered, received)| received + 1e-9 >= *ordered) {
+        "recibida"
+    } else {
+        "parcial"
+    }
+}
+
+/// Payable status from its original amount and remaining balance
+pub fn payable_status(amount: Decimal, balance: Decimal) -> &'static str {
+    if balance <= Decimal::ZERO {
+        "pagada"
+    } else if balance < amount {
+        "parcial"
+    } else {
+        "pendiente"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::billing::d;
+
+    #[test]
+    fn test_receipt_quantities_and_status() {
+        assert!(validate_receipt(10.0, 4.0, 6.0).is_ok());
+        assert!(validate_receipt(10.0, 4.0, 7.0).is_err());
+        assert!(validate_receipt(10.0, 0.0, 0.0).is_err());
+
+        assert_eq!(status_after_receipt(&[(10.0, 10.0), (5.0, 2.0)]), "parcial");
+        assert_eq!(status_after_receipt(&[(10.0, 10.0), (5.0, 5.0)]), "recibida");
+
+        assert!(validate_status_change("borrador", "enviada").is_ok());
+        assert!(validate_status_change("parcial", "cancelada").is_err());
+    }
+
+    #[test]
+    fn test_line_total_and_payable_status() {
+        assert_eq!(line_total(3.0, d("45.335")).unwrap(), d("136.01"));
+        assert_eq!(payable_status(d("100"), d("100")), "pendiente");
+        assert_eq!(payable_status(d("100"), d("40")), "parcial");
+        assert_eq!(payable_status(d("100"), d("0")), "pagada");
+    }
+}
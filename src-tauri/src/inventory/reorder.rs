@@ -0,0 +1,93 @@
+// Reorder suggestions from reorder_level and recent consumption
+//
+// An item needs reordering when its stock is at or below its reorder level.
+// The suggested quantity brings stock back to the reorder level plus the
+// expected consumption for the coverage period, net of what is already on
+// order.
+
+/// Stock and demand figures for one item
+#[derive(Debug, Clone, Copy)]
+pub struct ReorderInput {
+    pub current_stock: f64,
+    pub reorder_level: f64,
+    /// Quantity still pending on open purchase orders
+    pub on_order: f64,
+    /// Outgoing quantity over the consumption window
+    pub consumed: f64,
+    pub window_days: u32,
+    pub coverage_days: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReorderPlan {
+    pub avg_daily_consumption: f64,
+    /// Days the current stock lasts at the average rate; None without consumption
+    pub days_of_cover: Option<f64>,
+    /// Whole units to order (0 when stock on hand and on order is enough)
+    pub suggested_quantity: f64,
+}
+
+pub fn needs_reorder(current_stock: f64, reorder_level: f64) -> bool {
+    current_stock <= reorder_level
+}
+
+pub fn plan(input: &ReorderInput) -> ReorderPlan {
+    let avg = if input.window_days > 0 {
+        input.consumed.max(0.0) / input.window_days as f64
+    } else {
+        0.0
+    };
+    let days_of_cover = if avg > 0.0 {
+        Some(input.current_stock.max(0.0) / avg)
+    } else {
+        None
+    };
+    let target = input.reorder_level + avg * input.coverage_days as f64;
+    let suggested = (target - input.current_stock - input.on_order).ceil().max(0.0);
+
+    ReorderPlan {
+        avg_daily_consumption: avg,
+        days_of_cover,
+        suggested_quantity: suggested,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_with_consumption_and_open_orders() {
+        // 90 units used in 90 days: 1/day, 30 days of coverage above a level of 10
+        let input = ReorderInput {
+            current_stock: 6.0,
+            reorder_level: 10.0,
+            on_order: 12.0,
+            consumed: 90.0,
+            window_days: 90,
+            coverage_days: 30,
+        };
+        let result = plan(&input);
+        assert_eq!(result.avg_daily_consumption, 1.0);
+        assert_eq!(result.days_of_cover, Some(6.0));
+        assert_eq!(result.suggested_quantity, 22.0);
+
+        let covered = plan(&ReorderInput { on_order: 40.0, ..input });
+        assert_eq!(covered.suggested_quantity, 0.0);
+    }
+
+    #[test]
+    fn test_plan_without_consumption() {
+        let result = plan(&ReorderInput {
+            current_stock: 2.0,
+            reorder_level: 5.0,
+            on_order: 0.0,
+            consumed: 0.0,
+            window_days: 90,
+            coverage_days: 30,
+        });
+        assert_eq!(result.days_of_cover, None);
+        assert_eq!(result.suggested_quantity, 3.0);
+        assert!(needs_reorder(5.0, 5.0));
+    }
+}
@@ -0,0 +1,52 @@
+// Stock transfers between branches
+//
+// Dispatching a transfer takes the stock out of the origin branch right away
+// ('salida' per lot) and leaves the document 'en_transito'. The receiving
+// branch confirms it ('recibida'), which enters the same lots there; a
+// transfer still in transit can be cancelled, returning the stock to origin.
+
+/// Reference type used on both sides of a transfer
+pub const TRANSFER_REFERENCE: &str = "traslado";
+
+pub fn validate_transfer(from_branch_id: &str, to_branch_id: &str, quantities: &[f64]) -> Result<(), String> {
+    if from_branch_id == to_branch_id {
+        return Err("La sucursal de origen y destino deben ser distintas".to_string());
+    }
+    if quantities.is_empty() {
+        return Err("El traslado debe tener al menos una línea".to_string());
+    }
+    if quantities.iter().any(|q| *q <= 0.0) {
+        return Err("La cantidad de cada línea debe ser mayor a cero".to_string());
+    }
+    Ok(())
+}
+
+/// Only transfers in transit can be received or cancelled
+pub fn ensure_in_transit(status: &str) -> Result<(), String> {
+    if status == "en_transito" {
+        Ok(())
+    } else {
+        Err(format!("El traslado no está en tránsito (estado: {})", status))
+    }
+}
+
+pub fn transfer_number(sequence: i64) -> String {
+    format!("TR-{:05}", sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_validation() {
+        assert!(validate_transfer("a", "b", &[1.0, 2.5]).is_ok());
+        assert!(validate_transfer("a", "a", &[1.0]).is_err());
+        assert!(validate_transfer("a", "b", &[]).is_err());
+        assert!(validate_transfer("a", "b", &[1.0, 0.0]).is_err());
+
+        assert!(ensure_in_transit("en_transito").is_ok());
+        assert!(ensure_in_transit("recibida").is_err());
+        assert_eq!(transfer_number(42), "TR-00042");
+    }
+}
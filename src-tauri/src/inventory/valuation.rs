@@ -0,0 +1,163 @@
+// FIFO inventory valuation and cost of goods sold
+//
+// The movement ledger is replayed per item in chronological order. Every
+// increase opens a cost layer at the unit cost recorded on the movement when
+// it was made (the lot's cost_price, else the item's); every decrease consumes layers of its own
+// lot first and then the oldest ones. What remains is the stock value; what
+// was consumed by sales in a period is the cost of goods sold.
+
+use std::collections::VecDeque;
+
+use rust_decimal::prelude::*;
+
+use super::ledger;
+use crate::billing::round_money;
+
+/// Reference type of outgoing movements that are not sales
+const NON_SALE_REFERENCES: &[&str] = &["traslado", "ajuste"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostLayer {
+    pub lot_id: Option<String>,
+    pub quantity: f64,
+    pub unit_cost: Decimal,
+}
+
+fn cost_of(quantity: f64, unit_cost: Decimal) -> Decimal {
+    Decimal::from_f64(quantity).unwrap_or_default() * unit_cost
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FifoLedger {
+    layers: VecDeque<CostLayer>,
+}
+
+impl FifoLedger {
+    pub fn receive(&mut self, lot_id: Option<&str>, quantity: f64, unit_cost: Decimal) {
+        if quantity > 0.0 {
+            self.layers.push_back(CostLayer {
+                lot_id: lot_id.map(str::to_string),
+                quantity,
+                unit_cost,
+            });
+        }
+    }
+
+    /// Take `quantity` out, from the given lot's layers first and then the
+    /// oldest layers. Units beyond what is on hand are costed at
+    /// `fallback_cost`. Returns the (unrounded) cost of the units taken.
+    pub fn issue(&mut self, lot_id: Option<&str>, quantity: f64, fallback_cost: Decimal) -> Decimal {
+        let mut remaining = quantity;
+        let mut cost = Decimal::ZERO;
+
+        let mut take = |layer: &mut CostLayer, remaining: &mut f64| {
+            let used = layer.quantity.min(*remaining);
+            layer.quantity -= used;
+            *remaining -= used;
+            cost += cost_of(used, layer.unit_cost);
+        };
+
+        if let Some(lot) = lot_id {
+            for layer in self.layers.iter_mut().filter(|l| l.lot_id.as_deref() == Some(lot)) {
+                if remaining <= 1e-9 {
+                    break;
+                }
+                take(layer, &mut remaining);
+            }
+        }
+        for layer in self.layers.iter_mut() {
+            if remaining <= 1e-9 {
+                break;
+            }
+            take(layer, &mut remaining);
+        }
+        self.layers.retain(|l| l.quantity > 1e-9);
+
+        if remaining > 1e-9 {
+            cost += cost_of(remaining, fallback_cost);
+        }
+        cost
+    }
+
+    /// Apply one ledger movement; returns the cost taken out (zero for increases)
+    pub fn apply(&mut self, movement_type: &str, quantity: f64, lot_id: Option<&str>, unit_cost: Decimal) -> Decimal {
+        let delta = ledger::stock_delta(movement_type, quantity);
+        if delta > 0.0 {
+            self.receive(lot_id, delta, unit_cost);
+            Decimal::ZERO
+        } else if delta < 0.0 {
+            self.issue(lot_id, -delta, unit_cost)
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    pub fn quantity(&self) -> f64 {
+        self.layers.iter().map(|l| l.quantity).sum()
+    }
+
+    pub fn value(&self) -> Decimal {
+        round_money(self.layers.iter().map(|l| cost_of(l.quantity, l.unit_cost)).sum())
+    }
+
+    /// Value per unit on hand, zero without stock
+    pub fn average_cost(&self) -> Decimal {
+        match Decimal::from_f64(self.quantity()) {
+            Some(quantity) if !quantity.is_zero() => round_money(self.value() / quantity),
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// Whether an outgoing movement counts as cost of goods sold
+pub fn is_sale(movement_type: &str, reference_type: Option<&str>) -> bool {
+    movement_type == "salida" && !reference_type.is_some_and(|r| NON_SALE_REFERENCES.contains(&r))
+}
+
+/// Gross margin and margin percent over revenue (None without revenue)
+pub fn gross_margin(revenue: Decimal, cogs: Decimal) -> (Decimal, Option<Decimal>) {
+    let margin = round_money(revenue - cogs);
+    let percent = if revenue > Decimal::ZERO {
+        Some((margin / revenue * Decimal::ONE_HUNDRED).round_dp(2))
+    } else {
+        None
+    };
+    (margin, percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::billing::d;
+
+    #[test]
+    fn test_fifo_issue_and_valuation() {
+        let mut fifo = FifoLedger::default();
+        assert_eq!(fifo.apply("entrada", 10.0, Some("A"), d("5.00")), Decimal::ZERO);
+        fifo.apply("entrada", 10.0, Some("B"), d("6.00"));
+
+        // Oldest layer first: 12 units = 10 x 5 + 2 x 6
+        assert_eq!(fifo.apply("salida", 12.0, None, d("6.00")), d("62.00"));
+        // Lot-specific issue takes from lot B
+        assert_eq!(fifo.apply("merma", 3.0, Some("B"), d("6.00")), d("18.00"));
+
+        assert_eq!(fifo.quantity(), 5.0);
+        assert_eq!(fifo.value(), d("30.00"));
+        assert_eq!(fifo.average_cost(), d("6.00"));
+
+        // Going past zero costs the excess at the fallback cost
+        assert_eq!(fifo.apply("salida", 7.0, None, d("6.50")), d("43.00"));
+        assert_eq!(fifo.quantity(), 0.0);
+    }
+
+    #[test]
+    fn test_sales_and_margin() {
+        assert!(is_sale("salida", Some("factura")));
+        assert!(is_sale("salida", None));
+        assert!(!is_sale("salida", Some("traslado")));
+        assert!(!is_sale("cortesia", None));
+
+        assert_eq!(gross_margin(d("200.00"), d("150.00")), (d("50.00"), Some(d("25.00"))));
+        assert_eq!(gross_margin(Decimal::ZERO, d("10.00")).1, None);
+    }
+}
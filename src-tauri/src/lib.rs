@@ -9,6 +9,13 @@ pub mod config;
 pub mod postgres;
 pub mod connection_manager;
 pub mod realtime;
+pub mod billing;
+pub mod fel;
+pub mod pdf;
+pub mod inventory;
+pub mod clinical;
+pub mod permissions;
+pub mod timezone;
 
 use db::Database;
 use config::AppConfig;
@@ -24,6 +31,20 @@ pub struct AppState {
     pub connection_manager: Arc<ConnectionManager>,
     pub config: AppConfig,
     pub realtime_manager: RwLock<RealtimeManager>,
+    /// Signed-in user whose roles authorize commands
+    pub session: std::sync::RwLock<Option<permissions::SessionUser>>,
+}
+
+impl AppState {
+    pub fn session_user(&self) -> Option<permissions::SessionUser> {
+        self.session.read().ok().and_then(|s| s.clone())
+    }
+
+    pub fn set_session_user(&self, user: Option<permissions::SessionUser>) {
+        if let Ok(mut session) = self.session.write() {
+            *session = user;
+        }
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -130,6 +151,7 @@ pub fn run() {
                 connection_manager,
                 config,
                 realtime_manager: RwLock::new(realtime_manager),
+                session: std::sync::RwLock::new(None),
             };
             let app_state = Arc::new(app_state);
 
@@ -145,7 +167,7 @@ pub fn run() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_store::Builder::default().build())
-        .invoke_handler(tauri::generate_handler![
+        .invoke_handler(permissions::guard(tauri::generate_handler![
             // Connection status command (NEW)
             commands::get_connection_status,
             // Sync commands
@@ -196,6 +218,7 @@ pub fn run() {
             commands::get_exam_eye,
             commands::get_exam_eyes_by_encounter,
             commands::upsert_exam_eye,
+            commands::recalculate_exam_logmar,
             // Studies (estudios)
             commands::get_studies_by_appointment,
             commands::get_studies_by_patient,
@@ -233,10 +256,20 @@ pub fn run() {
             commands::get_payments_by_date_range,
             commands::create_payment,
             commands::delete_payment,
+            // Electronic invoicing (FEL)
+            commands::certify_invoice_fel,
+            commands::cancel_invoice_fel,
+            commands::get_fel_document,
+            commands::get_fel_xml,
+            commands::get_fel_pdf,
             // Service prices (precios de servicios)
             commands::get_service_prices,
             commands::create_service_price,
             commands::update_service_price,
+            // Exchange rates (tipos de cambio)
+            commands::get_exchange_rates,
+            commands::get_exchange_rate_for_date,
+            commands::upsert_exchange_rate,
             // Inventory (inventario)
             commands::get_inventory_items,
             commands::create_inventory_item,
@@ -248,12 +281,17 @@ pub fn run() {
             commands::create_inventory_lot,
             commands::get_inventory_movements,
             commands::create_inventory_movement,
+            commands::consume_inventory_stock,
+            commands::get_expiring_lots,
+            commands::write_off_expired_lots,
+            commands::get_inventory_reconciliation,
             // Cash closure reports
             commands::get_service_sales,
             commands::get_service_details,
             commands::get_inventory_sales,
             commands::get_inventory_details,
             commands::get_payment_method_summary,
+            commands::get_payment_currency_summary,
             commands::generate_invoice_number,
             // Products report
             commands::get_products_report,
@@ -263,6 +301,13 @@ pub fn run() {
             commands::get_daily_summary,
             commands::get_daily_invoices,
             commands::create_cash_closure,
+            // Cash register sessions (sesiones de caja)
+            commands::open_cash_session,
+            commands::get_open_cash_session,
+            commands::get_cash_sessions,
+            commands::get_cash_session_detail,
+            commands::create_cash_movement,
+            commands::close_cash_session,
             // CRM pipelines
             commands::get_crm_pipelines,
             commands::get_crm_pipeline_by_id,
@@ -324,6 +369,74 @@ pub fn run() {
             commands::update_room_inventory_stock,
             commands::create_room_inventory_movement,
             commands::get_room_inventory_movements,
+            commands::get_room_inventory_reconciliation,
+            // Stock counts (conteos físicos)
+            commands::start_stock_count,
+            commands::get_stock_counts,
+            commands::get_stock_count_detail,
+            commands::record_stock_counts,
+            commands::post_stock_count,
+            commands::cancel_stock_count,
+            // Purchasing (compras y cuentas por pagar)
+            commands::create_purchase_order,
+            commands::get_purchase_orders,
+            commands::get_purchase_order_detail,
+            commands::update_purchase_order_status,
+            commands::receive_purchase_order,
+            commands::get_supplier_payables,
+            commands::pay_supplier_payable,
+            commands::get_reorder_suggestions,
+            // Stock transfers (traslados entre sucursales)
+            commands::create_stock_transfer,
+            commands::get_stock_transfers,
+            commands::get_stock_transfer_detail,
+            commands::receive_stock_transfer,
+            commands::cancel_stock_transfer,
+            // Surgery kits (materiales por tipo de cirugía)
+            commands::get_surgery_type_materials,
+            commands::set_surgery_type_materials,
+            commands::get_surgery_material_usage,
+            commands::get_surgery_cost_report,
+            // Barcodes (códigos de barras / GS1)
+            commands::resolve_inventory_barcode,
+            // Inventory valuation (valorización y costo de ventas)
+            commands::get_inventory_valuation,
+            commands::get_product_margin_report,
+            // Prescriptions (recetas de lentes)
+            commands::create_prescription,
+            commands::get_prescriptions_by_encounter,
+            commands::get_prescription_pdf,
+            // Refraction (cálculos de refracción)
+            commands::calculate_refraction,
+            commands::get_encounter_refraction,
+            // IOL calculation (biometría y lente intraocular)
+            commands::get_iol_models,
+            commands::create_iol_model,
+            commands::update_iol_model,
+            commands::get_biometry_by_patient,
+            commands::create_biometry,
+            commands::delete_biometry,
+            commands::calculate_iol,
+            commands::set_surgery_iol,
+            // Clinical timeline (historia por ojo)
+            commands::get_eye_timeline,
+            // ICD-10 (catálogo CIE-10)
+            commands::search_icd10,
+            commands::get_icd10_code,
+            commands::load_icd10_catalog,
+            // Encounter templates (plantillas de consulta)
+            commands::get_encounter_templates,
+            commands::create_encounter_template,
+            commands::update_encounter_template,
+            // Encounter signing (firma y enmiendas)
+            commands::sign_encounter,
+            commands::amend_encounter,
+            commands::get_encounter_history,
+            // Audit log (bitácora de auditoría)
+            commands::get_audit_log,
+            // Patient access log (registro de accesos)
+            commands::get_patient_access_log,
+            commands::get_unusual_patient_access,
             // Analytics v2 (for Analytics.tsx offline)
             commands::get_analytics_service_sales,
             commands::get_analytics_payment_methods,
@@ -346,7 +459,7 @@ pub fn run() {
             auth::clear_cached_session,
             auth::has_valid_cached_session,
             auth::get_cached_user,
-        ])
+        ]))
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
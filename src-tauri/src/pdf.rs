@@ -0,0 +1,131 @@
+// Minimal PDF writer for documents generated on the clinic workstation
+// (FEL representation when the certifier does not return one, prescriptions)
+//
+// Produces plain text pages in Helvetica, US Letter size. Text is encoded as
+// WinAnsi so Spanish characters (á, ñ, ¿) print correctly.
+
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const MARGIN: f32 = 50.0;
+const TITLE_SIZE: f32 = 14.0;
+const BODY_SIZE: f32 = 10.0;
+const LINE_HEIGHT: f32 = 14.0;
+
+/// Build a PDF with a bold title followed by one line of text per entry.
+/// Long documents continue on additional pages.
+pub fn text_document(title: &str, lines: &[String]) -> Vec<u8> {
+    let lines_per_page = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize - 2;
+    let mut pages: Vec<&[String]> = lines.chunks(lines_per_page.max(1)).collect();
+    if pages.is_empty() {
+        pages.push(&[]);
+    }
+
+    let mut contents = Vec::with_capacity(pages.len());
+    for (idx, page_lines) in pages.iter().enumerate() {
+        // Text position is relative: start at the top margin and move down per line
+        let mut stream = String::from("BT\n");
+        let top = PAGE_HEIGHT - MARGIN;
+        if idx == 0 {
+            stream.push_str(&format!(
+                "/F2 {} Tf\n{} {} Td\n({}) Tj\n",
+                TITLE_SIZE,
+                MARGIN,
+                top,
+                escape_text(title)
+            ));
+            stream.push_str(&format!("/F1 {} Tf\n0 {} Td\n", BODY_SIZE, -2.0 * LINE_HEIGHT));
+        } else {
+            stream.push_str(&format!("/F1 {} Tf\n{} {} Td\n", BODY_SIZE, MARGIN, top));
+        }
+        for line in page_lines.iter() {
+            stream.push_str(&format!("({}) Tj\n0 {} Td\n", escape_text(line), -LINE_HEIGHT));
+        }
+        stream.push_str("ET\n");
+        contents.push(stream);
+    }
+
+    // Object layout: 1 catalog, 2 pages, 3-4 fonts, then (page, content) pairs
+    let page_ids: Vec<usize> = (0..contents.len()).map(|i| 5 + i * 2).collect();
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" "),
+            page_ids.len()
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_vec(),
+    ];
+    for (page_id, content) in page_ids.iter().zip(&contents) {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_id + 1
+            )
+            .into_bytes(),
+        );
+        let body = encode_win_ansi(content);
+        let mut stream = format!("<< /Length {} >>\nstream\n", body.len()).into_bytes();
+        stream.extend_from_slice(&body);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut out: Vec<u8> = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (idx, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n", idx + 1).as_bytes());
+        out.extend_from_slice(object);
+        out.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref_offset = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+    out
+}
+
+/// Escape PDF string delimiters
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
+}
+
+/// Encode as WinAnsi (Latin-1 subset); characters outside it become '?'
+fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if (c as u32) < 256 { c as u32 as u8 } else { b'?' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_document_structure() {
+        let lines: Vec<String> = (0..100).map(|i| format!("Línea {} (ñ)", i)).collect();
+        let pdf = text_document("Receta médica", &lines);
+
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8_lossy(&pdf);
+        // 47 lines per page
+        assert!(text.contains("/Count 3"));
+        assert!(text.contains("\\(\u{FFFD}\\)"));
+    }
+}
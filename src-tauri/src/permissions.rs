@@ -0,0 +1,348 @@
+// Role-based authorization for Tauri commands
+//
+// Every command is mapped to the permission it needs; the invoke handler
+// checks it against the roles of the signed-in user before running the
+// command. Roles come from user_roles when the session is cached (see
+// auth.rs), not from the UI. Commands missing from the map are admin-only,
+// so a new command stays closed until it is mapped here.
+
+use std::sync::Arc;
+use tauri::ipc::Invoke;
+use tauri::Manager;
+
+use crate::AppState;
+
+/// Roles stored in user_roles (app_role enum)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Doctor,
+    Nurse,
+    Reception,
+    Diagnostico,
+    Estudios,
+    Caja,
+    Contabilidad,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "admin" => Some(Role::Admin),
+            "doctor" => Some(Role::Doctor),
+            "nurse" => Some(Role::Nurse),
+            "reception" => Some(Role::Reception),
+            "diagnostico" => Some(Role::Diagnostico),
+            "estudios" => Some(Role::Estudios),
+            "caja" => Some(Role::Caja),
+            "contabilidad" => Some(Role::Contabilidad),
+            _ => None,
+        }
+    }
+
+    /// Mirrors the screens each role can open in the UI (App.tsx routes)
+    fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            // Admin is checked before the table and may do everything
+            Role::Admin => &[],
+            Role::Doctor => &[
+                PatientsRead, PatientsWrite, Agenda, ClinicalRead, ClinicalWrite, ClinicalSign, InventoryRead,
+                InventoryUse,
+            ],
+            Role::Nurse => &[
+                PatientsRead, PatientsWrite, Agenda, ClinicalRead, ClinicalWrite, InventoryRead, InventoryUse,
+                RoomInventory, Crm,
+            ],
+            Role::Reception => &[PatientsRead, PatientsWrite, Agenda, Crm],
+            Role::Diagnostico => &[PatientsRead, Agenda, ClinicalRead, ClinicalWrite, Crm],
+            Role::Estudios => &[PatientsRead, Agenda, ClinicalRead, ClinicalWrite],
+            Role::Caja => &[
+                PatientsRead, Agenda, BillingRead, BillingCharge, CashRegister, InventoryRead, InventoryUse, Reports,
+                Crm,
+            ],
+            Role::Contabilidad => &[
+                PatientsRead, Agenda, BillingRead, BillingCharge, BillingVoid, CashRegister, InventoryRead,
+                InventoryUse, InventoryManage, Prices, Reports, Crm,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Administration,
+    PatientsRead,
+    PatientsWrite,
+    PatientsDelete,
+    Agenda,
+    ClinicalRead,
+    ClinicalWrite,
+    /// Sign and amend encounters, templates, IOL choice
+    ClinicalSign,
+    BillingRead,
+    BillingCharge,
+    /// Delete payments, cancel certified invoices
+    BillingVoid,
+    /// Service prices and exchange rates
+    Prices,
+    CashRegister,
+    InventoryRead,
+    /// Consume stock, courtesies
+    InventoryUse,
+    InventoryManage,
+    /// Room (sala) inventory kept by nursing
+    RoomInventory,
+    Crm,
+    Reports,
+    Research,
+    Audit,
+}
+
+impl Permission {
+    /// Shown to the user when the permission is missing
+    pub fn description(self) -> &'static str {
+        match self {
+            Permission::Administration => "administrar la configuración y los usuarios",
+            Permission::PatientsRead => "consultar pacientes",
+            Permission::PatientsWrite => "registrar o modificar pacientes",
+            Permission::PatientsDelete => "eliminar pacientes",
+            Permission::Agenda => "gestionar la agenda",
+            Permission::ClinicalRead => "consultar el expediente clínico",
+            Permission::ClinicalWrite => "registrar datos clínicos",
+            Permission::ClinicalSign => "firmar o enmendar consultas",
+            Permission::BillingRead => "consultar facturas y pagos",
+            Permission::BillingCharge => "facturar y registrar pagos",
+            Permission::BillingVoid => "anular pagos o facturas",
+            Permission::Prices => "modificar precios y tipos de cambio",
+            Permission::CashRegister => "operar la caja",
+            Permission::InventoryRead => "consultar el inventario",
+            Permission::InventoryUse => "registrar consumo de materiales",
+            Permission::InventoryManage => "gestionar inventario y compras",
+            Permission::RoomInventory => "gestionar el inventario de sala",
+            Permission::Crm => "gestionar el seguimiento de pacientes",
+            Permission::Reports => "ver reportes y análisis",
+            Permission::Research => "consultar datos de investigación",
+            Permission::Audit => "revisar la auditoría y los accesos",
+        }
+    }
+}
+
+/// What a command requires from the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Connection, sync and login commands, usable before signing in
+    Public,
+    /// Any signed-in user (catalogs, settings, files)
+    Session,
+    Requires(Permission),
+}
+
+/// Signed-in user and the roles loaded from user_roles
+#[derive(Debug, Clone)]
+pub struct SessionUser {
+    pub user_id: String,
+    pub roles: Vec<Role>,
+}
+
+impl SessionUser {
+    /// Unknown role names are ignored
+    pub fn new(user_id: &str, roles: &[String]) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            roles: roles.iter().filter_map(|r| Role::parse(r)).collect(),
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.roles
+            .iter()
+            .any(|role| *role == Role::Admin || role.permissions().contains(&permission))
+    }
+}
+
+/// Permission required by each command
+pub fn command_access(command: &str) -> Access {
+    use Permission::*;
+    let permission = match command {
+        // Connection, sync and auth
+        "get_connection_status" | "trigger_initial_sync" | "check_network_status" | "process_sync_queue"
+        | "get_pending_sync_count" | "get_sync_status" | "get_sync_pending_count" | "get_sync_pending_details"
+        | "get_local_storage_status" | "cache_auth_session" | "get_cached_session" | "clear_cached_session"
+        | "has_valid_cached_session" | "get_cached_user" => return Access::Public,
+
+        // Catalogs and shared lookups
+        "get_branches" | "get_rooms" | "get_all_rooms" | "get_doctors" | "get_profile_by_user_id"
+        | "get_user_roles" | "print_webview" | "upload_file_to_local_storage" | "read_file_from_local_storage"
+        | "list_local_storage_files" | "get_app_settings" | "get_surgery_types" | "get_study_types"
+        | "get_procedure_types" | "get_referring_doctors" | "get_service_prices" | "get_exchange_rates"
+        | "get_exchange_rate_for_date" | "get_crm_procedure_types" | "search_icd10" | "get_icd10_code"
+        | "get_iol_models" | "calculate_refraction" => return Access::Session,
+
+        // Administration
+        "create_branch" | "update_branch" | "delete_branch" | "create_room" | "update_room"
+        | "get_all_users_with_profiles" | "get_pending_registrations" | "add_user_role"
+        | "update_profile_visibility" | "update_profile_doctor_info" | "update_app_setting" | "create_surgery_type"
+        | "create_study_type" | "create_procedure_type" | "update_surgery_type" | "update_study_type"
+        | "update_procedure_type" | "delete_surgery_type" | "delete_study_type" | "delete_procedure_type"
+        | "create_iol_model" | "update_iol_model" | "load_icd10_catalog" | "recalculate_exam_logmar" => Administration,
+
+        "create_service_price" | "update_service_price" | "upsert_exchange_rate" => Prices,
+
+        // Patients
+        "get_patients" | "get_patient_by_id" => PatientsRead,
+        "create_patient" | "update_patient" | "create_referring_doctor" => PatientsWrite,
+        "delete_patient" => PatientsDelete,
+
+        // Agenda
+        "get_appointments" | "create_appointment" | "update_appointment" | "delete_appointment"
+        | "save_appointments_to_sqlite" | "remove_appointment_from_sqlite" | "get_schedule_blocks"
+        | "create_schedule_block" | "delete_schedule_block" | "get_invoice_by_appointment" => Agenda,
+
+        // Clinical record
+        "get_encounter_by_id" | "get_encounters_by_patient" | "get_encounter_by_appointment" | "get_exam_eye"
+        | "get_exam_eyes_by_encounter" | "get_studies_by_appointment" | "get_studies_by_patient"
+        | "get_surgeries_by_appointment" | "get_surgeries_by_patient" | "get_procedures_by_appointment"
+        | "get_procedures_by_patient" | "get_diagnoses_by_encounter" | "get_prescriptions_by_encounter"
+        | "get_prescription_pdf" | "get_encounter_refraction" | "get_biometry_by_patient" | "calculate_iol"
+        | "get_eye_timeline" | "get_encounter_templates" | "get_encounter_history"
+        | "get_consent_signature_by_surgery" | "get_consent_signatures_by_patient" => ClinicalRead,
+        "create_encounter" | "update_encounter" | "upsert_exam_eye" | "create_study" | "update_study_status"
+        | "create_surgery" | "update_surgery" | "delete_surgery" | "delete_surgery_file" | "delete_study_file"
+        | "create_procedure" | "update_procedure" | "create_diagnosis" | "update_diagnosis" | "delete_diagnosis"
+        | "create_prescription" | "create_biometry" | "delete_biometry" | "link_consent_signature_to_surgery" => {
+            ClinicalWrite
+        }
+        "sign_encounter" | "amend_encounter" | "create_encounter_template" | "update_encounter_template"
+        | "set_surgery_iol" => ClinicalSign,
+
+        // Billing and cash
+        "get_invoices_by_patient" | "get_invoices_by_branch_and_date" | "get_invoice_by_id"
+        | "get_invoice_items" | "get_pending_invoices_by_branch"
+        | "get_payments_by_invoice" | "get_payments_by_date_range" | "get_fel_document" | "get_fel_xml"
+        | "get_fel_pdf" | "get_service_sales" | "get_service_details" | "get_inventory_sales"
+        | "get_inventory_details" | "get_payment_method_summary" | "get_payment_currency_summary"
+        | "get_daily_summary" | "get_daily_invoices" => BillingRead,
+        "create_invoice" | "generate_invoice_number" | "create_payment" | "certify_invoice_fel"
+        | "update_invoice_status" => BillingCharge,
+        "delete_payment" | "cancel_invoice_fel" => BillingVoid,
+        "open_cash_session" | "get_open_cash_session" | "get_cash_sessions" | "get_cash_session_detail"
+        | "create_cash_movement" | "close_cash_session" | "create_cash_closure" => CashRegister,
+
+        // Inventory and purchasing
+        "get_inventory_items" | "get_suppliers" | "get_inventory_lots" | "get_all_inventory_lots"
+        | "get_inventory_movements" | "get_expiring_lots" | "resolve_inventory_barcode"
+        | "get_surgery_type_materials" | "get_surgery_material_usage" => InventoryRead,
+        "consume_inventory_stock" | "create_inventory_movement" => InventoryUse,
+        "get_room_inventory_categories" | "get_room_inventory_items" | "get_room_inventory_movements"
+        | "create_room_inventory_movement" | "update_room_inventory_stock" | "create_room_inventory_category"
+        | "update_room_inventory_category" | "delete_room_inventory_category" | "create_room_inventory_item"
+        | "update_room_inventory_item" | "delete_room_inventory_item" | "get_room_inventory_reconciliation" => {
+            RoomInventory
+        }
+        "create_inventory_item" | "update_inventory_item" | "create_supplier" | "create_inventory_lot"
+        | "write_off_expired_lots" | "get_inventory_reconciliation" | "start_stock_count" | "get_stock_counts" | "get_stock_count_detail"
+        | "record_stock_counts" | "post_stock_count" | "cancel_stock_count" | "create_purchase_order"
+        | "get_purchase_orders" | "get_purchase_order_detail" | "update_purchase_order_status"
+        | "receive_purchase_order" | "get_supplier_payables" | "pay_supplier_payable" | "get_reorder_suggestions"
+        | "create_stock_transfer" | "get_stock_transfers" | "get_stock_transfer_detail" | "receive_stock_transfer"
+        | "cancel_stock_transfer" | "set_surgery_type_materials" | "get_inventory_valuation" => InventoryManage,
+
+        // CRM
+        "get_crm_pipelines" | "get_crm_pipeline_by_id" | "create_crm_pipeline" | "update_crm_pipeline_stage"
+        | "get_crm_pipeline_stages" | "get_crm_pipeline_notes" | "create_crm_pipeline_note"
+        | "get_crm_activity_read" | "upsert_crm_activity_read" | "get_crm_unread_activities"
+        | "get_crm_recent_activities" => Crm,
+
+        // Reports and analytics
+        "get_products_report" | "get_services_report" | "get_surgery_cost_report" | "get_product_margin_report"
+        | "get_analytics_service_sales" | "get_analytics_payment_methods" | "get_analytics_inventory_details"
+        | "get_analytics_service_details" | "get_clinical_stats_with_revenue" | "get_analytics_invoices"
+        | "get_analytics_closures" | "get_analytics_appointments" | "get_analytics_doctors"
+        | "get_doctor_activity_detail" | "get_referred_studies_by_doctor" => Reports,
+
+        "get_clinical_research_data" | "get_clinical_research_data_by_patient" => Research,
+
+        "get_audit_log" | "get_patient_access_log" | "get_unusual_patient_access" => Audit,
+
+        _ => Administration,
+    };
+    Access::Requires(permission)
+}
+
+/// Check that the session may run a command. The error is shown to the user.
+pub fn authorize(session: Option<&SessionUser>, command: &str) -> Result<(), String> {
+    let permission = match command_access(command) {
+        Access::Public => return Ok(()),
+        Access::Session => None,
+        Access::Requires(permission) => Some(permission),
+    };
+
+    let user = session.ok_or("Sesión no iniciada: inicie sesión para continuar")?;
+    match permission {
+        Some(permission) if !user.has(permission) => {
+            log::warn!("Command {} denied to user {}", command, user.user_id);
+            Err(format!("Acceso denegado: su rol no permite {}", permission.description()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Wrap the generated invoke handler so every command is authorized first
+pub fn guard<F>(handler: F) -> impl Fn(Invoke) -> bool + Send + Sync + 'static
+where
+    F: Fn(Invoke) -> bool + Send + Sync + 'static,
+{
+    move |invoke: Invoke| {
+        let result = {
+            let webview = invoke.message.webview();
+            match webview.try_state::<Arc<AppState>>() {
+                Some(state) => authorize(state.session_user().as_ref(), invoke.message.command()),
+                None => Err("La aplicación no ha terminado de iniciar".to_string()),
+            }
+        };
+
+        match result {
+            Ok(()) => handler(invoke),
+            Err(message) => {
+                invoke.resolver.reject(message);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(roles: &[&str]) -> SessionUser {
+        SessionUser::new("u1", &roles.iter().map(|r| r.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_reception_cannot_void_payments() {
+        let reception = user(&["reception"]);
+        assert!(authorize(Some(&reception), "get_patients").is_ok());
+        assert!(authorize(Some(&reception), "create_appointment").is_ok());
+        assert!(authorize(Some(&reception), "delete_payment").is_err());
+        assert!(authorize(Some(&reception), "get_encounters_by_patient").is_err());
+        assert!(authorize(Some(&user(&["caja"])), "create_payment").is_ok());
+        assert!(authorize(Some(&user(&["caja"])), "delete_payment").is_err());
+        assert!(authorize(Some(&user(&["contabilidad"])), "delete_payment").is_ok());
+        assert!(authorize(Some(&user(&["reception", "contabilidad"])), "delete_payment").is_ok());
+        assert!(authorize(Some(&user(&["admin"])), "delete_payment").is_ok());
+    }
+
+    #[test]
+    fn test_session_and_unmapped_commands() {
+        assert!(authorize(None, "get_connection_status").is_ok());
+        assert!(authorize(None, "get_branches").is_err());
+        assert!(authorize(Some(&user(&["estudios"])), "get_branches").is_ok());
+        assert!(authorize(Some(&user(&["nurse"])), "update_room_inventory_stock").is_ok());
+        assert!(authorize(Some(&user(&["diagnostico"])), "create_study").is_ok());
+        assert!(authorize(Some(&user(&["doctor"])), "some_new_command").is_err());
+        assert!(authorize(Some(&user(&["admin"])), "some_new_command").is_ok());
+        assert!(authorize(Some(&user(&["recepcion"])), "get_patients").is_err());
+    }
+}
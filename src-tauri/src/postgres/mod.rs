@@ -13,8 +13,11 @@ use crate::commands::{
     Procedure, ProcedureInput, ProcedureUpdate,
     Diagnosis, DiagnosisInput, DiagnosisUpdate,
     Invoice, InvoiceItem, InvoiceInput, InvoiceItemInput, InvoiceWithPatient,
-    Payment, PaymentInput,
+    Payment, PaymentInput, FelDocument,
+    CashSession, CashSessionOpenInput, CashSessionCloseInput, CashSessionDetail,
+    CashMovement, CashMovementInput,
     ServicePrice, ServicePriceInput, ServicePriceUpdate,
+    ExchangeRate, ExchangeRateInput,
     InventoryItem, InventoryItemInput, InventoryItemUpdate, Supplier,
     CRMPipeline, CRMPipelineInput, CRMPipelineStage, CRMPipelineNote, CRMPipelineNoteInput,
     CRMProcedureType, BranchEmbed,
@@ -28,20 +31,125 @@ use crate::commands::{
     RoomInventoryCategory, RoomInventoryCategoryInput, RoomInventoryCategoryUpdate,
     RoomInventoryItem, RoomInventoryItemInput, RoomInventoryItemUpdate,
     RoomInventoryMovement, RoomInventoryMovementInput,
+    StockCountSession, StockCountSessionInput, StockCountLine, StockCountEntry, StockCountDetail,
+    PurchaseOrder, PurchaseOrderInput, PurchaseOrderLine, PurchaseOrderDetail,
+    GoodsReceipt, GoodsReceiptInput, SupplierPayable, SupplierPaymentInput,
+    ReorderSuggestion, ReorderSupplierGroup, PurchaseOrderLineInput,
+    StockTransfer, StockTransferInput, StockTransferLine, StockTransferDetail,
+    SurgeryTypeMaterial, SurgeryTypeMaterialInput, SurgeryMaterialUsage, SurgeryCostLine,
+    BarcodeResolution, InventoryValuationLine, ProductMarginLine,
+    Prescription, PrescriptionInput,
+    EyeRefraction, EncounterRefraction,
+    IolModel, IolModelInput, BiometryRecord, BiometryInput, IolCalculationInput, IolCalculation,
+    IolFormulaResult, IolPowerOption, SurgeryIolInput,
+    ClinicalTimeline, ClinicalTimelineEvent,
+    Icd10Code,
+    EncounterTemplate, EncounterTemplateInput, TemplateDiagnosis,
+    EncounterContent, EncounterAmendmentInput, EncounterAmendment, EncounterHistory,
+    AuditLogEntry, AuditLogFilters,
+    PatientAccessEntry, UnusualAccess,
     SupplierInput, InventoryLot, InventoryLotInput, InventoryLotWithProduct,
     InventoryMovement, InventoryMovementInput,
     InventoryItemEmbed, InventoryLotEmbed,
     ServiceSales, ServiceDetail, InventorySales, InventoryDetail, PaymentMethodSummary,
 };
+use crate::billing::{self, CurrencyTotals};
+use crate::billing::cash::DenominationCount;
+use crate::billing::tax::{compute_invoice_tax, TaxableLine, DEFAULT_TAX_RATE};
+use crate::fel::certifier::{Cancellation, Certification};
+use crate::fel::FelReceiver;
+use crate::clinical::{acuity, icd10, iol, prescription, record, refraction, timeline};
+use crate::inventory::{barcode, count, kits, ledger, lots, purchasing, reorder, transfer, valuation};
 use crate::config::LocalServerConfig;
-use deadpool_postgres::{Config, Pool, Runtime, PoolError};
+use crate::timezone;
+use deadpool_postgres::{Config, Hook, HookError, Pool, Runtime, PoolError};
 use tokio_postgres::NoTls;
+use rust_decimal::Decimal;
 use std::sync::Arc;
 
+/// Column list returned by inventory movement inserts (see map_inventory_movement_row)
+const INVENTORY_MOVEMENT_COLUMNS: &str = "id, branch_id, item_id, lot_id, movement_type::text, quantity::float8,
+     reference_type, reference_id, notes, created_at";
+
+/// Column list for stock count session queries, aliased as `s` (see map_stock_count_session_row)
+const STOCK_COUNT_SESSION_COLUMNS: &str = "s.id, s.branch_id, s.inventory_type, s.category, s.status, s.started_by,
+    s.started_at, s.posted_at, s.notes,
+    (SELECT COUNT(*) FROM stock_count_lines l WHERE l.session_id = s.id),
+    (SELECT COUNT(*) FROM stock_count_lines l WHERE l.session_id = s.id AND l.counted_quantity IS NOT NULL)";
+
+/// Purchase order select with supplier name (see map_purchase_order_row)
+const PURCHASE_ORDER_SELECT: &str = "SELECT o.id, o.order_number, o.branch_id, o.supplier_id, s.name, o.status, o.currency,
+        o.expected_date, o.notes, o.total_amount, o.created_by, o.created_at
+     FROM purchase_orders o
+     LEFT JOIN suppliers s ON o.supplier_id = s.id";
+
+/// Supplier payable select with order and invoice references (see map_supplier_payable_row)
+const SUPPLIER_PAYABLE_SELECT: &str = "SELECT p.id, p.supplier_id, s.name, p.branch_id, p.receipt_id, o.order_number,
+        r.supplier_invoice, p.currency, p.amount, p.balance, p.due_date, p.status, p.created_at
+     FROM supplier_payables p
+     LEFT JOIN suppliers s ON p.supplier_id = s.id
+     LEFT JOIN goods_receipts r ON p.receipt_id = r.id
+     LEFT JOIN purchase_orders o ON r.order_id = o.id";
+
+/// Stock transfer select with branch names (see map_stock_transfer_row)
+const STOCK_TRANSFER_SELECT: &str = "SELECT t.id, t.transfer_number, t.from_branch_id, bf.name, t.to_branch_id, bt.name, t.status,
+        t.notes, t.created_by, t.created_at, t.received_by, t.received_at, t.cancelled_at, t.cancellation_reason
+     FROM stock_transfers t
+     LEFT JOIN branches bf ON t.from_branch_id = bf.id
+     LEFT JOIN branches bt ON t.to_branch_id = bt.id";
+
+/// Column list for prescription queries (see map_prescription_row)
+const PRESCRIPTION_COLUMNS: &str = "id, encounter_id, patient_id, kind,
+     od_sphere::float8, od_cylinder::float8, od_axis, od_add::float8,
+     os_sphere::float8, os_cylinder::float8, os_axis, os_add::float8,
+     pupillary_distance::float8, notes, created_by, created_at,
+     od_base_curve::float8, od_diameter::float8, od_brand,
+     os_base_curve::float8, os_diameter::float8, os_brand";
+
+/// Column list for IOL model queries (see map_iol_model_row)
+const IOL_MODEL_COLUMNS: &str = "id, name, manufacturer, a_constant::float8, surgeon_factor::float8, pacd::float8, active";
+
+/// Column list for biometry queries (see map_biometry_row)
+const BIOMETRY_COLUMNS: &str = "id, patient_id, eye, axial_length::float8, k1::float8, k2::float8, k1_axis,
+     acd::float8, lens_thickness::float8, white_to_white::float8, device, measured_at,
+     notes, created_by, created_at";
+
+/// Column list for ICD-10 catalog queries (see map_icd10_row)
+const ICD10_COLUMNS: &str = "code, description, synonyms, lateral";
+
+/// Column list for encounter template queries (see map_encounter_template_row)
+const ENCOUNTER_TEMPLATE_COLUMNS: &str = "id, name, encounter_type, doctor_id, summary, motivo_consulta, plan_tratamiento,
+     proxima_cita, exam_slit_lamp, exam_fundus, exam_plan, diagnoses, studies, active";
+
+/// Column list shared by cash register session queries (see map_cash_session_row)
+const CASH_SESSION_COLUMNS: &str = "id, branch_id, cashier_id, status, opening_float, opened_at, closed_at,
+    efectivo_total, cash_in_total, cash_out_total, expected_cash, counted_cash, over_short, notes";
+
 /// PostgreSQL connection pool wrapper
 pub struct PostgresPool {
     pool: Pool,
     config: LocalServerConfig,
+    /// Signed-in user, recorded by the audit triggers as app.user_id
+    audit_user: Arc<std::sync::RwLock<Option<uuid::Uuid>>>,
+}
+
+/// Hook setting app.user_id on a connection each time the pool hands it out
+fn audit_user_hook(audit_user: Arc<std::sync::RwLock<Option<uuid::Uuid>>>) -> Hook {
+    Hook::async_fn(move |client, _| {
+        let user = audit_user
+            .read()
+            .ok()
+            .and_then(|u| *u)
+            .map(|u| u.to_string())
+            .unwrap_or_default();
+        Box::pin(async move {
+            client
+                .simple_query(&format!("SELECT set_config('app.user_id', '{}', false)", user))
+                .await
+                .map_err(HookError::Backend)?;
+            Ok(())
+        })
+    })
 }
 
 impl PostgresPool {
@@ -54,8 +162,14 @@ impl PostgresPool {
         cfg.user = Some(config.user.clone());
         cfg.password = Some(config.password.clone());
 
+        let audit_user = Arc::new(std::sync::RwLock::new(None));
         let pool = cfg
-            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .builder(NoTls)
+            .map_err(|e| format!("Failed to create PostgreSQL pool: {}", e))?
+            .runtime(Runtime::Tokio1)
+            .post_create(audit_user_hook(audit_user.clone()))
+            .post_recycle(audit_user_hook(audit_user.clone()))
+            .build()
             .map_err(|e| format!("Failed to create PostgreSQL pool: {}", e))?;
 
         log::info!("PostgreSQL pool created for {}:{}", config.host, config.port);
@@ -63,9 +177,18 @@ impl PostgresPool {
         Ok(Self {
             pool,
             config: config.clone(),
+            audit_user,
         })
     }
 
+    /// Set (or clear on logout) the user recorded in the audit log
+    pub fn set_audit_user(&self, user_id: Option<&str>) {
+        let user = user_id.and_then(|id| uuid::Uuid::parse_str(id).ok());
+        if let Ok(mut current) = self.audit_user.write() {
+            *current = user;
+        }
+    }
+
     /// Check if the connection is healthy
     pub async fn health_check(&self) -> bool {
         match self.pool.get().await {
@@ -1070,7 +1193,8 @@ impl PostgresPool {
                         e.type::text, e.summary, e.motivo_consulta, e.plan_tratamiento,
                         e.cirugias, e.estudios, e.proxima_cita, e.excursiones_od, e.excursiones_os,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        e.signed_at, e.signed_by
                  FROM encounters e
                  LEFT JOIN patients p ON e.patient_id = p.id
                  LEFT JOIN profiles pr ON e.doctor_id = pr.user_id
@@ -1094,7 +1218,8 @@ impl PostgresPool {
                         e.type::text, e.summary, e.motivo_consulta, e.plan_tratamiento,
                         e.cirugias, e.estudios, e.proxima_cita, e.excursiones_od, e.excursiones_os,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        e.signed_at, e.signed_by
                  FROM encounters e
                  LEFT JOIN patients p ON e.patient_id = p.id
                  LEFT JOIN profiles pr ON e.doctor_id = pr.user_id
@@ -1119,7 +1244,8 @@ impl PostgresPool {
                         e.type::text, e.summary, e.motivo_consulta, e.plan_tratamiento,
                         e.cirugias, e.estudios, e.proxima_cita, e.excursiones_od, e.excursiones_os,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        e.signed_at, e.signed_by
                  FROM encounters e
                  LEFT JOIN patients p ON e.patient_id = p.id
                  LEFT JOIN profiles pr ON e.doctor_id = pr.user_id
@@ -1134,7 +1260,7 @@ impl PostgresPool {
 
     /// Create a new encounter
     pub async fn create_encounter(&self, encounter: &EncounterInput) -> Result<Encounter, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let id = uuid::Uuid::new_v4();
         let now = chrono::Utc::now();
 
@@ -1144,10 +1270,26 @@ impl PostgresPool {
         let doctor_uuid: Option<uuid::Uuid> = encounter.doctor_id.as_ref()
             .and_then(|id| uuid::Uuid::parse_str(id).ok());
 
-        client
+        let template = match &encounter.template_id {
+            Some(template_id) => Some(self.encounter_template_for(template_id, encounter, doctor_uuid).await?),
+            None => None,
+        };
+        let summary = template.as_ref().and_then(|t| t.summary.clone());
+        let motivo_consulta = template.as_ref().and_then(|t| t.motivo_consulta.clone());
+        let plan_tratamiento = template.as_ref().and_then(|t| t.plan_tratamiento.clone());
+        let proxima_cita = template.as_ref().and_then(|t| t.proxima_cita.clone());
+        let template_diagnoses = match &template {
+            Some(template) => self.check_diagnosis_list(&template.diagnoses).await?,
+            None => Vec::new(),
+        };
+
+        // The encounter and what its template fills in are created together
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        tx
             .execute(
-                "INSERT INTO encounters (id, patient_id, appointment_id, doctor_id, date, type, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                "INSERT INTO encounters (id, patient_id, appointment_id, doctor_id, date, type, created_at, updated_at,
+                    summary, motivo_consulta, plan_tratamiento, proxima_cita)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
                 &[
                     &id,
                     &patient_uuid,
@@ -1157,24 +1299,49 @@ impl PostgresPool {
                     &encounter.encounter_type,
                     &now,
                     &now,
+                    &summary,
+                    &motivo_consulta,
+                    &plan_tratamiento,
+                    &proxima_cita,
                 ],
             )
             .await
             .map_err(|e| e.to_string())?;
 
+        if let Some(template) = &template {
+            Self::apply_encounter_template(&tx, &id, encounter, template, &template_diagnoses).await?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+
         // Return the created encounter
         self.get_encounter_by_id(&id.to_string())
             .await?
             .ok_or_else(|| "Encounter not found after creation".to_string())
     }
 
-    /// Update an encounter
+    /// Update an encounter (rejected once it is signed)
     pub async fn update_encounter(&self, id: &str, updates: &EncounterUpdate) -> Result<Encounter, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let encounter_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        Self::ensure_encounter_unsigned(&tx, &encounter_uuid).await?;
+        Self::write_encounter_update(&tx, &encounter_uuid, updates).await?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        self.get_encounter_by_id(id)
+            .await?
+            .ok_or_else(|| "Encounter not found after update".to_string())
+    }
+
+    async fn write_encounter_update(
+        tx: &deadpool_postgres::Transaction<'_>,
+        encounter_uuid: &uuid::Uuid,
+        updates: &EncounterUpdate,
+    ) -> Result<(), String> {
         let now = chrono::Utc::now();
 
-        client
+        tx
             .execute(
                 "UPDATE encounters SET
                     updated_at = $1,
@@ -1197,15 +1364,26 @@ impl PostgresPool {
                     &updates.proxima_cita,
                     &updates.excursiones_od,
                     &updates.excursiones_os,
-                    &encounter_uuid,
+                    encounter_uuid,
                 ],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        self.get_encounter_by_id(id)
-            .await?
-            .ok_or_else(|| "Encounter not found after update".to_string())
+        Ok(())
+    }
+
+    /// Reject changes to a signed encounter; they must go through amend_encounter
+    async fn ensure_encounter_unsigned(tx: &deadpool_postgres::Transaction<'_>, encounter_uuid: &uuid::Uuid) -> Result<(), String> {
+        let row = tx
+            .query_opt("SELECT signed_at FROM encounters WHERE id = $1 FOR UPDATE", &[encounter_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Consulta no encontrada")?;
+        if row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(0).is_some() {
+            return Err("La consulta está firmada; los cambios deben registrarse como enmienda".to_string());
+        }
+        Ok(())
     }
 
     /// Helper to map encounter row to struct
@@ -1243,6 +1421,8 @@ impl PostgresPool {
             proxima_cita: row.get(11),
             excursiones_od: row.get(12),
             excursiones_os: row.get(13),
+            signed_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(22).map(|d| d.to_rfc3339()),
+            signed_by: row.get::<_, Option<uuid::Uuid>>(23).map(|u| u.to_string()),
             patient: patient_embed,
             doctor: doctor_embed,
         }
@@ -1263,7 +1443,8 @@ impl PostgresPool {
                         ref_sphere, ref_cyl, ref_axis,
                         ref_subj_sphere, ref_subj_cyl, ref_subj_axis,
                         rx_sphere, rx_cyl, rx_axis, rx_add,
-                        iop, slit_lamp, fundus, plan
+                        iop, slit_lamp, fundus, plan,
+                        av_sc_logmar::float8, av_cc_logmar::float8
                  FROM exam_eye
                  WHERE encounter_id = $1 AND side = $2",
                 &[&encounter_uuid, &side],
@@ -1285,7 +1466,8 @@ impl PostgresPool {
                         ref_sphere, ref_cyl, ref_axis,
                         ref_subj_sphere, ref_subj_cyl, ref_subj_axis,
                         rx_sphere, rx_cyl, rx_axis, rx_add,
-                        iop, slit_lamp, fundus, plan
+                        iop, slit_lamp, fundus, plan,
+                        av_sc_logmar::float8, av_cc_logmar::float8
                  FROM exam_eye
                  WHERE encounter_id = $1
                  ORDER BY side",
@@ -1297,17 +1479,37 @@ impl PostgresPool {
         Ok(rows.iter().map(|row| self.map_exam_eye_row(row)).collect())
     }
 
-    /// Upsert exam eye (insert or update based on encounter_id + side)
+    /// Upsert exam eye (insert or update based on encounter_id + side);
+    /// rejected once the encounter is signed
     pub async fn upsert_exam_eye(&self, exam: &ExamEyeInput) -> Result<ExamEye, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let encounter_uuid = uuid::Uuid::parse_str(&exam.encounter_id).map_err(|e| e.to_string())?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        Self::ensure_encounter_unsigned(&tx, &encounter_uuid).await?;
+        Self::write_exam_eye(&tx, &encounter_uuid, exam).await?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        // Return the upserted record
+        self.get_exam_eye(&exam.encounter_id, &exam.side)
+            .await?
+            .ok_or_else(|| "ExamEye not found after upsert".to_string())
+    }
+
+    async fn write_exam_eye(
+        tx: &deadpool_postgres::Transaction<'_>,
+        encounter_uuid: &uuid::Uuid,
+        exam: &ExamEyeInput,
+    ) -> Result<(), String> {
         let now = chrono::Utc::now();
+        let av_sc_logmar = acuity::field_logmar(exam.av_sc.as_deref());
+        let av_cc_logmar = acuity::field_logmar(exam.av_cc.as_deref());
 
         // Check if record exists
-        let existing = client
+        let existing = tx
             .query_opt(
                 "SELECT id FROM exam_eye WHERE encounter_id = $1 AND side = $2",
-                &[&encounter_uuid, &exam.side],
+                &[encounter_uuid, &exam.side],
             )
             .await
             .map_err(|e| e.to_string())?;
@@ -1315,7 +1517,7 @@ impl PostgresPool {
         if let Some(row) = existing {
             // Update existing
             let id: uuid::Uuid = row.get(0);
-            client
+            tx
                 .execute(
                     "UPDATE exam_eye SET
                         av_sc = $1, av_cc = $2,
@@ -1323,7 +1525,8 @@ impl PostgresPool {
                         ref_subj_sphere = $6, ref_subj_cyl = $7, ref_subj_axis = $8,
                         rx_sphere = $9, rx_cyl = $10, rx_axis = $11, rx_add = $12,
                         iop = $13, slit_lamp = $14, fundus = $15, plan = $16,
-                        updated_at = $17
+                        updated_at = $17,
+                        av_sc_logmar = $19::float8, av_cc_logmar = $20::float8
                      WHERE id = $18",
                     &[
                         &exam.av_sc, &exam.av_cc,
@@ -1331,7 +1534,7 @@ impl PostgresPool {
                         &exam.ref_subj_sphere, &exam.ref_subj_cyl, &exam.ref_subj_axis,
                         &exam.rx_sphere, &exam.rx_cyl, &exam.rx_axis, &exam.rx_add,
                         &exam.iop, &exam.slit_lamp, &exam.fundus, &exam.plan,
-                        &now, &id,
+                        &now, &id, &av_sc_logmar, &av_cc_logmar,
                     ],
                 )
                 .await
@@ -1339,31 +1542,70 @@ impl PostgresPool {
         } else {
             // Insert new
             let id = uuid::Uuid::new_v4();
-            client
+            tx
                 .execute(
                     "INSERT INTO exam_eye (id, encounter_id, side, av_sc, av_cc,
                         ref_sphere, ref_cyl, ref_axis,
                         ref_subj_sphere, ref_subj_cyl, ref_subj_axis,
                         rx_sphere, rx_cyl, rx_axis, rx_add,
-                        iop, slit_lamp, fundus, plan, created_at, updated_at)
-                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)",
+                        iop, slit_lamp, fundus, plan, created_at, updated_at,
+                        av_sc_logmar, av_cc_logmar)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21,
+                             $22::float8, $23::float8)",
                     &[
-                        &id, &encounter_uuid, &exam.side, &exam.av_sc, &exam.av_cc,
+                        &id, encounter_uuid, &exam.side, &exam.av_sc, &exam.av_cc,
                         &exam.ref_sphere, &exam.ref_cyl, &exam.ref_axis,
                         &exam.ref_subj_sphere, &exam.ref_subj_cyl, &exam.ref_subj_axis,
                         &exam.rx_sphere, &exam.rx_cyl, &exam.rx_axis, &exam.rx_add,
                         &exam.iop, &exam.slit_lamp, &exam.fundus, &exam.plan,
-                        &now, &now,
+                        &now, &now, &av_sc_logmar, &av_cc_logmar,
                     ],
                 )
                 .await
                 .map_err(|e| e.to_string())?;
         }
 
-        // Return the upserted record
-        self.get_exam_eye(&exam.encounter_id, &exam.side)
-            .await?
-            .ok_or_else(|| "ExamEye not found after upsert".to_string())
+        Ok(())
+    }
+
+    /// Recompute the stored logMAR of every exam from its acuity text
+    /// (exams saved before the columns existed). Exams of signed
+    /// encounters are left as signed. Returns rows changed.
+    pub async fn recalculate_exam_logmar(&self) -> Result<u64, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let rows = tx
+            .query(
+                "SELECT ee.id, ee.av_sc, ee.av_cc, ee.av_sc_logmar::float8, ee.av_cc_logmar::float8
+                 FROM exam_eye ee
+                 JOIN encounters e ON e.id = ee.encounter_id
+                 WHERE (ee.av_sc IS NOT NULL OR ee.av_cc IS NOT NULL)
+                   AND e.signed_at IS NULL",
+                &[],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut changed = 0;
+        for row in &rows {
+            let id: uuid::Uuid = row.get(0);
+            let sc = acuity::field_logmar(row.get::<_, Option<String>>(1).as_deref());
+            let cc = acuity::field_logmar(row.get::<_, Option<String>>(2).as_deref());
+            if sc == row.get::<_, Option<f64>>(3) && cc == row.get::<_, Option<f64>>(4) {
+                continue;
+            }
+            changed += tx
+                .execute(
+                    "UPDATE exam_eye SET av_sc_logmar = $1::float8, av_cc_logmar = $2::float8 WHERE id = $3",
+                    &[&sc, &cc, &id],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(changed)
     }
 
     /// Helper to map exam_eye row to struct
@@ -1374,6 +1616,8 @@ impl PostgresPool {
             side: row.get(2),
             av_sc: row.get(3),
             av_cc: row.get(4),
+            av_sc_logmar: row.get(19),
+            av_cc_logmar: row.get(20),
             ref_sphere: row.get(5),
             ref_cyl: row.get(6),
             ref_axis: row.get(7),
@@ -1663,7 +1907,8 @@ impl PostgresPool {
                 "SELECT s.id, s.appointment_id, s.patient_id, s.surgery_type::text, s.eye::text,
                         s.date, s.status::text, s.surgeon_id, s.notes,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        s.biometry_id, s.iol_model_id, s.iol_power::float8, s.iol_formula, s.iol_target_refraction::float8
                  FROM surgeries s
                  LEFT JOIN patients p ON s.patient_id = p.id
                  LEFT JOIN profiles pr ON s.surgeon_id = pr.user_id
@@ -1694,7 +1939,8 @@ impl PostgresPool {
                 "SELECT s.id, s.appointment_id, s.patient_id, s.surgery_type::text, s.eye::text,
                         s.date, s.status::text, s.surgeon_id, s.notes,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        s.biometry_id, s.iol_model_id, s.iol_power::float8, s.iol_formula, s.iol_target_refraction::float8
                  FROM surgeries s
                  LEFT JOIN patients p ON s.patient_id = p.id
                  LEFT JOIN profiles pr ON s.surgeon_id = pr.user_id
@@ -1794,6 +2040,11 @@ impl PostgresPool {
             status: "scheduled".to_string(),
             surgeon_id: surgery.surgeon_id.clone(),
             notes: surgery.notes.clone(),
+            biometry_id: None,
+            iol_model_id: None,
+            iol_power: None,
+            iol_formula: None,
+            iol_target_refraction: None,
             surgery_files: None,
             patient: patient_embed,
             surgeon: None,
@@ -1801,8 +2052,9 @@ impl PostgresPool {
     }
 
     /// Update a surgery
+    /// Marking it completed consumes the surgery type's kit from room inventory
     pub async fn update_surgery(&self, id: &str, updates: &SurgeryUpdate) -> Result<Surgery, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let surgery_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
         let now = chrono::Utc::now();
 
@@ -1811,7 +2063,16 @@ impl PostgresPool {
         let date: Option<chrono::NaiveDate> = updates.date.as_ref()
             .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
 
-        client
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let previous_status: String = tx
+            .query_opt("SELECT status::text FROM surgeries WHERE id = $1 FOR UPDATE", &[&surgery_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Cirugía no encontrada")?
+            .get(0);
+
+        tx
             .execute(
                 "UPDATE surgeries SET
                     updated_at = $1,
@@ -1836,13 +2097,20 @@ impl PostgresPool {
             .await
             .map_err(|e| e.to_string())?;
 
+        if kits::completes_surgery(&previous_status, updates.status.as_deref()) {
+            self.consume_surgery_kit(&tx, &surgery_uuid).await?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+
         // Fetch updated surgery
         let row = client
             .query_one(
                 "SELECT s.id, s.appointment_id, s.patient_id, s.surgery_type::text, s.eye::text,
                         s.date, s.status::text, s.surgeon_id, s.notes,
                         p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
-                        pr.user_id, pr.full_name, pr.specialty
+                        pr.user_id, pr.full_name, pr.specialty,
+                        s.biometry_id, s.iol_model_id, s.iol_power::float8, s.iol_formula, s.iol_target_refraction::float8
                  FROM surgeries s
                  LEFT JOIN patients p ON s.patient_id = p.id
                  LEFT JOIN profiles pr ON s.surgeon_id = pr.user_id
@@ -1931,6 +2199,11 @@ impl PostgresPool {
             status: row.get(6),
             surgeon_id: row.get::<_, Option<uuid::Uuid>>(7).map(|u| u.to_string()),
             notes: row.get(8),
+            biometry_id: row.get::<_, Option<uuid::Uuid>>(17).map(|u| u.to_string()),
+            iol_model_id: row.get::<_, Option<uuid::Uuid>>(18).map(|u| u.to_string()),
+            iol_power: row.get(19),
+            iol_formula: row.get(20),
+            iol_target_refraction: row.get(21),
             surgery_files: if files.is_empty() { None } else { Some(files) },
             patient: patient_embed,
             surgeon: surgeon_embed,
@@ -2174,6 +2447,14 @@ impl PostgresPool {
 
         let encounter_uuid = uuid::Uuid::parse_str(&diagnosis.encounter_id).map_err(|e| e.to_string())?;
         let is_primary = diagnosis.is_primary.unwrap_or(false);
+        self.ensure_diagnoses_editable(Some(encounter_uuid), None).await?;
+
+        // Catalog code and eye; the description defaults to the catalog one
+        let (code, eye, catalog_description) = self.check_diagnosis(diagnosis.code.as_deref(), diagnosis.eye.as_deref()).await?;
+        let description = match diagnosis.description.trim() {
+            "" => catalog_description.ok_or("La descripción del diagnóstico es obligatoria")?,
+            d => d.to_string(),
+        };
 
         client
             .execute(
@@ -2182,9 +2463,9 @@ impl PostgresPool {
                 &[
                     &id,
                     &encounter_uuid,
-                    &diagnosis.code,
-                    &diagnosis.description,
-                    &diagnosis.eye,
+                    &code,
+                    &description,
+                    &eye,
                     &is_primary,
                     &now,
                     &now,
@@ -2196,9 +2477,9 @@ impl PostgresPool {
         Ok(Diagnosis {
             id: id.to_string(),
             encounter_id: diagnosis.encounter_id.clone(),
-            code: diagnosis.code.clone(),
-            description: diagnosis.description.clone(),
-            eye: diagnosis.eye.clone(),
+            code,
+            description,
+            eye,
             is_primary,
         })
     }
@@ -2208,6 +2489,25 @@ impl PostgresPool {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let diagnosis_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
         let now = chrono::Utc::now();
+        self.ensure_diagnoses_editable(None, Some(diagnosis_uuid)).await?;
+
+        // A new code is checked with the eye it will have; a new eye alone is only normalized
+        let (code, eye) = if updates.code.is_some() {
+            let current_eye: Option<String> = match &updates.eye {
+                Some(_) => None,
+                None => client
+                    .query_opt("SELECT eye FROM diagnoses WHERE id = $1", &[&diagnosis_uuid])
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .and_then(|row| row.get(0)),
+            };
+            let eye = updates.eye.clone().or(current_eye);
+            let (code, eye, _) = self.check_diagnosis(updates.code.as_deref(), eye.as_deref()).await?;
+            (code, eye)
+        } else {
+            let (_, eye, _) = self.check_diagnosis(None, updates.eye.as_deref()).await?;
+            (None, eye)
+        };
 
         client
             .execute(
@@ -2220,9 +2520,9 @@ impl PostgresPool {
                  WHERE id = $6",
                 &[
                     &now,
-                    &updates.code,
+                    &code,
                     &updates.description,
-                    &updates.eye,
+                    &eye,
                     &updates.is_primary,
                     &diagnosis_uuid,
                 ],
@@ -2250,11 +2550,34 @@ impl PostgresPool {
         })
     }
 
+    /// Reject diagnosis changes on a signed encounter, given the encounter
+    /// or one of its diagnoses
+    async fn ensure_diagnoses_editable(&self, encounter_uuid: Option<uuid::Uuid>, diagnosis_uuid: Option<uuid::Uuid>) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let signed: bool = client
+            .query_one(
+                "SELECT EXISTS (
+                    SELECT 1 FROM encounters e
+                    WHERE e.signed_at IS NOT NULL
+                      AND (e.id = $1 OR e.id = (SELECT encounter_id FROM diagnoses WHERE id = $2))
+                 )",
+                &[&encounter_uuid, &diagnosis_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+        if signed {
+            return Err("La consulta está firmada; los cambios deben registrarse como enmienda".to_string());
+        }
+        Ok(())
+    }
+
     /// Delete a diagnosis (soft delete)
     pub async fn delete_diagnosis(&self, id: &str) -> Result<(), String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let diagnosis_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
         let now = chrono::Utc::now();
+        self.ensure_diagnoses_editable(None, Some(diagnosis_uuid)).await?;
 
         client
             .execute(
@@ -2281,7 +2604,8 @@ impl PostgresPool {
                 "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                         i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                         i.discount_reason, i.status::text, i.notes, i.created_at,
-                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        i.taxable_amount, i.exempt_amount, i.tax_amount
                  FROM invoices i
                  LEFT JOIN patients p ON i.patient_id = p.id
                  WHERE i.patient_id = $1
@@ -2306,7 +2630,8 @@ impl PostgresPool {
                 "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                         i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                         i.discount_reason, i.status::text, i.notes, i.created_at,
-                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        i.taxable_amount, i.exempt_amount, i.tax_amount
                  FROM invoices i
                  LEFT JOIN patients p ON i.patient_id = p.id
                  WHERE i.branch_id = $1 AND DATE(i.created_at) = $2
@@ -2331,7 +2656,8 @@ impl PostgresPool {
                     "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                             i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                             i.discount_reason, i.status::text, i.notes, i.created_at,
-                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                            i.taxable_amount, i.exempt_amount, i.tax_amount
                      FROM invoices i
                      LEFT JOIN patients p ON i.patient_id = p.id
                      WHERE i.branch_id = $1 AND i.status != 'cancelada' AND i.balance_due > 0
@@ -2347,7 +2673,8 @@ impl PostgresPool {
                     "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                             i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                             i.discount_reason, i.status::text, i.notes, i.created_at,
-                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                            i.taxable_amount, i.exempt_amount, i.tax_amount
                      FROM invoices i
                      LEFT JOIN patients p ON i.patient_id = p.id
                      WHERE i.branch_id = $1 AND i.status != 'cancelada' AND i.balance_due > 0
@@ -2362,7 +2689,8 @@ impl PostgresPool {
                     "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                             i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                             i.discount_reason, i.status::text, i.notes, i.created_at,
-                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                            p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                            i.taxable_amount, i.exempt_amount, i.tax_amount
                      FROM invoices i
                      LEFT JOIN patients p ON i.patient_id = p.id
                      WHERE i.branch_id = $1 AND i.status != 'cancelada' AND i.balance_due > 0
@@ -2393,7 +2721,8 @@ impl PostgresPool {
                 "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                         i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                         i.discount_reason, i.status::text, i.notes, i.created_at,
-                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        i.taxable_amount, i.exempt_amount, i.tax_amount
                  FROM invoices i
                  LEFT JOIN patients p ON i.patient_id = p.id
                  WHERE i.id = $1",
@@ -2415,7 +2744,8 @@ impl PostgresPool {
                 "SELECT i.id, i.invoice_number, i.patient_id, i.appointment_id, i.branch_id,
                         i.total_amount, i.balance_due, i.discount_type, i.discount_value,
                         i.discount_reason, i.status::text, i.notes, i.created_at,
-                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        i.taxable_amount, i.exempt_amount, i.tax_amount
                  FROM invoices i
                  LEFT JOIN patients p ON i.patient_id = p.id
                  WHERE i.appointment_id = $1",
@@ -2441,25 +2771,58 @@ impl PostgresPool {
         // Generate invoice number
         let invoice_number = self.generate_invoice_number(&invoice.branch_id).await?;
 
-        // Calculate totals
-        let subtotal: f64 = items.iter()
-            .map(|item| item.unit_price * item.quantity as f64)
-            .sum();
+        // Calculate totals: prices in another currency are converted at the
+        // rate in effect today, each line is rounded to centavos and the
+        // discount is computed once on the rounded subtotal
+        let today = timezone::today();
+        let catalog = Self::get_item_catalog_settings(&client, items).await?;
+        let mut unit_prices = Vec::with_capacity(items.len());
+        let mut lines = Vec::with_capacity(items.len());
+        for (item, (tax_rate, tax_exempt, currency)) in items.iter().zip(catalog) {
+            let currency = billing::normalize_currency(Some(&currency))?;
+            let exchange_rate: Option<Decimal> = if billing::is_base_currency(&currency) {
+                None
+            } else {
+                client
+                    .query_opt(
+                        "SELECT rate::numeric FROM exchange_rates
+                         WHERE currency = $1 AND rate_date <= $2
+                         ORDER BY rate_date DESC
+                         LIMIT 1",
+                        &[&currency, &today],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .map(|row| row.get(0))
+            };
+            let unit_price = billing::unit_price_in_base(item.unit_price, &currency, exchange_rate)
+                .map_err(|e| format!("{}: {} al {}", item.description, e, today))?;
+
+            unit_prices.push(unit_price);
+            lines.push(TaxableLine {
+                amount: billing::line_subtotal(unit_price, item.quantity),
+                tax_rate,
+                tax_exempt,
+            });
+        }
+        let subtotal: Decimal = lines.iter().map(|l| l.amount).sum();
 
-        let discount_amount = match (invoice.discount_type.as_deref(), invoice.discount_value) {
-            (Some("percentage"), Some(value)) => subtotal * (value / 100.0),
-            (Some("fixed"), Some(value)) => value,
-            _ => 0.0,
-        };
+        let discount_amount = billing::discount_amount(
+            subtotal,
+            invoice.discount_type.as_deref(),
+            invoice.discount_value,
+        );
 
         let total_amount = subtotal - discount_amount;
+        let tax = compute_invoice_tax(&lines, discount_amount);
 
         client
             .execute(
                 "INSERT INTO invoices (id, invoice_number, patient_id, appointment_id, branch_id,
                                       total_amount, balance_due, discount_type, discount_value,
-                                      discount_reason, status, notes, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'pending', $11, $12, $13)",
+                                      discount_reason, status, notes, created_at, updated_at,
+                                      taxable_amount, exempt_amount, tax_amount)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'pending', $11, $12, $13, $14, $15, $16)",
                 &[
                     &id,
                     &invoice_number,
@@ -2474,25 +2837,28 @@ impl PostgresPool {
                     &invoice.notes,
                     &now,
                     &now,
+                    &tax.taxable_amount,
+                    &tax.exempt_amount,
+                    &tax.tax_amount,
                 ],
             )
             .await
             .map_err(|e| e.to_string())?;
 
         // Insert invoice items
-        for item in items {
+        for (((item, unit_price), line), line_tax) in items.iter().zip(&unit_prices).zip(&lines).zip(&tax.lines) {
             let item_id = uuid::Uuid::new_v4();
             let service_uuid: Option<uuid::Uuid> = item.service_id.as_ref()
                 .and_then(|id| uuid::Uuid::parse_str(id).ok());
             let product_uuid: Option<uuid::Uuid> = item.product_id.as_ref()
                 .and_then(|id| uuid::Uuid::parse_str(id).ok());
-            let subtotal = item.unit_price * item.quantity as f64;
 
             client
                 .execute(
                     "INSERT INTO invoice_items (id, invoice_id, service_id, product_id, description,
-                                               quantity, unit_price, subtotal, created_at)
-                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                                               quantity, unit_price, subtotal, created_at,
+                                               discount_amount, tax_rate, tax_exempt, tax_amount)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
                     &[
                         &item_id,
                         &id,
@@ -2500,9 +2866,13 @@ impl PostgresPool {
                         &product_uuid,
                         &item.description,
                         &item.quantity,
-                        &item.unit_price,
-                        &subtotal,
+                        unit_price,
+                        &line.amount,
                         &now,
+                        &line_tax.discount_amount,
+                        &line.tax_rate,
+                        &line.tax_exempt,
+                        &line_tax.tax_amount,
                     ],
                 )
                 .await
@@ -2530,6 +2900,9 @@ impl PostgresPool {
             discount_type: invoice.discount_type.clone(),
             discount_value: invoice.discount_value,
             discount_reason: invoice.discount_reason.clone(),
+            taxable_amount: tax.taxable_amount,
+            exempt_amount: tax.exempt_amount,
+            tax_amount: tax.tax_amount,
             status: "pending".to_string(),
             notes: invoice.notes.clone(),
             created_at: now.to_rfc3339(),
@@ -2594,7 +2967,8 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT id, invoice_id, service_id, product_id, description, quantity, unit_price, subtotal
+                "SELECT id, invoice_id, service_id, product_id, description, quantity, unit_price, subtotal,
+                        discount_amount, tax_rate, tax_exempt, tax_amount
                  FROM invoice_items
                  WHERE invoice_id = $1
                  ORDER BY created_at",
@@ -2612,9 +2986,55 @@ impl PostgresPool {
             quantity: row.get(5),
             unit_price: row.get(6),
             subtotal: row.get(7),
+            discount_amount: row.get(8),
+            tax_rate: row.get(9),
+            tax_exempt: row.get(10),
+            tax_amount: row.get(11),
         }).collect())
     }
 
+    /// IVA rate, exemption and price currency of the catalog entry behind
+    /// each invoice line, read on the connection the invoice already holds.
+    /// Free-text lines use the general rate and the base currency.
+    async fn get_item_catalog_settings(
+        client: &tokio_postgres::Client,
+        items: &[InvoiceItemInput],
+    ) -> Result<Vec<(Decimal, bool, String)>, String> {
+        let parse = |id: &Option<String>| id.as_ref().and_then(|id| uuid::Uuid::parse_str(id).ok());
+        let service_ids: Vec<uuid::Uuid> = items.iter().filter_map(|i| parse(&i.service_id)).collect();
+        let product_ids: Vec<uuid::Uuid> = items.iter().filter_map(|i| parse(&i.product_id)).collect();
+
+        let by_id = |rows: Vec<tokio_postgres::Row>| -> std::collections::HashMap<uuid::Uuid, (Decimal, bool, String)> {
+            rows.iter().map(|r| (r.get(0), (r.get(1), r.get(2), r.get(3)))).collect()
+        };
+        let services = by_id(
+            client
+                .query("SELECT id, tax_rate, tax_exempt, currency FROM service_prices WHERE id = ANY($1)", &[&service_ids])
+                .await
+                .map_err(|e| e.to_string())?,
+        );
+        let products = by_id(
+            client
+                .query("SELECT id, tax_rate, tax_exempt, currency FROM inventory_items WHERE id = ANY($1)", &[&product_ids])
+                .await
+                .map_err(|e| e.to_string())?,
+        );
+
+        Ok(items
+            .iter()
+            .map(|item| {
+                let settings = match (parse(&item.service_id), parse(&item.product_id)) {
+                    (Some(service_id), _) => services.get(&service_id),
+                    (None, Some(product_id)) => products.get(&product_id),
+                    (None, None) => None,
+                };
+                settings
+                    .cloned()
+                    .unwrap_or_else(|| (DEFAULT_TAX_RATE, false, billing::BASE_CURRENCY.to_string()))
+            })
+            .collect())
+    }
+
     /// Helper to map invoice row
     fn map_invoice_row(&self, row: &tokio_postgres::Row) -> Invoice {
         let patient_embed = row.get::<_, Option<uuid::Uuid>>(13).map(|p_id| {
@@ -2638,6 +3058,9 @@ impl PostgresPool {
             discount_type: row.get(7),
             discount_value: row.get(8),
             discount_reason: row.get(9),
+            taxable_amount: row.get(18),
+            exempt_amount: row.get(19),
+            tax_amount: row.get(20),
             status: row.get(10),
             notes: row.get(11),
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(12).to_rfc3339(),
@@ -2656,7 +3079,8 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT id, invoice_id, amount, payment_method::text, date, created_at
+                "SELECT id, invoice_id, amount, payment_method::text, date, created_at,
+                        currency, exchange_rate::numeric, COALESCE(amount_base, amount)::numeric
                  FROM payments
                  WHERE invoice_id = $1 AND deleted_at IS NULL
                  ORDER BY date DESC, created_at DESC",
@@ -2669,6 +3093,9 @@ impl PostgresPool {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
             invoice_id: row.get::<_, uuid::Uuid>(1).to_string(),
             amount: row.get(2),
+            currency: row.get(6),
+            exchange_rate: row.get(7),
+            amount_base: row.get(8),
             payment_method: row.get(3),
             date: row.get::<_, chrono::NaiveDate>(4).to_string(),
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
@@ -2689,7 +3116,8 @@ impl PostgresPool {
             .query(
                 "SELECT pay.id, pay.invoice_id, pay.amount, pay.payment_method::text, pay.date, pay.created_at,
                         i.id as i_id, i.invoice_number, i.patient_id, i.total_amount, i.balance_due,
-                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        pay.currency, pay.exchange_rate::numeric, COALESCE(pay.amount_base, pay.amount)::numeric
                  FROM payments pay
                  JOIN invoices i ON pay.invoice_id = i.id
                  LEFT JOIN patients p ON i.patient_id = p.id
@@ -2724,6 +3152,9 @@ impl PostgresPool {
                 id: row.get::<_, uuid::Uuid>(0).to_string(),
                 invoice_id: row.get::<_, uuid::Uuid>(1).to_string(),
                 amount: row.get(2),
+                currency: row.get(16),
+                exchange_rate: row.get(17),
+                amount_base: row.get(18),
                 payment_method: row.get(3),
                 date: row.get::<_, chrono::NaiveDate>(4).to_string(),
                 created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
@@ -2737,33 +3168,69 @@ impl PostgresPool {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let id = uuid::Uuid::new_v4();
         let now = chrono::Utc::now();
-        let today = now.date_naive();
+        let today = timezone::today();
 
         let invoice_uuid = uuid::Uuid::parse_str(&payment.invoice_id).map_err(|e| e.to_string())?;
 
+        // Foreign currency payments are converted with the rate in effect today
+        let currency = billing::normalize_currency(payment.currency.as_deref())?;
+        let exchange_rate = if billing::is_base_currency(&currency) {
+            Decimal::ONE
+        } else {
+            self.get_exchange_rate_for_date(&currency, &today.to_string())
+                .await?
+                .map(|r| r.rate)
+                .ok_or_else(|| format!("No hay tipo de cambio registrado para {} al {}", currency, today))?
+        };
+        let amount = billing::round_money(payment.amount);
+        let amount_base = billing::to_base_currency(amount, exchange_rate);
+
+        let cash_session_uuid = match payment.cash_session_id.as_deref() {
+            Some(session_id) => {
+                let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+                let open = client
+                    .query_opt(
+                        "SELECT 1 FROM cash_register_sessions WHERE id = $1 AND status = 'abierta'",
+                        &[&session_uuid],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if open.is_none() {
+                    return Err("La sesión de caja no está abierta".to_string());
+                }
+                Some(session_uuid)
+            }
+            None => None,
+        };
+
         // Create payment
         client
             .execute(
-                "INSERT INTO payments (id, invoice_id, amount, payment_method, date, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                "INSERT INTO payments (id, invoice_id, amount, payment_method, date, created_at, updated_at,
+                                      currency, exchange_rate, amount_base, cash_session_id)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
                 &[
                     &id,
                     &invoice_uuid,
-                    &payment.amount,
+                    &amount,
                     &payment.payment_method,
                     &today,
                     &now,
                     &now,
+                    &currency,
+                    &exchange_rate,
+                    &amount_base,
+                    &cash_session_uuid,
                 ],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        // Update invoice balance_due
+        // Update invoice balance_due (invoices are always kept in the base currency)
         client
             .execute(
                 "UPDATE invoices SET balance_due = balance_due - $1, updated_at = $2 WHERE id = $3",
-                &[&payment.amount, &now, &invoice_uuid],
+                &[&amount_base, &now, &invoice_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
@@ -2780,7 +3247,10 @@ impl PostgresPool {
         Ok(Payment {
             id: id.to_string(),
             invoice_id: payment.invoice_id.clone(),
-            amount: payment.amount,
+            amount,
+            currency,
+            exchange_rate,
+            amount_base,
             payment_method: payment.payment_method.clone(),
             date: today.to_string(),
             created_at: now.to_rfc3339(),
@@ -2797,14 +3267,14 @@ impl PostgresPool {
         // Get payment info
         let payment_row = client
             .query_one(
-                "SELECT invoice_id, amount FROM payments WHERE id = $1",
+                "SELECT invoice_id, COALESCE(amount_base, amount)::numeric FROM payments WHERE id = $1",
                 &[&payment_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
 
         let invoice_uuid: uuid::Uuid = payment_row.get(0);
-        let amount: f64 = payment_row.get(1);
+        let amount: Decimal = payment_row.get(1);
 
         // Soft delete payment
         client
@@ -2827,6 +3297,178 @@ impl PostgresPool {
         Ok(())
     }
 
+    // ============================================================
+    // ELECTRONIC INVOICING (FEL)
+    // ============================================================
+
+    /// Latest FEL document of an invoice
+    pub async fn get_fel_document(&self, invoice_id: &str) -> Result<Option<FelDocument>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let invoice_uuid = uuid::Uuid::parse_str(invoice_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                "SELECT id, invoice_id, status, authorization_uuid, serie, numero,
+                        receiver_nit, receiver_name, certifier, certified_at,
+                        cancelled_at, cancellation_reason, pdf IS NOT NULL, created_at
+                 FROM fel_documents
+                 WHERE invoice_id = $1
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+                &[&invoice_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|r| self.map_fel_document_row(&r)))
+    }
+
+    /// Certified XML of the latest FEL document of an invoice
+    pub async fn get_fel_xml(&self, invoice_id: &str) -> Result<Option<String>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let invoice_uuid = uuid::Uuid::parse_str(invoice_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                "SELECT certified_xml FROM fel_documents
+                 WHERE invoice_id = $1
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+                &[&invoice_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|r| r.get(0)))
+    }
+
+    /// PDF of the latest FEL document of an invoice
+    pub async fn get_fel_pdf(&self, invoice_id: &str) -> Result<Option<Vec<u8>>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let invoice_uuid = uuid::Uuid::parse_str(invoice_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                "SELECT pdf FROM fel_documents
+                 WHERE invoice_id = $1
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+                &[&invoice_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.and_then(|r| r.get(0)))
+    }
+
+    /// Store a certified FEL document
+    pub async fn create_fel_document(
+        &self,
+        invoice_id: &str,
+        receiver: &FelReceiver,
+        certifier: &str,
+        signed_xml: &str,
+        certification: &Certification,
+    ) -> Result<FelDocument, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let invoice_uuid = uuid::Uuid::parse_str(invoice_id).map_err(|e| e.to_string())?;
+        let certified_at = chrono::DateTime::parse_from_rfc3339(&certification.certified_at)
+            .map_err(|e| format!("Fecha de certificación inválida: {}", e))?
+            .with_timezone(&chrono::Utc);
+
+        let row = client
+            .query_one(
+                "INSERT INTO fel_documents (invoice_id, document_type, status, authorization_uuid, serie, numero,
+                                            receiver_nit, receiver_name, certifier, signed_xml, certified_xml,
+                                            pdf, certified_at)
+                 VALUES ($1, 'FACT', 'certificado', $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                 RETURNING id, invoice_id, status, authorization_uuid, serie, numero,
+                           receiver_nit, receiver_name, certifier, certified_at,
+                           cancelled_at, cancellation_reason, pdf IS NOT NULL, created_at",
+                &[
+                    &invoice_uuid,
+                    &certification.authorization_uuid,
+                    &certification.serie,
+                    &certification.numero,
+                    &receiver.nit,
+                    &receiver.name,
+                    &certifier,
+                    &signed_xml,
+                    &certification.certified_xml,
+                    &certification.pdf,
+                    &certified_at,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_fel_document_row(&row))
+    }
+
+    /// Mark a FEL document as cancelled and cancel its invoice
+    pub async fn cancel_fel_document(
+        &self,
+        id: &str,
+        reason: &str,
+        cancellation: &Cancellation,
+    ) -> Result<FelDocument, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let document_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        let cancelled_at = chrono::DateTime::parse_from_rfc3339(&cancellation.cancelled_at)
+            .map_err(|e| format!("Fecha de anulación inválida: {}", e))?
+            .with_timezone(&chrono::Utc);
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let row = tx
+            .query_one(
+                "UPDATE fel_documents SET
+                    status = 'anulado',
+                    cancelled_at = $2,
+                    cancellation_reason = $3,
+                    cancellation_xml = $4
+                 WHERE id = $1
+                 RETURNING id, invoice_id, status, authorization_uuid, serie, numero,
+                           receiver_nit, receiver_name, certifier, certified_at,
+                           cancelled_at, cancellation_reason, pdf IS NOT NULL, created_at",
+                &[&document_uuid, &cancelled_at, &reason, &cancellation.certified_xml],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let invoice_uuid: uuid::Uuid = row.get(1);
+        tx.execute(
+            "UPDATE invoices SET status = 'cancelada', updated_at = $2 WHERE id = $1",
+            &[&invoice_uuid, &cancelled_at],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(self.map_fel_document_row(&row))
+    }
+
+    /// Helper to map FEL document row
+    fn map_fel_document_row(&self, row: &tokio_postgres::Row) -> FelDocument {
+        FelDocument {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            invoice_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            status: row.get(2),
+            authorization_uuid: row.get(3),
+            serie: row.get(4),
+            numero: row.get(5),
+            receiver_nit: row.get(6),
+            receiver_name: row.get(7),
+            certifier: row.get(8),
+            certified_at: row.get::<_, chrono::DateTime<chrono::Utc>>(9).to_rfc3339(),
+            cancelled_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(10).map(|d| d.to_rfc3339()),
+            cancellation_reason: row.get(11),
+            has_pdf: row.get(12),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(13).to_rfc3339(),
+        }
+    }
+
     // ============================================================
     // SERVICE PRICES (PRECIOS DE SERVICIOS)
     // ============================================================
@@ -2837,7 +3479,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT id, service_name, service_type::text, price, active
+                "SELECT id, service_name, service_type::text, price, active, currency, tax_rate, tax_exempt
                  FROM service_prices
                  WHERE deleted_at IS NULL
                  ORDER BY service_type, service_name",
@@ -2846,13 +3488,7 @@ impl PostgresPool {
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(rows.iter().map(|row| ServicePrice {
-            id: row.get::<_, uuid::Uuid>(0).to_string(),
-            service_name: row.get(1),
-            service_type: row.get(2),
-            price: row.get(3),
-            active: row.get::<_, Option<bool>>(4).unwrap_or(true),
-        }).collect())
+        Ok(rows.iter().map(|row| self.map_service_price_row(row)).collect())
     }
 
     /// Create a service price
@@ -2860,11 +3496,15 @@ impl PostgresPool {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let id = uuid::Uuid::new_v4();
         let now = chrono::Utc::now();
+        let currency = billing::normalize_currency(service.currency.as_deref())?;
+        let tax_rate = service.tax_rate.unwrap_or(DEFAULT_TAX_RATE);
+        let tax_exempt = service.tax_exempt.unwrap_or(false);
 
         client
             .execute(
-                "INSERT INTO service_prices (id, service_name, service_type, price, active, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, true, $5, $6)",
+                "INSERT INTO service_prices (id, service_name, service_type, price, active, created_at, updated_at, currency,
+                                            tax_rate, tax_exempt)
+                 VALUES ($1, $2, $3, $4, true, $5, $6, $7, $8, $9)",
                 &[
                     &id,
                     &service.service_name,
@@ -2872,6 +3512,9 @@ impl PostgresPool {
                     &service.price,
                     &now,
                     &now,
+                    &currency,
+                    &tax_rate,
+                    &tax_exempt,
                 ],
             )
             .await
@@ -2882,6 +3525,9 @@ impl PostgresPool {
             service_name: service.service_name.clone(),
             service_type: service.service_type.clone(),
             price: service.price,
+            currency,
+            tax_rate,
+            tax_exempt,
             active: true,
         })
     }
@@ -2891,6 +3537,10 @@ impl PostgresPool {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let service_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
         let now = chrono::Utc::now();
+        let currency = match updates.currency.as_deref() {
+            Some(c) => Some(billing::normalize_currency(Some(c))?),
+            None => None,
+        };
 
         client
             .execute(
@@ -2899,7 +3549,10 @@ impl PostgresPool {
                     service_name = COALESCE($2, service_name),
                     service_type = COALESCE($3, service_type),
                     price = COALESCE($4, price),
-                    active = COALESCE($5, active)
+                    active = COALESCE($5, active),
+                    currency = COALESCE($7, currency),
+                    tax_rate = COALESCE($8, tax_rate),
+                    tax_exempt = COALESCE($9, tax_exempt)
                  WHERE id = $6",
                 &[
                     &now,
@@ -2908,6 +3561,9 @@ impl PostgresPool {
                     &updates.price,
                     &updates.active,
                     &service_uuid,
+                    &currency,
+                    &updates.tax_rate,
+                    &updates.tax_exempt,
                 ],
             )
             .await
@@ -2916,88 +3572,200 @@ impl PostgresPool {
         // Fetch updated service
         let row = client
             .query_one(
-                "SELECT id, service_name, service_type::text, price, active
+                "SELECT id, service_name, service_type::text, price, active, currency, tax_rate, tax_exempt
                  FROM service_prices WHERE id = $1",
                 &[&service_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(ServicePrice {
+        Ok(self.map_service_price_row(&row))
+    }
+
+    /// Helper to map service price row
+    fn map_service_price_row(&self, row: &tokio_postgres::Row) -> ServicePrice {
+        ServicePrice {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
             service_name: row.get(1),
             service_type: row.get(2),
             price: row.get(3),
+            currency: row.get(5),
+            tax_rate: row.get(6),
+            tax_exempt: row.get(7),
             active: row.get::<_, Option<bool>>(4).unwrap_or(true),
-        })
+        }
     }
 
     // ============================================================
-    // INVENTORY (INVENTARIO)
+    // EXCHANGE RATES (TIPOS DE CAMBIO)
     // ============================================================
 
-    /// Get inventory items by branch
-    pub async fn get_inventory_items(&self, branch_id: &str) -> Result<Vec<InventoryItem>, String> {
+    /// Get stored exchange rates, optionally filtered by currency
+    pub async fn get_exchange_rates(&self, currency: Option<&str>, limit: i64) -> Result<Vec<ExchangeRate>, String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
-        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
 
         let rows = client
             .query(
-                "SELECT id, name, category, cost_price, sell_price, supplier_id, branch_id,
-                        active, current_stock, reorder_level
-                 FROM inventory_items
-                 WHERE branch_id = $1 AND deleted_at IS NULL
-                 ORDER BY category, name",
-                &[&branch_uuid],
+                "SELECT id, currency, rate_date, rate::numeric, source, created_at
+                 FROM exchange_rates
+                 WHERE ($1::text IS NULL OR currency = $1)
+                 ORDER BY rate_date DESC, currency
+                 LIMIT $2",
+                &[&currency, &limit],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(rows.iter().map(|row| InventoryItem {
-            id: row.get::<_, uuid::Uuid>(0).to_string(),
-            name: row.get(1),
-            category: row.get(2),
-            cost_price: row.get(3),
-            sell_price: row.get(4),
-            supplier_id: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
-            branch_id: row.get::<_, uuid::Uuid>(6).to_string(),
-            active: row.get::<_, Option<bool>>(7).unwrap_or(true),
-            current_stock: row.get::<_, Option<i32>>(8).unwrap_or(0),
-            reorder_level: row.get(9),
-        }).collect())
+        Ok(rows.iter().map(|row| self.map_exchange_rate_row(row)).collect())
+    }
+
+    /// Get the latest exchange rate on or before a date
+    pub async fn get_exchange_rate_for_date(&self, currency: &str, date: &str) -> Result<Option<ExchangeRate>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let currency = billing::normalize_currency(Some(currency))?;
+        let date_parsed = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| format!("Invalid date format: {}", e))?;
+
+        let result = client
+            .query_opt(
+                "SELECT id, currency, rate_date, rate::numeric, source, created_at
+                 FROM exchange_rates
+                 WHERE currency = $1 AND rate_date <= $2
+                 ORDER BY rate_date DESC
+                 LIMIT 1",
+                &[&currency, &date_parsed],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(result.map(|row| self.map_exchange_rate_row(&row)))
+    }
+
+    /// Create or replace the exchange rate for a currency and date
+    pub async fn upsert_exchange_rate(&self, input: &ExchangeRateInput) -> Result<ExchangeRate, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let currency = billing::normalize_currency(Some(&input.currency))?;
+        if billing::is_base_currency(&currency) {
+            return Err(format!("{} es la moneda base y no requiere tipo de cambio", currency));
+        }
+        if input.rate <= Decimal::ZERO {
+            return Err("El tipo de cambio debe ser mayor a cero".to_string());
+        }
+        let rate_date = chrono::NaiveDate::parse_from_str(&input.rate_date, "%Y-%m-%d")
+            .map_err(|e| format!("Invalid date format: {}", e))?;
+
+        let row = client
+            .query_one(
+                "INSERT INTO exchange_rates (currency, rate_date, rate, source)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (currency, rate_date)
+                 DO UPDATE SET rate = EXCLUDED.rate, source = EXCLUDED.source
+                 RETURNING id, currency, rate_date, rate::numeric, source, created_at",
+                &[&currency, &rate_date, &input.rate, &input.source],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_exchange_rate_row(&row))
+    }
+
+    /// Helper to map exchange rate row
+    fn map_exchange_rate_row(&self, row: &tokio_postgres::Row) -> ExchangeRate {
+        ExchangeRate {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            currency: row.get(1),
+            rate_date: row.get::<_, chrono::NaiveDate>(2).to_string(),
+            rate: row.get(3),
+            source: row.get(4),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+        }
+    }
+
+    // ============================================================
+    // INVENTORY (INVENTARIO)
+    // ============================================================
+
+    /// Get inventory items by branch
+    pub async fn get_inventory_items(&self, branch_id: &str) -> Result<Vec<InventoryItem>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT id, name, category, cost_price, sell_price, supplier_id, branch_id,
+                        active, current_stock, reorder_level, currency, tax_rate, tax_exempt, barcode
+                 FROM inventory_items
+                 WHERE branch_id = $1 AND deleted_at IS NULL
+                 ORDER BY category, name",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_inventory_item_row(row)).collect())
     }
 
     /// Create an inventory item
+    /// Initial stock is recorded as an 'entrada' movement so the ledger starts at zero.
     pub async fn create_inventory_item(&self, item: &InventoryItemInput) -> Result<InventoryItem, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let id = uuid::Uuid::new_v4();
         let now = chrono::Utc::now();
 
         let branch_uuid = uuid::Uuid::parse_str(&item.branch_id).map_err(|e| e.to_string())?;
         let supplier_uuid: Option<uuid::Uuid> = item.supplier_id.as_ref()
             .and_then(|id| uuid::Uuid::parse_str(id).ok());
+        let currency = billing::normalize_currency(item.currency.as_deref())?;
+        let tax_rate = item.tax_rate.unwrap_or(DEFAULT_TAX_RATE);
+        let tax_exempt = item.tax_exempt.unwrap_or(false);
+        let initial_stock = item.current_stock.unwrap_or(0);
+        if initial_stock < 0 {
+            return Err("El stock inicial no puede ser negativo".to_string());
+        }
+        let barcode = item.barcode.as_deref().and_then(barcode::clean_barcode);
 
-        client
-            .execute(
-                "INSERT INTO inventory_items (id, name, category, cost_price, sell_price, supplier_id,
-                                             branch_id, active, current_stock, reorder_level, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, true, $8, $9, $10, $11)",
-                &[
-                    &id,
-                    &item.name,
-                    &item.category,
-                    &item.cost_price,
-                    &item.sell_price,
-                    &supplier_uuid,
-                    &branch_uuid,
-                    &item.current_stock.unwrap_or(0),
-                    &item.reorder_level,
-                    &now,
-                    &now,
-                ],
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        if let Some(code) = &barcode {
+            self.ensure_barcode_available(&tx, &branch_uuid, code, None).await?;
+        }
+
+        tx.execute(
+            "INSERT INTO inventory_items (id, name, category, cost_price, sell_price, supplier_id,
+                                         branch_id, active, current_stock, reorder_level, created_at, updated_at,
+                                         currency, tax_rate, tax_exempt, barcode)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, true, 0, $8, $9, $10, $11, $12, $13, $14)",
+            &[
+                &id,
+                &item.name,
+                &item.category,
+                &item.cost_price,
+                &item.sell_price,
+                &supplier_uuid,
+                &branch_uuid,
+                &item.reorder_level,
+                &now,
+                &now,
+                &currency,
+                &tax_rate,
+                &tax_exempt,
+                &barcode,
+            ],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        if initial_stock > 0 {
+            tx.execute(
+                "INSERT INTO inventory_movements (branch_id, item_id, movement_type, quantity, reference_type, notes)
+                 VALUES ($1, $2, 'entrada'::inventory_movement_type, $3, 'ajuste', 'Stock inicial')",
+                &[&branch_uuid, &id, &(initial_stock as f64)],
             )
             .await
             .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
 
         Ok(InventoryItem {
             id: id.to_string(),
@@ -3008,61 +3776,115 @@ impl PostgresPool {
             supplier_id: item.supplier_id.clone(),
             branch_id: item.branch_id.clone(),
             active: true,
-            current_stock: item.current_stock.unwrap_or(0),
+            current_stock: initial_stock,
             reorder_level: item.reorder_level,
+            currency,
+            tax_rate,
+            tax_exempt,
+            barcode,
         })
     }
 
-    /// Update an inventory item
+    /// Update an inventory item. A new current_stock is not written directly:
+    /// the difference is posted as an 'ajuste' movement.
     pub async fn update_inventory_item(&self, id: &str, updates: &InventoryItemUpdate) -> Result<InventoryItem, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let item_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
         let now = chrono::Utc::now();
 
         let supplier_uuid: Option<uuid::Uuid> = updates.supplier_id.as_ref()
             .and_then(|id| uuid::Uuid::parse_str(id).ok());
+        let currency = match updates.currency.as_deref() {
+            Some(c) => Some(billing::normalize_currency(Some(c))?),
+            None => None,
+        };
 
-        client
-            .execute(
-                "UPDATE inventory_items SET
-                    updated_at = $1,
-                    name = COALESCE($2, name),
-                    category = COALESCE($3, category),
-                    cost_price = COALESCE($4, cost_price),
-                    sell_price = COALESCE($5, sell_price),
-                    supplier_id = COALESCE($6, supplier_id),
-                    current_stock = COALESCE($7, current_stock),
-                    reorder_level = COALESCE($8, reorder_level),
-                    active = COALESCE($9, active)
-                 WHERE id = $10",
-                &[
-                    &now,
-                    &updates.name,
-                    &updates.category,
-                    &updates.cost_price,
-                    &updates.sell_price,
-                    &supplier_uuid,
-                    &updates.current_stock,
-                    &updates.reorder_level,
-                    &updates.active,
-                    &item_uuid,
-                ],
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let current = tx
+            .query_opt(
+                "SELECT branch_id, current_stock FROM inventory_items WHERE id = $1 FOR UPDATE",
+                &[&item_uuid],
             )
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| e.to_string())?
+            .ok_or("Producto no encontrado")?;
+
+        // An empty barcode clears it
+        let barcode = updates.barcode.as_deref().map(|b| barcode::clean_barcode(b).unwrap_or_default());
+        if let Some(code) = barcode.as_deref().filter(|b| !b.is_empty()) {
+            self.ensure_barcode_available(&tx, &current.get(0), code, Some(&item_uuid)).await?;
+        }
+
+        tx.execute(
+            "UPDATE inventory_items SET
+                updated_at = $1,
+                name = COALESCE($2, name),
+                category = COALESCE($3, category),
+                cost_price = COALESCE($4, cost_price),
+                sell_price = COALESCE($5, sell_price),
+                supplier_id = COALESCE($6, supplier_id),
+                reorder_level = COALESCE($7, reorder_level),
+                active = COALESCE($8, active),
+                currency = COALESCE($10, currency),
+                tax_rate = COALESCE($11, tax_rate),
+                tax_exempt = COALESCE($12, tax_exempt),
+                barcode = CASE WHEN $13::text IS NULL THEN barcode ELSE NULLIF($13, '') END
+             WHERE id = $9",
+            &[
+                &now,
+                &updates.name,
+                &updates.category,
+                &updates.cost_price,
+                &updates.sell_price,
+                &supplier_uuid,
+                &updates.reorder_level,
+                &updates.active,
+                &item_uuid,
+                &currency,
+                &updates.tax_rate,
+                &updates.tax_exempt,
+                &barcode,
+            ],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        if let Some(target) = updates.current_stock {
+            if target < 0 {
+                return Err("El stock no puede ser negativo".to_string());
+            }
+            let branch_uuid: uuid::Uuid = current.get(0);
+            let delta = target - current.get::<_, Option<i32>>(1).unwrap_or(0);
+            if delta != 0 {
+                tx.execute(
+                    "INSERT INTO inventory_movements (branch_id, item_id, movement_type, quantity, reference_type, notes)
+                     VALUES ($1, $2, 'ajuste'::inventory_movement_type, $3, 'ajuste', 'Ajuste de stock desde edición de producto')",
+                    &[&branch_uuid, &item_uuid, &(delta as f64)],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+        }
 
         // Fetch updated item
-        let row = client
+        let row = tx
             .query_one(
                 "SELECT id, name, category, cost_price, sell_price, supplier_id, branch_id,
-                        active, current_stock, reorder_level
+                        active, current_stock, reorder_level, currency, tax_rate, tax_exempt, barcode
                  FROM inventory_items WHERE id = $1",
                 &[&item_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(InventoryItem {
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(self.map_inventory_item_row(&row))
+    }
+
+    /// Helper to map inventory item row
+    fn map_inventory_item_row(&self, row: &tokio_postgres::Row) -> InventoryItem {
+        InventoryItem {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
             name: row.get(1),
             category: row.get(2),
@@ -3073,7 +3895,35 @@ impl PostgresPool {
             active: row.get::<_, Option<bool>>(7).unwrap_or(true),
             current_stock: row.get::<_, Option<i32>>(8).unwrap_or(0),
             reorder_level: row.get(9),
-        })
+            currency: row.get(10),
+            tax_rate: row.get(11),
+            tax_exempt: row.get(12),
+            barcode: row.get(13),
+        }
+    }
+
+    /// Reject a barcode already used by another item of the branch
+    async fn ensure_barcode_available(
+        &self,
+        tx: &deadpool_postgres::Transaction<'_>,
+        branch_uuid: &uuid::Uuid,
+        barcode: &str,
+        item_uuid: Option<&uuid::Uuid>,
+    ) -> Result<(), String> {
+        let existing = tx
+            .query_opt(
+                "SELECT name FROM inventory_items
+                 WHERE branch_id = $1 AND barcode = $2 AND deleted_at IS NULL
+                   AND ($3::uuid IS NULL OR id <> $3)
+                 LIMIT 1",
+                &[branch_uuid, &barcode, &item_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        match existing {
+            Some(row) => Err(format!("El código de barras {} ya está asignado a {}", barcode, row.get::<_, String>(0))),
+            None => Ok(()),
+        }
     }
 
     /// Get all suppliers
@@ -3127,7 +3977,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT id, item_id, lot_number, expiration_date, quantity, created_at
+                "SELECT id, item_id, lot_number, expiry_date, quantity::float8, created_at, barcode
                  FROM inventory_lots
                  WHERE item_id = $1
                  ORDER BY created_at DESC",
@@ -3145,6 +3995,7 @@ impl PostgresPool {
                 expiration_date: exp_date.map(|d| d.to_string()),
                 quantity: row.get::<_, f64>(4),
                 created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+                barcode: row.get(6),
             }
         }).collect())
     }
@@ -3169,7 +4020,7 @@ impl PostgresPool {
 
         Ok(rows.iter().map(|row| {
             let exp_date: Option<chrono::NaiveDate> = row.get(3);
-            let cost: Option<f64> = row.get(5);
+            let cost: Option<Decimal> = row.get(5);
             let item_name: Option<String> = row.get(7);
             let item_code: Option<String> = row.get(8);
 
@@ -3195,13 +4046,14 @@ impl PostgresPool {
         let item_uuid = uuid::Uuid::parse_str(&input.item_id).map_err(|e| e.to_string())?;
         let expiry = input.expiry_date.as_ref()
             .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+        let barcode = input.barcode.as_deref().and_then(barcode::clean_barcode);
 
         let row = client
             .query_one(
-                "INSERT INTO inventory_lots (item_id, lot_number, quantity, expiry_date, cost_price)
-                 VALUES ($1, $2, $3, $4, $5)
-                 RETURNING id, item_id, lot_number, expiry_date, quantity, created_at",
-                &[&item_uuid, &input.lot_number, &input.quantity, &expiry, &input.cost_price],
+                "INSERT INTO inventory_lots (item_id, lot_number, quantity, expiry_date, cost_price, barcode)
+                 VALUES ($1, $2, $3::float8, $4, $5, $6)
+                 RETURNING id, item_id, lot_number, expiry_date, quantity::float8, created_at, barcode",
+                &[&item_uuid, &input.lot_number, &input.quantity, &expiry, &input.cost_price, &barcode],
             )
             .await
             .map_err(|e| e.to_string())?;
@@ -3215,6 +4067,7 @@ impl PostgresPool {
             expiration_date: exp_date.map(|d| d.to_string()),
             quantity: row.get::<_, f64>(4),
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+            barcode: row.get(6),
         })
     }
 
@@ -3273,7 +4126,8 @@ impl PostgresPool {
         }).collect())
     }
 
-    /// Create inventory movement (trigger will update stock)
+    /// Create inventory movement (trigger will update stock).
+    /// Outgoing movements cannot use an expired lot.
     pub async fn create_inventory_movement(&self, input: &crate::commands::InventoryMovementInput) -> Result<crate::commands::InventoryMovement, String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
@@ -3282,21 +4136,141 @@ impl PostgresPool {
             Some(id) if !id.is_empty() => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
             _ => None,
         };
+        let ref_uuid = match &input.reference_id {
+            Some(id) if !id.is_empty() => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            _ => None,
+        };
+
+        if let Some(lot_uuid) = lot_uuid {
+            let lot = client
+                .query_one(
+                    "SELECT lot_number, expiry_date FROM inventory_lots WHERE id = $1 AND item_id = $2",
+                    &[&lot_uuid, &item_uuid],
+                )
+                .await
+                .map_err(|_| "Lote no encontrado para este producto".to_string())?;
+            let lot_number: String = lot.get(0);
+            lots::ensure_usable(&lot_number, lot.get(1), &input.movement_type, timezone::today())?;
+        }
 
         let row = client
             .query_one(
-                "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, notes)
-                 VALUES ($1, $2, $3, $4::inventory_movement_type, $5, $6, $7)
-                 RETURNING id, branch_id, item_id, lot_id, movement_type::text, quantity, reference_type, reference_id, notes, created_at",
-                &[&branch_uuid, &item_uuid, &lot_uuid, &input.movement_type, &input.quantity, &input.reference_type, &input.notes],
+                &format!(
+                    "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+                     VALUES ($1, $2, $3, $4::inventory_movement_type, $5, $6, $7, $8)
+                     RETURNING {}",
+                    INVENTORY_MOVEMENT_COLUMNS
+                ),
+                &[&branch_uuid, &item_uuid, &lot_uuid, &input.movement_type, &input.quantity, &input.reference_type, &ref_uuid, &input.notes],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_inventory_movement_row(&row))
+    }
+
+    /// Outgoing movement with automatic lot selection, in one transaction.
+    /// Items without lots get a single movement; an explicit lot_id is
+    /// honoured after the expiry check.
+    pub async fn consume_inventory_stock(&self, input: &crate::commands::InventoryMovementInput) -> Result<Vec<crate::commands::InventoryMovement>, String> {
+        if !lots::is_outgoing(&input.movement_type) || input.movement_type == lots::WRITE_OFF_MOVEMENT {
+            return Err(format!("Tipo de movimiento no válido para consumo: {}", input.movement_type));
+        }
+        if input.lot_id.as_deref().is_some_and(|id| !id.is_empty()) {
+            return Ok(vec![self.create_inventory_movement(input).await?]);
+        }
+
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
+        let item_uuid = uuid::Uuid::parse_str(&input.item_id).map_err(|e| e.to_string())?;
+        let ref_uuid = match &input.reference_id {
+            Some(id) if !id.is_empty() => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            _ => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        let allocations = self.allocate_item_lots(&tx, &item_uuid, input.quantity).await?;
+
+        let insert = format!(
+            "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+             VALUES ($1, $2, $3, $4::inventory_movement_type, $5, $6, $7, $8)
+             RETURNING {}",
+            INVENTORY_MOVEMENT_COLUMNS
+        );
+        let mut movements = Vec::with_capacity(allocations.len());
+        for (lot_uuid, quantity) in allocations {
+            let row = tx
+                .query_one(
+                    &insert,
+                    &[&branch_uuid, &item_uuid, &lot_uuid, &input.movement_type, &quantity, &input.reference_type, &ref_uuid, &input.notes],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            movements.push(self.map_inventory_movement_row(&row));
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(movements)
+    }
+
+    /// Lock the item and choose the lots an outgoing quantity comes from
+    /// (FEFO for the categories in the `fefo_categories` setting, FIFO
+    /// otherwise). Items without lots get a single allocation with no lot.
+    async fn allocate_item_lots(
+        &self,
+        tx: &deadpool_postgres::Transaction<'_>,
+        item_uuid: &uuid::Uuid,
+        quantity: f64,
+    ) -> Result<Vec<(Option<uuid::Uuid>, f64)>, String> {
+        // Lock the item so concurrent sales allocate against the same lot quantities
+        let category: String = tx
+            .query_opt("SELECT category FROM inventory_items WHERE id = $1 FOR UPDATE", &[item_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Producto no encontrado")?
+            .get(0);
+
+        let lot_rows = tx
+            .query(
+                "SELECT id, lot_number, expiry_date, quantity::float8, created_at
+                 FROM inventory_lots
+                 WHERE item_id = $1 AND quantity > 0",
+                &[item_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
 
+        if lot_rows.is_empty() {
+            return Ok(vec![(None, quantity)]);
+        }
+
+        let available: Vec<lots::LotStock> = lot_rows.iter().map(|row| lots::LotStock {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            lot_number: row.get(1),
+            expiry_date: row.get(2),
+            quantity: row.get(3),
+            created_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(4).unwrap_or_default(),
+        }).collect();
+
+        let setting: Option<serde_json::Value> = tx
+            .query_opt("SELECT value FROM app_settings WHERE key = $1", &[&lots::FEFO_SETTING])
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|row| row.get(0));
+        let fefo = lots::uses_fefo(&category, &lots::fefo_categories(setting.as_ref()));
+
+        lots::allocate(&available, quantity, fefo, timezone::today())?
+            .into_iter()
+            .map(|a| Ok((Some(uuid::Uuid::parse_str(&a.lot_id).map_err(|e| e.to_string())?), a.quantity)))
+            .collect()
+    }
+
+    /// Helper to map a row selected with INVENTORY_MOVEMENT_COLUMNS
+    fn map_inventory_movement_row(&self, row: &tokio_postgres::Row) -> crate::commands::InventoryMovement {
         let lot_id: Option<uuid::Uuid> = row.get(3);
         let ref_id: Option<uuid::Uuid> = row.get(7);
 
-        Ok(crate::commands::InventoryMovement {
+        crate::commands::InventoryMovement {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
             branch_id: row.get::<_, uuid::Uuid>(1).to_string(),
             item_id: row.get::<_, uuid::Uuid>(2).to_string(),
@@ -3309,7 +4283,216 @@ impl PostgresPool {
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(9).to_rfc3339(),
             inventory_items: None,
             inventory_lots: None,
-        })
+        }
+    }
+
+    // ============================================================
+    // STOCK RECONCILIATION
+    // ============================================================
+
+    /// Compare each pharmacy item's current_stock with its movement ledger
+    pub async fn get_inventory_reconciliation(&self, branch_id: &str) -> Result<Vec<crate::commands::StockReconciliationLine>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let items = client
+            .query(
+                "SELECT id, name, code, category, current_stock FROM inventory_items WHERE branch_id = $1 ORDER BY name",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let movements = client
+            .query(
+                "SELECT m.item_id, m.movement_type::text, m.quantity::float8
+                 FROM inventory_movements m
+                 JOIN inventory_items i ON m.item_id = i.id
+                 WHERE i.branch_id = $1",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut by_item: std::collections::HashMap<uuid::Uuid, (f64, i64)> = std::collections::HashMap::new();
+        for row in &movements {
+            let movement_type: String = row.get(1);
+            let entry = by_item.entry(row.get(0)).or_insert((0.0, 0));
+            entry.0 += ledger::stock_delta(&movement_type, row.get(2));
+            entry.1 += 1;
+        }
+
+        Ok(items.iter().filter_map(|row| {
+            let item_uuid: uuid::Uuid = row.get(0);
+            let recorded = row.get::<_, Option<i32>>(4).unwrap_or(0) as f64;
+            let (ledger_stock, movement_count) = by_item.get(&item_uuid).copied().unwrap_or((0.0, 0));
+            let difference = recorded - ledger_stock;
+            if difference.abs() < 0.0005 {
+                return None;
+            }
+            Some(crate::commands::StockReconciliationLine {
+                item_id: item_uuid.to_string(),
+                item_name: row.get(1),
+                item_code: row.get(2),
+                category: row.get(3),
+                recorded_stock: recorded,
+                ledger_stock,
+                difference,
+                movement_count,
+            })
+        }).collect())
+    }
+
+    /// Compare each room item's current_stock with its replayed movement ledger
+    pub async fn get_room_inventory_reconciliation(&self, branch_id: &str) -> Result<Vec<crate::commands::StockReconciliationLine>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let items = client
+            .query(
+                "SELECT i.id, i.name, i.code, c.name, i.current_stock
+                 FROM room_inventory_items i
+                 LEFT JOIN room_inventory_categories c ON i.category_id = c.id
+                 WHERE i.branch_id = $1 AND i.active = true
+                 ORDER BY i.name",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Adjustments set the stock, so movements must be replayed in order
+        let movements = client
+            .query(
+                "SELECT m.item_id, m.movement_type::text, m.quantity
+                 FROM room_inventory_movements m
+                 JOIN room_inventory_items i ON m.item_id = i.id
+                 WHERE i.branch_id = $1
+                 ORDER BY m.created_at, m.id",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut by_item: std::collections::HashMap<uuid::Uuid, Vec<(String, i32)>> = std::collections::HashMap::new();
+        for row in &movements {
+            by_item.entry(row.get(0)).or_default().push((row.get(1), row.get(2)));
+        }
+
+        Ok(items.iter().filter_map(|row| {
+            let item_uuid: uuid::Uuid = row.get(0);
+            let recorded: i32 = row.get(4);
+            let history = by_item.get(&item_uuid).map(|m| m.as_slice()).unwrap_or(&[]);
+            let ledger_stock = ledger::room_ledger_stock(history.iter().map(|(t, q)| (t.as_str(), *q)));
+            if recorded == ledger_stock {
+                return None;
+            }
+            Some(crate::commands::StockReconciliationLine {
+                item_id: item_uuid.to_string(),
+                item_name: row.get(1),
+                item_code: row.get(2),
+                category: row.get(3),
+                recorded_stock: recorded as f64,
+                ledger_stock: ledger_stock as f64,
+                difference: (recorded - ledger_stock) as f64,
+                movement_count: history.len() as i64,
+            })
+        }).collect())
+    }
+
+    // ============================================================
+    // LOT EXPIRY
+    // ============================================================
+
+    /// Lots with stock expiring within `days`, already expired ones first
+    pub async fn get_expiring_lots(&self, branch_id: &str, days: i32) -> Result<Vec<crate::commands::ExpiringLot>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let today = timezone::today();
+        let limit = today + chrono::Duration::days(days.max(0) as i64);
+
+        let rows = client
+            .query(
+                "SELECT l.id, l.item_id, i.name, i.code, i.category, l.lot_number, l.expiry_date,
+                        l.quantity::float8, l.cost_price
+                 FROM inventory_lots l
+                 JOIN inventory_items i ON l.item_id = i.id
+                 WHERE i.branch_id = $1 AND l.quantity > 0
+                   AND l.expiry_date IS NOT NULL AND l.expiry_date <= $2
+                 ORDER BY l.expiry_date ASC, i.name",
+                &[&branch_uuid, &limit],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| {
+            let expiry: chrono::NaiveDate = row.get(6);
+            crate::commands::ExpiringLot {
+                lot_id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                item_name: row.get(2),
+                item_code: row.get(3),
+                category: row.get(4),
+                lot_number: row.get(5),
+                expiry_date: expiry.to_string(),
+                days_to_expiry: lots::days_to_expiry(expiry, today),
+                expired: lots::is_expired(Some(expiry), today),
+                quantity: row.get(7),
+                cost_price: row.get(8),
+            }
+        }).collect())
+    }
+
+    /// Write off ('merma') the remaining quantity of every expired lot
+    pub async fn write_off_expired_lots(&self, branch_id: &str, notes: Option<&str>) -> Result<Vec<crate::commands::InventoryMovement>, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let today = timezone::today();
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let expired = tx
+            .query(
+                "SELECT l.id, l.item_id, l.lot_number, l.expiry_date, l.quantity::float8
+                 FROM inventory_lots l
+                 JOIN inventory_items i ON l.item_id = i.id
+                 WHERE i.branch_id = $1 AND l.quantity > 0 AND l.expiry_date < $2
+                 ORDER BY l.expiry_date
+                 FOR UPDATE OF l",
+                &[&branch_uuid, &today],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let insert = format!(
+            "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, notes)
+             VALUES ($1, $2, $3, $4::inventory_movement_type, $5, 'ajuste', $6)
+             RETURNING {}",
+            INVENTORY_MOVEMENT_COLUMNS
+        );
+        let mut movements = Vec::with_capacity(expired.len());
+        for lot in &expired {
+            let lot_uuid: uuid::Uuid = lot.get(0);
+            let item_uuid: uuid::Uuid = lot.get(1);
+            let lot_number: String = lot.get(2);
+            let expiry: chrono::NaiveDate = lot.get(3);
+            let quantity: f64 = lot.get(4);
+            let note = match notes {
+                Some(n) if !n.trim().is_empty() => format!("Baja por vencimiento - lote {} ({}): {}", lot_number, expiry, n.trim()),
+                _ => format!("Baja por vencimiento - lote {} ({})", lot_number, expiry),
+            };
+
+            let row = tx
+                .query_one(
+                    &insert,
+                    &[&branch_uuid, &item_uuid, &Some(lot_uuid), &lots::WRITE_OFF_MOVEMENT, &quantity, &note],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            movements.push(self.map_inventory_movement_row(&row));
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(movements)
     }
 
     // ============================================================
@@ -3323,7 +4506,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT sp.service_type, COUNT(*)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::float8 as total
+                "SELECT sp.service_type, COUNT(*)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::numeric as total
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  JOIN service_prices sp ON ii.item_id = sp.id
@@ -3353,7 +4536,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT sp.service_name, sp.service_type, SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::float8 as total
+                "SELECT sp.service_name, sp.service_type, SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::numeric as total
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  JOIN service_prices sp ON ii.item_id = sp.id
@@ -3384,7 +4567,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT COALESCE(inv.category, 'Sin categoría') as category, SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::float8 as total
+                "SELECT COALESCE(inv.category, 'Sin categoría') as category, SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::numeric as total
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  LEFT JOIN inventory_items inv ON ii.item_id = inv.id
@@ -3415,7 +4598,7 @@ impl PostgresPool {
         let rows = client
             .query(
                 "SELECT COALESCE(inv.category, 'Sin categoría') as category, COALESCE(inv.name, ii.description) as product_name,
-                        SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::float8 as total
+                        SUM(ii.quantity)::bigint as cantidad, COALESCE(SUM(ii.subtotal), 0)::numeric as total
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  LEFT JOIN inventory_items inv ON ii.item_id = inv.id
@@ -3446,7 +4629,7 @@ impl PostgresPool {
 
         let rows = client
             .query(
-                "SELECT p.payment_method, COUNT(*)::bigint as cantidad, COALESCE(SUM(p.amount), 0)::float8 as total
+                "SELECT p.payment_method, COUNT(*)::bigint as cantidad, COALESCE(SUM(p.amount), 0)::numeric as total
                  FROM payments p
                  JOIN invoices i ON p.invoice_id = i.id
                  WHERE i.branch_id = $1
@@ -3467,7 +4650,37 @@ impl PostgresPool {
         }).collect())
     }
 
-    /// Get products report with detailed sales data
+    /// Get collected totals grouped by currency and payment method
+    pub async fn get_payment_currency_summary(&self, branch_id: &str, start_date: &str, end_date: &str) -> Result<Vec<CurrencyTotals>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT p.currency, p.payment_method,
+                        COALESCE(SUM(p.amount), 0)::numeric as total,
+                        COALESCE(SUM(COALESCE(p.amount_base, p.amount)), 0)::numeric as total_base
+                 FROM payments p
+                 JOIN invoices i ON p.invoice_id = i.id
+                 WHERE i.branch_id = $1
+                   AND p.created_at >= $2::timestamptz
+                   AND p.created_at <= $3::timestamptz
+                   AND p.status = 'completado'
+                 GROUP BY p.currency, p.payment_method",
+                &[&branch_uuid, &start_date, &end_date],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let grouped: Vec<(String, String, Decimal, Decimal)> = rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+            .collect();
+
+        Ok(billing::group_currency_totals(&grouped))
+    }
+
+    /// Get products report with detailed sales data
     pub async fn get_products_report(&self, branch_id: &str, start_date: &str, end_date: &str) -> Result<Vec<crate::commands::ProductReportItem>, String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
@@ -3482,10 +4695,10 @@ impl PostgresPool {
                     COALESCE(ii.category, 'N/A') as category,
                     COALESCE(s.name, 'Sin proveedor') as supplier_name,
                     it.quantity::float8,
-                    it.unit_price::float8,
-                    COALESCE(ii.cost_price, 0)::float8 as cost_price,
-                    it.subtotal::float8,
-                    (it.subtotal - COALESCE(ii.cost_price, 0) * it.quantity)::float8 as profit
+                    it.unit_price::numeric,
+                    COALESCE(ii.cost_price, 0)::numeric as cost_price,
+                    it.subtotal::numeric,
+                    (it.subtotal - COALESCE(ii.cost_price, 0) * it.quantity)::numeric as profit
                  FROM invoice_items it
                  JOIN invoices inv ON it.invoice_id = inv.id
                  LEFT JOIN patients p ON inv.patient_id = p.id
@@ -3531,11 +4744,14 @@ impl PostgresPool {
                     COALESCE(sp.service_name, it.description, 'Servicio') as service_name,
                     COALESCE(sp.service_type, 'N/A') as service_type,
                     it.quantity::float8,
-                    it.unit_price::float8,
-                    it.subtotal::float8,
+                    it.unit_price::numeric,
+                    it.subtotal::numeric,
                     inv.discount_type,
-                    COALESCE(inv.discount_value, 0)::float8 as discount_value,
-                    inv.discount_reason
+                    COALESCE(inv.discount_value, 0)::numeric as discount_value,
+                    inv.discount_reason,
+                    it.tax_rate,
+                    it.tax_exempt,
+                    it.tax_amount
                  FROM invoice_items it
                  JOIN invoices inv ON it.invoice_id = inv.id
                  LEFT JOIN patients p ON inv.patient_id = p.id
@@ -3563,6 +4779,9 @@ impl PostgresPool {
             discount_type: row.get(8),
             discount_value: row.get(9),
             discount_reason: row.get(10),
+            tax_rate: row.get(11),
+            tax_exempt: row.get(12),
+            tax_amount: row.get(13),
         }).collect())
     }
 
@@ -3579,9 +4798,10 @@ impl PostgresPool {
         let invoice_row = client
             .query_one(
                 "SELECT
-                    COALESCE(SUM(total_amount), 0)::float8 as total_invoiced,
-                    COALESCE(SUM(balance_due), 0)::float8 as total_pending,
-                    COALESCE(SUM(discount_value), 0)::float8 as total_discounts
+                    COALESCE(SUM(total_amount), 0)::numeric as total_invoiced,
+                    COALESCE(SUM(balance_due), 0)::numeric as total_pending,
+                    COALESCE(SUM(discount_value), 0)::numeric as total_discounts,
+                    COALESCE(SUM(tax_amount), 0)::numeric as total_tax
                  FROM invoices
                  WHERE branch_id = $1
                    AND created_at >= $2::timestamptz
@@ -3595,7 +4815,7 @@ impl PostgresPool {
         // Get payments total
         let payment_row = client
             .query_one(
-                "SELECT COALESCE(SUM(p.amount), 0)::float8 as total_collected
+                "SELECT COALESCE(SUM(p.amount), 0)::numeric as total_collected
                  FROM payments p
                  JOIN invoices inv ON p.invoice_id = inv.id
                  WHERE inv.branch_id = $1
@@ -3612,6 +4832,7 @@ impl PostgresPool {
             total_collected: payment_row.get(0),
             total_pending: invoice_row.get(1),
             total_discounts: invoice_row.get(2),
+            total_tax: invoice_row.get(3),
         })
     }
 
@@ -3625,7 +4846,7 @@ impl PostgresPool {
                 "SELECT
                     inv.invoice_number,
                     COALESCE(p.first_name || ' ' || p.last_name, 'Sin paciente') as patient_name,
-                    inv.total_amount::float8,
+                    inv.total_amount::numeric,
                     inv.status::text,
                     (SELECT payment_method::text FROM payments WHERE invoice_id = inv.id ORDER BY created_at DESC LIMIT 1)
                  FROM invoices inv
@@ -3648,7 +4869,8 @@ impl PostgresPool {
         }).collect())
     }
 
-    /// Create cash closure
+    /// Create cash closure. The IVA total is taken from the invoices of the
+    /// closed period, like the daily summary.
     pub async fn create_cash_closure(&self, closure: crate::commands::CashClosureInput) -> Result<serde_json::Value, String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
         let branch_uuid = uuid::Uuid::parse_str(&closure.branch_id).map_err(|e| e.to_string())?;
@@ -3665,13 +4887,19 @@ impl PostgresPool {
                     estudios_total, estudios_count,
                     inventory_total, inventory_count,
                     efectivo_total, tarjeta_total, transferencia_total, cheque_total, otro_total,
-                    detailed_data, closed_by
+                    detailed_data, closed_by, total_tax
                 ) VALUES (
                     $1, $2::timestamptz, $3::timestamptz,
                     $4, $5, $6, $7,
                     $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
                     $18, $19, $20, $21, $22,
-                    $23, $24
+                    $23, $24,
+                    (SELECT COALESCE(SUM(tax_amount), 0)
+                     FROM invoices
+                     WHERE branch_id = $1
+                       AND created_at >= $2::timestamptz
+                       AND created_at <= $3::timestamptz
+                       AND status != 'cancelada')
                 ) RETURNING id, created_at",
                 &[
                     &branch_uuid,
@@ -3706,12 +4934,346 @@ impl PostgresPool {
         let id: uuid::Uuid = row.get(0);
         let created_at: chrono::DateTime<chrono::Utc> = row.get(1);
 
+        // Store collected totals per currency for the same period
+        let currency_totals = self
+            .get_payment_currency_summary(&closure.branch_id, &closure.period_start, &closure.period_end)
+            .await?;
+
+        for totals in &currency_totals {
+            client
+                .execute(
+                    "INSERT INTO cash_closure_currency_totals (
+                        closure_id, currency,
+                        efectivo_total, tarjeta_total, transferencia_total, cheque_total, otro_total,
+                        total_collected, total_collected_base
+                    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                    &[
+                        &id,
+                        &totals.currency,
+                        &totals.efectivo_total,
+                        &totals.tarjeta_total,
+                        &totals.transferencia_total,
+                        &totals.cheque_total,
+                        &totals.otro_total,
+                        &totals.total_collected,
+                        &totals.total_collected_base,
+                    ],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
         Ok(serde_json::json!({
             "id": id.to_string(),
-            "created_at": created_at.to_rfc3339()
+            "created_at": created_at.to_rfc3339(),
+            "currency_totals": currency_totals
         }))
     }
 
+    // ============================================================
+    // CASH REGISTER SESSIONS (SESIONES DE CAJA)
+    // ============================================================
+
+    /// Open a cash register session with its opening float
+    pub async fn open_cash_session(&self, input: &CashSessionOpenInput) -> Result<CashSession, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
+        let cashier_uuid = uuid::Uuid::parse_str(&input.cashier_id).map_err(|e| e.to_string())?;
+
+        if input.opening_float < Decimal::ZERO {
+            return Err("El fondo inicial no puede ser negativo".to_string());
+        }
+        if self.get_open_cash_session(&input.branch_id, &input.cashier_id).await?.is_some() {
+            return Err("El cajero ya tiene una sesión de caja abierta en esta sucursal".to_string());
+        }
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO cash_register_sessions (branch_id, cashier_id, opening_float, notes)
+                     VALUES ($1, $2, $3, $4)
+                     RETURNING {}",
+                    CASH_SESSION_COLUMNS
+                ),
+                &[&branch_uuid, &cashier_uuid, &billing::round_money(input.opening_float), &input.notes],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_cash_session_row(&row))
+    }
+
+    /// Open session of a cashier at a branch, if any
+    pub async fn get_open_cash_session(&self, branch_id: &str, cashier_id: &str) -> Result<Option<CashSession>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let cashier_uuid = uuid::Uuid::parse_str(cashier_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "SELECT {} FROM cash_register_sessions
+                     WHERE branch_id = $1 AND cashier_id = $2 AND status = 'abierta'",
+                    CASH_SESSION_COLUMNS
+                ),
+                &[&branch_uuid, &cashier_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|r| self.map_cash_session_row(&r)))
+    }
+
+    /// Sessions opened at a branch within a period
+    pub async fn get_cash_sessions(&self, branch_id: &str, start_date: &str, end_date: &str) -> Result<Vec<CashSession>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM cash_register_sessions
+                     WHERE branch_id = $1
+                       AND opened_at >= $2::timestamptz
+                       AND opened_at <= $3::timestamptz
+                     ORDER BY opened_at DESC",
+                    CASH_SESSION_COLUMNS
+                ),
+                &[&branch_uuid, &start_date, &end_date],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_cash_session_row(row)).collect())
+    }
+
+    /// Session with its movements and denomination counts
+    pub async fn get_cash_session_detail(&self, session_id: &str) -> Result<CashSessionDetail, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM cash_register_sessions WHERE id = $1", CASH_SESSION_COLUMNS),
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Sesión de caja no encontrada".to_string())?;
+        let mut session = self.map_cash_session_row(&row);
+
+        // Blind count: expected amounts are only revealed once the session is closed
+        if session.status == "abierta" {
+            session.expected_cash = None;
+            session.over_short = None;
+        }
+
+        let movements = client
+            .query(
+                "SELECT id, session_id, movement_type, amount, reason, created_by, created_at
+                 FROM cash_register_movements
+                 WHERE session_id = $1
+                 ORDER BY created_at",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|r| CashMovement {
+                id: r.get::<_, uuid::Uuid>(0).to_string(),
+                session_id: r.get::<_, uuid::Uuid>(1).to_string(),
+                movement_type: r.get(2),
+                amount: r.get(3),
+                reason: r.get(4),
+                created_by: r.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
+                created_at: r.get::<_, chrono::DateTime<chrono::Utc>>(6).to_rfc3339(),
+            })
+            .collect();
+
+        let counts = client
+            .query(
+                "SELECT denomination, quantity FROM cash_register_counts
+                 WHERE session_id = $1
+                 ORDER BY denomination DESC",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|r| DenominationCount {
+                denomination: r.get(0),
+                quantity: r.get(1),
+            })
+            .collect();
+
+        Ok(CashSessionDetail { session, movements, counts })
+    }
+
+    /// Record a cash-in (entrada) or cash-out (salida) during an open session
+    pub async fn create_cash_movement(&self, input: &CashMovementInput) -> Result<CashMovement, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let session_uuid = uuid::Uuid::parse_str(&input.session_id).map_err(|e| e.to_string())?;
+        let created_by_uuid: Option<uuid::Uuid> = input.created_by.as_ref()
+            .and_then(|id| uuid::Uuid::parse_str(id).ok());
+
+        if input.movement_type != "entrada" && input.movement_type != "salida" {
+            return Err(format!("Tipo de movimiento de caja no válido: {}", input.movement_type));
+        }
+        if input.amount <= Decimal::ZERO {
+            return Err("El monto debe ser mayor a cero".to_string());
+        }
+        if input.reason.trim().is_empty() {
+            return Err("El motivo es requerido".to_string());
+        }
+
+        let row = client
+            .query_opt(
+                "INSERT INTO cash_register_movements (session_id, movement_type, amount, reason, created_by)
+                 SELECT id, $2, $3, $4, $5 FROM cash_register_sessions
+                 WHERE id = $1 AND status = 'abierta'
+                 RETURNING id, session_id, movement_type, amount, reason, created_by, created_at",
+                &[
+                    &session_uuid,
+                    &input.movement_type,
+                    &billing::round_money(input.amount),
+                    &input.reason.trim(),
+                    &created_by_uuid,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "La sesión de caja no está abierta".to_string())?;
+
+        Ok(CashMovement {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            session_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            movement_type: row.get(2),
+            amount: row.get(3),
+            reason: row.get(4),
+            created_by: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(6).to_rfc3339(),
+        })
+    }
+
+    /// Close a session with a blind count and compute the over/short
+    pub async fn close_cash_session(&self, input: &CashSessionCloseInput) -> Result<CashSessionDetail, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let session_uuid = uuid::Uuid::parse_str(&input.session_id).map_err(|e| e.to_string())?;
+        let counted_cash = billing::cash::count_total(&input.counts)?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        // Lock the session so concurrent closings cannot both succeed
+        let session_row = tx
+            .query_opt(
+                "SELECT opening_float, status FROM cash_register_sessions WHERE id = $1 FOR UPDATE",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Sesión de caja no encontrada".to_string())?;
+        let opening_float: Decimal = session_row.get(0);
+        let status: String = session_row.get(1);
+        if status != "abierta" {
+            return Err("La sesión de caja ya fue cerrada".to_string());
+        }
+
+        // Cash sales in the base currency received through this session
+        let efectivo_total: Decimal = tx
+            .query_one(
+                "SELECT COALESCE(SUM(amount), 0)::numeric
+                 FROM payments
+                 WHERE cash_session_id = $1
+                   AND payment_method = 'efectivo'
+                   AND currency = $2
+                   AND status = 'completado'
+                   AND deleted_at IS NULL",
+                &[&session_uuid, &billing::BASE_CURRENCY],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+
+        let movement_row = tx
+            .query_one(
+                "SELECT
+                    COALESCE(SUM(amount) FILTER (WHERE movement_type = 'entrada'), 0)::numeric,
+                    COALESCE(SUM(amount) FILTER (WHERE movement_type = 'salida'), 0)::numeric
+                 FROM cash_register_movements
+                 WHERE session_id = $1",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let cash_in: Decimal = movement_row.get(0);
+        let cash_out: Decimal = movement_row.get(1);
+
+        let expected = billing::cash::expected_cash(opening_float, efectivo_total, cash_in, cash_out);
+        let over_short = billing::cash::over_short(counted_cash, expected);
+
+        for count in &input.counts {
+            tx.execute(
+                "INSERT INTO cash_register_counts (session_id, denomination, quantity)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (session_id, denomination) DO UPDATE SET quantity = cash_register_counts.quantity + EXCLUDED.quantity",
+                &[&session_uuid, &count.denomination, &count.quantity],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.execute(
+            "UPDATE cash_register_sessions SET
+                status = 'cerrada',
+                closed_at = now(),
+                efectivo_total = $2,
+                cash_in_total = $3,
+                cash_out_total = $4,
+                expected_cash = $5,
+                counted_cash = $6,
+                over_short = $7,
+                notes = COALESCE($8, notes)
+             WHERE id = $1",
+            &[
+                &session_uuid,
+                &efectivo_total,
+                &cash_in,
+                &cash_out,
+                &expected,
+                &counted_cash,
+                &over_short,
+                &input.notes,
+            ],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+
+        self.get_cash_session_detail(&input.session_id).await
+    }
+
+    /// Helper to map cash session row (columns in CASH_SESSION_COLUMNS order)
+    fn map_cash_session_row(&self, row: &tokio_postgres::Row) -> CashSession {
+        CashSession {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            branch_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            cashier_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            status: row.get(3),
+            opening_float: row.get(4),
+            opened_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+            closed_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(6).map(|d| d.to_rfc3339()),
+            efectivo_total: row.get(7),
+            cash_in_total: row.get(8),
+            cash_out_total: row.get(9),
+            expected_cash: row.get(10),
+            counted_cash: row.get(11),
+            over_short: row.get(12),
+            notes: row.get(13),
+        }
+    }
+
     // ============================================================
     // CRM PIPELINES
     // ============================================================
@@ -5005,7 +6567,8 @@ impl PostgresPool {
                             i.created_at, i.updated_at,
                             c.id as c_id, c.name as c_name, c.parent_id as c_parent_id,
                             c.display_order as c_display_order, c.active as c_active,
-                            c.branch_id as c_branch_id, c.created_at as c_created_at, c.updated_at as c_updated_at
+                            c.branch_id as c_branch_id, c.created_at as c_created_at, c.updated_at as c_updated_at,
+                            i.unit_cost
                      FROM room_inventory_items i
                      LEFT JOIN room_inventory_categories c ON i.category_id = c.id
                      WHERE i.branch_id = $1 AND i.category_id = $2 AND i.active = true
@@ -5022,7 +6585,8 @@ impl PostgresPool {
                             i.created_at, i.updated_at,
                             c.id as c_id, c.name as c_name, c.parent_id as c_parent_id,
                             c.display_order as c_display_order, c.active as c_active,
-                            c.branch_id as c_branch_id, c.created_at as c_created_at, c.updated_at as c_updated_at
+                            c.branch_id as c_branch_id, c.created_at as c_created_at, c.updated_at as c_updated_at,
+                            i.unit_cost
                      FROM room_inventory_items i
                      LEFT JOIN room_inventory_categories c ON i.category_id = c.id
                      WHERE i.branch_id = $1 AND i.active = true
@@ -5062,29 +6626,47 @@ impl PostgresPool {
                 branch_id: row.get::<_, uuid::Uuid>(11).to_string(),
                 created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(12).to_rfc3339(),
                 updated_at: row.get::<_, chrono::DateTime<chrono::Utc>>(13).to_rfc3339(),
+                unit_cost: row.get(22),
                 category,
             }
         }).collect())
     }
 
     /// Create a room inventory item
+    /// Initial stock is recorded as an 'entrada' movement
     pub async fn create_room_inventory_item(&self, input: &RoomInventoryItemInput) -> Result<RoomInventoryItem, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
         let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
         let category_uuid = uuid::Uuid::parse_str(&input.category_id).map_err(|e| e.to_string())?;
-        let current_stock = input.current_stock.unwrap_or(0);
+        let current_stock = input.current_stock.unwrap_or(0).max(0);
         let min_stock = input.min_stock.unwrap_or(5);
         let unit = input.unit.clone().unwrap_or_else(|| "unidad".to_string());
+        let unit_cost = billing::round_money(input.unit_cost.unwrap_or(Decimal::ZERO).max(Decimal::ZERO));
 
-        let row = client
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let row = tx
             .query_one(
-                "INSERT INTO room_inventory_items (category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, branch_id)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-                 RETURNING id, category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, active, branch_id, created_at, updated_at",
-                &[&category_uuid, &input.name, &input.code, &input.brand, &input.specification, &current_stock, &min_stock, &unit, &input.notes, &branch_uuid],
+                "INSERT INTO room_inventory_items (category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, branch_id, unit_cost)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                 RETURNING id, category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, active, branch_id, created_at, updated_at, unit_cost",
+                &[&category_uuid, &input.name, &input.code, &input.brand, &input.specification, &current_stock, &min_stock, &unit, &input.notes, &branch_uuid, &unit_cost],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if current_stock > 0 {
+            let item_uuid: uuid::Uuid = row.get(0);
+            tx.execute(
+                "INSERT INTO room_inventory_movements (item_id, quantity, movement_type, notes, branch_id)
+                 VALUES ($1, $2, 'entrada'::room_inventory_movement_type, 'Stock inicial', $3)",
+                &[&item_uuid, &current_stock, &branch_uuid],
             )
             .await
             .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
 
         Ok(RoomInventoryItem {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
@@ -5101,6 +6683,7 @@ impl PostgresPool {
             branch_id: row.get::<_, uuid::Uuid>(11).to_string(),
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(12).to_rfc3339(),
             updated_at: row.get::<_, chrono::DateTime<chrono::Utc>>(13).to_rfc3339(),
+            unit_cost: row.get(14),
             category: None,
         })
     }
@@ -5113,6 +6696,7 @@ impl PostgresPool {
             Some(c) => Some(uuid::Uuid::parse_str(c).map_err(|e| e.to_string())?),
             None => None,
         };
+        let unit_cost = updates.unit_cost.map(|c| billing::round_money(c.max(Decimal::ZERO)));
         let now = chrono::Utc::now();
 
         let row = client
@@ -5126,10 +6710,11 @@ impl PostgresPool {
                      min_stock = COALESCE($6, min_stock),
                      unit = COALESCE($7, unit),
                      notes = COALESCE($8, notes),
+                     unit_cost = COALESCE($11, unit_cost),
                      updated_at = $9
                  WHERE id = $10
-                 RETURNING id, category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, active, branch_id, created_at, updated_at",
-                &[&category_uuid, &updates.name, &updates.code, &updates.brand, &updates.specification, &updates.min_stock, &updates.unit, &updates.notes, &now, &item_uuid],
+                 RETURNING id, category_id, name, code, brand, specification, current_stock, min_stock, unit, notes, active, branch_id, created_at, updated_at, unit_cost",
+                &[&category_uuid, &updates.name, &updates.code, &updates.brand, &updates.specification, &updates.min_stock, &updates.unit, &updates.notes, &now, &item_uuid, &unit_cost],
             )
             .await
             .map_err(|e| e.to_string())?;
@@ -5149,151 +6734,3634 @@ impl PostgresPool {
             branch_id: row.get::<_, uuid::Uuid>(11).to_string(),
             created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(12).to_rfc3339(),
             updated_at: row.get::<_, chrono::DateTime<chrono::Utc>>(13).to_rfc3339(),
+            unit_cost: row.get(14),
             category: None,
         })
     }
 
-    /// Soft delete a room inventory item
-    pub async fn delete_room_inventory_item(&self, id: &str) -> Result<(), String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
-        let item_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
-        let now = chrono::Utc::now();
+    /// Soft delete a room inventory item
+    pub async fn delete_room_inventory_item(&self, id: &str) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let item_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        let now = chrono::Utc::now();
+
+        client
+            .execute(
+                "UPDATE room_inventory_items SET active = false, updated_at = $1 WHERE id = $2",
+                &[&now, &item_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    /// Set room inventory item stock to a counted value, recorded as an 'ajuste' movement
+    pub async fn update_room_inventory_stock(&self, id: &str, new_stock: i32) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let item_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt("SELECT branch_id, current_stock FROM room_inventory_items WHERE id = $1", &[&item_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Item no encontrado")?;
+        drop(client);
+
+        let branch_uuid: uuid::Uuid = row.get(0);
+        if row.get::<_, i32>(1) == new_stock.max(0) {
+            return Ok(());
+        }
+
+        self.create_room_inventory_movement(&RoomInventoryMovementInput {
+            item_id: id.to_string(),
+            quantity: new_stock.max(0),
+            movement_type: "ajuste".to_string(),
+            notes: Some("Ajuste directo de stock".to_string()),
+            user_id: None,
+            branch_id: branch_uuid.to_string(),
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create a room inventory movement and apply it to the item stock
+    pub async fn create_room_inventory_movement(&self, input: &RoomInventoryMovementInput) -> Result<RoomInventoryMovement, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
+        let item_uuid = uuid::Uuid::parse_str(&input.item_id).map_err(|e| e.to_string())?;
+        let user_uuid = match &input.user_id {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let current: i32 = tx
+            .query_opt("SELECT current_stock FROM room_inventory_items WHERE id = $1 FOR UPDATE", &[&item_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Item no encontrado")?
+            .get(0);
+        let new_stock = ledger::room_stock_after(current, &input.movement_type, input.quantity)?;
+
+        let row = tx
+            .query_one(
+                "INSERT INTO room_inventory_movements (item_id, quantity, movement_type, notes, user_id, branch_id)
+                 VALUES ($1, $2, $3::room_inventory_movement_type, $4, $5, $6)
+                 RETURNING id, item_id, quantity, movement_type::text, notes, user_id, branch_id, created_at",
+                &[&item_uuid, &input.quantity, &input.movement_type, &input.notes, &user_uuid, &branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if new_stock != current {
+            tx.execute(
+                "UPDATE room_inventory_items SET current_stock = $1, updated_at = now() WHERE id = $2",
+                &[&new_stock, &item_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(RoomInventoryMovement {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            quantity: row.get(2),
+            movement_type: row.get(3),
+            notes: row.get(4),
+            user_id: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
+            branch_id: row.get::<_, uuid::Uuid>(6).to_string(),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
+            item: None,
+        })
+    }
+
+    /// Get room inventory movements
+    pub async fn get_room_inventory_movements(&self, branch_id: &str, item_id: Option<&str>, limit: i32) -> Result<Vec<RoomInventoryMovement>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let limit_i64 = limit as i64;
+
+        let rows = if let Some(item) = item_id {
+            let item_uuid = uuid::Uuid::parse_str(item).map_err(|e| e.to_string())?;
+            client
+                .query(
+                    "SELECT m.id, m.item_id, m.quantity, m.movement_type::text, m.notes, m.user_id, m.branch_id, m.created_at,
+                            i.id as i_id, i.category_id, i.name, i.code, i.brand, i.specification,
+                            i.current_stock, i.min_stock, i.unit, i.notes as i_notes, i.active,
+                            i.branch_id as i_branch_id, i.created_at as i_created_at, i.updated_at as i_updated_at,
+                            i.unit_cost
+                     FROM room_inventory_movements m
+                     LEFT JOIN room_inventory_items i ON m.item_id = i.id
+                     WHERE m.branch_id = $1 AND m.item_id = $2
+                     ORDER BY m.created_at DESC
+                     LIMIT $3",
+                    &[&branch_uuid, &item_uuid, &limit_i64],
+                )
+                .await
+                .map_err(|e| e.to_string())?
+        } else {
+            client
+                .query(
+                    "SELECT m.id, m.item_id, m.quantity, m.movement_type::text, m.notes, m.user_id, m.branch_id, m.created_at,
+                            i.id as i_id, i.category_id, i.name, i.code, i.brand, i.specification,
+                            i.current_stock, i.min_stock, i.unit, i.notes as i_notes, i.active,
+                            i.branch_id as i_branch_id, i.created_at as i_created_at, i.updated_at as i_updated_at,
+                            i.unit_cost
+                     FROM room_inventory_movements m
+                     LEFT JOIN room_inventory_items i ON m.item_id = i.id
+                     WHERE m.branch_id = $1
+                     ORDER BY m.created_at DESC
+                     LIMIT $2",
+                    &[&branch_uuid, &limit_i64],
+                )
+                .await
+                .map_err(|e| e.to_string())?
+        };
+
+        Ok(rows.iter().map(|row| {
+            let item = row.get::<_, Option<uuid::Uuid>>(8).map(|_| {
+                RoomInventoryItem {
+                    id: row.get::<_, uuid::Uuid>(8).to_string(),
+                    category_id: row.get::<_, uuid::Uuid>(9).to_string(),
+                    name: row.get(10),
+                    code: row.get(11),
+                    brand: row.get(12),
+                    specification: row.get(13),
+                    current_stock: row.get(14),
+                    min_stock: row.get(15),
+                    unit: row.get(16),
+                    notes: row.get(17),
+                    active: row.get(18),
+                    branch_id: row.get::<_, uuid::Uuid>(19).to_string(),
+                    created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(20).to_rfc3339(),
+                    updated_at: row.get::<_, chrono::DateTime<chrono::Utc>>(21).to_rfc3339(),
+                    unit_cost: row.get(22),
+                    category: None,
+                }
+            });
+
+            RoomInventoryMovement {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                quantity: row.get(2),
+                movement_type: row.get(3),
+                notes: row.get(4),
+                user_id: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
+                branch_id: row.get::<_, uuid::Uuid>(6).to_string(),
+                created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
+                item,
+            }
+        }).collect())
+    }
+
+    // ============================================================
+    // STOCK COUNTS (CONTEOS FÍSICOS)
+    // ============================================================
+
+    /// Start a count session. Pharmacy counts get one line per lot with stock
+    /// (or per item when it has none); room counts one line per item.
+    pub async fn start_stock_count(&self, input: &StockCountSessionInput) -> Result<StockCountSession, String> {
+        count::validate_inventory_type(&input.inventory_type)?;
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
+        let started_by = match &input.started_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let category = input.category.as_ref().filter(|c| !c.is_empty());
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let session_uuid: uuid::Uuid = tx
+            .query_one(
+                "INSERT INTO stock_count_sessions (branch_id, inventory_type, category, started_by, notes)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING id",
+                &[&branch_uuid, &input.inventory_type, &category, &started_by, &input.notes],
+            )
+            .await
+            .map_err(|e| {
+                if e.to_string().contains("idx_stock_count_sessions_one_open") {
+                    "Ya hay un conteo abierto para este inventario en la sucursal".to_string()
+                } else {
+                    e.to_string()
+                }
+            })?
+            .get(0);
+
+        let snapshot = if input.inventory_type == "farmacia" {
+            "INSERT INTO stock_count_lines (session_id, item_id, lot_id, expected_quantity)
+             SELECT $1, i.id, l.id, COALESCE(l.quantity, i.current_stock)
+             FROM inventory_items i
+             LEFT JOIN inventory_lots l ON l.item_id = i.id AND l.quantity > 0
+             WHERE i.branch_id = $2 AND i.active = true AND ($3::text IS NULL OR i.category = $3)"
+        } else {
+            "INSERT INTO stock_count_lines (session_id, item_id, lot_id, expected_quantity)
+             SELECT $1, i.id, NULL, i.current_stock
+             FROM room_inventory_items i
+             WHERE i.branch_id = $2 AND i.active = true AND ($3::text IS NULL OR i.category_id::text = $3)"
+        };
+        let lines = tx
+            .execute(snapshot, &[&session_uuid, &branch_uuid, &category])
+            .await
+            .map_err(|e| e.to_string())?;
+        if lines == 0 {
+            return Err("No hay productos para contar con estos filtros".to_string());
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        self.get_stock_count_session(&session_uuid).await
+    }
+
+    /// Count sessions for a branch, most recent first
+    pub async fn get_stock_counts(&self, branch_id: &str) -> Result<Vec<StockCountSession>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM stock_count_sessions s WHERE s.branch_id = $1 ORDER BY s.started_at DESC",
+                    STOCK_COUNT_SESSION_COLUMNS
+                ),
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_stock_count_session_row(row)).collect())
+    }
+
+    async fn get_stock_count_session(&self, session_uuid: &uuid::Uuid) -> Result<StockCountSession, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM stock_count_sessions s WHERE s.id = $1", STOCK_COUNT_SESSION_COLUMNS),
+                &[session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Conteo no encontrado")?;
+        Ok(self.map_stock_count_session_row(&row))
+    }
+
+    /// Session with its lines; open sessions compare against the live stock
+    pub async fn get_stock_count_detail(&self, session_id: &str) -> Result<StockCountDetail, String> {
+        let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+        let session = self.get_stock_count_session(&session_uuid).await?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        let query = if session.inventory_type == "farmacia" {
+            "SELECT c.id, c.item_id, i.name, i.code, c.lot_id, l.lot_number,
+                    c.expected_quantity::float8, COALESCE(l.quantity, i.current_stock)::float8,
+                    c.counted_quantity::float8, c.variance::float8
+             FROM stock_count_lines c
+             JOIN inventory_items i ON c.item_id = i.id
+             LEFT JOIN inventory_lots l ON c.lot_id = l.id
+             WHERE c.session_id = $1
+             ORDER BY i.name, l.expiry_date NULLS LAST"
+        } else {
+            "SELECT c.id, c.item_id, i.name, i.code, c.lot_id, NULL::text,
+                    c.expected_quantity::float8, i.current_stock::float8,
+                    c.counted_quantity::float8, c.variance::float8
+             FROM stock_count_lines c
+             JOIN room_inventory_items i ON c.item_id = i.id
+             WHERE c.session_id = $1
+             ORDER BY i.name"
+        };
+        let rows = client.query(query, &[&session_uuid]).await.map_err(|e| e.to_string())?;
+        let posted = session.status == "publicado";
+
+        let lines = rows.iter().map(|row| {
+            let current: f64 = row.get(7);
+            let counted: Option<f64> = row.get(8);
+            let posted_variance: Option<f64> = row.get(9);
+            StockCountLine {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                item_name: row.get(2),
+                item_code: row.get(3),
+                lot_id: row.get::<_, Option<uuid::Uuid>>(4).map(|u| u.to_string()),
+                lot_number: row.get(5),
+                expected_quantity: row.get(6),
+                current_quantity: current,
+                counted_quantity: counted,
+                variance: if posted { posted_variance } else { count::variance(counted, current) },
+            }
+        }).collect();
+
+        Ok(StockCountDetail { session, lines })
+    }
+
+    /// Record counted quantities; lines can be recounted while the session is open
+    pub async fn record_stock_counts(&self, session_id: &str, entries: &[StockCountEntry]) -> Result<StockCountDetail, String> {
+        let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+        let session = self.get_stock_count_session(&session_uuid).await?;
+        if session.status != "abierto" {
+            return Err("El conteo ya no está abierto".to_string());
+        }
+
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        for entry in entries {
+            count::validate_counted(&session.inventory_type, entry.counted_quantity)?;
+            let item_uuid = uuid::Uuid::parse_str(&entry.item_id).map_err(|e| e.to_string())?;
+            let lot_uuid = match &entry.lot_id {
+                Some(id) if !id.is_empty() => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+                _ => None,
+            };
+
+            let updated = tx
+                .execute(
+                    "UPDATE stock_count_lines SET counted_quantity = $1::float8, counted_at = now()
+                     WHERE session_id = $2 AND item_id = $3 AND lot_id IS NOT DISTINCT FROM $4",
+                    &[&entry.counted_quantity, &session_uuid, &item_uuid, &lot_uuid],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            if updated == 0 {
+                return Err(format!("El producto {} no forma parte de este conteo", entry.item_id));
+            }
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_stock_count_detail(session_id).await
+    }
+
+    /// Post one 'ajuste' movement per counted line that differs from the
+    /// current stock, all in one transaction. Uncounted lines are left as is.
+    pub async fn post_stock_count(&self, session_id: &str) -> Result<StockCountDetail, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let session = tx
+            .query_opt(
+                "SELECT branch_id, inventory_type, status FROM stock_count_sessions WHERE id = $1 FOR UPDATE",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Conteo no encontrado")?;
+        let branch_uuid: uuid::Uuid = session.get(0);
+        let inventory_type: String = session.get(1);
+        let status: String = session.get(2);
+        if status != "abierto" {
+            return Err("El conteo ya no está abierto".to_string());
+        }
+
+        let note = "Ajuste por conteo físico";
+
+        if inventory_type == "farmacia" {
+            let lines = tx
+                .query(
+                    "SELECT c.id, c.item_id, c.lot_id, c.counted_quantity::float8,
+                            COALESCE(l.quantity, i.current_stock)::float8
+                     FROM stock_count_lines c
+                     JOIN inventory_items i ON c.item_id = i.id
+                     LEFT JOIN inventory_lots l ON c.lot_id = l.id
+                     WHERE c.session_id = $1 AND c.counted_quantity IS NOT NULL
+                     FOR UPDATE OF i",
+                    &[&session_uuid],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+
+            for line in &lines {
+                let line_uuid: uuid::Uuid = line.get(0);
+                let item_uuid: uuid::Uuid = line.get(1);
+                let lot_uuid: Option<uuid::Uuid> = line.get(2);
+                let variance = line.get::<_, f64>(3) - line.get::<_, f64>(4);
+
+                if variance != 0.0 {
+                    tx.execute(
+                        "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+                         VALUES ($1, $2, $3, 'ajuste'::inventory_movement_type, $4, 'ajuste', $5, $6)",
+                        &[&branch_uuid, &item_uuid, &lot_uuid, &variance, &session_uuid, &note],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+                tx.execute("UPDATE stock_count_lines SET variance = $1::float8 WHERE id = $2", &[&variance, &line_uuid])
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        } else {
+            let lines = tx
+                .query(
+                    "SELECT c.id, c.item_id, c.counted_quantity::float8, i.current_stock
+                     FROM stock_count_lines c
+                     JOIN room_inventory_items i ON c.item_id = i.id
+                     WHERE c.session_id = $1 AND c.counted_quantity IS NOT NULL
+                     FOR UPDATE OF i",
+                    &[&session_uuid],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+
+            for line in &lines {
+                let line_uuid: uuid::Uuid = line.get(0);
+                let item_uuid: uuid::Uuid = line.get(1);
+                let counted = line.get::<_, f64>(2) as i32;
+                let current: i32 = line.get(3);
+                let variance = (counted - current) as f64;
+
+                if counted != current {
+                    // Room adjustments record the counted stock, not the difference
+                    tx.execute(
+                        "INSERT INTO room_inventory_movements (item_id, quantity, movement_type, notes, branch_id)
+                         VALUES ($1, $2, 'ajuste'::room_inventory_movement_type, $3, $4)",
+                        &[&item_uuid, &counted, &note, &branch_uuid],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                    tx.execute(
+                        "UPDATE room_inventory_items SET current_stock = $1, updated_at = now() WHERE id = $2",
+                        &[&counted, &item_uuid],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+                tx.execute("UPDATE stock_count_lines SET variance = $1::float8 WHERE id = $2", &[&variance, &line_uuid])
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        tx.execute(
+            "UPDATE stock_count_sessions SET status = 'publicado', posted_at = now() WHERE id = $1",
+            &[&session_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_stock_count_detail(session_id).await
+    }
+
+    /// Discard an open count without touching stock
+    pub async fn cancel_stock_count(&self, session_id: &str) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let session_uuid = uuid::Uuid::parse_str(session_id).map_err(|e| e.to_string())?;
+
+        let updated = client
+            .execute(
+                "UPDATE stock_count_sessions SET status = 'cancelado' WHERE id = $1 AND status = 'abierto'",
+                &[&session_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("El conteo ya no está abierto".to_string());
+        }
+        Ok(())
+    }
+
+    /// Helper to map a row selected with STOCK_COUNT_SESSION_COLUMNS
+    fn map_stock_count_session_row(&self, row: &tokio_postgres::Row) -> StockCountSession {
+        StockCountSession {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            branch_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            inventory_type: row.get(2),
+            category: row.get(3),
+            status: row.get(4),
+            started_by: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
+            started_at: row.get::<_, chrono::DateTime<chrono::Utc>>(6).to_rfc3339(),
+            posted_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(7).map(|d| d.to_rfc3339()),
+            notes: row.get(8),
+            line_count: row.get(9),
+            counted_count: row.get(10),
+        }
+    }
+
+    // ============================================================
+    // PURCHASING (COMPRAS)
+    // ============================================================
+
+    /// Create a purchase order as a draft ('borrador')
+    pub async fn create_purchase_order(&self, input: &PurchaseOrderInput) -> Result<PurchaseOrderDetail, String> {
+        if input.lines.is_empty() {
+            return Err("La orden de compra debe tener al menos una línea".to_string());
+        }
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
+        let supplier_uuid = uuid::Uuid::parse_str(&input.supplier_id).map_err(|e| e.to_string())?;
+        let created_by = match &input.created_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let currency = billing::normalize_currency(input.currency.as_deref())?;
+        let expected_date = match input.expected_date.as_deref().filter(|d| !d.is_empty()) {
+            Some(d) => Some(chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| format!("Fecha no válida: {}", d))?),
+            None => None,
+        };
+
+        let mut lines = Vec::with_capacity(input.lines.len());
+        for line in &input.lines {
+            if line.quantity <= 0.0 {
+                return Err("La cantidad de cada línea debe ser mayor a cero".to_string());
+            }
+            if line.unit_cost < Decimal::ZERO {
+                return Err("El costo unitario no puede ser negativo".to_string());
+            }
+            let item_uuid = uuid::Uuid::parse_str(&line.item_id).map_err(|e| e.to_string())?;
+            lines.push((item_uuid, line, purchasing::line_total(line.quantity, line.unit_cost)?));
+        }
+        let total: Decimal = lines.iter().map(|(_, _, t)| *t).sum();
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let sequence: i64 = tx
+            .query_one("SELECT COUNT(*) + 1 FROM purchase_orders WHERE branch_id = $1", &[&branch_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+        let order_number = format!("OC-{:05}", sequence);
+
+        let order_uuid: uuid::Uuid = tx
+            .query_one(
+                "INSERT INTO purchase_orders (order_number, branch_id, supplier_id, currency, expected_date, notes, total_amount, created_by)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 RETURNING id",
+                &[&order_number, &branch_uuid, &supplier_uuid, &currency, &expected_date, &input.notes, &total, &created_by],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+
+        for (item_uuid, line, line_total) in &lines {
+            tx.execute(
+                "INSERT INTO purchase_order_lines (order_id, item_id, quantity_ordered, unit_cost, line_total)
+                 VALUES ($1, $2, $3::float8, $4, $5)",
+                &[&order_uuid, item_uuid, &line.quantity, &line.unit_cost, line_total],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_purchase_order_detail(&order_uuid.to_string()).await
+    }
+
+    /// Purchase orders for a branch, optionally filtered by status
+    pub async fn get_purchase_orders(&self, branch_id: &str, status: Option<&str>) -> Result<Vec<PurchaseOrder>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "{} WHERE o.branch_id = $1 AND ($2::text IS NULL OR o.status = $2) ORDER BY o.created_at DESC",
+                    PURCHASE_ORDER_SELECT
+                ),
+                &[&branch_uuid, &status],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_purchase_order_row(row)).collect())
+    }
+
+    /// Order with its lines and receipts
+    pub async fn get_purchase_order_detail(&self, order_id: &str) -> Result<PurchaseOrderDetail, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let order_uuid = uuid::Uuid::parse_str(order_id).map_err(|e| e.to_string())?;
+
+        let order_row = client
+            .query_opt(&format!("{} WHERE o.id = $1", PURCHASE_ORDER_SELECT), &[&order_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Orden de compra no encontrada")?;
+
+        let line_rows = client
+            .query(
+                "SELECT l.id, l.item_id, i.name, l.quantity_ordered::float8, l.quantity_received::float8,
+                        l.unit_cost, l.line_total
+                 FROM purchase_order_lines l
+                 JOIN inventory_items i ON l.item_id = i.id
+                 WHERE l.order_id = $1
+                 ORDER BY i.name",
+                &[&order_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let receipt_rows = client
+            .query(
+                "SELECT id, order_id, received_by, received_at, supplier_invoice, total_amount, notes
+                 FROM goods_receipts
+                 WHERE order_id = $1
+                 ORDER BY received_at",
+                &[&order_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(PurchaseOrderDetail {
+            order: self.map_purchase_order_row(&order_row),
+            lines: line_rows.iter().map(|row| PurchaseOrderLine {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                item_name: row.get(2),
+                quantity_ordered: row.get(3),
+                quantity_received: row.get(4),
+                unit_cost: row.get(5),
+                line_total: row.get(6),
+            }).collect(),
+            receipts: receipt_rows.iter().map(|row| GoodsReceipt {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                order_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                received_by: row.get::<_, Option<uuid::Uuid>>(2).map(|u| u.to_string()),
+                received_at: row.get::<_, chrono::DateTime<chrono::Utc>>(3).to_rfc3339(),
+                supplier_invoice: row.get(4),
+                total_amount: row.get(5),
+                notes: row.get(6),
+            }).collect(),
+        })
+    }
+
+    /// Send or cancel an order (see purchasing::validate_status_change)
+    pub async fn update_purchase_order_status(&self, order_id: &str, status: &str) -> Result<PurchaseOrder, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let order_uuid = uuid::Uuid::parse_str(order_id).map_err(|e| e.to_string())?;
+
+        let current: String = client
+            .query_opt("SELECT status FROM purchase_orders WHERE id = $1", &[&order_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Orden de compra no encontrada")?
+            .get(0);
+        purchasing::validate_status_change(&current, status)?;
+
+        client
+            .execute(
+                "UPDATE purchase_orders SET status = $1, updated_at = now() WHERE id = $2 AND status = $3",
+                &[&status, &order_uuid, &current],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_one(&format!("{} WHERE o.id = $1", PURCHASE_ORDER_SELECT), &[&order_uuid])
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(self.map_purchase_order_row(&row))
+    }
+
+    /// Receive goods against an order in one transaction: lots, 'entrada'
+    /// movements at the received cost, latest cost on the item, order status
+    /// and a supplier payable for the receipt total.
+    pub async fn receive_purchase_order(&self, input: &GoodsReceiptInput) -> Result<PurchaseOrderDetail, String> {
+        if input.lines.is_empty() {
+            return Err("La recepción debe tener al menos una línea".to_string());
+        }
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let order_uuid = uuid::Uuid::parse_str(&input.order_id).map_err(|e| e.to_string())?;
+        let received_by = match &input.received_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let due_date = match input.due_date.as_deref().filter(|d| !d.is_empty()) {
+            Some(d) => Some(chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| format!("Fecha no válida: {}", d))?),
+            None => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let order = tx
+            .query_opt(
+                "SELECT branch_id, supplier_id, status, currency, order_number FROM purchase_orders WHERE id = $1 FOR UPDATE",
+                &[&order_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Orden de compra no encontrada")?;
+        let branch_uuid: uuid::Uuid = order.get(0);
+        let supplier_uuid: uuid::Uuid = order.get(1);
+        let status: String = order.get(2);
+        let currency: String = order.get(3);
+        let order_number: String = order.get(4);
+        if !purchasing::OPEN_ORDER_STATUSES.contains(&status.as_str()) {
+            return Err(format!("La orden {} no está pendiente de recepción (estado: {})", order_number, status));
+        }
+
+        let mut order_lines: std::collections::HashMap<uuid::Uuid, (uuid::Uuid, f64, f64, Decimal)> = tx
+            .query(
+                "SELECT id, item_id, quantity_ordered::float8, quantity_received::float8, unit_cost
+                 FROM purchase_order_lines WHERE order_id = $1",
+                &[&order_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|row| (row.get(0), (row.get(1), row.get(2), row.get(3), row.get(4))))
+            .collect();
+
+        let receipt_uuid: uuid::Uuid = tx
+            .query_one(
+                "INSERT INTO goods_receipts (order_id, branch_id, received_by, supplier_invoice, notes)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING id",
+                &[&order_uuid, &branch_uuid, &received_by, &input.supplier_invoice, &input.notes],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+
+        let movement_note = format!("Recepción de orden {}", order_number);
+        let mut receipt_total = Decimal::ZERO;
+
+        for line in &input.lines {
+            let line_uuid = uuid::Uuid::parse_str(&line.order_line_id).map_err(|e| e.to_string())?;
+            let (item_uuid, ordered, received, order_cost) = order_lines
+                .get_mut(&line_uuid)
+                .ok_or("La línea no pertenece a esta orden de compra")?;
+            purchasing::validate_receipt(*ordered, *received, line.quantity)?;
+            *received += line.quantity;
+
+            let unit_cost = line.unit_cost.unwrap_or(*order_cost);
+            if unit_cost < Decimal::ZERO {
+                return Err("El costo unitario no puede ser negativo".to_string());
+            }
+            receipt_total += purchasing::line_total(line.quantity, unit_cost)?;
+
+            let lot_uuid = match line.lot_number.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+                Some(lot_number) => {
+                    let expiry = match line.expiry_date.as_deref().filter(|d| !d.is_empty()) {
+                        Some(d) => Some(chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| format!("Fecha no válida: {}", d))?),
+                        None => None,
+                    };
+                    let existing = tx
+                        .query_opt(
+                            "SELECT id FROM inventory_lots WHERE item_id = $1 AND lot_number = $2",
+                            &[&*item_uuid, &lot_number],
+                        )
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    let lot_uuid: uuid::Uuid = match existing {
+                        Some(row) => {
+                            let id: uuid::Uuid = row.get(0);
+                            tx.execute(
+                                "UPDATE inventory_lots SET expiry_date = COALESCE($1, expiry_date), cost_price = $2 WHERE id = $3",
+                                &[&expiry, &unit_cost, &id],
+                            )
+                            .await
+                            .map_err(|e| e.to_string())?;
+                            id
+                        }
+                        // Quantity starts at zero: the 'entrada' movement adds the received stock
+                        None => tx
+                            .query_one(
+                                "INSERT INTO inventory_lots (item_id, lot_number, quantity, expiry_date, cost_price)
+                                 VALUES ($1, $2, 0, $3, $4)
+                                 RETURNING id",
+                                &[&*item_uuid, &lot_number, &expiry, &unit_cost],
+                            )
+                            .await
+                            .map_err(|e| e.to_string())?
+                            .get(0),
+                    };
+                    Some(lot_uuid)
+                }
+                None => None,
+            };
+
+            tx.execute(
+                "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+                 VALUES ($1, $2, $3, 'entrada'::inventory_movement_type, $4, 'compra', $5, $6)",
+                &[&branch_uuid, &*item_uuid, &lot_uuid, &line.quantity, &receipt_uuid, &movement_note],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.execute(
+                "INSERT INTO goods_receipt_lines (receipt_id, order_line_id, item_id, lot_id, quantity, unit_cost)
+                 VALUES ($1, $2, $3, $4, $5::float8, $6)",
+                &[&receipt_uuid, &line_uuid, &*item_uuid, &lot_uuid, &line.quantity, &unit_cost],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.execute(
+                "UPDATE purchase_order_lines SET quantity_received = quantity_received + $1::float8 WHERE id = $2",
+                &[&line.quantity, &line_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.execute(
+                "UPDATE inventory_items SET cost_price = $1, updated_at = now() WHERE id = $2",
+                &[&unit_cost, &*item_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        let progress: Vec<(f64, f64)> = order_lines.values().map(|(_, ordered, received, _)| (*ordered, *received)).collect();
+        tx.execute(
+            "UPDATE purchase_orders SET status = $1, updated_at = now() WHERE id = $2",
+            &[&purchasing::status_after_receipt(&progress), &order_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE goods_receipts SET total_amount = $1 WHERE id = $2",
+            &[&receipt_total, &receipt_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        // Bonus goods at zero cost do not generate a payable
+        if receipt_total > Decimal::ZERO {
+            tx.execute(
+                "INSERT INTO supplier_payables (supplier_id, branch_id, receipt_id, currency, amount, balance, due_date)
+                 VALUES ($1, $2, $3, $4, $5, $5, $6)",
+                &[&supplier_uuid, &branch_uuid, &receipt_uuid, &currency, &receipt_total, &due_date],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_purchase_order_detail(&input.order_id).await
+    }
+
+    /// Payables for a branch, oldest due first
+    pub async fn get_supplier_payables(&self, branch_id: &str, supplier_id: Option<&str>, include_paid: bool) -> Result<Vec<SupplierPayable>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let supplier_uuid = match supplier_id {
+            Some(id) => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let rows = client
+            .query(
+                &format!(
+                    "{} WHERE p.branch_id = $1
+                       AND ($2::uuid IS NULL OR p.supplier_id = $2)
+                       AND ($3 OR p.status <> 'pagada')
+                     ORDER BY p.due_date ASC NULLS LAST, p.created_at",
+                    SUPPLIER_PAYABLE_SELECT
+                ),
+                &[&branch_uuid, &supplier_uuid, &include_paid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_supplier_payable_row(row)).collect())
+    }
+
+    /// Register a payment to a supplier against one payable
+    pub async fn pay_supplier_payable(&self, input: &SupplierPaymentInput) -> Result<SupplierPayable, String> {
+        if input.amount <= Decimal::ZERO {
+            return Err("El monto del pago debe ser mayor a cero".to_string());
+        }
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let payable_uuid = uuid::Uuid::parse_str(&input.payable_id).map_err(|e| e.to_string())?;
+        let paid_by = match &input.paid_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let payable = tx
+            .query_opt("SELECT amount, balance FROM supplier_payables WHERE id = $1 FOR UPDATE", &[&payable_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Cuenta por pagar no encontrada")?;
+        let amount: Decimal = payable.get(0);
+        let balance: Decimal = payable.get(1);
+        if input.amount > balance {
+            return Err(format!("El pago (Q{}) excede el saldo pendiente (Q{})", input.amount, balance));
+        }
+        let new_balance = balance - input.amount;
+
+        tx.execute(
+            "INSERT INTO supplier_payments (payable_id, amount, payment_method, reference, notes, paid_by)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&payable_uuid, &input.amount, &input.payment_method, &input.reference, &input.notes, &paid_by],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE supplier_payables SET balance = $1, status = $2 WHERE id = $3",
+            &[&new_balance, &purchasing::payable_status(amount, new_balance), &payable_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let row = tx
+            .query_one(&format!("{} WHERE p.id = $1", SUPPLIER_PAYABLE_SELECT), &[&payable_uuid])
+            .await
+            .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        Ok(self.map_supplier_payable_row(&row))
+    }
+
+    /// Reorder suggestions per supplier, optionally creating draft orders
+    pub async fn get_reorder_suggestions(
+        &self,
+        branch_id: &str,
+        window_days: i32,
+        coverage_days: i32,
+        create_drafts: bool,
+        created_by: Option<&str>,
+    ) -> Result<Vec<ReorderSupplierGroup>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let window_days = window_days.max(1);
+        let coverage_days = coverage_days.max(0);
+
+        // Demand counts sales and courtesies; expiry write-offs are not consumption
+        let rows = client
+            .query(
+                "SELECT i.id, i.name, i.category, i.supplier_id, s.name, i.current_stock::float8, i.reorder_level,
+                        i.cost_price,
+                        COALESCE((SELECT SUM(ABS(m.quantity)) FROM inventory_movements m
+                                  WHERE m.item_id = i.id
+                                    AND m.movement_type::text IN ('salida', 'cortesia')
+                                    AND m.created_at >= now() - make_interval(days => $2)), 0)::float8,
+                        COALESCE((SELECT SUM(l.quantity_ordered - l.quantity_received) FROM purchase_order_lines l
+                                  JOIN purchase_orders o ON l.order_id = o.id
+                                  WHERE l.item_id = i.id AND o.status IN ('borrador', 'enviada', 'parcial')), 0)::float8
+                 FROM inventory_items i
+                 LEFT JOIN suppliers s ON i.supplier_id = s.id
+                 WHERE i.branch_id = $1 AND i.active = true
+                   AND i.reorder_level IS NOT NULL AND i.current_stock <= i.reorder_level
+                 ORDER BY s.name NULLS LAST, i.name",
+                &[&branch_uuid, &window_days],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut groups: Vec<ReorderSupplierGroup> = Vec::new();
+        for row in &rows {
+            let current_stock: f64 = row.get(5);
+            let reorder_level: i32 = row.get(6);
+            let unit_cost: Option<Decimal> = row.get(7);
+            let on_order: f64 = row.get(9);
+            let consumed: f64 = row.get(8);
+            if !reorder::needs_reorder(current_stock, reorder_level as f64) {
+                continue;
+            }
+
+            let plan = reorder::plan(&reorder::ReorderInput {
+                current_stock,
+                reorder_level: reorder_level as f64,
+                on_order,
+                consumed,
+                window_days: window_days as u32,
+                coverage_days: coverage_days as u32,
+            });
+            let estimated_cost = purchasing::line_total(plan.suggested_quantity, unit_cost.unwrap_or(Decimal::ZERO))?;
+
+            let supplier_id = row.get::<_, Option<uuid::Uuid>>(3).map(|u| u.to_string());
+            let suggestion = ReorderSuggestion {
+                item_id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_name: row.get(1),
+                category: row.get(2),
+                current_stock,
+                reorder_level,
+                on_order,
+                consumed,
+                avg_daily_consumption: plan.avg_daily_consumption,
+                days_of_cover: plan.days_of_cover,
+                suggested_quantity: plan.suggested_quantity,
+                unit_cost,
+                estimated_cost,
+            };
+
+            // Rows are ordered by supplier, so each group is contiguous
+            match groups.last_mut() {
+                Some(group) if group.supplier_id == supplier_id => {
+                    group.estimated_total += estimated_cost;
+                    group.items.push(suggestion);
+                }
+                _ => groups.push(ReorderSupplierGroup {
+                    supplier_id,
+                    supplier_name: row.get(4),
+                    items: vec![suggestion],
+                    estimated_total: estimated_cost,
+                    purchase_order_id: None,
+                }),
+            }
+        }
+        drop(client);
+
+        if create_drafts {
+            for group in groups.iter_mut() {
+                let Some(supplier_id) = group.supplier_id.clone() else {
+                    continue;
+                };
+                let lines: Vec<PurchaseOrderLineInput> = group
+                    .items
+                    .iter()
+                    .filter(|item| item.suggested_quantity > 0.0)
+                    .map(|item| PurchaseOrderLineInput {
+                        item_id: item.item_id.clone(),
+                        quantity: item.suggested_quantity,
+                        unit_cost: item.unit_cost.unwrap_or(Decimal::ZERO),
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    continue;
+                }
+
+                let order = self
+                    .create_purchase_order(&PurchaseOrderInput {
+                        branch_id: branch_id.to_string(),
+                        supplier_id,
+                        currency: None,
+                        expected_date: None,
+                        notes: Some("Generada desde sugerencias de reorden".to_string()),
+                        created_by: created_by.map(str::to_string),
+                        lines,
+                    })
+                    .await?;
+                group.purchase_order_id = Some(order.order.id);
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Helper to map a row selected with PURCHASE_ORDER_SELECT
+    fn map_purchase_order_row(&self, row: &tokio_postgres::Row) -> PurchaseOrder {
+        PurchaseOrder {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            order_number: row.get(1),
+            branch_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            supplier_id: row.get::<_, uuid::Uuid>(3).to_string(),
+            supplier_name: row.get(4),
+            status: row.get(5),
+            currency: row.get(6),
+            expected_date: row.get::<_, Option<chrono::NaiveDate>>(7).map(|d| d.to_string()),
+            notes: row.get(8),
+            total_amount: row.get(9),
+            created_by: row.get::<_, Option<uuid::Uuid>>(10).map(|u| u.to_string()),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(11).to_rfc3339(),
+        }
+    }
+
+    /// Helper to map a row selected with SUPPLIER_PAYABLE_SELECT
+    fn map_supplier_payable_row(&self, row: &tokio_postgres::Row) -> SupplierPayable {
+        SupplierPayable {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            supplier_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            supplier_name: row.get(2),
+            branch_id: row.get::<_, uuid::Uuid>(3).to_string(),
+            receipt_id: row.get::<_, uuid::Uuid>(4).to_string(),
+            order_number: row.get(5),
+            supplier_invoice: row.get(6),
+            currency: row.get(7),
+            amount: row.get(8),
+            balance: row.get(9),
+            due_date: row.get::<_, Option<chrono::NaiveDate>>(10).map(|d| d.to_string()),
+            status: row.get(11),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(12).to_rfc3339(),
+        }
+    }
+
+    // ============================================================
+    // STOCK TRANSFERS (TRASLADOS)
+    // ============================================================
+
+    /// Dispatch a transfer: one 'salida' per lot at the origin branch, all in
+    /// one transaction. The document stays 'en_transito' until received.
+    pub async fn create_stock_transfer(&self, input: &StockTransferInput) -> Result<StockTransferDetail, String> {
+        let quantities: Vec<f64> = input.lines.iter().map(|l| l.quantity).collect();
+        transfer::validate_transfer(&input.from_branch_id, &input.to_branch_id, &quantities)?;
+
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let from_uuid = uuid::Uuid::parse_str(&input.from_branch_id).map_err(|e| e.to_string())?;
+        let to_uuid = uuid::Uuid::parse_str(&input.to_branch_id).map_err(|e| e.to_string())?;
+        let created_by = match &input.created_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let sequence: i64 = tx
+            .query_one("SELECT COUNT(*) + 1 FROM stock_transfers WHERE from_branch_id = $1", &[&from_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+        let transfer_number = transfer::transfer_number(sequence);
+
+        let transfer_uuid: uuid::Uuid = tx
+            .query_one(
+                "INSERT INTO stock_transfers (transfer_number, from_branch_id, to_branch_id, notes, created_by)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING id",
+                &[&transfer_number, &from_uuid, &to_uuid, &input.notes, &created_by],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+
+        let note = format!("Traslado {} en tránsito", transfer_number);
+
+        for line in &input.lines {
+            let item_uuid = uuid::Uuid::parse_str(&line.item_id).map_err(|e| e.to_string())?;
+            let item_branch: uuid::Uuid = tx
+                .query_opt("SELECT branch_id FROM inventory_items WHERE id = $1", &[&item_uuid])
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("Producto no encontrado")?
+                .get(0);
+            if item_branch != from_uuid {
+                return Err("El producto no pertenece a la sucursal de origen".to_string());
+            }
+
+            let allocations = match line.lot_id.as_deref().filter(|id| !id.is_empty()) {
+                Some(lot_id) => {
+                    let lot_uuid = uuid::Uuid::parse_str(lot_id).map_err(|e| e.to_string())?;
+                    let lot = tx
+                        .query_opt(
+                            "SELECT lot_number, expiry_date, quantity::float8 FROM inventory_lots WHERE id = $1 AND item_id = $2 FOR UPDATE",
+                            &[&lot_uuid, &item_uuid],
+                        )
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .ok_or("Lote no encontrado para este producto")?;
+                    let lot_number: String = lot.get(0);
+                    lots::ensure_usable(&lot_number, lot.get(1), "salida", timezone::today())?;
+                    if lot.get::<_, f64>(2) < line.quantity {
+                        return Err(format!("El lote {} no tiene suficiente stock", lot_number));
+                    }
+                    vec![(Some(lot_uuid), line.quantity)]
+                }
+                None => self.allocate_item_lots(&tx, &item_uuid, line.quantity).await?,
+            };
+
+            for (lot_uuid, quantity) in allocations {
+                tx.execute(
+                    "INSERT INTO stock_transfer_lines (transfer_id, item_id, lot_id, lot_number, expiry_date, cost_price, quantity)
+                     SELECT $1, $2, l.id, l.lot_number, l.expiry_date, l.cost_price, $4::float8
+                     FROM (SELECT $3::uuid AS id) AS pick
+                     LEFT JOIN inventory_lots l ON l.id = pick.id",
+                    &[&transfer_uuid, &item_uuid, &lot_uuid, &quantity],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+
+                tx.execute(
+                    "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+                     VALUES ($1, $2, $3, 'salida'::inventory_movement_type, $4, $5, $6, $7)",
+                    &[&from_uuid, &item_uuid, &lot_uuid, &quantity, &transfer::TRANSFER_REFERENCE, &transfer_uuid, &note],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_stock_transfer_detail(&transfer_uuid.to_string()).await
+    }
+
+    /// Transfers sent from or to a branch, most recent first
+    pub async fn get_stock_transfers(&self, branch_id: &str, status: Option<&str>) -> Result<Vec<StockTransfer>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "{} WHERE (t.from_branch_id = $1 OR t.to_branch_id = $1) AND ($2::text IS NULL OR t.status = $2)
+                     ORDER BY t.created_at DESC",
+                    STOCK_TRANSFER_SELECT
+                ),
+                &[&branch_uuid, &status],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_stock_transfer_row(row)).collect())
+    }
+
+    pub async fn get_stock_transfer_detail(&self, transfer_id: &str) -> Result<StockTransferDetail, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let transfer_uuid = uuid::Uuid::parse_str(transfer_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(&format!("{} WHERE t.id = $1", STOCK_TRANSFER_SELECT), &[&transfer_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Traslado no encontrado")?;
+
+        let lines = client
+            .query(
+                "SELECT l.id, l.item_id, i.name, l.lot_id, l.lot_number, l.expiry_date, l.quantity::float8,
+                        l.dest_item_id, l.dest_lot_id
+                 FROM stock_transfer_lines l
+                 JOIN inventory_items i ON l.item_id = i.id
+                 WHERE l.transfer_id = $1
+                 ORDER BY i.name, l.expiry_date NULLS LAST",
+                &[&transfer_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(StockTransferDetail {
+            transfer: self.map_stock_transfer_row(&row),
+            lines: lines.iter().map(|row| StockTransferLine {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                item_name: row.get(2),
+                lot_id: row.get::<_, Option<uuid::Uuid>>(3).map(|u| u.to_string()),
+                lot_number: row.get(4),
+                expiry_date: row.get::<_, Option<chrono::NaiveDate>>(5).map(|d| d.to_string()),
+                quantity: row.get(6),
+                dest_item_id: row.get::<_, Option<uuid::Uuid>>(7).map(|u| u.to_string()),
+                dest_lot_id: row.get::<_, Option<uuid::Uuid>>(8).map(|u| u.to_string()),
+            }).collect(),
+        })
+    }
+
+    /// Receive a transfer: enter each line into the matching item at the
+    /// destination branch (same code, or same name when there is no code),
+    /// creating the item and lot there if they do not exist yet.
+    pub async fn receive_stock_transfer(&self, transfer_id: &str, received_by: Option<&str>) -> Result<StockTransferDetail, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let transfer_uuid = uuid::Uuid::parse_str(transfer_id).map_err(|e| e.to_string())?;
+        let received_by = match received_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let header = tx
+            .query_opt(
+                "SELECT to_branch_id, status, transfer_number FROM stock_transfers WHERE id = $1 FOR UPDATE",
+                &[&transfer_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Traslado no encontrado")?;
+        let to_uuid: uuid::Uuid = header.get(0);
+        let status: String = header.get(1);
+        let transfer_number: String = header.get(2);
+        transfer::ensure_in_transit(&status)?;
+
+        let lines = tx
+            .query(
+                "SELECT id, item_id, lot_number, expiry_date, cost_price, quantity::float8
+                 FROM stock_transfer_lines WHERE transfer_id = $1",
+                &[&transfer_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let note = format!("Recepción de traslado {}", transfer_number);
+        let mut dest_items: std::collections::HashMap<uuid::Uuid, uuid::Uuid> = std::collections::HashMap::new();
+
+        for line in &lines {
+            let line_uuid: uuid::Uuid = line.get(0);
+            let item_uuid: uuid::Uuid = line.get(1);
+            let lot_number: Option<String> = line.get(2);
+            let expiry: Option<chrono::NaiveDate> = line.get(3);
+            let cost_price: Option<Decimal> = line.get(4);
+            let quantity: f64 = line.get(5);
+
+            let dest_item = match dest_items.get(&item_uuid) {
+                Some(id) => *id,
+                None => {
+                    let existing = tx
+                        .query_opt(
+                            "SELECT d.id
+                             FROM inventory_items o
+                             JOIN inventory_items d ON d.branch_id = $2 AND d.active = true
+                              AND ((o.code IS NOT NULL AND d.code = o.code) OR (o.code IS NULL AND d.name = o.name))
+                             WHERE o.id = $1
+                             ORDER BY d.created_at
+                             LIMIT 1",
+                            &[&item_uuid, &to_uuid],
+                        )
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    let id: uuid::Uuid = match existing {
+                        Some(row) => row.get(0),
+                        None => tx
+                            .query_one(
+                                "INSERT INTO inventory_items (name, code, category, cost_price, sell_price, supplier_id,
+                                                             branch_id, active, current_stock, reorder_level, created_at, updated_at,
+                                                             currency, tax_rate, tax_exempt)
+                                 SELECT name, code, category, cost_price, sell_price, supplier_id,
+                                        $2, true, 0, reorder_level, now(), now(),
+                                        currency, tax_rate, tax_exempt
+                                 FROM inventory_items WHERE id = $1
+                                 RETURNING id",
+                                &[&item_uuid, &to_uuid],
+                            )
+                            .await
+                            .map_err(|e| e.to_string())?
+                            .get(0),
+                    };
+                    dest_items.insert(item_uuid, id);
+                    id
+                }
+            };
+
+            let dest_lot: Option<uuid::Uuid> = match &lot_number {
+                Some(lot_number) => {
+                    let existing = tx
+                        .query_opt(
+                            "SELECT id FROM inventory_lots WHERE item_id = $1 AND lot_number = $2",
+                            &[&dest_item, lot_number],
+                        )
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    Some(match existing {
+                        Some(row) => row.get(0),
+                        None => tx
+                            .query_one(
+                                "INSERT INTO inventory_lots (item_id, lot_number, quantity, expiry_date, cost_price)
+                                 VALUES ($1, $2, 0, $3, $4)
+                                 RETURNING id",
+                                &[&dest_item, lot_number, &expiry, &cost_price],
+                            )
+                            .await
+                            .map_err(|e| e.to_string())?
+                            .get(0),
+                    })
+                }
+                None => None,
+            };
+
+            tx.execute(
+                "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+                 VALUES ($1, $2, $3, 'entrada'::inventory_movement_type, $4, $5, $6, $7)",
+                &[&to_uuid, &dest_item, &dest_lot, &quantity, &transfer::TRANSFER_REFERENCE, &transfer_uuid, &note],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.execute(
+                "UPDATE stock_transfer_lines SET dest_item_id = $1, dest_lot_id = $2 WHERE id = $3",
+                &[&dest_item, &dest_lot, &line_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.execute(
+            "UPDATE stock_transfers SET status = 'recibida', received_by = $1, received_at = now() WHERE id = $2",
+            &[&received_by, &transfer_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_stock_transfer_detail(transfer_id).await
+    }
+
+    /// Cancel a transfer in transit, entering the stock back at the origin lots
+    pub async fn cancel_stock_transfer(&self, transfer_id: &str, reason: &str) -> Result<StockTransferDetail, String> {
+        if reason.trim().is_empty() {
+            return Err("Debe indicar el motivo de la cancelación".to_string());
+        }
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let transfer_uuid = uuid::Uuid::parse_str(transfer_id).map_err(|e| e.to_string())?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let header = tx
+            .query_opt(
+                "SELECT from_branch_id, status, transfer_number FROM stock_transfers WHERE id = $1 FOR UPDATE",
+                &[&transfer_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Traslado no encontrado")?;
+        let from_uuid: uuid::Uuid = header.get(0);
+        let status: String = header.get(1);
+        let transfer_number: String = header.get(2);
+        transfer::ensure_in_transit(&status)?;
+
+        let note = format!("Cancelación de traslado {}", transfer_number);
+        tx.execute(
+            "INSERT INTO inventory_movements (branch_id, item_id, lot_id, movement_type, quantity, reference_type, reference_id, notes)
+             SELECT $1, item_id, lot_id, 'entrada'::inventory_movement_type, quantity, $2, $3, $4
+             FROM stock_transfer_lines WHERE transfer_id = $3",
+            &[&from_uuid, &transfer::TRANSFER_REFERENCE, &transfer_uuid, &note],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE stock_transfers SET status = 'cancelada', cancelled_at = now(), cancellation_reason = $1 WHERE id = $2",
+            &[&reason.trim(), &transfer_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_stock_transfer_detail(transfer_id).await
+    }
+
+    /// Helper to map a row selected with STOCK_TRANSFER_SELECT
+    fn map_stock_transfer_row(&self, row: &tokio_postgres::Row) -> StockTransfer {
+        StockTransfer {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            transfer_number: row.get(1),
+            from_branch_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            from_branch_name: row.get(3),
+            to_branch_id: row.get::<_, uuid::Uuid>(4).to_string(),
+            to_branch_name: row.get(5),
+            status: row.get(6),
+            notes: row.get(7),
+            created_by: row.get::<_, Option<uuid::Uuid>>(8).map(|u| u.to_string()),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(9).to_rfc3339(),
+            received_by: row.get::<_, Option<uuid::Uuid>>(10).map(|u| u.to_string()),
+            received_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(11).map(|d| d.to_rfc3339()),
+            cancelled_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(12).map(|d| d.to_rfc3339()),
+            cancellation_reason: row.get(13),
+        }
+    }
+
+    // ============================================================
+    // SURGERY KITS (MATERIALES POR TIPO DE CIRUGÍA)
+    // ============================================================
+
+    /// Kit lines of a surgery type, optionally limited to one branch's room items
+    pub async fn get_surgery_type_materials(&self, surgery_type_id: &str, branch_id: Option<&str>) -> Result<Vec<SurgeryTypeMaterial>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let type_uuid = uuid::Uuid::parse_str(surgery_type_id).map_err(|e| e.to_string())?;
+        let branch_uuid = match branch_id {
+            Some(b) if !b.is_empty() => Some(uuid::Uuid::parse_str(b).map_err(|e| e.to_string())?),
+            _ => None,
+        };
+
+        let rows = client
+            .query(
+                "SELECT m.id, m.surgery_type_id, m.room_item_id, i.name, i.unit, i.branch_id, m.quantity, m.per_eye,
+                        i.unit_cost, i.current_stock
+                 FROM surgery_type_materials m
+                 JOIN room_inventory_items i ON m.room_item_id = i.id
+                 WHERE m.surgery_type_id = $1 AND ($2::uuid IS NULL OR i.branch_id = $2)
+                 ORDER BY i.name",
+                &[&type_uuid, &branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| SurgeryTypeMaterial {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            surgery_type_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            room_item_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            item_name: row.get(3),
+            unit: row.get(4),
+            branch_id: row.get::<_, uuid::Uuid>(5).to_string(),
+            quantity: row.get(6),
+            per_eye: row.get(7),
+            unit_cost: row.get(8),
+            current_stock: row.get(9),
+        }).collect())
+    }
+
+    /// Replace a surgery type's kit for one branch
+    pub async fn set_surgery_type_materials(
+        &self,
+        surgery_type_id: &str,
+        branch_id: &str,
+        materials: &[SurgeryTypeMaterialInput],
+    ) -> Result<Vec<SurgeryTypeMaterial>, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let type_uuid = uuid::Uuid::parse_str(surgery_type_id).map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "DELETE FROM surgery_type_materials m
+             USING room_inventory_items i
+             WHERE m.room_item_id = i.id AND m.surgery_type_id = $1 AND i.branch_id = $2",
+            &[&type_uuid, &branch_uuid],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        for material in materials {
+            kits::validate_kit_line(material.quantity)?;
+            let item_uuid = uuid::Uuid::parse_str(&material.room_item_id).map_err(|e| e.to_string())?;
+            let item_branch: uuid::Uuid = tx
+                .query_opt("SELECT branch_id FROM room_inventory_items WHERE id = $1", &[&item_uuid])
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("Item no encontrado")?
+                .get(0);
+            if item_branch != branch_uuid {
+                return Err("El material no pertenece al inventario de sala de esta sucursal".to_string());
+            }
+
+            tx.execute(
+                "INSERT INTO surgery_type_materials (surgery_type_id, room_item_id, quantity, per_eye)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (surgery_type_id, room_item_id)
+                 DO UPDATE SET quantity = EXCLUDED.quantity, per_eye = EXCLUDED.per_eye",
+                &[&type_uuid, &item_uuid, &material.quantity, &material.per_eye],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        drop(client);
+        self.get_surgery_type_materials(surgery_type_id, Some(branch_id)).await
+    }
+
+    /// Consume the kit of a completed surgery from its branch's room inventory.
+    /// Lines already recorded for the surgery are skipped, so completing it
+    /// again after a status change does not consume twice.
+    async fn consume_surgery_kit(&self, tx: &deadpool_postgres::Transaction<'_>, surgery_uuid: &uuid::Uuid) -> Result<(), String> {
+        let surgery = tx
+            .query_one(
+                "SELECT s.surgery_type::text, s.eye::text, a.branch_id
+                 FROM surgeries s
+                 LEFT JOIN appointments a ON s.appointment_id = a.id
+                 WHERE s.id = $1",
+                &[surgery_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let surgery_type: String = surgery.get(0);
+        let eye: Option<String> = surgery.get(1);
+        let branch_uuid: uuid::Uuid = match surgery.get::<_, Option<uuid::Uuid>>(2) {
+            Some(b) => b,
+            None => {
+                log::warn!("consume_surgery_kit: surgery {} has no appointment branch, kit not consumed", surgery_uuid);
+                return Ok(());
+            }
+        };
+
+        let materials = tx
+            .query(
+                "SELECT i.id, i.current_stock, i.unit_cost, m.quantity, m.per_eye
+                 FROM surgery_type_materials m
+                 JOIN surgery_types t ON m.surgery_type_id = t.id
+                 JOIN room_inventory_items i ON m.room_item_id = i.id
+                 WHERE t.name = $1 AND t.deleted_at IS NULL AND i.branch_id = $2 AND i.active = true
+                   AND NOT EXISTS (
+                       SELECT 1 FROM surgery_material_usage u
+                       WHERE u.surgery_id = $3 AND u.room_item_id = i.id
+                   )
+                 FOR UPDATE OF i",
+                &[&surgery_type, &branch_uuid, surgery_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let note = format!("Cirugía: {}", surgery_type);
+        for material in &materials {
+            let item_uuid: uuid::Uuid = material.get(0);
+            let current: i32 = material.get(1);
+            let unit_cost: Decimal = material.get(2);
+            let quantity = kits::kit_quantity(material.get(3), material.get(4), eye.as_deref());
+            let new_stock = ledger::room_stock_after(current, "uso", quantity)?;
+
+            let movement_uuid: uuid::Uuid = tx
+                .query_one(
+                    "INSERT INTO room_inventory_movements (item_id, quantity, movement_type, notes, branch_id)
+                     VALUES ($1, $2, 'uso'::room_inventory_movement_type, $3, $4)
+                     RETURNING id",
+                    &[&item_uuid, &quantity, &note, &branch_uuid],
+                )
+                .await
+                .map_err(|e| e.to_string())?
+                .get(0);
+
+            tx.execute(
+                "UPDATE room_inventory_items SET current_stock = $1, updated_at = now() WHERE id = $2",
+                &[&new_stock, &item_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.execute(
+                "INSERT INTO surgery_material_usage (surgery_id, room_item_id, movement_id, quantity, unit_cost, total_cost)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+                &[surgery_uuid, &item_uuid, &movement_uuid, &quantity, &unit_cost, &kits::usage_cost(quantity, unit_cost)],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Materials consumed by one surgery, with their cost
+    pub async fn get_surgery_material_usage(&self, surgery_id: &str) -> Result<Vec<SurgeryMaterialUsage>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let surgery_uuid = uuid::Uuid::parse_str(surgery_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT u.id, u.surgery_id, u.room_item_id, i.name, u.quantity, u.unit_cost, u.total_cost, u.created_at
+                 FROM surgery_material_usage u
+                 JOIN room_inventory_items i ON u.room_item_id = i.id
+                 WHERE u.surgery_id = $1
+                 ORDER BY i.name",
+                &[&surgery_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| SurgeryMaterialUsage {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            surgery_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            room_item_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            item_name: row.get(3),
+            quantity: row.get(4),
+            unit_cost: row.get(5),
+            total_cost: row.get(6),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
+        }).collect())
+    }
+
+    /// Material cost per completed surgery of a branch in a date range
+    pub async fn get_surgery_cost_report(&self, branch_id: &str, start_date: &str, end_date: &str) -> Result<Vec<SurgeryCostLine>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let start = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d").map_err(|e| e.to_string())?;
+        let end = chrono::NaiveDate::parse_from_str(end_date, "%Y-%m-%d").map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT s.id, s.date, s.surgery_type::text, s.eye::text, s.patient_id,
+                        TRIM(COALESCE(p.first_name, '') || ' ' || COALESCE(p.last_name, '')),
+                        pr.full_name,
+                        COUNT(u.id), COALESCE(SUM(u.total_cost), 0)
+                 FROM surgeries s
+                 JOIN appointments a ON s.appointment_id = a.id
+                 LEFT JOIN patients p ON s.patient_id = p.id
+                 LEFT JOIN profiles pr ON s.surgeon_id = pr.user_id
+                 LEFT JOIN surgery_material_usage u ON u.surgery_id = s.id
+                 WHERE a.branch_id = $1 AND s.status::text = $2 AND s.deleted_at IS NULL
+                   AND s.date BETWEEN $3 AND $4
+                 GROUP BY s.id, s.date, s.surgery_type, s.eye, s.patient_id, p.first_name, p.last_name, pr.full_name
+                 ORDER BY s.date DESC, s.surgery_type",
+                &[&branch_uuid, &kits::SURGERY_COMPLETED_STATUS, &start, &end],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| SurgeryCostLine {
+            surgery_id: row.get::<_, uuid::Uuid>(0).to_string(),
+            date: row.get::<_, Option<chrono::NaiveDate>>(1).map(|d| d.to_string()),
+            surgery_type: row.get(2),
+            eye: row.get(3),
+            patient_id: row.get::<_, uuid::Uuid>(4).to_string(),
+            patient_name: row.get(5),
+            surgeon_name: row.get(6),
+            material_count: row.get(7),
+            material_cost: row.get(8),
+        }).collect())
+    }
+
+    // ============================================================
+    // BARCODES (CÓDIGOS DE BARRAS / GS1)
+    // ============================================================
+
+    /// Resolve a scanned code to an item of the branch and, when the code
+    /// carries a lot (GS1 AI 10) or is a lot barcode, to that lot.
+    /// A lot that does not exist yet is returned only as lot_number/expiry,
+    /// so goods receipts can create it.
+    pub async fn resolve_inventory_barcode(&self, branch_id: &str, code: &str) -> Result<BarcodeResolution, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let scanned = barcode::parse(code)?;
+
+        let lot_columns = "l.id, l.item_id, l.lot_number, l.expiry_date, l.quantity::float8, l.created_at, l.barcode";
+
+        // A barcode printed on the lot itself identifies both item and lot
+        let mut lot_row = client
+            .query_opt(
+                &format!(
+                    "SELECT {} FROM inventory_lots l
+                     JOIN inventory_items i ON l.item_id = i.id
+                     WHERE i.branch_id = $1 AND i.deleted_at IS NULL AND l.barcode = $2
+                     ORDER BY l.created_at DESC
+                     LIMIT 1",
+                    lot_columns
+                ),
+                &[&branch_uuid, &scanned.raw],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let item_row = match &lot_row {
+            Some(lot) => client
+                .query_opt(
+                    "SELECT id, name, category, cost_price, sell_price, supplier_id, branch_id,
+                            active, current_stock, reorder_level, currency, tax_rate, tax_exempt, barcode
+                     FROM inventory_items WHERE id = $1",
+                    &[&lot.get::<_, uuid::Uuid>(1)],
+                )
+                .await
+                .map_err(|e| e.to_string())?,
+            None => client
+                .query_opt(
+                    "SELECT id, name, category, cost_price, sell_price, supplier_id, branch_id,
+                            active, current_stock, reorder_level, currency, tax_rate, tax_exempt, barcode
+                     FROM inventory_items
+                     WHERE branch_id = $1 AND deleted_at IS NULL
+                       AND (barcode = $2 OR code = $2
+                            OR ($3::text IS NOT NULL AND barcode ~ '^[0-9]+$' AND LPAD(barcode, 14, '0') = $3))
+                     ORDER BY (barcode = $2) DESC NULLS LAST, active DESC, name
+                     LIMIT 1",
+                    &[&branch_uuid, &scanned.raw, &scanned.gtin],
+                )
+                .await
+                .map_err(|e| e.to_string())?,
+        };
+
+        if lot_row.is_none() {
+            if let (Some(item), Some(lot_number)) = (&item_row, &scanned.lot_number) {
+                lot_row = client
+                    .query_opt(
+                        &format!("SELECT {} FROM inventory_lots l WHERE l.item_id = $1 AND l.lot_number = $2", lot_columns),
+                        &[&item.get::<_, uuid::Uuid>(0), lot_number],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        let lot = lot_row.as_ref().map(|row| {
+            let exp_date: Option<chrono::NaiveDate> = row.get(3);
+            InventoryLot {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                lot_number: row.get(2),
+                expiration_date: exp_date.map(|d| d.to_string()),
+                quantity: row.get(4),
+                created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+                barcode: row.get(6),
+            }
+        });
+
+        let expiry = lot_row
+            .as_ref()
+            .and_then(|row| row.get::<_, Option<chrono::NaiveDate>>(3))
+            .or(scanned.expiry_date);
+
+        Ok(BarcodeResolution {
+            lot_expired: lots::is_expired(expiry, timezone::today()),
+            lot_number: lot.as_ref().map(|l| l.lot_number.clone()).or(scanned.lot_number),
+            expiry_date: expiry.map(|d| d.to_string()),
+            scanned: scanned.raw,
+            gtin: scanned.gtin,
+            serial_number: scanned.serial_number,
+            item: item_row.as_ref().map(|row| self.map_inventory_item_row(row)),
+            lot,
+        })
+    }
+
+    // ============================================================
+    // INVENTORY VALUATION (VALORIZACIÓN Y COSTO DE VENTAS)
+    // ============================================================
+
+    /// Replay the branch ledger up to `until` through FIFO cost layers.
+    /// Costs taken out from `period_start` on are added per item as
+    /// (sales, courtesies, write-offs).
+    async fn replay_inventory_costs(
+        &self,
+        branch_uuid: &uuid::Uuid,
+        until: &chrono::DateTime<chrono::Utc>,
+        period_start: Option<&chrono::DateTime<chrono::Utc>>,
+    ) -> Result<std::collections::HashMap<uuid::Uuid, (valuation::FifoLedger, [Decimal; 3])>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT m.item_id, m.movement_type::text, m.quantity::float8, m.lot_id, m.reference_type,
+                        m.created_at, COALESCE(m.unit_cost, l.cost_price, i.cost_price, 0)
+                 FROM inventory_movements m
+                 JOIN inventory_items i ON m.item_id = i.id
+                 LEFT JOIN inventory_lots l ON m.lot_id = l.id
+                 WHERE m.branch_id = $1 AND m.created_at < $2
+                 ORDER BY m.item_id, m.created_at, m.id",
+                &[branch_uuid, until],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut ledgers: std::collections::HashMap<uuid::Uuid, (valuation::FifoLedger, [Decimal; 3])> = std::collections::HashMap::new();
+        for row in &rows {
+            let movement_type: String = row.get(1);
+            let lot_id = row.get::<_, Option<uuid::Uuid>>(3).map(|u| u.to_string());
+            let reference_type: Option<String> = row.get(4);
+            let created_at: chrono::DateTime<chrono::Utc> = row.get(5);
+
+            let (fifo, costs) = ledgers.entry(row.get(0)).or_default();
+            let cost = fifo.apply(&movement_type, row.get(2), lot_id.as_deref(), row.get(6));
+
+            if period_start.is_some_and(|start| created_at >= *start) {
+                if valuation::is_sale(&movement_type, reference_type.as_deref()) {
+                    costs[0] += cost;
+                } else if movement_type == "cortesia" {
+                    costs[1] += cost;
+                } else if movement_type == lots::WRITE_OFF_MOVEMENT {
+                    costs[2] += cost;
+                }
+            }
+        }
+
+        Ok(ledgers)
+    }
+
+    /// FIFO stock value per item at the end of `as_of` (Guatemala time)
+    pub async fn get_inventory_valuation(&self, branch_id: &str, as_of: &str) -> Result<Vec<InventoryValuationLine>, String> {
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let date = chrono::NaiveDate::parse_from_str(as_of, "%Y-%m-%d").map_err(|e| e.to_string())?;
+        let ledgers = self.replay_inventory_costs(&branch_uuid, &timezone::day_end_utc(date), None).await?;
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let items = client
+            .query(
+                "SELECT id, name, code, category FROM inventory_items
+                 WHERE branch_id = $1 AND deleted_at IS NULL
+                 ORDER BY category, name",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(items.iter().filter_map(|row| {
+            let item_uuid: uuid::Uuid = row.get(0);
+            let (fifo, _) = ledgers.get(&item_uuid)?;
+            let quantity = fifo.quantity();
+            if quantity <= 1e-9 {
+                return None;
+            }
+            Some(InventoryValuationLine {
+                item_id: item_uuid.to_string(),
+                item_name: row.get(1),
+                item_code: row.get(2),
+                category: row.get(3),
+                quantity,
+                average_cost: fifo.average_cost(),
+                total_value: fifo.value(),
+            })
+        }).collect())
+    }
+
+    /// Revenue from invoices (after the invoice discount, without IVA) and
+    /// FIFO cost of goods sold per product
+    pub async fn get_product_margin_report(&self, branch_id: &str, start_date: &str, end_date: &str) -> Result<Vec<ProductMarginLine>, String> {
+        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
+        let start = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d").map_err(|e| e.to_string())?;
+        let end = chrono::NaiveDate::parse_from_str(end_date, "%Y-%m-%d").map_err(|e| e.to_string())?;
+        let period_start = timezone::day_start_utc(start);
+        let period_end = timezone::day_end_utc(end);
+
+        let ledgers = self.replay_inventory_costs(&branch_uuid, &period_end, Some(&period_start)).await?;
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let sales = client
+            .query(
+                "SELECT it.item_id, SUM(it.quantity)::float8,
+                        COALESCE(SUM(it.subtotal - it.discount_amount - it.tax_amount), 0)::numeric
+                 FROM invoice_items it
+                 JOIN invoices inv ON it.invoice_id = inv.id
+                 WHERE inv.branch_id = $1
+                   AND it.item_type = 'producto'
+                   AND it.item_id IS NOT NULL
+                   AND inv.created_at >= $2
+                   AND inv.created_at < $3
+                   AND inv.status != 'cancelada'
+                 GROUP BY it.item_id",
+                &[&branch_uuid, &period_start, &period_end],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let sales: std::collections::HashMap<uuid::Uuid, (f64, Decimal)> = sales
+            .iter()
+            .map(|row| (row.get(0), (row.get::<_, Option<f64>>(1).unwrap_or(0.0), row.get(2))))
+            .collect();
+
+        let items = client
+            .query(
+                "SELECT id, name, category FROM inventory_items WHERE branch_id = $1 ORDER BY category, name",
+                &[&branch_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut lines: Vec<ProductMarginLine> = items.iter().filter_map(|row| {
+            let item_uuid: uuid::Uuid = row.get(0);
+            let (quantity_sold, revenue) = sales.get(&item_uuid).copied().unwrap_or((0.0, Decimal::ZERO));
+            let costs = ledgers.get(&item_uuid).map(|(_, c)| *c).unwrap_or_default();
+            if revenue.is_zero() && costs.iter().all(|c| c.is_zero()) {
+                return None;
+            }
+            let cogs = billing::round_money(costs[0]);
+            let (gross_margin, margin_percent) = valuation::gross_margin(revenue, cogs);
+            Some(ProductMarginLine {
+                item_id: item_uuid.to_string(),
+                item_name: row.get(1),
+                category: row.get(2),
+                quantity_sold,
+                revenue,
+                cost_of_goods_sold: cogs,
+                gross_margin,
+                margin_percent,
+                courtesy_cost: billing::round_money(costs[1]),
+                write_off_cost: billing::round_money(costs[2]),
+            })
+        }).collect();
+
+        lines.sort_by_key(|l| std::cmp::Reverse(l.gross_margin));
+        Ok(lines)
+    }
+
+    // ============================================================
+    // PRESCRIPTIONS (RECETAS DE LENTES)
+    // ============================================================
+
+    /// Build a prescription from the final Rx of the encounter's OD/OI exam,
+    /// render its PDF and store both with the encounter. Contact lens
+    /// prescriptions store the powers converted to the corneal plane.
+    pub async fn create_prescription(&self, input: &PrescriptionInput) -> Result<Prescription, String> {
+        prescription::validate_kind(&input.kind)?;
+        if let Some(pd) = input.pupillary_distance {
+            if !(40.0..=80.0).contains(&pd) {
+                return Err("La distancia pupilar debe estar entre 40 y 80 mm".to_string());
+            }
+        }
+
+        let encounter = self.get_encounter_by_id(&input.encounter_id).await?.ok_or("Consulta no encontrada")?;
+        let exams = self.get_exam_eyes_by_encounter(&input.encounter_id).await?;
+        let eye = |side: &str| {
+            exams.iter().find(|e| e.side == side).map(|e| prescription::EyeRx {
+                sphere: e.rx_sphere,
+                cylinder: e.rx_cyl,
+                axis: e.rx_axis,
+                add: e.rx_add,
+            }).unwrap_or_default()
+        };
+        let mut od = eye("OD");
+        let mut os = eye("OI");
+        if od.is_empty() && os.is_empty() {
+            return Err("El examen no tiene Rx final para la receta".to_string());
+        }
+        prescription::validate_eye("OD", &od)?;
+        prescription::validate_eye("OI", &os)?;
+
+        let contact = input.kind == "contacto";
+        let (od_fit, os_fit) = if contact {
+            prescription::validate_fit("OD", &od, input.od_fit.as_ref())?;
+            prescription::validate_fit("OI", &os, input.os_fit.as_ref())?;
+            od = prescription::contact_lens_rx(&od);
+            os = prescription::contact_lens_rx(&os);
+            (input.od_fit.clone().filter(|_| !od.is_empty()), input.os_fit.clone().filter(|_| !os.is_empty()))
+        } else {
+            (None, None)
+        };
+
+        let patient_name = encounter.patient.as_ref()
+            .map(|p| format!("{} {}", p.first_name.as_deref().unwrap_or(""), p.last_name.as_deref().unwrap_or("")).trim().to_string())
+            .unwrap_or_default();
+        let lines = prescription::document_lines(&prescription::PrescriptionDocument {
+            kind: &input.kind,
+            patient_name: &patient_name,
+            patient_code: encounter.patient.as_ref().and_then(|p| p.code.as_deref()),
+            date: &encounter.date,
+            doctor_name: encounter.doctor.as_ref().and_then(|d| d.full_name.as_deref()),
+            od,
+            os,
+            od_fit: od_fit.as_ref(),
+            os_fit: os_fit.as_ref(),
+            pupillary_distance: input.pupillary_distance,
+            notes: input.notes.as_deref(),
+        });
+        let pdf = crate::pdf::text_document(prescription::title(&input.kind), &lines);
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let encounter_uuid = uuid::Uuid::parse_str(&encounter.id).map_err(|e| e.to_string())?;
+        let patient_uuid = uuid::Uuid::parse_str(&encounter.patient_id).map_err(|e| e.to_string())?;
+        let created_by = match &input.created_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO prescriptions (encounter_id, patient_id, kind,
+                                                od_sphere, od_cylinder, od_axis, od_add,
+                                                os_sphere, os_cylinder, os_axis, os_add,
+                                                pupillary_distance, notes, pdf, created_by,
+                                                od_base_curve, od_diameter, od_brand,
+                                                os_base_curve, os_diameter, os_brand)
+                     VALUES ($1, $2, $3, $4::float8, $5::float8, $6, $7::float8,
+                             $8::float8, $9::float8, $10, $11::float8, $12::float8, $13, $14, $15,
+                             $16::float8, $17::float8, $18, $19::float8, $20::float8, $21)
+                     RETURNING {}",
+                    PRESCRIPTION_COLUMNS
+                ),
+                &[
+                    &encounter_uuid, &patient_uuid, &input.kind,
+                    &od.sphere, &od.cylinder, &od.axis, &od.add,
+                    &os.sphere, &os.cylinder, &os.axis, &os.add,
+                    &input.pupillary_distance, &input.notes, &pdf, &created_by,
+                    &od_fit.as_ref().and_then(|f| f.base_curve),
+                    &od_fit.as_ref().and_then(|f| f.diameter),
+                    &od_fit.as_ref().and_then(|f| f.brand.clone()),
+                    &os_fit.as_ref().and_then(|f| f.base_curve),
+                    &os_fit.as_ref().and_then(|f| f.diameter),
+                    &os_fit.as_ref().and_then(|f| f.brand.clone()),
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_prescription_row(&row))
+    }
+
+    pub async fn get_prescriptions_by_encounter(&self, encounter_id: &str) -> Result<Vec<Prescription>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let encounter_uuid = uuid::Uuid::parse_str(encounter_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM prescriptions WHERE encounter_id = $1 ORDER BY created_at DESC",
+                    PRESCRIPTION_COLUMNS
+                ),
+                &[&encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_prescription_row(row)).collect())
+    }
+
+    pub async fn get_prescription_pdf(&self, prescription_id: &str) -> Result<Option<Vec<u8>>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let prescription_uuid = uuid::Uuid::parse_str(prescription_id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt("SELECT pdf FROM prescriptions WHERE id = $1", &[&prescription_uuid])
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.and_then(|r| r.get(0)))
+    }
+
+    /// Helper to map a row selected with PRESCRIPTION_COLUMNS
+    fn map_prescription_row(&self, row: &tokio_postgres::Row) -> Prescription {
+        let fit = |base_curve: Option<f64>, diameter: Option<f64>, brand: Option<String>| {
+            if base_curve.is_none() && diameter.is_none() && brand.is_none() {
+                None
+            } else {
+                Some(prescription::ContactLensFit { base_curve, diameter, brand })
+            }
+        };
+        Prescription {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            encounter_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            patient_id: row.get::<_, uuid::Uuid>(2).to_string(),
+            kind: row.get(3),
+            od_sphere: row.get(4),
+            od_cylinder: row.get(5),
+            od_axis: row.get(6),
+            od_add: row.get(7),
+            os_sphere: row.get(8),
+            os_cylinder: row.get(9),
+            os_axis: row.get(10),
+            os_add: row.get(11),
+            od_fit: fit(row.get(16), row.get(17), row.get(18)),
+            os_fit: fit(row.get(19), row.get(20), row.get(21)),
+            pupillary_distance: row.get(12),
+            notes: row.get(13),
+            created_by: row.get::<_, Option<uuid::Uuid>>(14).map(|u| u.to_string()),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(15).to_rfc3339(),
+        }
+    }
+
+    // ============================================================
+    // REFRACTION (CÁLCULOS DE REFRACCIÓN)
+    // ============================================================
+
+    /// Refraction of an exam eye: final Rx, else subjective, else objective
+    fn exam_refraction(exam: &ExamEye) -> Option<(&'static str, refraction::Refraction)> {
+        refraction::Refraction::from_parts(exam.rx_sphere, exam.rx_cyl, exam.rx_axis)
+            .map(|r| ("rx", r))
+            .or_else(|| refraction::Refraction::from_parts(exam.ref_subj_sphere, exam.ref_subj_cyl, exam.ref_subj_axis).map(|r| ("subjetiva", r)))
+            .or_else(|| refraction::Refraction::from_parts(exam.ref_sphere, exam.ref_cyl, exam.ref_axis).map(|r| ("objetiva", r)))
+    }
+
+    pub async fn get_encounter_refraction(&self, encounter_id: &str, vertex_distance_mm: Option<f64>) -> Result<EncounterRefraction, String> {
+        let vertex = vertex_distance_mm.unwrap_or(refraction::DEFAULT_VERTEX_DISTANCE_MM);
+        if !(0.0..=25.0).contains(&vertex) {
+            return Err("La distancia al vértice debe estar entre 0 y 25 mm".to_string());
+        }
+
+        let exams = self.get_exam_eyes_by_encounter(encounter_id).await?;
+        let eye = |side: &str| {
+            exams.iter()
+                .find(|e| e.side == side)
+                .and_then(Self::exam_refraction)
+        };
+        let od = eye("OD");
+        let os = eye("OI");
+
+        let (anisometropia, is_anisometropic) = match (&od, &os) {
+            (Some((_, od)), Some((_, os))) => (Some(refraction::anisometropia(od, os)), refraction::is_anisometropic(od, os)),
+            _ => (None, false),
+        };
+        let summary = |side: &str, eye: Option<(&'static str, refraction::Refraction)>| {
+            eye.map(|(source, rx)| EyeRefraction {
+                side: side.to_string(),
+                source: source.to_string(),
+                summary: rx.summary(vertex),
+            })
+        };
+
+        Ok(EncounterRefraction {
+            encounter_id: encounter_id.to_string(),
+            od: summary("OD", od),
+            os: summary("OI", os),
+            anisometropia,
+            is_anisometropic,
+        })
+    }
+
+    /// Exam eyes of many encounters keyed by encounter id (research export)
+    async fn get_exam_eyes_by_encounters(
+        &self,
+        encounter_ids: &[uuid::Uuid],
+    ) -> Result<std::collections::HashMap<String, Vec<ExamEye>>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let rows = client
+            .query(
+                "SELECT id, encounter_id, side::text, av_sc, av_cc,
+                        ref_sphere, ref_cyl, ref_axis,
+                        ref_subj_sphere, ref_subj_cyl, ref_subj_axis,
+                        rx_sphere, rx_cyl, rx_axis, rx_add,
+                        iop, slit_lamp, fundus, plan,
+                        av_sc_logmar::float8, av_cc_logmar::float8
+                 FROM exam_eye
+                 WHERE encounter_id = ANY($1)",
+                &[&encounter_ids],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut result: std::collections::HashMap<String, Vec<ExamEye>> = std::collections::HashMap::new();
+        for row in &rows {
+            let exam = self.map_exam_eye_row(row);
+            result.entry(exam.encounter_id.clone()).or_default().push(exam);
+        }
+        Ok(result)
+    }
+
+    // ============================================================
+    // IOL CALCULATION (BIOMETRÍA Y CÁLCULO DE LENTE INTRAOCULAR)
+    // ============================================================
+
+    pub async fn get_iol_models(&self, include_inactive: bool) -> Result<Vec<IolModel>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM iol_models WHERE ($1 OR active) ORDER BY name",
+                    IOL_MODEL_COLUMNS
+                ),
+                &[&include_inactive],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_iol_model_row(row)).collect())
+    }
+
+    fn validate_iol_model(model: &IolModelInput) -> Result<(), String> {
+        if model.name.trim().is_empty() {
+            return Err("El nombre del lente es requerido".to_string());
+        }
+        iol::validate_constants(&iol::LensConstants {
+            a_constant: model.a_constant,
+            surgeon_factor: model.surgeon_factor,
+            pacd: model.pacd,
+        })
+    }
+
+    pub async fn create_iol_model(&self, model: &IolModelInput) -> Result<IolModel, String> {
+        Self::validate_iol_model(model)?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO iol_models (name, manufacturer, a_constant, surgeon_factor, pacd, active)
+                     VALUES ($1, $2, $3::float8, $4::float8, $5::float8, COALESCE($6, true))
+                     RETURNING {}",
+                    IOL_MODEL_COLUMNS
+                ),
+                &[
+                    &model.name.trim(), &model.manufacturer, &model.a_constant,
+                    &model.surgeon_factor, &model.pacd, &model.active,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_iol_model_row(&row))
+    }
+
+    pub async fn update_iol_model(&self, id: &str, model: &IolModelInput) -> Result<IolModel, String> {
+        Self::validate_iol_model(model)?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let model_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE iol_models SET
+                        name = $2, manufacturer = $3, a_constant = $4::float8,
+                        surgeon_factor = $5::float8, pacd = $6::float8,
+                        active = COALESCE($7, active), updated_at = now()
+                     WHERE id = $1
+                     RETURNING {}",
+                    IOL_MODEL_COLUMNS
+                ),
+                &[
+                    &model_uuid, &model.name.trim(), &model.manufacturer, &model.a_constant,
+                    &model.surgeon_factor, &model.pacd, &model.active,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Lente no encontrado")?;
+
+        Ok(self.map_iol_model_row(&row))
+    }
+
+    pub async fn get_biometry_by_patient(&self, patient_id: &str) -> Result<Vec<BiometryRecord>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let patient_uuid = uuid::Uuid::parse_str(patient_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM biometry_records
+                     WHERE patient_id = $1 AND deleted_at IS NULL
+                     ORDER BY measured_at DESC, created_at DESC",
+                    BIOMETRY_COLUMNS
+                ),
+                &[&patient_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_biometry_row(row)).collect())
+    }
+
+    pub async fn create_biometry(&self, input: &BiometryInput) -> Result<BiometryRecord, String> {
+        if input.eye != "OD" && input.eye != "OS" {
+            return Err("El ojo debe ser OD u OS".to_string());
+        }
+        iol::validate_biometry(input.axial_length, input.k1, input.k2, input.acd)?;
+        if let Some(axis) = input.k1_axis {
+            if !(0..=180).contains(&axis) {
+                return Err("El eje de K1 debe estar entre 0 y 180".to_string());
+            }
+        }
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let patient_uuid = uuid::Uuid::parse_str(&input.patient_id).map_err(|e| e.to_string())?;
+        let measured_at = match &input.measured_at {
+            Some(d) => chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| "Fecha de medición no válida")?,
+            None => chrono::Local::now().date_naive(),
+        };
+        let created_by = match &input.created_by {
+            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO biometry_records (patient_id, eye, axial_length, k1, k2, k1_axis, acd,
+                                                   lens_thickness, white_to_white, device, measured_at,
+                                                   notes, created_by)
+                     VALUES ($1, $2, $3::float8, $4::float8, $5::float8, $6, $7::float8,
+                             $8::float8, $9::float8, $10, $11, $12, $13)
+                     RETURNING {}",
+                    BIOMETRY_COLUMNS
+                ),
+                &[
+                    &patient_uuid, &input.eye, &input.axial_length, &input.k1, &input.k2, &input.k1_axis,
+                    &input.acd, &input.lens_thickness, &input.white_to_white, &input.device,
+                    &measured_at, &input.notes, &created_by,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_biometry_row(&row))
+    }
+
+    pub async fn delete_biometry(&self, id: &str) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let biometry_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        client
+            .execute(
+                "UPDATE biometry_records SET deleted_at = now() WHERE id = $1",
+                &[&biometry_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    async fn get_biometry(&self, id: &str) -> Result<BiometryRecord, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let biometry_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM biometry_records WHERE id = $1 AND deleted_at IS NULL", BIOMETRY_COLUMNS),
+                &[&biometry_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Biometría no encontrada")?;
+
+        Ok(self.map_biometry_row(&row))
+    }
+
+    async fn get_iol_model(&self, id: &str) -> Result<IolModel, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let model_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(&format!("SELECT {} FROM iol_models WHERE id = $1", IOL_MODEL_COLUMNS), &[&model_uuid])
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Lente no encontrado")?;
+
+        Ok(self.map_iol_model_row(&row))
+    }
+
+    pub async fn calculate_iol(&self, input: &IolCalculationInput) -> Result<IolCalculation, String> {
+        let target = input.target_refraction.unwrap_or(0.0);
+        if !(-10.0..=5.0).contains(&target) {
+            return Err("La refracción objetivo debe estar entre -10 y +5 D".to_string());
+        }
+        let biometry = self.get_biometry(&input.biometry_id).await?;
+        let model = self.get_iol_model(&input.iol_model_id).await?;
+
+        let bio = iol::Biometry { axial_length: biometry.axial_length, k1: biometry.k1, k2: biometry.k2 };
+        let constants = iol::LensConstants {
+            a_constant: model.a_constant,
+            surgeon_factor: model.surgeon_factor,
+            pacd: model.pacd,
+        };
+
+        let results = iol::FORMULAS
+            .iter()
+            .map(|formula| {
+                let result = iol::calculate(*formula, &bio, &constants, target);
+                IolFormulaResult {
+                    formula: formula.code().to_string(),
+                    formula_name: formula.name().to_string(),
+                    exact_power: result.exact_power,
+                    recommended_power: result.recommended.power,
+                    recommended_refraction: result.recommended.refraction,
+                    options: result.options.iter().map(|o| IolPowerOption { power: o.power, refraction: o.refraction }).collect(),
+                }
+            })
+            .collect();
+
+        Ok(IolCalculation {
+            suggested_formula: iol::suggested_formula(bio.axial_length).code().to_string(),
+            biometry,
+            model,
+            target_refraction: target,
+            results,
+        })
+    }
+
+    /// Store the chosen lens (model, power, formula and target) on a surgery
+    pub async fn set_surgery_iol(&self, surgery_id: &str, input: &SurgeryIolInput) -> Result<Surgery, String> {
+        iol::Formula::from_code(&input.iol_formula)?;
+        if !(-10.0..=40.0).contains(&input.iol_power) || (input.iol_power * 4.0).fract() != 0.0 {
+            return Err("La potencia del lente debe estar entre -10 y +40 D en pasos de 0.25".to_string());
+        }
+        if !(-10.0..=5.0).contains(&input.target_refraction) {
+            return Err("La refracción objetivo debe estar entre -10 y +5 D".to_string());
+        }
+        let biometry = self.get_biometry(&input.biometry_id).await?;
+        let model = self.get_iol_model(&input.iol_model_id).await?;
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let surgery_uuid = uuid::Uuid::parse_str(surgery_id).map_err(|e| e.to_string())?;
+        let biometry_uuid = uuid::Uuid::parse_str(&biometry.id).map_err(|e| e.to_string())?;
+        let model_uuid = uuid::Uuid::parse_str(&model.id).map_err(|e| e.to_string())?;
+
+        let surgery = client
+            .query_opt(
+                "SELECT patient_id, eye::text FROM surgeries WHERE id = $1 AND deleted_at IS NULL",
+                &[&surgery_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Cirugía no encontrada")?;
+        if surgery.get::<_, uuid::Uuid>(0).to_string() != biometry.patient_id {
+            return Err("La biometría es de otro paciente".to_string());
+        }
+        // Surgeries record the left eye as OI, biometry as OS
+        let surgery_eye: Option<String> = surgery.get(1);
+        if let Some(eye) = surgery_eye.as_deref().map(|e| if e == "OI" { "OS" } else { e }) {
+            if (eye == "OD" || eye == "OS") && eye != biometry.eye {
+                return Err("La biometría no corresponde al ojo de la cirugía".to_string());
+            }
+        }
+
+        client
+            .execute(
+                "UPDATE surgeries SET
+                    biometry_id = $2, iol_model_id = $3, iol_power = $4::float8,
+                    iol_formula = $5, iol_target_refraction = $6::float8, updated_at = now()
+                 WHERE id = $1",
+                &[
+                    &surgery_uuid, &biometry_uuid, &model_uuid, &input.iol_power,
+                    &input.iol_formula, &input.target_refraction,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_one(
+                "SELECT s.id, s.appointment_id, s.patient_id, s.surgery_type::text, s.eye::text,
+                        s.date, s.status::text, s.surgeon_id, s.notes,
+                        p.id as p_id, p.first_name, p.last_name, p.code, p.phone,
+                        pr.user_id, pr.full_name, pr.specialty,
+                        s.biometry_id, s.iol_model_id, s.iol_power::float8, s.iol_formula, s.iol_target_refraction::float8
+                 FROM surgeries s
+                 LEFT JOIN patients p ON s.patient_id = p.id
+                 LEFT JOIN profiles pr ON s.surgeon_id = pr.user_id
+                 WHERE s.id = $1",
+                &[&surgery_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let files = self.get_surgery_files(surgery_id).await?;
+        Ok(self.map_surgery_row(&row, files))
+    }
+
+    /// Helper to map a row selected with IOL_MODEL_COLUMNS
+    fn map_iol_model_row(&self, row: &tokio_postgres::Row) -> IolModel {
+        IolModel {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            name: row.get(1),
+            manufacturer: row.get(2),
+            a_constant: row.get(3),
+            surgeon_factor: row.get(4),
+            pacd: row.get(5),
+            active: row.get(6),
+        }
+    }
+
+    /// Helper to map a row selected with BIOMETRY_COLUMNS
+    fn map_biometry_row(&self, row: &tokio_postgres::Row) -> BiometryRecord {
+        BiometryRecord {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            patient_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            eye: row.get(2),
+            axial_length: row.get(3),
+            k1: row.get(4),
+            k2: row.get(5),
+            k1_axis: row.get(6),
+            acd: row.get(7),
+            lens_thickness: row.get(8),
+            white_to_white: row.get(9),
+            device: row.get(10),
+            measured_at: row.get::<_, chrono::NaiveDate>(11).to_string(),
+            notes: row.get(12),
+            created_by: row.get::<_, Option<uuid::Uuid>>(13).map(|u| u.to_string()),
+            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(14).to_rfc3339(),
+        }
+    }
+
+    // ============================================================
+    // CLINICAL TIMELINE (HISTORIA POR OJO)
+    // ============================================================
+
+    /// Chronological history of one eye of a patient, with the IOP trend
+    pub async fn get_eye_timeline(&self, patient_id: &str, eye: &str) -> Result<ClinicalTimeline, String> {
+        let eye = timeline::normalize_eye(eye)?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let patient_uuid = uuid::Uuid::parse_str(patient_id).map_err(|e| e.to_string())?;
+
+        let event = |date: chrono::NaiveDate, kind: &str, source_id: uuid::Uuid, title: String| ClinicalTimelineEvent {
+            date: date.to_string(),
+            kind: kind.to_string(),
+            source_id: source_id.to_string(),
+            encounter_id: None,
+            title,
+            detail: None,
+            status: None,
+            eye: None,
+            iop: None,
+            av_sc: None,
+            av_cc: None,
+            av_sc_logmar: None,
+            av_cc_logmar: None,
+            sphere: None,
+            cylinder: None,
+            axis: None,
+            spherical_equivalent: None,
+        };
+        let mut events: Vec<ClinicalTimelineEvent> = Vec::new();
+        let mut iop_points: Vec<(chrono::NaiveDate, f64)> = Vec::new();
+
+        // Exams
+        let rows = client
+            .query(
+                "SELECT ee.id, ee.encounter_id, ee.side::text, ee.av_sc, ee.av_cc,
+                        ee.ref_sphere, ee.ref_cyl, ee.ref_axis,
+                        ee.ref_subj_sphere, ee.ref_subj_cyl, ee.ref_subj_axis,
+                        ee.rx_sphere, ee.rx_cyl, ee.rx_axis, ee.rx_add,
+                        ee.iop, ee.slit_lamp, ee.fundus, ee.plan,
+                        ee.av_sc_logmar::float8, ee.av_cc_logmar::float8,
+                        e.date
+                 FROM exam_eye ee
+                 JOIN encounters e ON e.id = ee.encounter_id
+                 WHERE e.patient_id = $1 AND e.deleted_at IS NULL AND ee.side::text = $2",
+                &[&patient_uuid, &eye],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        for row in &rows {
+            let exam = self.map_exam_eye_row(row);
+            let date: chrono::NaiveDate = row.get(21);
+            let rx = Self::exam_refraction(&exam).map(|(_, r)| r);
+            if let Some(iop) = exam.iop {
+                iop_points.push((date, iop));
+            }
+            events.push(ClinicalTimelineEvent {
+                encounter_id: Some(exam.encounter_id.clone()),
+                detail: exam.plan.clone(),
+                eye: Some(exam.side.clone()),
+                iop: exam.iop,
+                av_sc: exam.av_sc.clone(),
+                av_cc: exam.av_cc.clone(),
+                av_sc_logmar: exam.av_sc_logmar,
+                av_cc_logmar: exam.av_cc_logmar,
+                sphere: rx.map(|r| r.sphere),
+                cylinder: rx.map(|r| r.cylinder),
+                axis: rx.map(|r| r.axis),
+                spherical_equivalent: rx.map(|r| r.spherical_equivalent()),
+                ..event(date, "examen", row.get(0), "Examen".to_string())
+            });
+        }
+
+        // Diagnoses
+        let rows = client
+            .query(
+                "SELECT d.id, d.encounter_id, e.date, d.code, d.description, d.eye
+                 FROM diagnoses d
+                 JOIN encounters e ON e.id = d.encounter_id
+                 WHERE e.patient_id = $1 AND e.deleted_at IS NULL AND d.deleted_at IS NULL",
+                &[&patient_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        for row in &rows {
+            let record_eye: Option<String> = row.get(5);
+            if !timeline::applies_to_eye(record_eye.as_deref(), eye) {
+                continue;
+            }
+            let code: Option<String> = row.get(3);
+            let description: String = row.get(4);
+            events.push(ClinicalTimelineEvent {
+                encounter_id: Some(row.get::<_, uuid::Uuid>(1).to_string()),
+                eye: record_eye,
+                ..event(
+                    row.get(2),
+                    "diagnostico",
+                    row.get(0),
+                    match code {
+                        Some(code) if !code.is_empty() => format!("{} - {}", code, description),
+                        _ => description,
+                    },
+                )
+            });
+        }
+
+        // Studies (not recorded per eye)
+        let rows = client
+            .query(
+                "SELECT s.id, COALESCE(s.date, s.created_at::date), s.study_type::text, s.status::text, s.notes
+                 FROM studies s
+                 WHERE s.patient_id = $1 AND s.deleted_at IS NULL",
+                &[&patient_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        for row in &rows {
+            events.push(ClinicalTimelineEvent {
+                status: row.get(3),
+                detail: row.get(4),
+                ..event(row.get(1), "estudio", row.get(0), row.get(2))
+            });
+        }
+
+        // Surgeries, with the planned lens
+        let rows = client
+            .query(
+                "SELECT s.id, COALESCE(s.date, s.created_at::date), s.surgery_type::text, s.status::text,
+                        s.notes, s.eye::text, m.name, s.iol_power::float8
+                 FROM surgeries s
+                 LEFT JOIN iol_models m ON m.id = s.iol_model_id
+                 WHERE s.patient_id = $1 AND s.deleted_at IS NULL",
+                &[&patient_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        for row in &rows {
+            let record_eye: Option<String> = row.get(5);
+            if !timeline::applies_to_eye(record_eye.as_deref(), eye) {
+                continue;
+            }
+            let surgery_type: String = row.get(2);
+            let title = match (row.get::<_, Option<String>>(6), row.get::<_, Option<f64>>(7)) {
+                (Some(model), Some(power)) => format!("{} (LIO {} {:+.2} D)", surgery_type, model, power),
+                _ => surgery_type,
+            };
+            events.push(ClinicalTimelineEvent {
+                status: row.get(3),
+                detail: row.get(4),
+                eye: record_eye,
+                ..event(row.get(1), "cirugia", row.get(0), title)
+            });
+        }
+
+        // Procedures
+        let rows = client
+            .query(
+                "SELECT p.id, COALESCE(p.date, p.created_at::date), p.procedure_type::text, p.status::text,
+                        p.notes, p.eye::text
+                 FROM procedures p
+                 WHERE p.patient_id = $1 AND p.deleted_at IS NULL",
+                &[&patient_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        for row in &rows {
+            let record_eye: Option<String> = row.get(5);
+            if !timeline::applies_to_eye(record_eye.as_deref(), eye) {
+                continue;
+            }
+            events.push(ClinicalTimelineEvent {
+                status: row.get(3),
+                detail: row.get(4),
+                eye: record_eye,
+                ..event(row.get(1), "procedimiento", row.get(0), row.get(2))
+            });
+        }
+
+        const KIND_ORDER: &[&str] = &["examen", "diagnostico", "estudio", "procedimiento", "cirugia"];
+        let rank = |kind: &str| KIND_ORDER.iter().position(|k| *k == kind).unwrap_or(KIND_ORDER.len());
+        events.sort_by(|a, b| a.date.cmp(&b.date).then(rank(&a.kind).cmp(&rank(&b.kind))));
+        iop_points.sort_by_key(|(date, _)| *date);
+
+        Ok(ClinicalTimeline {
+            patient_id: patient_id.to_string(),
+            eye: eye.to_string(),
+            events,
+            iop_last: iop_points.last().map(|(_, v)| *v),
+            iop_max: iop_points.iter().map(|(_, v)| *v).reduce(f64::max),
+            iop_trend_per_year: timeline::trend_per_year(&iop_points),
+        })
+    }
+
+    // ============================================================
+    // ICD-10 (CATÁLOGO CIE-10)
+    // ============================================================
+
+    /// Load (or refresh) the bundled ICD-10 catalog; returns the number of codes
+    pub async fn load_icd10_catalog(&self) -> Result<usize, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let stmt = tx
+            .prepare(
+                "INSERT INTO icd10_codes (code, description, synonyms, search_text, lateral, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, NOW())
+                 ON CONFLICT (code) DO UPDATE SET
+                    description = EXCLUDED.description,
+                    synonyms = EXCLUDED.synonyms,
+                    search_text = EXCLUDED.search_text,
+                    lateral = EXCLUDED.lateral,
+                    updated_at = NOW()",
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let entries = icd10::entries();
+        for entry in &entries {
+            tx.execute(
+                &stmt,
+                &[&entry.code, &entry.description, &entry.synonyms.join(";"), &entry.search_text(), &entry.is_lateral()],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+        log::info!("ICD-10 catalog loaded ({} codes)", entries.len());
+        Ok(entries.len())
+    }
+
+    /// Load the bundled catalog the first time it is used
+    async fn ensure_icd10_catalog(&self) -> Result<(), String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let loaded: bool = client
+            .query_one("SELECT EXISTS (SELECT 1 FROM icd10_codes)", &[])
+            .await
+            .map_err(|e| e.to_string())?
+            .get(0);
+        drop(client);
+
+        if !loaded {
+            self.load_icd10_catalog().await?;
+        }
+        Ok(())
+    }
+
+    /// Search the catalog: codes starting with the query first, then codes
+    /// whose description or synonyms contain every word of it
+    pub async fn search_icd10(&self, query: &str, limit: i64) -> Result<Vec<Icd10Code>, String> {
+        let terms = icd10::search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let prefix = icd10::code_prefix(query);
+        self.ensure_icd10_catalog().await?;
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM icd10_codes
+                     WHERE ($1::text IS NOT NULL AND replace(code, '.', '') LIKE $1 || '%')
+                        OR NOT EXISTS (
+                            SELECT 1 FROM unnest($2::text[]) t
+                            WHERE search_text NOT LIKE '%' || t || '%'
+                        )
+                     ORDER BY ($1::text IS NOT NULL AND replace(code, '.', '') LIKE $1 || '%') DESC, code
+                     LIMIT $3",
+                    ICD10_COLUMNS
+                ),
+                &[&prefix, &terms, &limit],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_icd10_row(row)).collect())
+    }
+
+    /// Get a catalog code; the code is normalized first ("h251" finds H25.1)
+    pub async fn get_icd10_code(&self, code: &str) -> Result<Option<Icd10Code>, String> {
+        let Some(code) = icd10::normalize_code(code) else {
+            return Ok(None);
+        };
+        self.ensure_icd10_catalog().await?;
+
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let row = client
+            .query_opt(&format!("SELECT {} FROM icd10_codes WHERE code = $1", ICD10_COLUMNS), &[&code])
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|row| self.map_icd10_row(&row)))
+    }
+
+    /// Normalized code, canonical eye and catalog description of a diagnosis.
+    /// Without a code only the eye is normalized.
+    async fn check_diagnosis(&self, code: Option<&str>, eye: Option<&str>) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        let Some(code) = code.map(str::trim).filter(|c| !c.is_empty()) else {
+            let eye = match eye.filter(|e| !e.trim().is_empty()) {
+                Some(e) => Some(icd10::normalize_eye(e)?),
+                None => None,
+            };
+            return Ok((None, eye, None));
+        };
+
+        let normalized = icd10::normalize_code(code).ok_or_else(|| format!("Código CIE-10 no válido: {}", code))?;
+        let entry = self.get_icd10_code(&normalized).await?.map(|c| icd10::Icd10Entry {
+            code: c.code,
+            description: c.description,
+            synonyms: c.synonyms,
+        });
+        let eye = icd10::validate_diagnosis(&normalized, entry.as_ref(), eye)?;
+        Ok((Some(normalized), eye, entry.map(|e| e.description)))
+    }
+
+    /// Helper to map a row selected with ICD10_COLUMNS
+    fn map_icd10_row(&self, row: &tokio_postgres::Row) -> Icd10Code {
+        let synonyms: Option<String> = row.get(2);
+        Icd10Code {
+            code: row.get(0),
+            description: row.get(1),
+            synonyms: synonyms
+                .map(|s| s.split(';').filter(|x| !x.is_empty()).map(String::from).collect())
+                .unwrap_or_default(),
+            lateral: row.get(3),
+        }
+    }
+
+    // ============================================================
+    // ENCOUNTER TEMPLATES (PLANTILLAS DE CONSULTA)
+    // ============================================================
+
+    /// Templates for a doctor and encounter type, the doctor's own and
+    /// type-specific ones first. Without a doctor, all templates.
+    pub async fn get_encounter_templates(&self, doctor_id: Option<&str>, encounter_type: Option<&str>, include_inactive: bool) -> Result<Vec<EncounterTemplate>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let doctor_uuid: Option<uuid::Uuid> = doctor_id.and_then(|id| uuid::Uuid::parse_str(id).ok());
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM encounter_templates
+                     WHERE ($1 OR active)
+                       AND ($2::uuid IS NULL OR doctor_id IS NULL OR doctor_id = $2)
+                       AND ($3::text IS NULL OR encounter_type IS NULL OR encounter_type = $3)
+                     ORDER BY doctor_id IS NULL, encounter_type IS NULL, name",
+                    ENCOUNTER_TEMPLATE_COLUMNS
+                ),
+                &[&include_inactive, &doctor_uuid, &encounter_type],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| self.map_encounter_template_row(row)).collect())
+    }
+
+    pub async fn get_encounter_template(&self, id: &str) -> Result<Option<EncounterTemplate>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let template_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM encounter_templates WHERE id = $1", ENCOUNTER_TEMPLATE_COLUMNS),
+                &[&template_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(row.map(|row| self.map_encounter_template_row(&row)))
+    }
+
+    /// Check a template and return its doctor and its diagnoses checked
+    /// against the ICD-10 catalog, so applying it never fails validation
+    async fn validate_encounter_template(&self, template: &EncounterTemplateInput) -> Result<(Option<uuid::Uuid>, serde_json::Value, Vec<String>), String> {
+        if template.name.trim().is_empty() {
+            return Err("El nombre de la plantilla es requerido".to_string());
+        }
+        let doctor_uuid = match &template.doctor_id {
+            Some(id) => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let diagnoses = self.check_diagnosis_list(&template.diagnoses).await?;
+
+        let mut studies: Vec<String> = Vec::new();
+        for study in template.studies.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            if !studies.iter().any(|s| s == study) {
+                studies.push(study.to_string());
+            }
+        }
+
+        let diagnoses = serde_json::to_value(&diagnoses).map_err(|e| e.to_string())?;
+        Ok((doctor_uuid, diagnoses, studies))
+    }
+
+    pub async fn create_encounter_template(&self, template: &EncounterTemplateInput) -> Result<EncounterTemplate, String> {
+        let (doctor_uuid, diagnoses, studies) = self.validate_encounter_template(template).await?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO encounter_templates (name, encounter_type, doctor_id, summary, motivo_consulta,
+                        plan_tratamiento, proxima_cita, exam_slit_lamp, exam_fundus, exam_plan,
+                        diagnoses, studies, active)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, COALESCE($13, true))
+                     RETURNING {}",
+                    ENCOUNTER_TEMPLATE_COLUMNS
+                ),
+                &[
+                    &template.name.trim(), &template.encounter_type, &doctor_uuid,
+                    &template.summary, &template.motivo_consulta, &template.plan_tratamiento, &template.proxima_cita,
+                    &template.exam_slit_lamp, &template.exam_fundus, &template.exam_plan,
+                    &diagnoses, &studies, &template.active,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(self.map_encounter_template_row(&row))
+    }
+
+    pub async fn update_encounter_template(&self, id: &str, template: &EncounterTemplateInput) -> Result<EncounterTemplate, String> {
+        let (doctor_uuid, diagnoses, studies) = self.validate_encounter_template(template).await?;
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let template_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE encounter_templates SET
+                        name = $2, encounter_type = $3, doctor_id = $4, summary = $5, motivo_consulta = $6,
+                        plan_tratamiento = $7, proxima_cita = $8, exam_slit_lamp = $9, exam_fundus = $10,
+                        exam_plan = $11, diagnoses = $12, studies = $13,
+                        active = COALESCE($14, active), updated_at = now()
+                     WHERE id = $1
+                     RETURNING {}",
+                    ENCOUNTER_TEMPLATE_COLUMNS
+                ),
+                &[
+                    &template_uuid, &template.name.trim(), &template.encounter_type, &doctor_uuid,
+                    &template.summary, &template.motivo_consulta, &template.plan_tratamiento, &template.proxima_cita,
+                    &template.exam_slit_lamp, &template.exam_fundus, &template.exam_plan,
+                    &diagnoses, &studies, &template.active,
+                ],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Plantilla no encontrada")?;
+
+        Ok(self.map_encounter_template_row(&row))
+    }
+
+    /// Diagnosis list checked against the ICD-10 catalog, with at most one primary
+    async fn check_diagnosis_list(&self, diagnoses: &[TemplateDiagnosis]) -> Result<Vec<TemplateDiagnosis>, String> {
+        let mut checked = Vec::new();
+        for diagnosis in diagnoses {
+            let (code, eye, catalog_description) = self.check_diagnosis(diagnosis.code.as_deref(), diagnosis.eye.as_deref()).await?;
+            let description = match diagnosis.description.trim() {
+                "" => catalog_description.ok_or("La descripción del diagnóstico es obligatoria")?,
+                d => d.to_string(),
+            };
+            checked.push(TemplateDiagnosis { code, description, eye, is_primary: diagnosis.is_primary });
+        }
+        if checked.iter().filter(|d| d.is_primary).count() > 1 {
+            return Err("Solo puede haber un diagnóstico principal".to_string());
+        }
+        Ok(checked)
+    }
+
+    /// Template requested for a new encounter: active, for its type and for its doctor (or shared)
+    async fn encounter_template_for(&self, template_id: &str, encounter: &EncounterInput, doctor_uuid: Option<uuid::Uuid>) -> Result<EncounterTemplate, String> {
+        let template = self
+            .get_encounter_template(template_id)
+            .await?
+            .filter(|t| t.active)
+            .ok_or("Plantilla no encontrada o inactiva")?;
+
+        if let Some(encounter_type) = &template.encounter_type {
+            if *encounter_type != encounter.encounter_type {
+                return Err(format!("La plantilla {} es para consultas de tipo {}", template.name, encounter_type));
+            }
+        }
+        if let Some(doctor_id) = &template.doctor_id {
+            if doctor_uuid.map(|d| d.to_string()).as_ref() != Some(doctor_id) {
+                return Err(format!("La plantilla {} pertenece a otro médico", template.name));
+            }
+        }
+        Ok(template)
+    }
+
+    /// Exam defaults for both eyes, standard diagnoses and study orders of a
+    /// template, written in the transaction that creates the encounter.
+    /// `diagnoses` are the template ones already checked against the catalog.
+    async fn apply_encounter_template(
+        tx: &deadpool_postgres::Transaction<'_>,
+        encounter_uuid: &uuid::Uuid,
+        encounter: &EncounterInput,
+        template: &EncounterTemplate,
+        diagnoses: &[TemplateDiagnosis],
+    ) -> Result<(), String> {
+        let now = chrono::Utc::now();
+
+        if template.exam_slit_lamp.is_some() || template.exam_fundus.is_some() || template.exam_plan.is_some() {
+            for side in ["OD", "OI"] {
+                Self::write_exam_eye(tx, encounter_uuid, &ExamEyeInput {
+                    encounter_id: encounter_uuid.to_string(),
+                    side: side.to_string(),
+                    av_sc: None,
+                    av_cc: None,
+                    ref_sphere: None,
+                    ref_cyl: None,
+                    ref_axis: None,
+                    ref_subj_sphere: None,
+                    ref_subj_cyl: None,
+                    ref_subj_axis: None,
+                    rx_sphere: None,
+                    rx_cyl: None,
+                    rx_axis: None,
+                    rx_add: None,
+                    iop: None,
+                    slit_lamp: template.exam_slit_lamp.clone(),
+                    fundus: template.exam_fundus.clone(),
+                    plan: template.exam_plan.clone(),
+                })
+                .await?;
+            }
+        }
+
+        for diagnosis in diagnoses {
+            tx
+                .execute(
+                    "INSERT INTO diagnoses (id, encounter_id, code, description, eye, is_primary, created_at, updated_at)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    &[
+                        &uuid::Uuid::new_v4(),
+                        encounter_uuid,
+                        &diagnosis.code,
+                        &diagnosis.description,
+                        &diagnosis.eye,
+                        &diagnosis.is_primary,
+                        &now,
+                        &now,
+                    ],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let patient_uuid = uuid::Uuid::parse_str(&encounter.patient_id).map_err(|e| e.to_string())?;
+        let appointment_uuid: Option<uuid::Uuid> = encounter.appointment_id.as_ref()
+            .and_then(|id| uuid::Uuid::parse_str(id).ok());
+        let ordered_by_uuid: Option<uuid::Uuid> = encounter.doctor_id.as_ref()
+            .and_then(|id| uuid::Uuid::parse_str(id).ok());
+        for study_type in &template.studies {
+            tx
+                .execute(
+                    "INSERT INTO studies (id, appointment_id, patient_id, study_type, status, ordered_by, notes, created_at, updated_at)
+                     VALUES ($1, $2, $3, $4, 'pending', $5, NULL, $6, $7)",
+                    &[
+                        &uuid::Uuid::new_v4(),
+                        &appointment_uuid,
+                        &patient_uuid,
+                        study_type,
+                        &ordered_by_uuid,
+                        &now,
+                        &now,
+                    ],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Helper to map a row selected with ENCOUNTER_TEMPLATE_COLUMNS
+    fn map_encounter_template_row(&self, row: &tokio_postgres::Row) -> EncounterTemplate {
+        let diagnoses: serde_json::Value = row.get(11);
+        EncounterTemplate {
+            id: row.get::<_, uuid::Uuid>(0).to_string(),
+            name: row.get(1),
+            encounter_type: row.get(2),
+            doctor_id: row.get::<_, Option<uuid::Uuid>>(3).map(|u| u.to_string()),
+            summary: row.get(4),
+            motivo_consulta: row.get(5),
+            plan_tratamiento: row.get(6),
+            proxima_cita: row.get(7),
+            exam_slit_lamp: row.get(8),
+            exam_fundus: row.get(9),
+            exam_plan: row.get(10),
+            diagnoses: serde_json::from_value(diagnoses).unwrap_or_default(),
+            studies: row.get(12),
+            active: row.get(13),
+        }
+    }
+
+    // ============================================================
+    // ENCOUNTER SIGNING (FIRMA Y ENMIENDAS)
+    // ============================================================
+
+    /// Current signed content of an encounter, read inside `tx`
+    async fn encounter_content(&self, tx: &deadpool_postgres::Transaction<'_>, encounter_uuid: &uuid::Uuid) -> Result<EncounterContent, String> {
+        let row = tx
+            .query_opt(
+                "SELECT summary, motivo_consulta, plan_tratamiento, cirugias, estudios,
+                        proxima_cita, excursiones_od, excursiones_os
+                 FROM encounters WHERE id = $1",
+                &[encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Consulta no encontrada")?;
+
+        let exams = tx
+            .query(
+                "SELECT id, encounter_id, side::text, av_sc, av_cc,
+                        ref_sphere, ref_cyl, ref_axis,
+                        ref_subj_sphere, ref_subj_cyl, ref_subj_axis,
+                        rx_sphere, rx_cyl, rx_axis, rx_add,
+                        iop, slit_lamp, fundus, plan,
+                        av_sc_logmar::float8, av_cc_logmar::float8
+                 FROM exam_eye
+                 WHERE encounter_id = $1
+                 ORDER BY side",
+                &[encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let diagnoses = tx
+            .query(
+                "SELECT id, encounter_id, code, description, eye, is_primary
+                 FROM diagnoses
+                 WHERE encounter_id = $1 AND deleted_at IS NULL
+                 ORDER BY is_primary DESC, created_at, id",
+                &[encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(EncounterContent {
+            summary: row.get(0),
+            motivo_consulta: row.get(1),
+            plan_tratamiento: row.get(2),
+            cirugias: row.get(3),
+            estudios: row.get(4),
+            proxima_cita: row.get(5),
+            excursiones_od: row.get(6),
+            excursiones_os: row.get(7),
+            exams: exams.iter().map(|row| self.map_exam_eye_row(row)).collect(),
+            diagnoses: diagnoses.iter().map(|row| Diagnosis {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                encounter_id: row.get::<_, uuid::Uuid>(1).to_string(),
+                code: row.get(2),
+                description: row.get(3),
+                eye: row.get(4),
+                is_primary: row.get::<_, Option<bool>>(5).unwrap_or(false),
+            }).collect(),
+        })
+    }
+
+    /// Sign an encounter: only its doctor can, once. The content hash is
+    /// stored so later changes outside amendments can be detected.
+    pub async fn sign_encounter(&self, id: &str, doctor_id: &str) -> Result<Encounter, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let encounter_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        let doctor_uuid = uuid::Uuid::parse_str(doctor_id).map_err(|e| e.to_string())?;
 
-        client
-            .execute(
-                "UPDATE room_inventory_items SET active = false, updated_at = $1 WHERE id = $2",
-                &[&now, &item_uuid],
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        let row = tx
+            .query_opt(
+                "SELECT doctor_id, signed_at FROM encounters WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+                &[&encounter_uuid],
             )
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| e.to_string())?
+            .ok_or("Consulta no encontrada")?;
+        if row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(1).is_some() {
+            return Err("La consulta ya está firmada".to_string());
+        }
+        if let Some(encounter_doctor) = row.get::<_, Option<uuid::Uuid>>(0) {
+            if encounter_doctor != doctor_uuid {
+                return Err("Solo el médico de la consulta puede firmarla".to_string());
+            }
+        }
 
-        Ok(())
+        let content = self.encounter_content(&tx, &encounter_uuid).await?;
+        let hash = record::content_hash(&content)?;
+        tx.execute(
+            "UPDATE encounters SET
+                signed_at = now(), signed_by = $2, content_hash = $3,
+                doctor_id = COALESCE(doctor_id, $2), updated_at = now()
+             WHERE id = $1",
+            &[&encounter_uuid, &doctor_uuid, &hash],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        self.get_encounter_by_id(id)
+            .await?
+            .ok_or_else(|| "Encounter not found after signing".to_string())
     }
 
-    /// Update room inventory item stock
-    pub async fn update_room_inventory_stock(&self, id: &str, new_stock: i32) -> Result<(), String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
-        let item_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
-        let now = chrono::Utc::now();
-        let stock = std::cmp::max(0, new_stock);
+    /// Amend a signed encounter. The changes, the previous and new content,
+    /// the author and the reason are written in one transaction.
+    pub async fn amend_encounter(&self, id: &str, author_id: &str, amendment: &EncounterAmendmentInput) -> Result<EncounterHistory, String> {
+        record::validate_amendment_reason(&amendment.reason)?;
+        let encounter_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        let author_uuid = uuid::Uuid::parse_str(author_id).map_err(|e| e.to_string())?;
+        for exam in &amendment.exams {
+            if uuid::Uuid::parse_str(&exam.encounter_id).ok() != Some(encounter_uuid) {
+                return Err("El examen enmendado no pertenece a la consulta".to_string());
+            }
+        }
+        let diagnoses = match &amendment.diagnoses {
+            Some(list) => Some(self.check_diagnosis_list(list).await?),
+            None => None,
+        };
 
-        client
-            .execute(
-                "UPDATE room_inventory_items SET current_stock = $1, updated_at = $2 WHERE id = $3",
-                &[&stock, &now, &item_uuid],
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        let row = tx
+            .query_opt(
+                "SELECT signed_at FROM encounters WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+                &[&encounter_uuid],
             )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Consulta no encontrada")?;
+        if row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(0).is_none() {
+            return Err("La consulta no está firmada; puede editarse directamente".to_string());
+        }
+
+        // Lets the signed-record triggers accept this transaction's changes
+        tx.batch_execute("SET LOCAL app.encounter_amendment = 'on'")
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(())
+        let previous = self.encounter_content(&tx, &encounter_uuid).await?;
+        Self::write_encounter_update(&tx, &encounter_uuid, &amendment.changes).await?;
+        for exam in &amendment.exams {
+            Self::write_exam_eye(&tx, &encounter_uuid, exam).await?;
+        }
+        if let Some(diagnoses) = &diagnoses {
+            tx.execute(
+                "UPDATE diagnoses SET deleted_at = now(), updated_at = now() WHERE encounter_id = $1 AND deleted_at IS NULL",
+                &[&encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            for diagnosis in diagnoses {
+                tx.execute(
+                    "INSERT INTO diagnoses (id, encounter_id, code, description, eye, is_primary, created_at, updated_at)
+                     VALUES ($1, $2, $3, $4, $5, $6, now(), now())",
+                    &[
+                        &uuid::Uuid::new_v4(), &encounter_uuid, &diagnosis.code,
+                        &diagnosis.description, &diagnosis.eye, &diagnosis.is_primary,
+                    ],
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+        }
+        let new_content = self.encounter_content(&tx, &encounter_uuid).await?;
+
+        let hash = record::content_hash(&new_content)?;
+        if hash == record::content_hash(&previous)? {
+            return Err("La enmienda no modifica la consulta".to_string());
+        }
+        let previous_json = serde_json::to_value(&previous).map_err(|e| e.to_string())?;
+        let new_json = serde_json::to_value(&new_content).map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "INSERT INTO encounter_amendments (encounter_id, version, reason, author_id, previous_content, new_content, content_hash)
+             SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5, $6
+             FROM encounter_amendments WHERE encounter_id = $1",
+            &[&encounter_uuid, &amendment.reason.trim(), &author_uuid, &previous_json, &new_json, &hash],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        tx.execute(
+            "UPDATE encounters SET content_hash = $2, updated_at = now() WHERE id = $1",
+            &[&encounter_uuid, &hash],
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        self.get_encounter_history(id).await
+    }
+
+    /// Signature, amendments and integrity check of an encounter
+    pub async fn get_encounter_history(&self, id: &str) -> Result<EncounterHistory, String> {
+        let mut client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let encounter_uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+        let row = tx
+            .query_opt(
+                "SELECT e.signed_at, e.signed_by, p.full_name, e.content_hash
+                 FROM encounters e
+                 LEFT JOIN profiles p ON p.user_id = e.signed_by
+                 WHERE e.id = $1",
+                &[&encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Consulta no encontrada")?;
+        let content_hash: Option<String> = row.get(3);
+
+        let rows = tx
+            .query(
+                "SELECT a.id, a.version, a.reason, a.author_id, p.full_name, a.created_at,
+                        a.previous_content, a.new_content, a.content_hash
+                 FROM encounter_amendments a
+                 LEFT JOIN profiles p ON p.user_id = a.author_id
+                 WHERE a.encounter_id = $1
+                 ORDER BY a.version",
+                &[&encounter_uuid],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut amendments = Vec::new();
+        for row in &rows {
+            amendments.push(EncounterAmendment {
+                id: row.get::<_, uuid::Uuid>(0).to_string(),
+                encounter_id: id.to_string(),
+                version: row.get(1),
+                reason: row.get(2),
+                author_id: row.get::<_, uuid::Uuid>(3).to_string(),
+                author_name: row.get(4),
+                created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(5).to_rfc3339(),
+                previous_content: serde_json::from_value(row.get(6)).map_err(|e| e.to_string())?,
+                new_content: serde_json::from_value(row.get(7)).map_err(|e| e.to_string())?,
+                content_hash: row.get(8),
+            });
+        }
+
+        // Unsigned encounters have no hash to check
+        let integrity_ok = match &content_hash {
+            Some(hash) => record::content_hash(&self.encounter_content(&tx, &encounter_uuid).await?)? == *hash,
+            None => true,
+        };
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(EncounterHistory {
+            encounter_id: id.to_string(),
+            signed_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(0).map(|d| d.to_rfc3339()),
+            signed_by: row.get::<_, Option<uuid::Uuid>>(1).map(|u| u.to_string()),
+            signed_by_name: row.get(2),
+            content_hash,
+            integrity_ok,
+            amendments,
+        })
     }
 
-    /// Create a room inventory movement
-    pub async fn create_room_inventory_movement(&self, input: &RoomInventoryMovementInput) -> Result<RoomInventoryMovement, String> {
+    // ============================================================
+    // AUDIT LOG (BITÁCORA DE AUDITORÍA)
+    // ============================================================
+
+    /// Audit log entries written by the audit triggers, newest first
+    pub async fn get_audit_log(&self, filters: &AuditLogFilters, limit: i64) -> Result<Vec<AuditLogEntry>, String> {
         let client = self.pool.get().await.map_err(|e| e.to_string())?;
-        let branch_uuid = uuid::Uuid::parse_str(&input.branch_id).map_err(|e| e.to_string())?;
-        let item_uuid = uuid::Uuid::parse_str(&input.item_id).map_err(|e| e.to_string())?;
-        let user_uuid = match &input.user_id {
-            Some(u) => Some(uuid::Uuid::parse_str(u).map_err(|e| e.to_string())?),
+        let patient_uuid = match &filters.patient_id {
+            Some(id) => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let user_uuid = match &filters.user_id {
+            Some(id) => Some(uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let start = match &filters.start_date {
+            Some(d) => Some(chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|e| format!("Invalid start date: {}", e))?),
+            None => None,
+        };
+        let end = match &filters.end_date {
+            Some(d) => Some(chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|e| format!("Invalid end date: {}", e))?),
             None => None,
         };
 
-        let row = client
-            .query_one(
-                "INSERT INTO room_inventory_movements (item_id, quantity, movement_type, notes, user_id, branch_id)
-                 VALUES ($1, $2, $3::room_inventory_movement_type, $4, $5, $6)
-                 RETURNING id, item_id, quantity, movement_type::text, notes, user_id, branch_id, created_at",
-                &[&item_uuid, &input.quantity, &input.movement_type, &input.notes, &user_uuid, &branch_uuid],
+        let rows = client
+            .query(
+                "SELECT a.id, a.created_at, a.user_id, p.full_name, COALESCE(a.target_table, ''),
+                        COALESCE(a.target_id, ''), a.patient_id, a.action, a.before_data, a.after_data
+                 FROM audit_logs a
+                 LEFT JOIN profiles p ON p.user_id = a.user_id
+                 WHERE ($1::uuid IS NULL OR a.patient_id = $1)
+                   AND ($2::uuid IS NULL OR a.user_id = $2)
+                   AND ($3::text IS NULL OR a.target_table = $3)
+                   AND ($4::date IS NULL OR a.created_at >= $4::date)
+                   AND ($5::date IS NULL OR a.created_at < $5::date + 1)
+                 ORDER BY a.created_at DESC, a.id DESC
+                 LIMIT $6",
+                &[&patient_uuid, &user_uuid, &filters.table_name, &start, &end, &limit],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(RoomInventoryMovement {
+        Ok(rows.iter().map(|row| AuditLogEntry {
             id: row.get::<_, uuid::Uuid>(0).to_string(),
-            item_id: row.get::<_, uuid::Uuid>(1).to_string(),
-            quantity: row.get(2),
-            movement_type: row.get(3),
-            notes: row.get(4),
-            user_id: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
-            branch_id: row.get::<_, uuid::Uuid>(6).to_string(),
-            created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
-            item: None,
-        })
+            occurred_at: row.get::<_, Option<chrono::DateTime<chrono::Utc>>>(1).unwrap_or_default().to_rfc3339(),
+            user_id: row.get::<_, Option<uuid::Uuid>>(2).map(|u| u.to_string()),
+            user_name: row.get(3),
+            table_name: row.get(4),
+            record_id: row.get(5),
+            patient_id: row.get::<_, Option<uuid::Uuid>>(6).map(|u| u.to_string()),
+            action: row.get(7),
+            before_data: row.get(8),
+            after_data: row.get(9),
+        }).collect())
     }
 
-    /// Get room inventory movements
-    pub async fn get_room_inventory_movements(&self, branch_id: &str, item_id: Option<&str>, limit: i32) -> Result<Vec<RoomInventoryMovement>, String> {
-        let client = self.pool.get().await.map_err(|e| e.to_string())?;
-        let branch_uuid = uuid::Uuid::parse_str(branch_id).map_err(|e| e.to_string())?;
-        let limit_i64 = limit as i64;
+    // ============================================================
+    // PATIENT ACCESS LOG (REGISTRO DE ACCESOS)
+    // ============================================================
 
-        let rows = if let Some(item) = item_id {
-            let item_uuid = uuid::Uuid::parse_str(item).map_err(|e| e.to_string())?;
+    /// Record a read of a patient's clinical data by the signed-in user.
+    /// A failed entry is logged and does not block the read.
+    pub async fn record_patient_access(&self, patient_id: &str, resource: &str, purpose: Option<&str>) {
+        let user = self.audit_user.read().ok().and_then(|u| *u);
+        let result = async {
+            let client = self.pool.get().await.map_err(|e| e.to_string())?;
+            let patient_uuid = uuid::Uuid::parse_str(patient_id).map_err(|e| e.to_string())?;
             client
-                .query(
-                    "SELECT m.id, m.item_id, m.quantity, m.movement_type::text, m.notes, m.user_id, m.branch_id, m.created_at,
-                            i.id as i_id, i.category_id, i.name, i.code, i.brand, i.specification,
-                            i.current_stock, i.min_stock, i.unit, i.notes as i_notes, i.active,
-                            i.branch_id as i_branch_id, i.created_at as i_created_at, i.updated_at as i_updated_at
-                     FROM room_inventory_movements m
-                     LEFT JOIN room_inventory_items i ON m.item_id = i.id
-                     WHERE m.branch_id = $1 AND m.item_id = $2
-                     ORDER BY m.created_at DESC
-                     LIMIT $3",
-                    &[&branch_uuid, &item_uuid, &limit_i64],
+                .execute(
+                    "INSERT INTO patient_access_log (user_id, patient_id, resource, purpose)
+                     VALUES ($1, $2, $3, $4)",
+                    &[&user, &patient_uuid, &resource, &purpose],
                 )
                 .await
-                .map_err(|e| e.to_string())?
-        } else {
+                .map_err(|e| e.to_string())
+        }
+        .await;
+
+        if let Err(e) = result {
+            log::warn!("Failed to record access to patient {}: {}", patient_id, e);
+        }
+    }
+
+    /// Record a read of the patient's data reached through one of their encounters
+    pub async fn record_encounter_access(&self, encounter_id: &str, resource: &str, purpose: Option<&str>) {
+        self.record_access_through("SELECT patient_id::text FROM encounters WHERE id = $1", encounter_id, resource, purpose).await;
+    }
+
+    /// Record a read of the patient's data reached through one of their prescriptions
+    pub async fn record_prescription_access(&self, prescription_id: &str, resource: &str, purpose: Option<&str>) {
+        self.record_access_through("SELECT patient_id::text FROM prescriptions WHERE id = $1", prescription_id, resource, purpose).await;
+    }
+
+    /// Patient of the record found with `lookup` (which selects its patient_id by id)
+    async fn record_access_through(&self, lookup: &str, id: &str, resource: &str, purpose: Option<&str>) {
+        let patient_id = async {
+            let client = self.pool.get().await.map_err(|e| e.to_string())?;
+            let uuid = uuid::Uuid::parse_str(id).map_err(|e| e.to_string())?;
+            let row = client.query_opt(lookup, &[&uuid]).await.map_err(|e| e.to_string())?;
+            Ok::<_, String>(row.map(|r| r.get::<_, String>(0)))
+        }
+        .await;
+
+        match patient_id {
+            Ok(Some(patient_id)) => self.record_patient_access(&patient_id, resource, purpose).await,
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to record {} access through {}: {}", resource, id, e),
+        }
+    }
+
+    /// Record one read of each patient, e.g. for a research export
+    pub async fn record_patients_access(&self, patient_ids: &[String], resource: &str, purpose: Option<&str>) {
+        let user = self.audit_user.read().ok().and_then(|u| *u);
+        let patient_uuids: Vec<uuid::Uuid> = patient_ids.iter().filter_map(|id| uuid::Uuid::parse_str(id).ok()).collect();
+        if patient_uuids.is_empty() {
+            return;
+        }
+        let result = async {
+            let client = self.pool.get().await.map_err(|e| e.to_string())?;
             client
-                .query(
-                    "SELECT m.id, m.item_id, m.quantity, m.movement_type::text, m.notes, m.user_id, m.branch_id, m.created_at,
-                            i.id as i_id, i.category_id, i.name, i.code, i.brand, i.specification,
-                            i.current_stock, i.min_stock, i.unit, i.notes as i_notes, i.active,
-                            i.branch_id as i_branch_id, i.created_at as i_created_at, i.updated_at as i_updated_at
-                     FROM room_inventory_movements m
-                     LEFT JOIN room_inventory_items i ON m.item_id = i.id
-                     WHERE m.branch_id = $1
-                     ORDER BY m.created_at DESC
-                     LIMIT $2",
-                    &[&branch_uuid, &limit_i64],
+                .execute(
+                    "INSERT INTO patient_access_log (user_id, patient_id, resource, purpose)
+                     SELECT $1, patient_id, $3, $4 FROM (SELECT DISTINCT unnest($2::uuid[]) AS patient_id) p",
+                    &[&user, &patient_uuids, &resource, &purpose],
                 )
                 .await
-                .map_err(|e| e.to_string())?
-        };
+                .map_err(|e| e.to_string())
+        }
+        .await;
 
-        Ok(rows.iter().map(|row| {
-            let item = row.get::<_, Option<uuid::Uuid>>(8).map(|_| {
-                RoomInventoryItem {
-                    id: row.get::<_, uuid::Uuid>(8).to_string(),
-                    category_id: row.get::<_, uuid::Uuid>(9).to_string(),
-                    name: row.get(10),
-                    code: row.get(11),
-                    brand: row.get(12),
-                    specification: row.get(13),
-                    current_stock: row.get(14),
-                    min_stock: row.get(15),
-                    unit: row.get(16),
-                    notes: row.get(17),
-                    active: row.get(18),
-                    branch_id: row.get::<_, uuid::Uuid>(19).to_string(),
-                    created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(20).to_rfc3339(),
-                    updated_at: row.get::<_, chrono::DateTime<chrono::Utc>>(21).to_rfc3339(),
-                    category: None,
-                }
-            });
+        if let Err(e) = result {
+            log::warn!("Failed to record {} access to {} patients: {}", resource, patient_uuids.len(), e);
+        }
+    }
 
-            RoomInventoryMovement {
-                id: row.get::<_, uuid::Uuid>(0).to_string(),
-                item_id: row.get::<_, uuid::Uuid>(1).to_string(),
-                quantity: row.get(2),
-                movement_type: row.get(3),
-                notes: row.get(4),
-                user_id: row.get::<_, Option<uuid::Uuid>>(5).map(|u| u.to_string()),
-                branch_id: row.get::<_, uuid::Uuid>(6).to_string(),
-                created_at: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
-                item,
-            }
+    pub async fn get_patient_access_log(&self, patient_id: &str, limit: i64) -> Result<Vec<PatientAccessEntry>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let patient_uuid = uuid::Uuid::parse_str(patient_id).map_err(|e| e.to_string())?;
+
+        let rows = client
+            .query(
+                "SELECT a.id, a.accessed_at, a.user_id, p.full_name, a.patient_id, a.resource, a.purpose
+                 FROM patient_access_log a
+                 LEFT JOIN profiles p ON p.user_id = a.user_id
+                 WHERE a.patient_id = $1
+                 ORDER BY a.accessed_at DESC, a.id DESC
+                 LIMIT $2",
+                &[&patient_uuid, &limit],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| PatientAccessEntry {
+            id: row.get(0),
+            accessed_at: row.get::<_, chrono::DateTime<chrono::Utc>>(1).to_rfc3339(),
+            user_id: row.get::<_, Option<uuid::Uuid>>(2).map(|u| u.to_string()),
+            user_name: row.get(3),
+            patient_id: row.get::<_, uuid::Uuid>(4).to_string(),
+            resource: row.get(5),
+            purpose: row.get(6),
+        }).collect())
+    }
+
+    /// Accesses between two dates (inclusive) that deserve review:
+    /// - a user assigned to branches opened a patient whose appointments are
+    ///   all at other branches (users without branches, e.g. admins, and
+    ///   patients without appointments are not flagged)
+    /// - a user opened more than `daily_patient_limit` patients in one day
+    pub async fn get_unusual_patient_access(
+        &self,
+        start_date: &str,
+        end_date: &str,
+        daily_patient_limit: i64,
+    ) -> Result<Vec<UnusualAccess>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let start = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d")
+            .map_err(|e| format!("Invalid start date: {}", e))?;
+        let end = chrono::NaiveDate::parse_from_str(end_date, "%Y-%m-%d")
+            .map_err(|e| format!("Invalid end date: {}", e))?;
+        // Days are Guatemala days, whatever the server time zone
+        let period_start = timezone::day_start_utc(start);
+        let period_end = timezone::day_end_utc(end);
+
+        let rows = client
+            .query(
+                "SELECT 'fuera_de_sucursal'::text, l.user_id, pr.full_name, l.patient_id,
+                        pa.first_name || ' ' || pa.last_name, NULL::date,
+                        COUNT(*), MIN(l.accessed_at), MAX(l.accessed_at)
+                 FROM patient_access_log l
+                 JOIN patients pa ON pa.id = l.patient_id
+                 LEFT JOIN profiles pr ON pr.user_id = l.user_id
+                 WHERE l.accessed_at >= $1 AND l.accessed_at < $2
+                   AND l.user_id IS NOT NULL
+                   AND EXISTS (SELECT 1 FROM user_branches ub WHERE ub.user_id = l.user_id)
+                   AND EXISTS (
+                       SELECT 1 FROM appointments a
+                       WHERE a.patient_id = l.patient_id AND a.deleted_at IS NULL
+                   )
+                   AND NOT EXISTS (
+                       SELECT 1 FROM appointments a
+                       JOIN user_branches ub ON ub.branch_id = a.branch_id AND ub.user_id = l.user_id
+                       WHERE a.patient_id = l.patient_id AND a.deleted_at IS NULL
+                   )
+                 GROUP BY l.user_id, pr.full_name, l.patient_id, pa.first_name, pa.last_name
+                 UNION ALL
+                 SELECT 'volumen_alto'::text, l.user_id, pr.full_name, NULL::uuid, NULL::text,
+                        (l.accessed_at AT TIME ZONE 'America/Guatemala')::date, COUNT(DISTINCT l.patient_id),
+                        MIN(l.accessed_at), MAX(l.accessed_at)
+                 FROM patient_access_log l
+                 LEFT JOIN profiles pr ON pr.user_id = l.user_id
+                 WHERE l.accessed_at >= $1 AND l.accessed_at < $2
+                   AND l.user_id IS NOT NULL
+                 GROUP BY l.user_id, pr.full_name, (l.accessed_at AT TIME ZONE 'America/Guatemala')::date
+                 HAVING COUNT(DISTINCT l.patient_id) > $3
+                 ORDER BY 9 DESC",
+                &[&period_start, &period_end, &daily_patient_limit],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows.iter().map(|row| UnusualAccess {
+            kind: row.get(0),
+            user_id: row.get::<_, uuid::Uuid>(1).to_string(),
+            user_name: row.get(2),
+            patient_id: row.get::<_, Option<uuid::Uuid>>(3).map(|u| u.to_string()),
+            patient_name: row.get(4),
+            day: row.get::<_, Option<chrono::NaiveDate>>(5).map(|d| d.to_string()),
+            access_count: row.get(6),
+            first_access: row.get::<_, chrono::DateTime<chrono::Utc>>(7).to_rfc3339(),
+            last_access: row.get::<_, chrono::DateTime<chrono::Utc>>(8).to_rfc3339(),
         }).collect())
     }
 
@@ -5311,7 +10379,7 @@ impl PostgresPool {
                 "SELECT
                     ii.service_type as service_type,
                     COUNT(*)::bigint as cantidad,
-                    COALESCE(SUM(ii.total), 0)::float8 as total
+                    COALESCE(SUM(ii.total), 0)::numeric as total
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  WHERE i.created_at >= $1::timestamptz
@@ -5343,7 +10411,7 @@ impl PostgresPool {
                 "SELECT
                     p.payment_method,
                     COUNT(*)::bigint as cantidad,
-                    COALESCE(SUM(p.amount), 0)::float8 as total
+                    COALESCE(SUM(p.amount), 0)::numeric as total
                  FROM payments p
                  JOIN invoices i ON p.invoice_id = i.id
                  WHERE p.created_at >= $1::timestamptz
@@ -5375,7 +10443,7 @@ impl PostgresPool {
                     COALESCE(ii.item_id::text, '') as item_id,
                     COALESCE(ii.description, 'Sin nombre') as item_name,
                     COALESCE(SUM(ii.quantity), 0)::bigint as total_quantity,
-                    COALESCE(SUM(ii.total), 0)::float8 as total_revenue
+                    COALESCE(SUM(ii.total), 0)::numeric as total_revenue
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  WHERE i.created_at >= $1::timestamptz
@@ -5410,7 +10478,7 @@ impl PostgresPool {
                     COALESCE(ii.item_id::text, '') as item_id,
                     COALESCE(ii.description, 'Sin nombre') as item_name,
                     COALESCE(SUM(ii.quantity), 0)::bigint as total_quantity,
-                    COALESCE(SUM(ii.total), 0)::float8 as total_revenue
+                    COALESCE(SUM(ii.total), 0)::numeric as total_revenue
                  FROM invoice_items ii
                  JOIN invoices i ON ii.invoice_id = i.id
                  WHERE i.created_at >= $1::timestamptz
@@ -5490,9 +10558,9 @@ impl PostgresPool {
                     doctor_name,
                     COUNT(*)::bigint as cantidad,
                     COUNT(DISTINCT patient_id)::bigint as pacientes_unicos,
-                    SUM(revenue_real)::float8 as revenue_real,
-                    SUM(revenue_estimado)::float8 as revenue_estimado,
-                    SUM(GREATEST(revenue_real, revenue_estimado))::float8 as revenue_total
+                    SUM(revenue_real)::numeric as revenue_real,
+                    SUM(revenue_estimado)::numeric as revenue_estimado,
+                    SUM(GREATEST(revenue_real, revenue_estimado))::numeric as revenue_total
                 FROM calculated_revenue
                 GROUP BY tipo_cita, doctor_id, doctor_name
                 ORDER BY revenue_total DESC",
@@ -5522,10 +10590,10 @@ impl PostgresPool {
             .query(
                 "SELECT
                     id::text,
-                    COALESCE(total_amount, 0)::float8 as total_amount,
+                    COALESCE(total_amount, 0)::numeric as total_amount,
                     created_at::text,
                     status,
-                    COALESCE(discount_value, 0)::float8 as discount_value,
+                    COALESCE(discount_value, 0)::numeric as discount_value,
                     discount_type
                  FROM invoices
                  WHERE created_at >= $1::timestamptz
@@ -5558,9 +10626,9 @@ impl PostgresPool {
                 "SELECT
                     c.id::text,
                     c.closure_date::text,
-                    COALESCE(c.total_invoiced, 0)::float8 as total_invoiced,
-                    COALESCE(c.total_collected, 0)::float8 as total_collected,
-                    COALESCE(c.total_pending, 0)::float8 as total_pending,
+                    COALESCE(c.total_invoiced, 0)::numeric as total_invoiced,
+                    COALESCE(c.total_collected, 0)::numeric as total_collected,
+                    COALESCE(c.total_pending, 0)::numeric as total_pending,
                     c.closed_by::text,
                     COALESCE(p.full_name, 'N/A') as user_name
                  FROM cash_closures c
@@ -5651,6 +10719,11 @@ impl PostgresPool {
         let search_field_type = filters.search_field_type.clone().unwrap_or_else(|| "diagnosis".to_string());
         let surgery_type_filter = filters.surgery_type_filter.clone();
         let appointment_type_filter = filters.appointment_type_filter.clone();
+        if let Some(eye) = filters.logmar_eye.as_deref() {
+            if eye != "OD" && eye != "OI" {
+                return Err(format!("Ojo inválido para el filtro de agudeza visual: {}", eye));
+            }
+        }
 
         // Build the complex query with dynamic text search
         let query = r#"
@@ -5676,6 +10749,7 @@ impl PostgresPool {
                 SELECT
                     d.encounter_id,
                     string_agg(DISTINCT d.description, '; ') as diagnosis_summary,
+                    string_agg(DISTINCT d.code, '; ') as diagnosis_codes,
                     string_agg(DISTINCT d.treatment_plan, '; ') as treatment_plan,
                     string_agg(DISTINCT d.recommended_surgeries, '; ') as recommended_surgeries,
                     string_agg(DISTINCT d.recommended_studies, '; ') as recommended_studies
@@ -5782,7 +10856,8 @@ impl PostgresPool {
                 pa.procedure_type,
                 pa.procedure_eye,
                 sta.study_type,
-                sta.study_status
+                sta.study_status,
+                da.diagnosis_codes
             FROM encounters_base eb
             JOIN patients pt ON eb.patient_id = pt.id
             LEFT JOIN diagnoses_agg da ON eb.encounter_id = da.encounter_id
@@ -5795,19 +10870,26 @@ impl PostgresPool {
                 $5::text IS NULL
                 OR (
                     CASE $6::text
-                        WHEN 'diagnosis' THEN COALESCE(da.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                        WHEN 'diagnosis' THEN (
+                            COALESCE(da.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                            OR replace(COALESCE(da.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
+                        )
                         WHEN 'treatment_plan' THEN COALESCE(da.treatment_plan, '') ILIKE '%' || $5 || '%'
                         WHEN 'surgeries' THEN COALESCE(da.recommended_surgeries, '') ILIKE '%' || $5 || '%'
                         WHEN 'studies' THEN COALESCE(da.recommended_studies, '') ILIKE '%' || $5 || '%'
                         WHEN 'chief_complaint' THEN COALESCE(eb.chief_complaint, '') ILIKE '%' || $5 || '%'
                         WHEN 'all' THEN (
                             COALESCE(da.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                            OR replace(COALESCE(da.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
                             OR COALESCE(da.treatment_plan, '') ILIKE '%' || $5 || '%'
                             OR COALESCE(da.recommended_surgeries, '') ILIKE '%' || $5 || '%'
                             OR COALESCE(da.recommended_studies, '') ILIKE '%' || $5 || '%'
                             OR COALESCE(eb.chief_complaint, '') ILIKE '%' || $5 || '%'
                         )
-                        ELSE COALESCE(da.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                        ELSE (
+                            COALESCE(da.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                            OR replace(COALESCE(da.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
+                        )
                     END
                 )
             )
@@ -5817,6 +10899,13 @@ impl PostgresPool {
             AND ($10::text IS NULL OR pt.gender = $10)
             AND ($11::boolean IS NULL OR ($11 = true AND COALESCE(pt.pathological_history->>'diabetes', 'false')::boolean = true))
             AND ($12::boolean IS NULL OR ($12 = true AND COALESCE(pt.pathological_history->>'hipertension', 'false')::boolean = true))
+            AND (($13::float8 IS NULL AND $14::float8 IS NULL) OR (
+                SELECT MIN(COALESCE(av.av_cc_logmar, av.av_sc_logmar))
+                FROM exam_eye av
+                WHERE av.encounter_id = eb.encounter_id
+                  AND av.side::text IN ('OD', 'OI')
+                  AND ($15::text IS NULL OR av.side::text = $15)
+            ) BETWEEN COALESCE($13::float8, '-Infinity') AND COALESCE($14::float8, 'Infinity'))
             ORDER BY eb.encounter_date DESC
             LIMIT 1000
         "#;
@@ -5837,12 +10926,15 @@ impl PostgresPool {
                     &filters.gender_filter,
                     &filters.has_diabetes,
                     &filters.has_hta,
+                    &filters.min_logmar,
+                    &filters.max_logmar,
+                    &filters.logmar_eye,
                 ],
             )
             .await
             .map_err(|e| e.to_string())?;
 
-        Ok(rows.iter().map(|row| crate::commands::ClinicalResearchRow {
+        let mut research_rows: Vec<crate::commands::ClinicalResearchRow> = rows.iter().map(|row| crate::commands::ClinicalResearchRow {
             patient_id: row.get(0),
             patient_code: row.get(1),
             patient_name: row.get(2),
@@ -5856,6 +10948,7 @@ impl PostgresPool {
             doctor_name: row.get(10),
             chief_complaint: row.get(11),
             diagnosis_summary: row.get(12),
+            diagnosis_codes: row.get(43),
             treatment_plan: row.get(13),
             recommended_surgeries: row.get(14),
             recommended_studies: row.get(15),
@@ -5879,14 +10972,105 @@ impl PostgresPool {
             oi_final_prescription: row.get(33),
             oi_slit_lamp: row.get(34),
             oi_fundus: row.get(35),
+            od_avsc_logmar: None,
+            od_avcc_logmar: None,
+            oi_avsc_logmar: None,
+            oi_avcc_logmar: None,
+            od_spherical_equivalent: None,
+            oi_spherical_equivalent: None,
+            anisometropia: None,
+            is_anisometropic: None,
             surgery_type: row.get(36),
             surgery_eye: row.get(37),
             surgery_date: row.get(38),
+            preop_logmar: None,
+            postop_logmar: None,
+            logmar_change: None,
             procedure_type: row.get(39),
             procedure_eye: row.get(40),
             study_type: row.get(41),
             study_status: row.get(42),
-        }).collect())
+        }).collect();
+
+        // Spherical equivalent, anisometropia and logMAR come from the numeric exam fields
+        let encounter_ids: Vec<uuid::Uuid> = research_rows.iter()
+            .filter_map(|r| r.encounter_id.as_deref().and_then(|id| uuid::Uuid::parse_str(id).ok()))
+            .collect();
+        let exams = self.get_exam_eyes_by_encounters(&encounter_ids).await?;
+        for research_row in research_rows.iter_mut() {
+            let Some(eyes) = research_row.encounter_id.as_ref().and_then(|id| exams.get(id)) else {
+                continue;
+            };
+            let od_exam = eyes.iter().find(|e| e.side == "OD");
+            let os_exam = eyes.iter().find(|e| e.side == "OI");
+            research_row.od_avsc_logmar = od_exam.and_then(|e| e.av_sc_logmar);
+            research_row.od_avcc_logmar = od_exam.and_then(|e| e.av_cc_logmar);
+            research_row.oi_avsc_logmar = os_exam.and_then(|e| e.av_sc_logmar);
+            research_row.oi_avcc_logmar = os_exam.and_then(|e| e.av_cc_logmar);
+
+            let od = od_exam.and_then(Self::exam_refraction).map(|(_, r)| r);
+            let os = os_exam.and_then(Self::exam_refraction).map(|(_, r)| r);
+            research_row.od_spherical_equivalent = od.map(|r| r.spherical_equivalent());
+            research_row.oi_spherical_equivalent = os.map(|r| r.spherical_equivalent());
+            if let (Some(od), Some(os)) = (od, os) {
+                research_row.anisometropia = Some(refraction::anisometropia(&od, &os));
+                research_row.is_anisometropic = Some(refraction::is_anisometropic(&od, &os));
+            }
+        }
+
+        // Pre/post-op acuity of the operated eye, over all of the patient's exams
+        let operated: Vec<uuid::Uuid> = research_rows.iter()
+            .filter(|r| matches!(r.surgery_eye.as_deref(), Some("OD") | Some("OI")) && r.surgery_date.is_some())
+            .filter_map(|r| r.patient_id.as_deref().and_then(|id| uuid::Uuid::parse_str(id).ok()))
+            .collect();
+        if !operated.is_empty() {
+            let history = self.get_logmar_history(&operated).await?;
+            for research_row in research_rows.iter_mut() {
+                let (Some(patient_id), Some(eye), Some(date)) = (
+                    research_row.patient_id.as_ref(),
+                    research_row.surgery_eye.as_ref(),
+                    research_row.surgery_date.as_deref().and_then(|d| d.get(..10)).and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()),
+                ) else {
+                    continue;
+                };
+                let Some(measurements) = history.get(&(patient_id.clone(), eye.clone())) else {
+                    continue;
+                };
+                let outcome = acuity::surgical_acuity(measurements, date);
+                research_row.preop_logmar = outcome.preop;
+                research_row.postop_logmar = outcome.postop;
+                research_row.logmar_change = outcome.change;
+            }
+        }
+
+        Ok(research_rows)
+    }
+
+    /// Dated best acuity (cc, else sc) per patient and eye, in logMAR
+    async fn get_logmar_history(
+        &self,
+        patient_ids: &[uuid::Uuid],
+    ) -> Result<std::collections::HashMap<(String, String), Vec<(chrono::NaiveDate, f64)>>, String> {
+        let client = self.pool.get().await.map_err(|e| e.to_string())?;
+        let rows = client
+            .query(
+                "SELECT e.patient_id::text, ee.side::text, e.created_at::date,
+                        COALESCE(ee.av_cc_logmar, ee.av_sc_logmar)::float8
+                 FROM exam_eye ee
+                 JOIN encounters e ON e.id = ee.encounter_id
+                 WHERE e.patient_id = ANY($1)
+                   AND ee.side::text IN ('OD', 'OI')
+                   AND COALESCE(ee.av_cc_logmar, ee.av_sc_logmar) IS NOT NULL",
+                &[&patient_ids],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut history: std::collections::HashMap<(String, String), Vec<(chrono::NaiveDate, f64)>> = std::collections::HashMap::new();
+        for row in &rows {
+            history.entry((row.get(0), row.get(1))).or_default().push((row.get(2), row.get(3)));
+        }
+        Ok(history)
     }
 
     /// Get clinical research data grouped by patient
@@ -5918,6 +11102,7 @@ impl PostgresPool {
                 LEFT JOIN profiles p ON a.doctor_id = p.user_id
                 LEFT JOIN LATERAL (
                     SELECT string_agg(DISTINCT description, '; ') as diagnosis_summary,
+                           string_agg(DISTINCT code, '; ') as diagnosis_codes,
                            string_agg(DISTINCT treatment_plan, '; ') as treatment_plan,
                            string_agg(DISTINCT recommended_surgeries, '; ') as recommended_surgeries
                     FROM diagnoses WHERE encounter_id = e.id
@@ -5944,15 +11129,22 @@ impl PostgresPool {
                       $5::text IS NULL
                       OR (
                           CASE $6::text
-                              WHEN 'diagnosis' THEN COALESCE(d.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                              WHEN 'diagnosis' THEN (
+                                  COALESCE(d.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                                  OR replace(COALESCE(d.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
+                              )
                               WHEN 'treatment_plan' THEN COALESCE(d.treatment_plan, '') ILIKE '%' || $5 || '%'
                               WHEN 'surgeries' THEN COALESCE(d.recommended_surgeries, '') ILIKE '%' || $5 || '%'
                               WHEN 'all' THEN (
                                   COALESCE(d.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                                  OR replace(COALESCE(d.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
                                   OR COALESCE(d.treatment_plan, '') ILIKE '%' || $5 || '%'
                                   OR COALESCE(d.recommended_surgeries, '') ILIKE '%' || $5 || '%'
                               )
-                              ELSE COALESCE(d.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                              ELSE (
+                                  COALESCE(d.diagnosis_summary, '') ILIKE '%' || $5 || '%'
+                                  OR replace(COALESCE(d.diagnosis_codes, ''), '.', '') ILIKE '%' || replace($5, '.', '') || '%'
+                              )
                           END
                       )
                   )
@@ -6076,7 +11268,7 @@ impl PostgresPool {
                 a.starts_at::text as appointment_date,
                 CASE WHEN i.id IS NOT NULL AND i.status != 'cancelada' THEN true ELSE false END as is_invoiced,
                 COALESCE(a.reason ILIKE '%cortesia%' OR a.reason ILIKE '%cortesía%', false) as is_courtesy,
-                COALESCE(i.total_amount, 0)::float8 as invoice_amount,
+                COALESCE(i.total_amount, 0)::numeric as invoice_amount,
                 (SELECT st.name FROM surgeries s
                  JOIN surgery_types st ON s.surgery_type_id = st.id
                  JOIN encounters e ON s.encounter_id = e.id
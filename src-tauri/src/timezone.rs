@@ -0,0 +1,55 @@
+// Local time of the clinic (Guatemala, UTC-6, no daylight saving)
+//
+// Timestamps are stored in UTC. Business dates (invoice and report days,
+// lot expiry, SAT document times) are Guatemala dates, so a day runs from
+// 06:00 UTC to 06:00 UTC of the next day.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+const UTC_OFFSET_HOURS: i32 = -6;
+
+pub fn offset() -> FixedOffset {
+    FixedOffset::east_opt(UTC_OFFSET_HOURS * 3600).expect("valid offset")
+}
+
+/// A UTC instant in Guatemala time
+pub fn local(instant: DateTime<Utc>) -> DateTime<FixedOffset> {
+    instant.with_timezone(&offset())
+}
+
+pub fn now() -> DateTime<FixedOffset> {
+    local(Utc::now())
+}
+
+/// Current date in Guatemala
+pub fn today() -> NaiveDate {
+    now().date_naive()
+}
+
+/// First instant of `date` in Guatemala, in UTC
+pub fn day_start_utc(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("valid time");
+    DateTime::from_naive_utc_and_offset(midnight - offset(), Utc)
+}
+
+/// First instant after `date` ends in Guatemala, in UTC
+pub fn day_end_utc(date: NaiveDate) -> DateTime<Utc> {
+    day_start_utc(date.succ_opt().unwrap_or(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guatemala_days() {
+        let date = NaiveDate::from_ymd_opt(2026, 3, 31).unwrap();
+        assert_eq!(day_start_utc(date).to_rfc3339(), "2026-03-31T06:00:00+00:00");
+        assert_eq!(day_end_utc(date).to_rfc3339(), "2026-04-01T06:00:00+00:00");
+
+        // 03:00 UTC is still the previous evening in Guatemala
+        let instant = DateTime::parse_from_rfc3339("2026-04-01T03:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(local(instant).to_rfc3339(), "2026-03-31T21:00:00-06:00");
+        assert_eq!(local(instant).date_naive(), date);
+    }
+}
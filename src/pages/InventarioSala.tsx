@@ -375,7 +375,7 @@ export default function InventarioSala() {
       newStock = Math.max(0, newStock);
 
       if (isLocalMode) {
-        // Create movement
+        // El servidor aplica el movimiento al stock en la misma transacción
         await invoke('create_room_inventory_movement', {
           input: {
             item_id: data.item_id,
@@ -386,11 +386,6 @@ export default function InventarioSala() {
             branch_id: currentBranch!.id,
           },
         });
-        // Update stock
-        await invoke('update_room_inventory_stock', {
-          id: data.item_id,
-          newStock,
-        });
         return;
       }
 